@@ -0,0 +1,107 @@
+//! Cancellation of in-flight operations
+//!
+//! Commands and file transfers run through [`Operations::run`], which
+//! registers a cancellation token for as long as the operation is in flight.
+//! The `cancel-all` tool (and shutdown) signals every registered token at
+//! once; each operation then stops waiting and fails with
+//! [`SshMcpError::Cancelled`].
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+use crate::error::{Result, SshMcpError};
+
+/// Cancellation tokens of the operations currently in flight
+#[derive(Debug, Default)]
+pub struct Operations {
+    next_id: AtomicU64,
+    active: std::sync::Mutex<HashMap<u64, Arc<Notify>>>,
+}
+
+/// Removes an operation's token once it finishes or is abandoned
+struct Registration<'a> {
+    operations: &'a Operations,
+    id: u64,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.operations.active.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Operations {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run an operation until it finishes or is cancelled
+    pub async fn run<T>(&self, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let token = Arc::new(Notify::new());
+        self.active.lock().unwrap().insert(id, token.clone());
+        let _registration = Registration {
+            operations: self,
+            id,
+        };
+
+        tokio::select! {
+            result = operation => result,
+            // notify_one stores a permit, so a cancel that lands before this
+            // point is not missed
+            () = token.notified() => Err(SshMcpError::Cancelled),
+        }
+    }
+
+    /// Number of operations currently in flight
+    pub fn in_flight(&self) -> usize {
+        self.active.lock().unwrap().len()
+    }
+
+    /// Signal every operation in flight to abort, returning how many were
+    pub fn cancel_all(&self) -> usize {
+        let active = std::mem::take(&mut *self.active.lock().unwrap());
+        for token in active.values() {
+            token.notify_one();
+        }
+        active.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_completed_operation_unregisters() {
+        let operations = Operations::new();
+        assert_eq!(operations.run(async { Ok(7) }).await.unwrap(), 7);
+        assert_eq!(operations.in_flight(), 0);
+        assert_eq!(operations.cancel_all(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_aborts_in_flight() {
+        let operations = Arc::new(Operations::new());
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..3 {
+            let operations = operations.clone();
+            tasks.spawn(async move { operations.run(std::future::pending::<Result<()>>()).await });
+        }
+        while operations.in_flight() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(operations.cancel_all(), 3);
+        while let Some(result) = tasks.join_next().await {
+            assert!(matches!(result.unwrap(), Err(SshMcpError::Cancelled)));
+        }
+        assert_eq!(operations.in_flight(), 0);
+    }
+}
@@ -1,9 +1,22 @@
 //! Configuration and CLI argument parsing for SSH MCP Server
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use regex::Regex;
+use std::fmt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use crate::error::{Result, SshMcpError};
+use crate::ssh::config::{DEFAULT_ELEVATION_COMMAND, NON_LOGIN_ELEVATION_COMMAND};
+use crate::ssh::handler::{
+    default_host_key_store, default_known_hosts, parse_fingerprint, HostKeyMode, HostKeyStore,
+};
+use crate::ssh::socks::{SocksProxy, DEFAULT_SOCKS_PORT};
+use crate::ssh::{AuthMethod, HostKeyPolicy};
+use crate::tools::container::ContainerRuntime;
+use crate::tools::exit_codes::ExitCodeMap;
+use crate::tools::template::CommandTemplate;
 
 /// Default timeout for command execution in milliseconds
 pub const DEFAULT_TIMEOUT_MS: u64 = 60_000; // 60 seconds
@@ -11,9 +24,67 @@ pub const DEFAULT_TIMEOUT_MS: u64 = 60_000; // 60 seconds
 /// Default max characters for command output (None = unlimited)
 pub const DEFAULT_MAX_CHARS: Option<usize> = Some(1000);
 
+/// Default hard ceiling on bytes read from the remote per tool call (10 MiB)
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 10 * 1024 * 1024;
+
+/// Smallest accepted --max-packet-size
+pub const MIN_PACKET_SIZE: u32 = 1024;
+
+/// Largest accepted --max-packet-size
+pub const MAX_PACKET_SIZE: u32 = 65535;
+
+/// MCP protocol revisions that --protocol-version can pin
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
 /// Connection timeout in seconds
 pub const CONNECTION_TIMEOUT_SECS: u64 = 30;
 
+/// Commands run on a PTY when `--pty-commands` is not given: `sudo` called
+/// directly (it may insist on a terminal to prompt), editors, pagers and
+/// full-screen tools, at the start of any part of a compound command
+pub const DEFAULT_PTY_COMMANDS: &[&str] =
+    &[r"(^|[;&|(]\s*)(sudo|top|htop|watch|vi|vim|nvim|nano|less|more)(\s|$)"];
+
+/// What to do when `--key` is readable by group or others (`--key-perm-check`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyPermCheck {
+    /// Refuse to start, like OpenSSH does
+    Error,
+
+    /// Log a warning and use the key anyway
+    #[default]
+    Warn,
+
+    /// Don't check
+    Ignore,
+}
+
+impl FromStr for KeyPermCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "error" => Ok(KeyPermCheck::Error),
+            "warn" => Ok(KeyPermCheck::Warn),
+            "ignore" => Ok(KeyPermCheck::Ignore),
+            other => Err(format!(
+                "unknown mode '{}' (supported: error, warn, ignore)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeyPermCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyPermCheck::Error => "error",
+            KeyPermCheck::Warn => "warn",
+            KeyPermCheck::Ignore => "ignore",
+        })
+    }
+}
+
 /// SSH MCP Server CLI Arguments
 #[derive(Parser, Debug, Clone)]
 #[command(name = "ssh-mcp")]
@@ -21,16 +92,26 @@ pub const CONNECTION_TIMEOUT_SECS: u64 = 30;
 #[command(version = "1.4.0")]
 #[command(about = "MCP server exposing SSH control for Linux systems via Model Context Protocol")]
 pub struct Args {
-    /// SSH host to connect to
-    #[arg(long, env = "SSH_MCP_HOST")]
+    /// SSH host to connect to (not needed with --local)
+    #[arg(
+        long,
+        env = "SSH_MCP_HOST",
+        default_value = "",
+        hide_default_value = true
+    )]
     pub host: String,
 
     /// SSH port
     #[arg(long, default_value = "22", env = "SSH_MCP_PORT")]
     pub port: u16,
 
-    /// SSH username
-    #[arg(long, env = "SSH_MCP_USER")]
+    /// SSH username (not needed with --local)
+    #[arg(
+        long,
+        env = "SSH_MCP_USER",
+        default_value = "",
+        hide_default_value = true
+    )]
     pub user: String,
 
     /// SSH password (alternative to key)
@@ -41,6 +122,69 @@ pub struct Args {
     #[arg(long, env = "SSH_MCP_KEY")]
     pub key: Option<PathBuf>,
 
+    /// What to do when the --key file is readable by group or others:
+    /// error (refuse it), warn or ignore
+    #[arg(long, default_value = "warn", env = "SSH_MCP_KEY_PERM_CHECK")]
+    pub key_perm_check: String,
+
+    /// How host keys are checked: strict (known_hosts or --host-fingerprint),
+    /// tofu (trust each host's first key and require it afterwards) or
+    /// insecure (accept any key)
+    #[arg(long, default_value = "strict", env = "SSH_MCP_HOST_KEY_POLICY")]
+    pub host_key_policy: String,
+
+    /// JSON file the keys trusted with --host-key-policy=tofu are kept in
+    /// (default: ~/.ssh/ssh-mcp-host-keys.json)
+    #[arg(long, env = "SSH_MCP_HOST_KEY_STORE")]
+    pub host_key_store: Option<PathBuf>,
+
+    /// known_hosts file the server's host key must be listed in
+    /// (default: ~/.ssh/known_hosts)
+    #[arg(long, env = "SSH_MCP_KNOWN_HOSTS")]
+    pub known_hosts: Option<PathBuf>,
+
+    /// SHA256 fingerprints (comma-separated, as printed by ssh-keygen -lf)
+    /// the server's host key must have, instead of a known_hosts entry
+    #[arg(long, value_delimiter = ',', env = "SSH_MCP_HOST_FINGERPRINT")]
+    pub host_fingerprint: Vec<String>,
+
+    /// Accept any host key without checking known_hosts, like
+    /// --host-key-policy=insecure. Only for trusted networks: it allows
+    /// man-in-the-middle attacks
+    #[arg(long, default_value = "false", env = "SSH_MCP_NO_HOST_KEY_CHECK")]
+    pub no_host_key_check: bool,
+
+    /// Unix socket for sharing one SSH connection between instances: the
+    /// first instance started with it connects and serves the socket, later
+    /// ones run their commands over that connection
+    #[arg(long, env = "SSH_MCP_CONTROL_PATH")]
+    pub control_path: Option<PathBuf>,
+
+    /// Order to try authentication methods in when several are configured
+    /// (comma-separated: password, key, agent). Default: password, then key;
+    /// the SSH agent is only used when listed
+    #[arg(long, value_delimiter = ',', env = "SSH_MCP_AUTH_ORDER")]
+    pub auth_order: Vec<String>,
+
+    /// Additional hosts (host or host:port, comma-separated) the
+    /// broadcast-exec tool runs commands on besides --host, using the same
+    /// user and credentials
+    #[arg(long, value_delimiter = ',', env = "SSH_MCP_BROADCAST_HOSTS")]
+    pub broadcast_hosts: Vec<String>,
+
+    /// SOCKS5 proxy (host or host:port, default port 1080) the SSH
+    /// connections are made through
+    #[arg(long, env = "SSH_MCP_SOCKS_PROXY")]
+    pub socks_proxy: Option<String>,
+
+    /// Username for the SOCKS5 proxy (requires --socks-password)
+    #[arg(long, env = "SSH_MCP_SOCKS_USER")]
+    pub socks_user: Option<String>,
+
+    /// Password for the SOCKS5 proxy
+    #[arg(long, env = "SSH_MCP_SOCKS_PASSWORD")]
+    pub socks_password: Option<String>,
+
     /// Password for `su` elevation
     #[arg(long, env = "SSH_MCP_SU_PASSWORD")]
     pub su_password: Option<String>,
@@ -49,6 +193,63 @@ pub struct Args {
     #[arg(long, env = "SSH_MCP_SUDO_PASSWORD")]
     pub sudo_password: Option<String>,
 
+    /// Command used with --su-password to open the persistent root shell,
+    /// e.g. "sudo -i" or "sudo su -" on systems where direct su is disabled.
+    /// --su-password is sent to whichever password prompt it shows.
+    #[arg(long, default_value = DEFAULT_ELEVATION_COMMAND, env = "SSH_MCP_ELEVATION_COMMAND")]
+    pub elevation_command: String,
+
+    /// Open the root shell as a login shell (`su -`), which resets the
+    /// environment and starts in root's home directory. With false, plain
+    /// `su` keeps the login user's environment and working directory.
+    #[arg(long, default_value_t = true, action = ArgAction::Set, env = "SSH_MCP_SU_LOGIN")]
+    pub su_login: bool,
+
+    /// Run exec and the other tools that run commands (exec-diff,
+    /// broadcast-exec, wait-for, ...) as the login user even when the su
+    /// root shell is available; sudo-exec and chown keep using it
+    #[arg(long, default_value = "false", env = "SSH_MCP_NO_ELEVATION_ON_EXEC")]
+    pub no_elevation_on_exec: bool,
+
+    /// Connect and elevate with --su-password at startup, and refuse to
+    /// start if elevation fails instead of running commands unprivileged
+    #[arg(long, default_value = "false", env = "SSH_MCP_REQUIRE_ELEVATION")]
+    pub require_elevation: bool,
+
+    /// Run sudo-exec on a PTY and send the sudo password only when sudo
+    /// prompts for it, instead of piping it to stdin up front
+    #[arg(long, default_value = "false", env = "SSH_MCP_SUDO_PTY")]
+    pub sudo_pty: bool,
+
+    /// End the sudo password piped to stdin with a newline. Some PAM modules
+    /// take stdin verbatim and reject the password with it; ignored with
+    /// --sudo-pty.
+    #[arg(long, default_value_t = true, action = ArgAction::Set, env = "SSH_MCP_SUDO_PASSWORD_NEWLINE")]
+    pub sudo_password_newline: bool,
+
+    /// Use --password, --su-password and --sudo-password exactly as given.
+    /// By default surrounding whitespace is trimmed, which catches a newline
+    /// pasted along with the password but breaks passwords that really start
+    /// or end with spaces
+    #[arg(long, default_value = "false", env = "SSH_MCP_NO_TRIM_PASSWORD")]
+    pub no_trim_password: bool,
+
+    /// Width in columns of the PTYs used for the su shell and --sudo-pty.
+    /// Commands like `ps` truncate their columns to it.
+    #[arg(long, default_value = "80", env = "SSH_MCP_PTY_COLS")]
+    pub pty_cols: u16,
+
+    /// Regular expressions (comma-separated) selecting exec commands that
+    /// run on a PTY; every other command keeps plain pipes. Default: direct
+    /// sudo, editors, pagers and top. Pass an empty value to disable
+    #[arg(long, value_delimiter = ',', env = "SSH_MCP_PTY_COMMANDS")]
+    pub pty_commands: Option<Vec<String>>,
+
+    /// How exec enters the container named by its `container` parameter:
+    /// docker, podman or nsenter (which takes a PID instead of a name)
+    #[arg(long, default_value = "docker", env = "SSH_MCP_CONTAINER_RUNTIME")]
+    pub container_runtime: String,
+
     /// Command execution timeout in milliseconds
     #[arg(long, default_value = "60000", env = "SSH_MCP_TIMEOUT")]
     pub timeout: u64,
@@ -59,9 +260,261 @@ pub struct Args {
     #[arg(long = "maxChars", env = "SSH_MCP_MAX_CHARS")]
     pub max_chars: Option<String>,
 
+    /// Path to a policy file with allow/deny rules for exec and sudo-exec
+    /// commands (e.g. "allow systemctl status *", "deny systemctl stop *")
+    #[arg(long, env = "SSH_MCP_POLICY_FILE")]
+    pub policy_file: Option<PathBuf>,
+
+    /// Only run this command template, e.g. "systemctl {action} {service}";
+    /// every other tool is hidden and callers only fill in the placeholders
+    #[arg(long, env = "SSH_MCP_ONLY_COMMAND")]
+    pub only_command: Option<String>,
+
+    /// Replace the description of the exec tool shown to the client,
+    /// e.g. to add guidance such as "prefer read-only commands"
+    #[arg(long, env = "SSH_MCP_EXEC_DESCRIPTION")]
+    pub exec_description: Option<String>,
+
+    /// Replace the description of the sudo-exec tool shown to the client
+    #[arg(long, env = "SSH_MCP_SUDO_DESCRIPTION")]
+    pub sudo_description: Option<String>,
+
     /// Disable the sudo-exec tool
     #[arg(long, default_value = "false", env = "SSH_MCP_DISABLE_SUDO")]
     pub disable_sudo: bool,
+
+    /// Offer connect and disconnect tools for opening and closing the SSH
+    /// session explicitly
+    #[arg(long, default_value = "false", env = "SSH_MCP_ENABLE_CONNECTION_TOOLS")]
+    pub enable_connection_tools: bool,
+
+    /// Turn off pagers (PAGER, GIT_PAGER, SYSTEMD_PAGER, MANPAGER) for every
+    /// command, not just those running on a terminal
+    #[arg(long, default_value = "false", env = "SSH_MCP_DISABLE_PAGER")]
+    pub disable_pager: bool,
+
+    /// Retry exec commands that fail with "Permission denied" through sudo,
+    /// as sudo-exec would, and return the elevated result when it succeeds
+    #[arg(long, default_value = "false", env = "SSH_MCP_AUTO_SUDO_ON_DENIED")]
+    pub auto_sudo_on_denied: bool,
+
+    /// Include the exact command sent (passwords redacted) in the `_meta` of
+    /// exec and sudo-exec results, for reproducing failures by hand
+    #[arg(long, default_value = "false", env = "SSH_MCP_ECHO_COMMAND")]
+    pub echo_command: bool,
+
+    /// Mark exec and sudo-exec results as errors when the command wrote to
+    /// stderr, even if it exited 0
+    #[arg(long, default_value = "false", env = "SSH_MCP_FAIL_ON_STDERR")]
+    pub fail_on_stderr: bool,
+
+    /// How exit codes are reported, as comma-separated [PREFIX:]CODE=CLASS
+    /// entries with CLASS one of success, warning or error, e.g.
+    /// "rsync:24=success,grep:1=success". Entries with a prefix apply to
+    /// commands starting with it, the others to every command
+    #[arg(long, env = "SSH_MCP_EXIT_CODE_MAP")]
+    pub exit_code_map: Option<String>,
+
+    /// Send exec output as progress notifications while the command runs,
+    /// to clients that pass a progress token. With false, output only
+    /// arrives with the result
+    #[arg(long, default_value_t = true, action = ArgAction::Set, env = "SSH_MCP_STREAM")]
+    pub stream: bool,
+
+    /// Collapse runs of identical consecutive lines in exec output into
+    /// `<line> (repeated N times)`
+    #[arg(long, default_value = "false", env = "SSH_MCP_COLLAPSE_REPEATS")]
+    pub collapse_repeats: bool,
+
+    /// Return stdout and stderr of exec and sudo-exec as separate content
+    /// items, labeled in their `_meta`, instead of one combined text
+    #[arg(long, default_value = "false", env = "SSH_MCP_SEPARATE_CONTENT")]
+    pub separate_content: bool,
+
+    /// Reject commands that consist only of comments and blank lines
+    /// ("command contains no executable statement") instead of running them
+    #[arg(long, default_value = "false", env = "SSH_MCP_REJECT_EMPTY_EFFECT")]
+    pub reject_empty_effect: bool,
+
+    /// Keep leading and trailing whitespace of commands instead of trimming
+    /// it, for whitespace-sensitive payloads such as here-documents
+    #[arg(long, default_value = "false", env = "SSH_MCP_NO_TRIM_COMMAND")]
+    pub no_trim_command: bool,
+
+    /// Hard limit on bytes read from the remote for a single command.
+    /// Reading stops and the channel is closed once exceeded. Use 0 to disable.
+    #[arg(long, default_value = "10485760", env = "SSH_MCP_MAX_OUTPUT_BYTES")]
+    pub max_output_bytes: usize,
+
+    /// Output streams longer than this many lines are shortened to their
+    /// first --output-head-lines and last --output-tail-lines lines.
+    /// Use 0 to return output in full.
+    #[arg(long, default_value = "2000", env = "SSH_MCP_MAX_OUTPUT_LINES")]
+    pub max_output_lines: usize,
+
+    /// Lines kept from the start of output shortened by --max-output-lines
+    #[arg(long, default_value = "200", env = "SSH_MCP_OUTPUT_HEAD_LINES")]
+    pub output_head_lines: usize,
+
+    /// Lines kept from the end of output shortened by --max-output-lines
+    #[arg(long, default_value = "200", env = "SSH_MCP_OUTPUT_TAIL_LINES")]
+    pub output_tail_lines: usize,
+
+    /// Locale to run remote commands under (sets LANG and LC_ALL), e.g. C.UTF-8.
+    /// Fixes garbled non-ASCII output on servers with an unset or C locale.
+    #[arg(long, env = "SSH_MCP_REMOTE_LOCALE")]
+    pub remote_locale: Option<String>,
+
+    /// Number of recent commands kept for the command-history tool (0 to disable)
+    #[arg(long, default_value = "50", env = "SSH_MCP_HISTORY_SIZE")]
+    pub history_size: usize,
+
+    /// Disable Nagle's algorithm on the SSH socket. Lowers latency for the
+    /// many small writes of interactive (su shell, PTY) use at the cost of
+    /// more packets on the wire.
+    #[arg(long, default_value_t = true, action = ArgAction::Set, env = "SSH_MCP_TCP_NODELAY")]
+    pub tcp_nodelay: bool,
+
+    /// SSH channel window size in bytes. Larger windows speed up bulk output
+    /// on high-latency links but let more unread data queue in memory.
+    #[arg(long, env = "SSH_MCP_WINDOW_SIZE")]
+    pub window_size: Option<u32>,
+
+    /// Maximum SSH packet size in bytes (1024-65535)
+    #[arg(long, env = "SSH_MCP_MAX_PACKET_SIZE")]
+    pub max_packet_size: Option<u32>,
+
+    /// Close and reconnect the SSH session once it is this many seconds old
+    /// (0 to disable). Checked between commands, never during one.
+    #[arg(long, default_value = "0", env = "SSH_MCP_MAX_CONNECTIONS_LIFETIME")]
+    pub max_connections_lifetime: u64,
+
+    /// Close and reconnect the SSH session after this many commands (0 to disable)
+    #[arg(long, default_value = "0", env = "SSH_MCP_MAX_CONNECTION_COMMANDS")]
+    pub max_connection_commands: u64,
+
+    /// After this many seconds without commands, check the SSH session with
+    /// a channel round trip and reconnect if it is dead (0 to disable)
+    #[arg(long, default_value = "0", env = "SSH_MCP_IDLE_REFRESH")]
+    pub idle_refresh: u64,
+
+    /// Consecutive failed connection attempts after which tool calls fail at
+    /// once for --circuit-cooldown seconds, instead of each one waiting for
+    /// the connection to fail again (0 to disable)
+    #[arg(long, default_value = "0", env = "SSH_MCP_CIRCUIT_THRESHOLD")]
+    pub circuit_threshold: u32,
+
+    /// Seconds calls fail fast once --circuit-threshold is reached
+    #[arg(long, default_value = "30", env = "SSH_MCP_CIRCUIT_COOLDOWN")]
+    pub circuit_cooldown: u64,
+
+    /// Extra connection attempts after a network failure (0 to fail at once).
+    /// Attempts are spaced by an exponential backoff.
+    #[arg(long, default_value = "0", env = "SSH_MCP_RECONNECT_ATTEMPTS")]
+    pub reconnect_attempts: u32,
+
+    /// Randomize each reconnect delay between zero and the backoff ("full
+    /// jitter"), so many clients losing the same server do not retry in lockstep
+    #[arg(long, default_value_t = true, action = ArgAction::Set, env = "SSH_MCP_RECONNECT_JITTER")]
+    pub reconnect_jitter: bool,
+
+    /// Extra su elevation attempts when elevating fails (0 to try once).
+    /// Attempts are spaced like reconnects.
+    #[arg(long, default_value = "0", env = "SSH_MCP_ELEVATION_RETRIES")]
+    pub elevation_retries: u32,
+
+    /// Milliseconds to keep reading a command's output after its exit status
+    /// or EOF arrives, so trailing data is not clipped (0 to stop at EOF)
+    #[arg(long, default_value = "50", env = "SSH_MCP_OUTPUT_DRAIN_MS")]
+    pub output_drain_ms: u64,
+
+    /// Remote directories the file tools may operate in, comma-separated.
+    /// When empty, file tools may access any path the SSH user can.
+    #[arg(long, value_delimiter = ',', env = "SSH_MCP_ALLOWED_PATHS")]
+    pub allowed_paths: Vec<String>,
+
+    /// Local directories the upload tool may read `local_path` from,
+    /// comma-separated. When empty, `local_path` is refused.
+    #[arg(long, value_delimiter = ',', env = "SSH_MCP_ALLOWED_LOCAL_PATHS")]
+    pub allowed_local_paths: Vec<String>,
+
+    /// Maximum number of files a glob in a file tool path may expand to
+    #[arg(long, default_value = "100", env = "SSH_MCP_MAX_GLOB_MATCHES")]
+    pub max_glob_matches: usize,
+
+    /// Maximum bytes a single append-file call may write (0 for unlimited)
+    #[arg(long, default_value = "1048576", env = "SSH_MCP_MAX_APPEND_BYTES")]
+    pub max_append_bytes: usize,
+
+    /// Maximum size of a file the download tool returns (0 for unlimited)
+    #[arg(long, default_value = "10485760", env = "SSH_MCP_MAX_DOWNLOAD_BYTES")]
+    pub max_download_bytes: u64,
+
+    /// Return downloaded files that are valid UTF-8 as text instead of base64
+    #[arg(long, default_value = "false", env = "SSH_MCP_DOWNLOAD_AS_TEXT")]
+    pub download_as_text: bool,
+
+    /// Maximum SFTP handles open at once (0 for unlimited). File operations
+    /// beyond it wait for one to close, for up to the connection timeout
+    #[arg(long, default_value = "0", env = "SSH_MCP_MAX_SFTP_HANDLES")]
+    pub max_sftp_handles: usize,
+
+    /// Address for an HTTP health endpoint (e.g. 0.0.0.0:8080) answering 200
+    /// while the SSH connection is up and 503 otherwise
+    #[arg(long, env = "SSH_MCP_HEALTH_ADDR")]
+    pub health_addr: Option<SocketAddr>,
+
+    /// Seconds between health probes that run a no-op command (0 to disable).
+    /// Probing also keeps the connection established.
+    #[arg(long, default_value = "0", env = "SSH_MCP_HEALTH_PROBE_INTERVAL")]
+    pub health_probe_interval: u64,
+
+    /// Maximum number of commands running at once (0 = unlimited)
+    #[arg(long, default_value = "0", env = "SSH_MCP_MAX_CONCURRENT")]
+    pub max_concurrent: usize,
+
+    /// Maximum number of commands waiting for a slot when --max-concurrent
+    /// commands are running; further commands are rejected as busy
+    #[arg(long, default_value = "10", env = "SSH_MCP_MAX_QUEUED")]
+    pub max_queued: usize,
+
+    /// Command run once at startup (e.g. "uname -a; hostname") whose output
+    /// is appended to the server instructions, so the model knows the host
+    /// without an extra tool call
+    #[arg(long, env = "SSH_MCP_WELCOME_COMMAND")]
+    pub welcome_command: Option<String>,
+
+    /// Command run once when the server shuts down, before the connection
+    /// is closed (e.g. to remove temp files or release a lock). It gets at
+    /// most 10 seconds
+    #[arg(long, env = "SSH_MCP_SHUTDOWN_COMMAND")]
+    pub shutdown_command: Option<String>,
+
+    /// MCP protocol version to advertise (2024-11-05, 2025-03-26 or
+    /// 2025-06-18), for clients that don't support the newest revision.
+    /// Defaults to the latest version supported by the MCP library.
+    #[arg(long, env = "SSH_MCP_PROTOCOL_VERSION")]
+    pub protocol_version: Option<String>,
+
+    /// Log verbosity on stderr: error, warn, info, debug or trace.
+    /// debug shows a per-phase timing breakdown of each SSH connect.
+    #[arg(long, default_value = "info", env = "SSH_MCP_LOG_LEVEL")]
+    pub log_level: tracing::Level,
+
+    /// Name for this instance (e.g. prod-db), shown on every log line and in
+    /// the server instructions. Defaults to user@host:port.
+    #[arg(long, env = "SSH_MCP_LABEL")]
+    pub label: Option<String>,
+
+    /// Connect, print the authentication methods the server offers and exit,
+    /// without sending credentials. Helps diagnose rejected logins.
+    #[arg(long, default_value = "false")]
+    pub probe_auth: bool,
+
+    /// Execute commands on this machine instead of over SSH.
+    /// Connection and authentication options are ignored.
+    #[arg(long, default_value = "false", env = "SSH_MCP_LOCAL")]
+    pub local: bool,
 }
 
 /// Parsed and validated configuration
@@ -82,20 +535,220 @@ pub struct Config {
     /// Path to SSH private key
     pub key: Option<PathBuf>,
 
+    /// How a key file accessible to group or others is treated
+    pub key_perm_check: KeyPermCheck,
+
+    /// How server host keys are verified
+    pub host_key_policy: HostKeyPolicy,
+
+    /// Order authentication methods are tried in (empty = default order)
+    pub auth_order: Vec<AuthMethod>,
+
+    /// Extra hosts for broadcast-exec, with their ports
+    pub broadcast_hosts: Vec<(String, u16)>,
+
+    /// SOCKS5 proxy the SSH connections go through
+    pub socks_proxy: Option<SocksProxy>,
+
     /// Password for su elevation
     pub su_password: Option<String>,
 
     /// Password for sudo commands
     pub sudo_password: Option<String>,
 
+    /// Command used to open the persistent root shell
+    pub elevation_command: String,
+
+    /// Whether exec and the other command tools run in the su root shell
+    /// when it is available
+    pub elevate_exec: bool,
+
+    /// Whether startup fails unless the su root shell can be opened
+    pub require_elevation: bool,
+
+    /// Socket for sharing the connection with other instances (None = off)
+    pub control_path: Option<PathBuf>,
+
+    /// Whether sudo-exec answers the password prompt on a PTY
+    pub sudo_pty: bool,
+
+    /// Whether the piped sudo password ends with a newline
+    pub sudo_password_newline: bool,
+
+    /// Width of the PTYs requested by the server
+    pub pty_cols: u16,
+
+    /// Exec commands matching any of these run on a PTY of `pty_cols`
+    pub pty_commands: Vec<Regex>,
+
+    /// Runtime used for exec's `container` parameter
+    pub container_runtime: ContainerRuntime,
+
     /// Command timeout in milliseconds
     pub timeout_ms: u64,
 
     /// Maximum command length (None = unlimited)
     pub max_chars: Option<usize>,
 
+    /// Whether `max_chars` is the built-in default rather than set with
+    /// --maxChars, so a rejection can point out the limit exists
+    pub max_chars_defaulted: bool,
+
+    /// Path to the command policy file
+    pub policy_file: Option<PathBuf>,
+
+    /// The only command the server runs (`--only-command`)
+    pub only_command: Option<CommandTemplate>,
+
+    /// Custom exec tool description (None = built-in default)
+    pub exec_description: Option<String>,
+
+    /// Custom sudo-exec tool description (None = built-in default)
+    pub sudo_description: Option<String>,
+
     /// Whether sudo-exec tool is disabled
     pub disable_sudo: bool,
+
+    /// Whether the connect and disconnect tools are offered
+    pub enable_connection_tools: bool,
+
+    /// Whether exec retries permission-denied failures through sudo
+    pub auto_sudo_on_denied: bool,
+
+    /// Whether pagers are turned off for every command
+    pub disable_pager: bool,
+
+    /// Whether results echo the command that was sent
+    pub echo_command: bool,
+
+    /// Whether stderr output marks a result as an error by default
+    pub fail_on_stderr: bool,
+
+    /// Exit codes reported differently from "0 is success"
+    pub exit_code_map: ExitCodeMap,
+
+    /// Whether exec output is streamed to clients that ask for progress
+    pub stream: bool,
+
+    /// Whether repeated output lines are collapsed by default
+    pub collapse_repeats: bool,
+
+    /// Whether each output stream gets its own content item
+    pub separate_content: bool,
+
+    /// Whether comment-only commands are rejected
+    pub reject_empty_effect: bool,
+
+    /// Whether surrounding whitespace is trimmed from commands
+    pub trim_command: bool,
+
+    /// Hard limit on bytes read per command (None = unlimited)
+    pub max_output_bytes: Option<usize>,
+
+    /// Line count above which an output stream is shortened to its head and
+    /// tail (None = never)
+    pub max_output_lines: Option<usize>,
+
+    /// Lines kept from the start of a shortened stream
+    pub output_head_lines: usize,
+
+    /// Lines kept from the end of a shortened stream
+    pub output_tail_lines: usize,
+
+    /// Whether commands run locally instead of over SSH
+    pub local: bool,
+
+    /// Name given with --label (None = derived from the target, see `label()`)
+    pub label: Option<String>,
+
+    /// Only report the server's authentication methods, then exit
+    pub probe_auth: bool,
+
+    /// Locale (LANG/LC_ALL) applied to executed commands
+    pub remote_locale: Option<String>,
+
+    /// Number of commands kept in the command history
+    pub history_size: usize,
+
+    /// Whether TCP_NODELAY is set on the SSH socket
+    pub tcp_nodelay: bool,
+
+    /// SSH channel window size (None = russh default)
+    pub window_size: Option<u32>,
+
+    /// Maximum SSH packet size (None = russh default)
+    pub max_packet_size: Option<u32>,
+
+    /// Maximum SSH session age in seconds before it is recycled (None = unlimited)
+    pub max_connections_lifetime: Option<u64>,
+
+    /// Maximum number of commands per SSH session (None = unlimited)
+    pub max_connection_commands: Option<u64>,
+
+    /// Seconds without commands after which the session is checked and
+    /// reconnected if dead (None = never)
+    pub idle_refresh: Option<u64>,
+
+    /// Consecutive connection failures that open the circuit breaker
+    /// (None = disabled)
+    pub circuit_threshold: Option<u32>,
+
+    /// Seconds the circuit breaker stays open
+    pub circuit_cooldown: u64,
+
+    /// Extra connection attempts after a network failure
+    pub reconnect_attempts: u32,
+
+    /// Whether reconnect delays are randomized
+    pub reconnect_jitter: bool,
+
+    /// Extra su elevation attempts after a failure
+    pub elevation_retries: u32,
+
+    /// Milliseconds output is still read after a command finishes
+    pub output_drain_ms: u64,
+
+    /// Remote directories the file tools are confined to (empty = unrestricted)
+    pub allowed_paths: Vec<String>,
+
+    /// Local directories upload may read from (empty = `local_path` refused)
+    pub allowed_local_paths: Vec<String>,
+
+    /// Maximum number of files a glob may expand to
+    pub max_glob_matches: usize,
+
+    /// Maximum bytes written by one append-file call (None = unlimited)
+    pub max_append_bytes: Option<usize>,
+
+    /// Maximum size of a downloaded file (None = unlimited)
+    pub max_download_bytes: Option<u64>,
+
+    /// Whether UTF-8 downloads are returned as text
+    pub download_as_text: bool,
+
+    /// Maximum SFTP handles open at once (None = unlimited)
+    pub max_sftp_handles: Option<usize>,
+
+    /// Address the health endpoint listens on (None = disabled)
+    pub health_addr: Option<SocketAddr>,
+
+    /// Seconds between health probes (None = no probing)
+    pub health_probe_interval: Option<u64>,
+
+    /// Maximum number of commands running at once (None = unlimited)
+    pub max_concurrent: Option<usize>,
+
+    /// Maximum number of commands waiting for a slot
+    pub max_queued: usize,
+
+    /// Pinned MCP protocol version (None = latest)
+    pub protocol_version: Option<String>,
+
+    /// Command whose output is added to the instructions (None = disabled)
+    pub welcome_command: Option<String>,
+
+    /// Command run on shutdown, before disconnecting (None = disabled)
+    pub shutdown_command: Option<String>,
 }
 
 impl Config {
@@ -104,37 +757,412 @@ impl Config {
         validate_args(&args)?;
 
         let max_chars = parse_max_chars(args.max_chars.as_deref());
+        let host_key_policy = host_key_policy(&args);
+        let pty_commands = pty_command_patterns(&args)
+            .filter_map(|pattern| Regex::new(pattern).ok())
+            .collect();
 
         Ok(Config {
             host: args.host,
             port: args.port,
             user: args.user,
-            password: sanitize_password(args.password),
+            password: sanitize_password(args.password, !args.no_trim_password),
             key: args.key,
-            su_password: sanitize_password(args.su_password),
-            sudo_password: sanitize_password(args.sudo_password),
+            key_perm_check: args.key_perm_check.parse().unwrap_or_default(),
+            host_key_policy,
+            auth_order: args
+                .auth_order
+                .iter()
+                .filter_map(|method| method.parse().ok())
+                .collect(),
+            broadcast_hosts: args
+                .broadcast_hosts
+                .iter()
+                .filter_map(|host| parse_host_port(host, args.port))
+                .collect(),
+            socks_proxy: args
+                .socks_proxy
+                .as_deref()
+                .and_then(|proxy| parse_host_port(proxy, DEFAULT_SOCKS_PORT))
+                .map(|(host, port)| {
+                    let proxy = SocksProxy::new(host, port);
+                    match (args.socks_user, args.socks_password) {
+                        (Some(user), Some(password)) => proxy.with_credentials(user, password),
+                        _ => proxy,
+                    }
+                }),
+            su_password: sanitize_password(args.su_password, !args.no_trim_password),
+            sudo_password: sanitize_password(args.sudo_password, !args.no_trim_password),
+            elevation_command: if args.su_login {
+                args.elevation_command
+            } else {
+                NON_LOGIN_ELEVATION_COMMAND.to_string()
+            },
+            elevate_exec: !args.no_elevation_on_exec,
+            require_elevation: args.require_elevation,
+            control_path: args.control_path,
+            sudo_pty: args.sudo_pty,
+            sudo_password_newline: args.sudo_password_newline,
+            pty_cols: args.pty_cols,
+            pty_commands,
+            container_runtime: args.container_runtime.parse().unwrap_or_default(),
             timeout_ms: args.timeout,
             max_chars,
+            max_chars_defaulted: args.max_chars.is_none(),
+            policy_file: args.policy_file,
+            only_command: args.only_command.and_then(|t| t.parse().ok()),
+            exec_description: args.exec_description.filter(|d| !d.trim().is_empty()),
+            sudo_description: args.sudo_description.filter(|d| !d.trim().is_empty()),
             disable_sudo: args.disable_sudo,
+            enable_connection_tools: args.enable_connection_tools,
+            auto_sudo_on_denied: args.auto_sudo_on_denied,
+            disable_pager: args.disable_pager,
+            echo_command: args.echo_command,
+            fail_on_stderr: args.fail_on_stderr,
+            exit_code_map: args
+                .exit_code_map
+                .and_then(|map| map.parse().ok())
+                .unwrap_or_default(),
+            stream: args.stream,
+            collapse_repeats: args.collapse_repeats,
+            separate_content: args.separate_content,
+            reject_empty_effect: args.reject_empty_effect,
+            trim_command: !args.no_trim_command,
+            max_output_bytes: Some(args.max_output_bytes).filter(|&n| n > 0),
+            max_output_lines: Some(args.max_output_lines).filter(|&n| n > 0),
+            output_head_lines: args.output_head_lines,
+            output_tail_lines: args.output_tail_lines,
+            local: args.local,
+            probe_auth: args.probe_auth,
+            label: args
+                .label
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty()),
+            remote_locale: args.remote_locale.filter(|l| !l.is_empty()),
+            history_size: args.history_size,
+            tcp_nodelay: args.tcp_nodelay,
+            window_size: args.window_size,
+            max_packet_size: args.max_packet_size,
+            max_connections_lifetime: Some(args.max_connections_lifetime).filter(|&n| n > 0),
+            idle_refresh: Some(args.idle_refresh).filter(|&n| n > 0),
+            circuit_threshold: Some(args.circuit_threshold).filter(|&n| n > 0),
+            circuit_cooldown: args.circuit_cooldown,
+            max_connection_commands: Some(args.max_connection_commands).filter(|&n| n > 0),
+            reconnect_attempts: args.reconnect_attempts,
+            elevation_retries: args.elevation_retries,
+            output_drain_ms: args.output_drain_ms,
+            reconnect_jitter: args.reconnect_jitter,
+            allowed_paths: args.allowed_paths,
+            allowed_local_paths: args.allowed_local_paths,
+            max_glob_matches: args.max_glob_matches,
+            max_append_bytes: Some(args.max_append_bytes).filter(|&n| n > 0),
+            max_download_bytes: Some(args.max_download_bytes).filter(|&n| n > 0),
+            download_as_text: args.download_as_text,
+            max_sftp_handles: Some(args.max_sftp_handles).filter(|&n| n > 0),
+            health_addr: args.health_addr,
+            health_probe_interval: Some(args.health_probe_interval).filter(|&n| n > 0),
+            max_concurrent: Some(args.max_concurrent).filter(|&n| n > 0),
+            max_queued: args.max_queued,
+            protocol_version: args.protocol_version,
+            welcome_command: args.welcome_command.filter(|c| !c.trim().is_empty()),
+            shutdown_command: args.shutdown_command.filter(|c| !c.trim().is_empty()),
         })
     }
+
+    /// Name identifying this instance in logs and instructions
+    ///
+    /// The `--label` if one was given, otherwise `user@host:port` (or `local`).
+    pub fn label(&self) -> String {
+        match self.label {
+            Some(ref label) => label.clone(),
+            None if self.local => "local".to_string(),
+            None => format!("{}@{}:{}", self.user, self.host, self.port),
+        }
+    }
 }
 
 /// Validate CLI arguments
 fn validate_args(args: &Args) -> Result<()> {
     let mut errors = Vec::new();
 
+    if let Some(ref locale) = args.remote_locale {
+        if !is_valid_locale(locale) {
+            errors.push(format!("Invalid --remote-locale: {}", locale));
+        }
+    }
+
+    if args.elevation_command.trim().is_empty() || args.elevation_command.contains(['\n', '\r']) {
+        errors.push("--elevation-command must be a single non-empty line".to_string());
+    }
+    if !args.su_login && args.elevation_command != DEFAULT_ELEVATION_COMMAND {
+        errors.push(
+            "--su-login=false only applies to the default su elevation; \
+             put the command you want in --elevation-command instead"
+                .to_string(),
+        );
+    }
+
+    if args
+        .label
+        .as_deref()
+        .is_some_and(|l| l.contains(['\n', '\r']))
+    {
+        errors.push("--label must be a single line".to_string());
+    }
+
+    if args.pty_cols == 0 {
+        errors.push("--pty-cols must be at least 1".to_string());
+    }
+
+    let kept_lines = args
+        .output_head_lines
+        .saturating_add(args.output_tail_lines);
+    if args.max_output_lines > 0 && (kept_lines == 0 || kept_lines >= args.max_output_lines) {
+        errors.push(format!(
+            "--output-head-lines plus --output-tail-lines must be between 1 and {} \
+             (one less than --max-output-lines)",
+            args.max_output_lines - 1
+        ));
+    }
+
+    if let Err(e) = args.key_perm_check.parse::<KeyPermCheck>() {
+        errors.push(format!("--key-perm-check: {}", e));
+    }
+
+    if let Some(Err(e)) = args
+        .only_command
+        .as_deref()
+        .map(str::parse::<CommandTemplate>)
+    {
+        errors.push(format!("--only-command: {}", e));
+    }
+
+    if let Some(Err(e)) = args.exit_code_map.as_deref().map(str::parse::<ExitCodeMap>) {
+        errors.push(format!("--exit-code-map: {}", e));
+    }
+
+    if let Err(e) = args.container_runtime.parse::<ContainerRuntime>() {
+        errors.push(format!("--container-runtime: {}", e));
+    }
+
+    for pattern in pty_command_patterns(args) {
+        if let Err(e) = Regex::new(pattern) {
+            errors.push(format!(
+                "Invalid --pty-commands pattern {:?}: {}",
+                pattern, e
+            ));
+        }
+    }
+
+    if let Some(ref version) = args.protocol_version {
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version.as_str()) {
+            errors.push(format!(
+                "Unsupported --protocol-version {} (supported: {})",
+                version,
+                SUPPORTED_PROTOCOL_VERSIONS.join(", ")
+            ));
+        }
+    }
+
+    // sudo -S and the su prompt both stop reading at the first line break
+    let passwords = [
+        ("--sudo-password", &args.sudo_password),
+        ("--su-password", &args.su_password),
+    ];
+    for (flag, password) in passwords {
+        let password = sanitize_password(password.clone(), !args.no_trim_password);
+        if password.is_some_and(|p| p.contains(['\n', '\r'])) {
+            errors.push(format!("{} must not contain line breaks", flag));
+        }
+    }
+
+    if let Some(ref policy_path) = args.policy_file {
+        if !policy_path.exists() {
+            errors.push(format!("Policy file not found: {}", policy_path.display()));
+        }
+    }
+
+    if let Some(size) = args.max_packet_size {
+        if !(MIN_PACKET_SIZE..=MAX_PACKET_SIZE).contains(&size) {
+            errors.push(format!(
+                "--max-packet-size must be between {} and {}",
+                MIN_PACKET_SIZE, MAX_PACKET_SIZE
+            ));
+        }
+    }
+
+    if let Some(window) = args.window_size {
+        let packet = args.max_packet_size.unwrap_or(MIN_PACKET_SIZE);
+        if window < packet {
+            errors.push(format!(
+                "--window-size must be at least the packet size ({} bytes)",
+                packet
+            ));
+        }
+    }
+
+    for path in &args.allowed_paths {
+        if !path.starts_with('/') {
+            errors.push(format!(
+                "--allowed-paths entries must be absolute: {}",
+                path
+            ));
+        }
+    }
+
+    for path in &args.allowed_local_paths {
+        if !path.starts_with('/') {
+            errors.push(format!(
+                "--allowed-local-paths entries must be absolute: {}",
+                path
+            ));
+        }
+    }
+
+    if args.probe_auth && args.local {
+        errors.push("--probe-auth needs an SSH server and cannot be used with --local".to_string());
+    }
+
+    if args.require_elevation {
+        if args.local {
+            errors.push("--require-elevation cannot be used with --local".to_string());
+        } else if sanitize_password(args.su_password.clone(), !args.no_trim_password).is_none() {
+            errors.push("--require-elevation needs --su-password".to_string());
+        }
+    }
+
+    if args.enable_connection_tools && args.local {
+        errors.push("--enable-connection-tools cannot be used with --local".to_string());
+    }
+
+    if args.control_path.is_some() {
+        if cfg!(not(unix)) {
+            errors.push("--control-path needs Unix domain sockets".to_string());
+        }
+        if args.local || !args.broadcast_hosts.is_empty() {
+            errors.push(
+                "--control-path cannot be used with --local or --broadcast-hosts".to_string(),
+            );
+        }
+    }
+
+    if args.local && !args.broadcast_hosts.is_empty() {
+        errors.push("--broadcast-hosts cannot be used with --local".to_string());
+    }
+
+    // Local mode doesn't use any of the connection settings
+    if args.local {
+        return finish_validation(errors);
+    }
+
     if args.host.is_empty() {
         errors.push("Missing required --host".to_string());
     }
 
+    for host in &args.broadcast_hosts {
+        if parse_host_port(host, args.port).is_none() {
+            errors.push(format!("Invalid --broadcast-hosts entry: {}", host));
+        }
+    }
+
     if args.user.is_empty() {
         errors.push("Missing required --user".to_string());
     }
 
-    // Must have either password or key
-    if args.password.is_none() && args.key.is_none() {
-        errors.push("Must provide either --password or --key".to_string());
+    match args.host_key_policy.parse::<HostKeyMode>() {
+        Ok(HostKeyMode::Tofu) if args.no_host_key_check => errors
+            .push("--no-host-key-check cannot be used with --host-key-policy=tofu".to_string()),
+        Ok(_) => {}
+        Err(e) => errors.push(format!("--host-key-policy: {}", e)),
+    }
+    let mode = host_key_mode(args);
+    if mode != HostKeyMode::Strict
+        && (args.known_hosts.is_some() || !args.host_fingerprint.is_empty())
+    {
+        errors.push(format!(
+            "--known-hosts and --host-fingerprint only apply to --host-key-policy=strict, not {}",
+            mode
+        ));
+    }
+    if mode != HostKeyMode::Tofu && args.host_key_store.is_some() {
+        errors.push("--host-key-store requires --host-key-policy=tofu".to_string());
+    }
+    if !args.host_fingerprint.is_empty() {
+        if args.known_hosts.is_some() {
+            errors.push("--host-fingerprint cannot be used with --known-hosts".to_string());
+        }
+        // The pins would apply to every broadcast host, which can't all have
+        // the primary's key
+        if !args.broadcast_hosts.is_empty() {
+            errors.push(
+                "--host-fingerprint cannot be used with --broadcast-hosts; \
+                 list the hosts' keys in --known-hosts instead"
+                    .to_string(),
+            );
+        }
+        for fingerprint in &args.host_fingerprint {
+            if let Err(e) = parse_fingerprint(fingerprint) {
+                errors.push(format!("--host-fingerprint: {}", e));
+            }
+        }
+    }
+
+    match args.socks_proxy {
+        Some(ref proxy) if parse_host_port(proxy, DEFAULT_SOCKS_PORT).is_none() => {
+            errors.push(format!("Invalid --socks-proxy: {}", proxy));
+        }
+        None if args.socks_user.is_some() || args.socks_password.is_some() => {
+            errors.push("--socks-user and --socks-password require --socks-proxy".to_string());
+        }
+        _ => {}
+    }
+    if args.socks_user.is_some() != args.socks_password.is_some() {
+        errors.push("--socks-user and --socks-password must be set together".to_string());
+    }
+    for (flag, value) in [
+        ("--socks-user", &args.socks_user),
+        ("--socks-password", &args.socks_password),
+    ] {
+        if value
+            .as_ref()
+            .is_some_and(|v| v.is_empty() || v.len() > 255)
+        {
+            errors.push(format!("{} must be 1 to 255 bytes long", flag));
+        }
+    }
+
+    // Must have a password, key or agent (the auth probe sends neither)
+    let uses_agent = args
+        .auth_order
+        .iter()
+        .any(|entry| entry.parse() == Ok(AuthMethod::Agent));
+    if args.password.is_none() && args.key.is_none() && !uses_agent && !args.probe_auth {
+        errors.push("Must provide either --password or --key (or --auth-order=agent)".to_string());
+    }
+
+    let mut listed = Vec::new();
+    for entry in &args.auth_order {
+        match entry.parse::<AuthMethod>() {
+            Ok(method) if listed.contains(&method) => {
+                errors.push(format!("--auth-order lists {} twice", method));
+            }
+            Ok(method) => {
+                let configured = match method {
+                    AuthMethod::Password => args.password.is_some(),
+                    AuthMethod::Key => args.key.is_some(),
+                    // Reached through SSH_AUTH_SOCK when connecting
+                    AuthMethod::Agent => true,
+                };
+                if !configured {
+                    errors.push(format!(
+                        "--auth-order lists {} but --{} is not set",
+                        method, method
+                    ));
+                }
+                listed.push(method);
+            }
+            Err(e) => errors.push(format!("Invalid --auth-order: {}", e)),
+        }
     }
 
     // If key is provided, check if file exists
@@ -144,6 +1172,11 @@ fn validate_args(args: &Args) -> Result<()> {
         }
     }
 
+    finish_validation(errors)
+}
+
+/// Turn collected validation errors into a single config error
+fn finish_validation(errors: Vec<String>) -> Result<()> {
     if !errors.is_empty() {
         return Err(SshMcpError::Config(format!(
             "Configuration error:\n{}",
@@ -154,6 +1187,47 @@ fn validate_args(args: &Args) -> Result<()> {
     Ok(())
 }
 
+/// Parse a `host`, `host:port` or `[ipv6]:port` entry
+///
+/// Entries without a port use `default_port`. Returns `None` for an empty
+/// host or an invalid port.
+pub fn parse_host_port(entry: &str, default_port: u16) -> Option<(String, u16)> {
+    let entry = entry.trim();
+    let (host, port) = if let Some(rest) = entry.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        match rest.strip_prefix(':') {
+            Some(port) => (host, Some(port)),
+            None if rest.is_empty() => (host, None),
+            None => return None,
+        }
+    } else {
+        match entry.split_once(':') {
+            // More than one colon is a bare IPv6 address
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (entry, None),
+        }
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+    let port = match port {
+        Some(port) => port.parse().ok().filter(|&p| p > 0)?,
+        None => default_port,
+    };
+    Some((host.to_string(), port))
+}
+
+/// Check that a locale name is safe to pass to the remote shell
+///
+/// Accepts names like `C`, `C.UTF-8`, `en_US.UTF-8` or `de_DE@euro`.
+pub fn is_valid_locale(locale: &str) -> bool {
+    !locale.is_empty()
+        && locale
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '@'))
+}
+
 /// Parse max_chars argument
 ///
 /// - "none" (case-insensitive) → None (unlimited)
@@ -178,11 +1252,54 @@ pub fn parse_max_chars(value: Option<&str>) -> Option<usize> {
     }
 }
 
-/// Sanitize password: return None if empty
-fn sanitize_password(password: Option<String>) -> Option<String> {
+/// Sanitize password: trim it if asked to, and return None if empty
+fn sanitize_password(password: Option<String>, trim: bool) -> Option<String> {
+    if trim {
+        return crate::ssh::sanitize_password(password.as_deref());
+    }
     password.filter(|p| !p.is_empty())
 }
 
+/// The `--host-key-policy` mode, with `--no-host-key-check` meaning insecure
+fn host_key_mode(args: &Args) -> HostKeyMode {
+    if args.no_host_key_check {
+        return HostKeyMode::Insecure;
+    }
+    args.host_key_policy.parse().unwrap_or_default()
+}
+
+/// How host keys are checked, from the host key flags
+fn host_key_policy(args: &Args) -> HostKeyPolicy {
+    match host_key_mode(args) {
+        HostKeyMode::Insecure => HostKeyPolicy::AcceptAny,
+        HostKeyMode::Tofu => HostKeyPolicy::Tofu(HostKeyStore::new(
+            args.host_key_store
+                .clone()
+                .unwrap_or_else(default_host_key_store),
+        )),
+        HostKeyMode::Strict if !args.host_fingerprint.is_empty() => HostKeyPolicy::Fingerprints(
+            args.host_fingerprint
+                .iter()
+                .filter_map(|fingerprint| parse_fingerprint(fingerprint).ok())
+                .collect(),
+        ),
+        HostKeyMode::Strict => {
+            HostKeyPolicy::KnownHosts(args.known_hosts.clone().unwrap_or_else(default_known_hosts))
+        }
+    }
+}
+
+/// The `--pty-commands` patterns, or the defaults when none were given
+///
+/// Empty entries are skipped, so an empty value disables the PTY selection.
+fn pty_command_patterns(args: &Args) -> impl Iterator<Item = &str> {
+    let patterns: Vec<&str> = match args.pty_commands {
+        Some(ref patterns) => patterns.iter().map(String::as_str).collect(),
+        None => DEFAULT_PTY_COMMANDS.to_vec(),
+    };
+    patterns.into_iter().filter(|pattern| !pattern.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -214,6 +1331,17 @@ mod tests {
         assert_eq!(parse_max_chars(Some("")), DEFAULT_MAX_CHARS);
     }
 
+    #[test]
+    fn test_max_chars_defaulted() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.max_chars, DEFAULT_MAX_CHARS);
+        assert!(config.max_chars_defaulted);
+
+        // Set explicitly, even to the default value
+        let args = Args::parse_from(["ssh-mcp", "--local", "--maxChars=1000"]);
+        assert!(!Config::from_args(args).unwrap().max_chars_defaulted);
+    }
+
     #[test]
     fn test_parse_max_chars_not_provided() {
         assert_eq!(parse_max_chars(None), DEFAULT_MAX_CHARS);
@@ -222,10 +1350,672 @@ mod tests {
     #[test]
     fn test_sanitize_password() {
         assert_eq!(
-            sanitize_password(Some("secret".to_string())),
+            sanitize_password(Some("secret".to_string()), true),
             Some("secret".to_string())
         );
-        assert_eq!(sanitize_password(Some("".to_string())), None);
-        assert_eq!(sanitize_password(None), None);
+        assert_eq!(sanitize_password(Some("".to_string()), true), None);
+        assert_eq!(sanitize_password(None, true), None);
+    }
+
+    #[test]
+    fn test_sanitize_password_trim() {
+        let padded = Some(" secret \n".to_string());
+        assert_eq!(
+            sanitize_password(padded.clone(), true),
+            Some("secret".to_string())
+        );
+        assert_eq!(sanitize_password(padded.clone(), false), padded);
+
+        // Empty is rejected either way, whitespace only when trimming
+        assert_eq!(sanitize_password(Some("".to_string()), false), None);
+        assert_eq!(sanitize_password(Some("  ".to_string()), true), None);
+        assert_eq!(
+            sanitize_password(Some("  ".to_string()), false),
+            Some("  ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_no_trim_password() {
+        let args = Args::parse_from(["ssh-mcp", "--local", "--sudo-password=pw  \n"]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.sudo_password.as_deref(), Some("pw"));
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--sudo-password=pw  ",
+            "--no-trim-password",
+        ]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.sudo_password.as_deref(), Some("pw  "));
+
+        // Untrimmed, the pasted newline reaches sudo and is rejected
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--sudo-password=pw\n",
+            "--no-trim-password",
+        ]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_local_mode_skips_connection_validation() {
+        let args = Args::parse_from(["ssh-mcp", "--local"]);
+        let config = Config::from_args(args).unwrap();
+        assert!(config.local);
+        assert!(config.host.is_empty());
+    }
+
+    #[test]
+    fn test_missing_host_without_local_mode() {
+        let args = Args::parse_from(["ssh-mcp", "--user=admin", "--password=secret"]);
+        let err = Config::from_args(args).unwrap_err();
+        assert!(err.to_string().contains("Missing required --host"));
+    }
+
+    #[test]
+    fn test_elevation_command() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.elevation_command, "su -");
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--elevation-command=sudo -i"]);
+        assert_eq!(
+            Config::from_args(args).unwrap().elevation_command,
+            "sudo -i"
+        );
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--elevation-command= "]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_su_login() {
+        let args = Args::parse_from(["ssh-mcp", "--local", "--su-login=true"]);
+        assert_eq!(Config::from_args(args).unwrap().elevation_command, "su -");
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--su-login=false"]);
+        assert_eq!(Config::from_args(args).unwrap().elevation_command, "su");
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--su-login=false",
+            "--elevation-command=sudo -i",
+        ]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_password_line_breaks_rejected() {
+        let args = Args::parse_from(["ssh-mcp", "--local", "--sudo-password=a\nb"]);
+        assert!(Config::from_args(args).is_err());
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--su-password=a\rb"]);
+        assert!(Config::from_args(args).is_err());
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--sudo-password=a%s\\b"]);
+        assert!(Config::from_args(args).is_ok());
+    }
+
+    #[test]
+    fn test_protocol_version() {
+        let args = Args::parse_from(["ssh-mcp", "--local", "--protocol-version=2024-11-05"]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.protocol_version.as_deref(), Some("2024-11-05"));
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--protocol-version=2023-01-01"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_label() {
+        let args = Args::parse_from(["ssh-mcp", "--host=db1", "--user=admin", "--password=x"]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.label, None);
+        assert_eq!(config.label(), "admin@db1:22");
+
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.label(), "local");
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--label= prod-db "]);
+        assert_eq!(Config::from_args(args).unwrap().label(), "prod-db");
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--label=a\nb"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_probe_auth() {
+        // No credentials are needed to list the methods
+        let args = Args::parse_from(["ssh-mcp", "--host=db1", "--user=admin", "--probe-auth"]);
+        assert!(Config::from_args(args).unwrap().probe_auth);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--probe-auth"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_parse_host_port() {
+        assert_eq!(parse_host_port("web1", 22), Some(("web1".to_string(), 22)));
+        assert_eq!(
+            parse_host_port(" web2:2222 ", 22),
+            Some(("web2".to_string(), 2222))
+        );
+        assert_eq!(
+            parse_host_port("[::1]:2200", 22),
+            Some(("::1".to_string(), 2200))
+        );
+        assert_eq!(
+            parse_host_port("fe80::1", 22),
+            Some(("fe80::1".to_string(), 22))
+        );
+        assert_eq!(parse_host_port("", 22), None);
+        assert_eq!(parse_host_port("web:0", 22), None);
+        assert_eq!(parse_host_port("web:ssh", 22), None);
+    }
+
+    #[test]
+    fn test_auth_order() {
+        let key = std::env::temp_dir().join("ssh-mcp-test-auth-order-key");
+        std::fs::write(&key, "key").unwrap();
+        let key_arg = format!("--key={}", key.display());
+        let parse = |order: &str| {
+            let order = format!("--auth-order={}", order);
+            let args = Args::parse_from([
+                "ssh-mcp",
+                "--host=web1",
+                "--user=admin",
+                "--password=x",
+                key_arg.as_str(),
+                order.as_str(),
+            ]);
+            Config::from_args(args)
+        };
+
+        let config = parse("key,password").unwrap();
+        assert_eq!(
+            config.auth_order,
+            vec![AuthMethod::Key, AuthMethod::Password]
+        );
+
+        let config = parse("key,password,agent").unwrap();
+        assert_eq!(
+            config.auth_order,
+            vec![AuthMethod::Key, AuthMethod::Password, AuthMethod::Agent]
+        );
+
+        let err = parse("hostbased,key").unwrap_err().to_string();
+        assert!(err.contains("unknown authentication method 'hostbased'"));
+        assert!(parse("key,key").is_err());
+
+        // Every listed method needs its credentials
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--host=web1",
+            "--user=admin",
+            "--password=x",
+            "--auth-order=key,password",
+        ]);
+        let err = Config::from_args(args).unwrap_err().to_string();
+        assert!(err.contains("--key is not set"));
+
+        // The agent needs no credentials of its own
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--host=web1",
+            "--user=admin",
+            "--auth-order=agent",
+        ]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.auth_order, vec![AuthMethod::Agent]);
+    }
+
+    #[test]
+    fn test_socks_proxy() {
+        let parse = |extra: &[&str]| {
+            let args = ["ssh-mcp", "--host=web1", "--user=admin", "--password=x"];
+            Config::from_args(Args::parse_from(args.iter().chain(extra)))
+        };
+
+        let config = parse(&["--socks-proxy=proxy.internal"]).unwrap();
+        assert_eq!(
+            config.socks_proxy,
+            Some(SocksProxy::new("proxy.internal", DEFAULT_SOCKS_PORT))
+        );
+
+        let config = parse(&[
+            "--socks-proxy=10.0.0.1:9050",
+            "--socks-user=me",
+            "--socks-password=pw",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.socks_proxy,
+            Some(SocksProxy::new("10.0.0.1", 9050).with_credentials("me", "pw"))
+        );
+
+        assert!(parse(&["--socks-proxy=proxy:0"]).is_err());
+        assert!(parse(&["--socks-proxy=proxy", "--socks-user=me"]).is_err());
+        assert!(parse(&["--socks-user=me", "--socks-password=pw"]).is_err());
+    }
+
+    #[test]
+    fn test_host_key_policy() {
+        let parse = |extra: &[&str]| {
+            let args = ["ssh-mcp", "--host=web1", "--user=admin", "--password=x"];
+            Config::from_args(Args::parse_from(args.iter().chain(extra)))
+        };
+
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.host_key_policy, HostKeyPolicy::default());
+
+        let config = parse(&["--known-hosts=/etc/ssh-mcp/known_hosts"]).unwrap();
+        assert_eq!(
+            config.host_key_policy,
+            HostKeyPolicy::KnownHosts(PathBuf::from("/etc/ssh-mcp/known_hosts"))
+        );
+
+        let config = parse(&["--no-host-key-check"]).unwrap();
+        assert_eq!(config.host_key_policy, HostKeyPolicy::AcceptAny);
+
+        assert!(parse(&["--no-host-key-check", "--known-hosts=/tmp/kh"]).is_err());
+
+        let github = "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU";
+        let other = "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s";
+        let config = parse(&[&format!("--host-fingerprint={},{}", github, other)]).unwrap();
+        let HostKeyPolicy::Fingerprints(fingerprints) = config.host_key_policy else {
+            panic!("expected a fingerprint policy");
+        };
+        assert_eq!(fingerprints.len(), 2);
+        assert_eq!(fingerprints[0].to_string(), github);
+
+        assert!(parse(&["--host-fingerprint=MD5:16:27:ac"]).is_err());
+
+        let config = parse(&["--host-key-policy=insecure"]).unwrap();
+        assert_eq!(config.host_key_policy, HostKeyPolicy::AcceptAny);
+
+        let config = parse(&["--host-key-policy=tofu"]).unwrap();
+        assert_eq!(
+            config.host_key_policy,
+            HostKeyPolicy::Tofu(HostKeyStore::new(default_host_key_store()))
+        );
+        let config = parse(&[
+            "--host-key-policy=tofu",
+            "--host-key-store=/var/lib/keys.json",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.host_key_policy,
+            HostKeyPolicy::Tofu(HostKeyStore::new("/var/lib/keys.json"))
+        );
+
+        assert!(parse(&["--host-key-policy=accept-new"]).is_err());
+        assert!(parse(&["--host-key-store=/tmp/keys.json"]).is_err());
+        assert!(parse(&["--host-key-policy=tofu", "--no-host-key-check"]).is_err());
+        assert!(parse(&["--host-key-policy=tofu", "--known-hosts=/tmp/kh"]).is_err());
+        assert!(parse(&[
+            &format!("--host-fingerprint={}", github),
+            "--no-host-key-check"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_broadcast_hosts() {
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--host=web1",
+            "--user=admin",
+            "--password=x",
+            "--port=2222",
+            "--broadcast-hosts=web2,web3:22",
+        ]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(
+            config.broadcast_hosts,
+            vec![("web2".to_string(), 2222), ("web3".to_string(), 22)]
+        );
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--broadcast-hosts=web2"]);
+        assert!(Config::from_args(args).is_err());
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--host=web1",
+            "--user=admin",
+            "--password=x",
+            "--broadcast-hosts=web2",
+            "--host-fingerprint=SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU",
+        ]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_log_level() {
+        let args = Args::parse_from(["ssh-mcp", "--local"]);
+        assert_eq!(args.log_level, tracing::Level::INFO);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--log-level=debug"]);
+        assert_eq!(args.log_level, tracing::Level::DEBUG);
+
+        assert!(Args::try_parse_from(["ssh-mcp", "--log-level=loud"]).is_err());
+    }
+
+    #[test]
+    fn test_transport_tuning() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert!(config.tcp_nodelay);
+        assert_eq!(config.window_size, None);
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--tcp-nodelay=false",
+            "--window-size=65536",
+            "--max-packet-size=32768",
+        ]);
+        let config = Config::from_args(args).unwrap();
+        assert!(!config.tcp_nodelay);
+        assert_eq!(config.window_size, Some(65536));
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--max-packet-size=100000"]);
+        assert!(Config::from_args(args).is_err());
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--window-size=512"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_reconnect_flags() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.reconnect_attempts, 0);
+        assert!(config.reconnect_jitter);
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--reconnect-attempts=5",
+            "--reconnect-jitter=false",
+        ]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.reconnect_attempts, 5);
+        assert!(!config.reconnect_jitter);
+    }
+
+    #[test]
+    fn test_download_limits() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.max_download_bytes, Some(10 * 1024 * 1024));
+        assert!(!config.download_as_text);
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--max-download-bytes=0",
+            "--download-as-text",
+        ]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.max_download_bytes, None);
+        assert!(config.download_as_text);
+    }
+
+    #[test]
+    fn test_allowed_local_paths() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert!(config.allowed_local_paths.is_empty());
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--allowed-local-paths=/srv/a,/srv/b"]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.allowed_local_paths, ["/srv/a", "/srv/b"]);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--allowed-local-paths=uploads"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_max_sftp_handles() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.max_sftp_handles, None);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--max-sftp-handles=4"]);
+        assert_eq!(Config::from_args(args).unwrap().max_sftp_handles, Some(4));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.circuit_threshold, None);
+        assert_eq!(config.circuit_cooldown, 30);
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--circuit-threshold=3",
+            "--circuit-cooldown=120",
+        ]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.circuit_threshold, Some(3));
+        assert_eq!(config.circuit_cooldown, 120);
+    }
+
+    #[test]
+    fn test_idle_refresh() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.idle_refresh, None);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--idle-refresh=300"]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.idle_refresh, Some(300));
+    }
+
+    #[test]
+    fn test_elevation_retries() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.elevation_retries, 0);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--elevation-retries=2"]);
+        assert_eq!(Config::from_args(args).unwrap().elevation_retries, 2);
+    }
+
+    #[test]
+    fn test_require_elevation() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["ssh-mcp", "--host=web1", "--user=admin", "--password=x"];
+            argv.extend_from_slice(extra);
+            Config::from_args(Args::parse_from(argv))
+        };
+        assert!(!parse(&[]).unwrap().require_elevation);
+        let config = parse(&["--require-elevation", "--su-password=root"]).unwrap();
+        assert!(config.require_elevation);
+
+        assert!(parse(&["--require-elevation"]).is_err());
+        assert!(parse(&["--require-elevation", "--su-password= "]).is_err());
+        let args = Args::parse_from(["ssh-mcp", "--local", "--require-elevation"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_enable_connection_tools() {
+        let base = ["ssh-mcp", "--host=web1", "--user=admin", "--password=x"];
+        let config = Config::from_args(Args::parse_from(base)).unwrap();
+        assert!(!config.enable_connection_tools);
+
+        let args = Args::parse_from(base.iter().chain(&["--enable-connection-tools"]));
+        assert!(Config::from_args(args).unwrap().enable_connection_tools);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--enable-connection-tools"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_control_path() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["ssh-mcp", "--host=web1", "--user=admin", "--password=x"];
+            argv.extend_from_slice(extra);
+            Config::from_args(Args::parse_from(argv))
+        };
+        assert_eq!(parse(&[]).unwrap().control_path, None);
+        let config = parse(&["--control-path=/run/ssh-mcp/web1.sock"]).unwrap();
+        assert_eq!(
+            config.control_path,
+            Some(PathBuf::from("/run/ssh-mcp/web1.sock"))
+        );
+        assert!(parse(&["--control-path=/tmp/s", "--broadcast-hosts=web2"]).is_err());
+    }
+
+    #[test]
+    fn test_output_drain_ms() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.output_drain_ms, 50);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--output-drain-ms=0"]);
+        assert_eq!(Config::from_args(args).unwrap().output_drain_ms, 0);
+    }
+
+    #[test]
+    fn test_pty_commands() {
+        let needs_pty = |config: &Config, command: &str| {
+            config.pty_commands.iter().any(|re| re.is_match(command))
+        };
+
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert!(needs_pty(&config, "sudo apt update"));
+        assert!(needs_pty(&config, "cd /etc && vim hosts"));
+        assert!(needs_pty(&config, "top"));
+        assert!(!needs_pty(&config, "ls -la"));
+        assert!(!needs_pty(&config, "topology-check"));
+        assert!(!needs_pty(&config, "echo sudo"));
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--pty-commands=^mysql\\b,^psql"]);
+        let config = Config::from_args(args).unwrap();
+        assert!(needs_pty(&config, "mysql -u root"));
+        assert!(needs_pty(&config, "psql"));
+        assert!(!needs_pty(&config, "sudo ls"));
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--pty-commands="]);
+        assert!(Config::from_args(args).unwrap().pty_commands.is_empty());
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--pty-commands=(unclosed"]);
+        let err = Config::from_args(args).unwrap_err();
+        assert!(err.to_string().contains("Invalid --pty-commands pattern"));
+    }
+
+    #[test]
+    fn test_key_perm_check() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.key_perm_check, KeyPermCheck::Warn);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--key-perm-check=error"]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.key_perm_check, KeyPermCheck::Error);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--key-perm-check=strict"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_container_runtime() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.container_runtime, ContainerRuntime::Docker);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--container-runtime=podman"]);
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.container_runtime, ContainerRuntime::Podman);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--container-runtime=lxc"]);
+        let err = Config::from_args(args).unwrap_err();
+        assert!(err.to_string().contains("unknown container runtime 'lxc'"));
+    }
+
+    #[test]
+    fn test_exit_code_map() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert!(config.exit_code_map.is_empty());
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--exit-code-map=rsync:24=success"]);
+        let config = Config::from_args(args).unwrap();
+        assert!(config
+            .exit_code_map
+            .classify("rsync -a a/ b/", 24)
+            .is_some());
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--exit-code-map=rsync:24"]);
+        let err = Config::from_args(args).unwrap_err();
+        assert!(err.to_string().contains("--exit-code-map"));
+    }
+
+    #[test]
+    fn test_stream() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert!(config.stream);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--stream=false"]);
+        assert!(!Config::from_args(args).unwrap().stream);
+    }
+
+    #[test]
+    fn test_only_command() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert!(config.only_command.is_none());
+
+        let args = Args::parse_from([
+            "ssh-mcp",
+            "--local",
+            "--only-command=systemctl {action} {service}",
+        ]);
+        let template = Config::from_args(args).unwrap().only_command.unwrap();
+        assert_eq!(template.placeholders(), vec!["action", "service"]);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--only-command=systemctl {action"]);
+        let err = Config::from_args(args).unwrap_err();
+        assert!(err.to_string().contains("--only-command: unmatched '{'"));
+    }
+
+    #[test]
+    fn test_pty_cols() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.pty_cols, 80);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--pty-cols=200"]);
+        assert_eq!(Config::from_args(args).unwrap().pty_cols, 200);
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--pty-cols=0"]);
+        assert!(Config::from_args(args).is_err());
+    }
+
+    #[test]
+    fn test_max_output_lines() {
+        let config = Config::from_args(Args::parse_from(["ssh-mcp", "--local"])).unwrap();
+        assert_eq!(config.max_output_lines, Some(2000));
+        assert_eq!(
+            (config.output_head_lines, config.output_tail_lines),
+            (200, 200)
+        );
+
+        let args = Args::parse_from(["ssh-mcp", "--local", "--max-output-lines=0"]);
+        assert_eq!(Config::from_args(args).unwrap().max_output_lines, None);
+
+        // Head and tail together must leave something to omit
+        for extra in [
+            ["--max-output-lines=400", "--output-head-lines=200"],
+            ["--output-head-lines=0", "--output-tail-lines=0"],
+        ] {
+            let args = Args::parse_from(["ssh-mcp", "--local"].iter().chain(&extra));
+            let err = Config::from_args(args).unwrap_err();
+            assert!(err.to_string().contains("--output-head-lines"));
+        }
+    }
+
+    #[test]
+    fn test_is_valid_locale() {
+        assert!(is_valid_locale("C"));
+        assert!(is_valid_locale("C.UTF-8"));
+        assert!(is_valid_locale("en_US.utf8"));
+        assert!(is_valid_locale("de_DE@euro"));
+        assert!(!is_valid_locale(""));
+        assert!(!is_valid_locale("C.UTF-8; rm -rf /"));
+        assert!(!is_valid_locale("'quoted'"));
     }
 }
@@ -0,0 +1,496 @@
+//! Connection sharing between server instances (`--control-path`)
+//!
+//! Like OpenSSH's ControlMaster, the first instance started with a given
+//! control path becomes the master: it opens the SSH connection and listens
+//! on a Unix socket at that path. Instances started later find the master
+//! listening and send their commands over the socket instead of connecting
+//! themselves; the master runs each one on a channel of its own connection.
+//! Each request is one JSON line on a fresh socket connection, answered with
+//! one JSON line.
+//!
+//! Only command execution is shared. The file tools need SFTP on a
+//! connection of their own and are not offered by instances using a master.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn, Instrument};
+
+use crate::error::{Result, SshMcpError};
+use crate::executor::Executor;
+use crate::ssh::{CommandOutput, EnvMethod};
+
+/// Time allowed beyond a command's own timeout for the master to answer
+const RESPONSE_GRACE: Duration = Duration::from_secs(5);
+
+/// How a shared command is run on the master's connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ExecKind {
+    /// [`Executor::exec_with_env`], elevated when the master can
+    Elevated,
+
+    /// [`Executor::exec_as_user`]
+    AsUser,
+
+    /// [`Executor::exec_with_stdin`]
+    Stdin,
+}
+
+/// A command sent to the master
+#[derive(Debug, Serialize, Deserialize)]
+struct ControlRequest {
+    /// `user@host:port` the sending instance is configured for
+    target: String,
+    kind: ExecKind,
+    command: String,
+    #[serde(default)]
+    env: Vec<(String, String)>,
+    term_width: Option<u16>,
+    timeout_ms: u64,
+    /// Standard input, base64-encoded
+    stdin: Option<String>,
+}
+
+/// The master's answer to a [`ControlRequest`]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ControlResponse {
+    output: Option<CommandOutput>,
+    #[serde(default)]
+    methods: Vec<(String, EnvMethod)>,
+    error: Option<String>,
+    /// Set when the error was the command timing out
+    timeout_ms: Option<u64>,
+    elevation_warning: Option<String>,
+}
+
+/// This instance's part in connection sharing
+pub enum Role {
+    /// No master was listening; this instance serves the socket
+    Master(UnixListener),
+
+    /// Commands go to the master listening on the socket
+    Client(ControlClient),
+}
+
+/// `user@host:port`, which master and clients must agree on
+pub fn control_target(user: &str, host: &str, port: u16) -> String {
+    format!("{}@{}:{}", user, host, port)
+}
+
+/// Join the master listening at `path`, or become the master
+///
+/// A socket file nobody listens on is left over from a master that exited,
+/// and is replaced; anything else at `path` is refused rather than deleted.
+/// If another instance wins the race to bind the path, it is joined instead.
+pub async fn connect_or_listen(path: &Path, target: &str) -> Result<Role> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if UnixStream::connect(path).await.is_ok() {
+        info!(
+            "Sharing the connection of the control master at {}",
+            path.display()
+        );
+        return Ok(Role::Client(ControlClient::new(path, target)));
+    }
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            debug!("Removing stale control socket {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(SshMcpError::config(format!(
+                "--control-path {} exists and is not a socket",
+                path.display()
+            )))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    match bind_private(path) {
+        Ok(listener) => {
+            info!("Control master listening on {}", path.display());
+            Ok(Role::Master(listener))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            info!(
+                "Sharing the connection of the control master at {}",
+                path.display()
+            );
+            Ok(Role::Client(ControlClient::new(path, target)))
+        }
+        Err(e) => Err(SshMcpError::config(format!(
+            "Cannot listen on --control-path {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// Bind a socket at `path` that only this user can connect to
+///
+/// Anyone who can connect can run commands on the target, so the socket is
+/// bound inside a fresh 0700 directory, restricted to 0600 and only then
+/// linked into place. Linking fails with `AlreadyExists` if another
+/// instance bound `path` in the meantime.
+fn bind_private(path: &Path) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir = path.with_file_name(format!(".{}.{:08x}", name, rand::random::<u32>()));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let staged = dir.join("socket");
+
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::hard_link(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&dir);
+    bound
+}
+
+/// Run the commands of other instances on `executor` until the task is dropped
+///
+/// Requests for any other `target` are refused, so an instance configured
+/// for a different host can't use this connection by mistake.
+pub fn serve(
+    listener: UnixListener,
+    executor: Arc<dyn Executor>,
+    target: String,
+) -> JoinHandle<()> {
+    let target = Arc::new(target);
+    tokio::spawn(
+        async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let executor = executor.clone();
+                        let target = target.clone();
+                        tokio::spawn(
+                            async move {
+                                if let Err(e) = respond(stream, executor.as_ref(), &target).await {
+                                    debug!("Control connection error: {}", e);
+                                }
+                            }
+                            .in_current_span(),
+                        );
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {}", e),
+                }
+            }
+        }
+        .in_current_span(),
+    )
+}
+
+/// Read one request, run it and write the response
+async fn respond(stream: UnixStream, executor: &dyn Executor, target: &str) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str::<ControlRequest>(&line) {
+        Ok(request) if request.target != target => ControlResponse {
+            error: Some(format!(
+                "The control master serves {}, not {}",
+                target, request.target
+            )),
+            ..Default::default()
+        },
+        Ok(request) => run(executor, request).await,
+        Err(e) => ControlResponse {
+            error: Some(format!("Invalid control request: {}", e)),
+            ..Default::default()
+        },
+    };
+
+    let mut reply = serde_json::to_string(&response).map_err(protocol_error)?;
+    reply.push('\n');
+    write.write_all(reply.as_bytes()).await?;
+    write.shutdown().await?;
+    Ok(())
+}
+
+/// Run a shared command on the master's executor
+async fn run(executor: &dyn Executor, request: ControlRequest) -> ControlResponse {
+    let timeout = Duration::from_millis(request.timeout_ms);
+    let result = match executor.prepare(request.kind == ExecKind::Elevated).await {
+        Err(e) => Err(e),
+        Ok(()) => match request.kind {
+            ExecKind::Elevated => {
+                executor
+                    .exec_with_env(&request.command, &request.env, request.term_width, timeout)
+                    .await
+            }
+            ExecKind::AsUser => {
+                executor
+                    .exec_as_user(&request.command, &request.env, request.term_width, timeout)
+                    .await
+            }
+            ExecKind::Stdin => match BASE64.decode(request.stdin.unwrap_or_default()) {
+                Ok(stdin) => executor
+                    .exec_with_stdin(&request.command, &stdin, timeout)
+                    .await
+                    .map(|output| (output, Vec::new())),
+                Err(e) => Err(SshMcpError::invalid_params(format!(
+                    "Invalid stdin in control request: {}",
+                    e
+                ))),
+            },
+        },
+    };
+
+    let elevation_warning = executor.elevation_warning();
+    match result {
+        Ok((output, methods)) => ControlResponse {
+            output: Some(output),
+            methods,
+            elevation_warning,
+            ..Default::default()
+        },
+        Err(e) => ControlResponse {
+            timeout_ms: match e {
+                SshMcpError::Timeout(ms) => Some(ms),
+                _ => None,
+            },
+            error: Some(e.to_string()),
+            elevation_warning,
+            ..Default::default()
+        },
+    }
+}
+
+/// Error for a control message that can't be encoded or decoded
+fn protocol_error(e: serde_json::Error) -> SshMcpError {
+    SshMcpError::connection(format!("Invalid control message: {}", e))
+}
+
+/// Runs commands through the control master at a socket path
+#[derive(Debug)]
+pub struct ControlClient {
+    path: PathBuf,
+    target: String,
+
+    /// What the master last reported about elevation
+    elevation_warning: Mutex<Option<String>>,
+}
+
+impl ControlClient {
+    /// Create a client for the master at `path`, serving `target`
+    pub fn new(path: impl Into<PathBuf>, target: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            target: target.into(),
+            elevation_warning: Mutex::new(None),
+        }
+    }
+
+    /// Send a command to the master and wait for its result
+    async fn request(
+        &self,
+        kind: ExecKind,
+        command: &str,
+        env: &[(String, String)],
+        term_width: Option<u16>,
+        stdin: Option<&[u8]>,
+        timeout: Duration,
+    ) -> Result<(CommandOutput, Vec<(String, EnvMethod)>)> {
+        let request = ControlRequest {
+            target: self.target.clone(),
+            kind,
+            command: command.to_string(),
+            env: env.to_vec(),
+            term_width,
+            timeout_ms: u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX),
+            stdin: stdin.map(|stdin| BASE64.encode(stdin)),
+        };
+        let mut line = serde_json::to_string(&request).map_err(protocol_error)?;
+        line.push('\n');
+
+        let exchange = async {
+            let stream = UnixStream::connect(&self.path).await.map_err(|e| {
+                SshMcpError::connection(format!(
+                    "The control master at {} is not reachable ({}); restart this server \
+                     to take over the connection",
+                    self.path.display(),
+                    e
+                ))
+            })?;
+            let (read, mut write) = stream.into_split();
+            write.write_all(line.as_bytes()).await?;
+            let mut reply = String::new();
+            BufReader::new(read).read_line(&mut reply).await?;
+            if reply.is_empty() {
+                return Err(SshMcpError::connection(
+                    "The control master closed the connection without answering",
+                ));
+            }
+            serde_json::from_str::<ControlResponse>(&reply).map_err(protocol_error)
+        };
+        let response = tokio::time::timeout(timeout + RESPONSE_GRACE, exchange)
+            .await
+            .map_err(|_| SshMcpError::Timeout(request.timeout_ms))??;
+
+        *self.elevation_warning.lock().unwrap() = response.elevation_warning;
+        match (response.output, response.error, response.timeout_ms) {
+            (Some(output), _, _) => Ok((output, response.methods)),
+            (None, _, Some(ms)) => Err(SshMcpError::Timeout(ms)),
+            (None, error, None) => Err(SshMcpError::connection(error.unwrap_or_else(|| {
+                "The control master sent neither output nor an error".to_string()
+            }))),
+        }
+    }
+
+    pub(crate) async fn exec_command(
+        &self,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<CommandOutput> {
+        self.request(ExecKind::Elevated, command, &[], None, None, timeout)
+            .await
+            .map(|(output, _)| output)
+    }
+
+    pub(crate) async fn exec_command_with_env(
+        &self,
+        command: &str,
+        env: &[(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> Result<(CommandOutput, Vec<(String, EnvMethod)>)> {
+        self.request(ExecKind::Elevated, command, env, term_width, None, timeout)
+            .await
+    }
+
+    pub(crate) async fn exec_command_as_user(
+        &self,
+        command: &str,
+        env: &[(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> Result<(CommandOutput, Vec<(String, EnvMethod)>)> {
+        self.request(ExecKind::AsUser, command, env, term_width, None, timeout)
+            .await
+    }
+
+    pub(crate) async fn exec_command_with_stdin(
+        &self,
+        command: &str,
+        stdin: &[u8],
+        timeout: Duration,
+    ) -> Result<CommandOutput> {
+        self.request(ExecKind::Stdin, command, &[], None, Some(stdin), timeout)
+            .await
+            .map(|(output, _)| output)
+    }
+
+    /// What the master last reported about running unprivileged
+    pub(crate) fn elevation_warning(&self) -> Option<String> {
+        self.elevation_warning.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::LocalExecutor;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ssh-mcp-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_second_instance_uses_master() {
+        let path = socket_path("control");
+        let target = control_target("admin", "web1", 22);
+
+        let Role::Master(listener) = connect_or_listen(&path, &target).await.unwrap() else {
+            panic!("the first instance must become the master");
+        };
+        let server = serve(listener, Arc::new(LocalExecutor::new()), target.clone());
+
+        let Role::Client(client) = connect_or_listen(&path, &target).await.unwrap() else {
+            panic!("the second instance must use the master");
+        };
+        let output = client
+            .exec_command("echo shared; exit 3", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, "shared\n");
+        assert_eq!(output.exit_code, Some(3));
+
+        let output = client
+            .exec_command_with_stdin("tr a-z A-Z", b"piped", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, "PIPED");
+
+        let result = client
+            .exec_command("sleep 5", Duration::from_millis(100))
+            .await;
+        assert!(matches!(result, Err(SshMcpError::Timeout(100))));
+
+        // An instance configured for another host is turned away
+        let other = ControlClient::new(&path, control_target("admin", "db1", 22));
+        let err = other
+            .exec_command("true", Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("serves admin@web1:22, not admin@db1:22"));
+
+        server.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_stale_socket_is_replaced() {
+        let path = socket_path("stale");
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let role = connect_or_listen(&path, "admin@web1:22").await.unwrap();
+        assert!(matches!(role, Role::Master(_)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_socket_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = socket_path("private");
+        let role = connect_or_listen(&path, "admin@web1:22").await.unwrap();
+        assert!(matches!(role, Role::Master(_)));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_regular_file_is_not_replaced() {
+        let path = socket_path("regular");
+        std::fs::write(&path, b"Host *\n").unwrap();
+
+        let err = connect_or_listen(&path, "admin@web1:22")
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is not a socket"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"Host *\n");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -36,6 +36,45 @@ pub enum SshMcpError {
     /// SSH key parsing error
     #[error("SSH key error: {0}")]
     SshKey(String),
+
+    /// Command rejected by the command policy
+    #[error("Command denied by policy: {0}")]
+    PolicyDenied(String),
+
+    /// SFTP operation failed
+    #[error("SFTP error: {0}")]
+    Sftp(String),
+
+    /// Too many commands running and queued
+    #[error("Server busy: {0}")]
+    Busy(String),
+
+    /// Operation aborted by cancel-all or shutdown
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    /// Remote filesystem ran out of space (or quota) during a write
+    #[error("Remote disk full: {0}")]
+    DiskFull(String),
+
+    /// The server presented a different host key than the one on record
+    #[error(
+        "HOST KEY CHANGED for {host}: expected {expected}, but the server offered {actual}. \
+         Someone may be intercepting the connection. {hint}"
+    )]
+    HostKeyChanged {
+        /// Host as `host` or `[host]:port`
+        host: String,
+
+        /// Fingerprint of the recorded key
+        expected: String,
+
+        /// Fingerprint of the key the server presented
+        actual: String,
+
+        /// How to proceed if the change is expected
+        hint: String,
+    },
 }
 
 /// Result type alias using SshMcpError
@@ -66,6 +105,21 @@ impl SshMcpError {
     pub fn config(msg: impl Into<String>) -> Self {
         SshMcpError::Config(msg.into())
     }
+
+    /// Create an SFTP error from a string
+    pub fn sftp(msg: impl Into<String>) -> Self {
+        SshMcpError::Sftp(msg.into())
+    }
+
+    /// Create a server busy error from a string
+    pub fn busy(msg: impl Into<String>) -> Self {
+        SshMcpError::Busy(msg.into())
+    }
+
+    /// Create a remote disk full error from a string
+    pub fn disk_full(msg: impl Into<String>) -> Self {
+        SshMcpError::DiskFull(msg.into())
+    }
 }
 
 #[cfg(test)]
@@ -79,5 +133,20 @@ mod tests {
 
         let err = SshMcpError::Timeout(5000);
         assert_eq!(err.to_string(), "Command timeout after 5000ms");
+
+        let err = SshMcpError::disk_full("no space left");
+        assert_eq!(err.to_string(), "Remote disk full: no space left");
+
+        let err = SshMcpError::HostKeyChanged {
+            host: "[web1]:2222".to_string(),
+            expected: "SHA256:old".to_string(),
+            actual: "SHA256:new".to_string(),
+            hint: "Check the host.".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "HOST KEY CHANGED for [web1]:2222: expected SHA256:old, but the server offered \
+             SHA256:new. Someone may be intercepting the connection. Check the host."
+        );
     }
 }
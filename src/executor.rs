@@ -0,0 +1,258 @@
+//! Command execution backends
+//!
+//! The [`Executor`] trait decouples the MCP tools from the transport that
+//! actually runs commands. [`SshConnectionManager`] executes over SSH and
+//! [`LocalExecutor`] runs commands on this machine, and `ControlClient` hands
+//! them to another instance's connection (`--control-path`); tests can plug
+//! in their own implementation.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+#[cfg(unix)]
+use crate::control::ControlClient;
+use crate::error::{Result, SshMcpError};
+use crate::local::LocalExecutor;
+use crate::ssh::env::export_prefix;
+use crate::ssh::{CommandOutput, EnvMethod, SshConnectionManager};
+
+/// Boxed future returned by [`Executor`] methods
+pub type ExecFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// A backend capable of running shell commands
+pub trait Executor: Send + Sync {
+    /// Make the backend ready to run a command
+    ///
+    /// `elevate` requests the privileged shell when the backend supports one.
+    /// Failing to elevate is not an error; the command then runs unprivileged.
+    fn prepare(&self, elevate: bool) -> ExecFuture<'_, ()> {
+        let _ = elevate;
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Execute a sanitized command, waiting at most `timeout` for it to finish
+    fn exec<'a>(&'a self, command: &'a str, timeout: Duration) -> ExecFuture<'a, CommandOutput>;
+
+    /// Execute a command with extra environment variables, optionally on a
+    /// terminal `term_width` columns wide
+    ///
+    /// Returns the output and how each variable was delivered. The default
+    /// exports every variable at the start of the command line, and stands in
+    /// for the terminal by exporting `COLUMNS`, which `ps` and most other
+    /// column-formatting tools honour.
+    fn exec_with_env<'a>(
+        &'a self,
+        command: &'a str,
+        env: &'a [(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> ExecFuture<'a, (CommandOutput, Vec<(String, EnvMethod)>)> {
+        Box::pin(async move {
+            let columns = match term_width {
+                Some(cols) => format!("export COLUMNS={}; ", cols),
+                None => String::new(),
+            };
+            let command = format!("{}{}{}", export_prefix(env), columns, command);
+            let output = self.exec(&command, timeout).await?;
+            let methods = env
+                .iter()
+                .map(|(name, _)| (name.clone(), EnvMethod::Shell))
+                .collect();
+            Ok((output, methods))
+        })
+    }
+
+    /// Like [`exec_with_env`](Self::exec_with_env), but as the login user
+    /// even when the backend holds a privileged shell
+    ///
+    /// The default runs the command like `exec_with_env`, for backends
+    /// without one.
+    fn exec_as_user<'a>(
+        &'a self,
+        command: &'a str,
+        env: &'a [(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> ExecFuture<'a, (CommandOutput, Vec<(String, EnvMethod)>)> {
+        self.exec_with_env(command, env, term_width, timeout)
+    }
+
+    /// Execute a command on a terminal, sending `response` when `prompt` appears
+    ///
+    /// Backends without terminal support return an error.
+    fn exec_prompted<'a>(
+        &'a self,
+        command: &'a str,
+        prompt: &'a str,
+        response: &'a str,
+        timeout: Duration,
+    ) -> ExecFuture<'a, CommandOutput> {
+        let _ = (command, prompt, response, timeout);
+        Box::pin(async {
+            Err(SshMcpError::config(
+                "Interactive prompts are not supported by this backend",
+            ))
+        })
+    }
+
+    /// Execute a command as the login user, with `stdin` on its standard
+    /// input
+    ///
+    /// Backends that can't feed input return an error.
+    fn exec_with_stdin<'a>(
+        &'a self,
+        command: &'a str,
+        stdin: &'a [u8],
+        timeout: Duration,
+    ) -> ExecFuture<'a, CommandOutput> {
+        let _ = (command, stdin, timeout);
+        Box::pin(async {
+            Err(SshMcpError::config(
+                "Standard input is not supported by this backend",
+            ))
+        })
+    }
+
+    /// Why commands are running unprivileged although elevation was
+    /// requested, if they are
+    fn elevation_warning(&self) -> Option<String> {
+        None
+    }
+
+    /// Release any resources held by the backend
+    fn close(&self) -> ExecFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl Executor for SshConnectionManager {
+    fn prepare(&self, elevate: bool) -> ExecFuture<'_, ()> {
+        Box::pin(async move {
+            self.ensure_connected().await?;
+
+            // If su elevation is configured and available, ensure we're elevated
+            // unless it was deliberately dropped
+            if elevate
+                && self.get_su_password().is_some()
+                && !self.is_elevated()
+                && !self.is_elevation_dropped()
+            {
+                let _ = self.elevate_with_retries().await;
+            }
+            Ok(())
+        })
+    }
+
+    fn exec<'a>(&'a self, command: &'a str, timeout: Duration) -> ExecFuture<'a, CommandOutput> {
+        Box::pin(self.exec_command(command, timeout))
+    }
+
+    fn exec_with_env<'a>(
+        &'a self,
+        command: &'a str,
+        env: &'a [(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> ExecFuture<'a, (CommandOutput, Vec<(String, EnvMethod)>)> {
+        Box::pin(self.exec_command_with_env(command, env, term_width, timeout))
+    }
+
+    fn exec_as_user<'a>(
+        &'a self,
+        command: &'a str,
+        env: &'a [(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> ExecFuture<'a, (CommandOutput, Vec<(String, EnvMethod)>)> {
+        Box::pin(self.exec_command_as_user(command, env, term_width, timeout))
+    }
+
+    fn exec_prompted<'a>(
+        &'a self,
+        command: &'a str,
+        prompt: &'a str,
+        response: &'a str,
+        timeout: Duration,
+    ) -> ExecFuture<'a, CommandOutput> {
+        Box::pin(self.exec_with_prompt(command, prompt, response, timeout))
+    }
+
+    fn exec_with_stdin<'a>(
+        &'a self,
+        command: &'a str,
+        stdin: &'a [u8],
+        timeout: Duration,
+    ) -> ExecFuture<'a, CommandOutput> {
+        Box::pin(self.exec_command_with_stdin(command, stdin, timeout))
+    }
+
+    fn elevation_warning(&self) -> Option<String> {
+        if self.get_su_password().is_none() || self.is_elevated() {
+            return None;
+        }
+        self.elevation_error()
+    }
+
+    fn close(&self) -> ExecFuture<'_, ()> {
+        Box::pin(async move {
+            SshConnectionManager::close(self).await;
+            Ok(())
+        })
+    }
+}
+
+impl Executor for LocalExecutor {
+    fn exec<'a>(&'a self, command: &'a str, timeout: Duration) -> ExecFuture<'a, CommandOutput> {
+        Box::pin(self.exec_command(command, timeout))
+    }
+
+    fn exec_with_stdin<'a>(
+        &'a self,
+        command: &'a str,
+        stdin: &'a [u8],
+        timeout: Duration,
+    ) -> ExecFuture<'a, CommandOutput> {
+        Box::pin(self.exec_command_with_stdin(command, stdin, timeout))
+    }
+}
+
+#[cfg(unix)]
+impl Executor for ControlClient {
+    fn exec<'a>(&'a self, command: &'a str, timeout: Duration) -> ExecFuture<'a, CommandOutput> {
+        Box::pin(self.exec_command(command, timeout))
+    }
+
+    fn exec_with_env<'a>(
+        &'a self,
+        command: &'a str,
+        env: &'a [(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> ExecFuture<'a, (CommandOutput, Vec<(String, EnvMethod)>)> {
+        Box::pin(self.exec_command_with_env(command, env, term_width, timeout))
+    }
+
+    fn exec_as_user<'a>(
+        &'a self,
+        command: &'a str,
+        env: &'a [(String, String)],
+        term_width: Option<u16>,
+        timeout: Duration,
+    ) -> ExecFuture<'a, (CommandOutput, Vec<(String, EnvMethod)>)> {
+        Box::pin(self.exec_command_as_user(command, env, term_width, timeout))
+    }
+
+    fn exec_with_stdin<'a>(
+        &'a self,
+        command: &'a str,
+        stdin: &'a [u8],
+        timeout: Duration,
+    ) -> ExecFuture<'a, CommandOutput> {
+        Box::pin(self.exec_command_with_stdin(command, stdin, timeout))
+    }
+
+    fn elevation_warning(&self) -> Option<String> {
+        ControlClient::elevation_warning(self)
+    }
+}
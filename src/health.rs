@@ -0,0 +1,193 @@
+//! Health/liveness HTTP endpoint
+//!
+//! A minimal HTTP/1.1 responder for container orchestration probes. Every
+//! request is answered with `200 OK` while the server is healthy and
+//! `503 Service Unavailable` otherwise, regardless of method or path. It runs
+//! as its own task, independent of the MCP transport.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn, Instrument};
+
+use crate::executor::Executor;
+use crate::ssh::SshConnectionManager;
+
+/// How long a probe command may take before it counts as failed
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a client may take to send its request head
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request head read before answering
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// Shared health state consulted by the endpoint
+pub struct HealthState {
+    /// SSH connection to report on (`None` in local mode)
+    connection: Option<Arc<SshConnectionManager>>,
+
+    /// Result of the most recent periodic probe (true when probing is off)
+    probe_ok: AtomicBool,
+}
+
+impl HealthState {
+    /// Create health state for the given connection
+    pub fn new(connection: Option<Arc<SshConnectionManager>>) -> Self {
+        Self {
+            connection,
+            probe_ok: AtomicBool::new(true),
+        }
+    }
+
+    /// Check whether the server is healthy
+    ///
+    /// Requires an established SSH session (in SSH mode) and a passing last
+    /// probe (when probing is enabled).
+    pub async fn is_healthy(&self) -> bool {
+        if !self.probe_ok.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        match self.connection {
+            Some(ref connection) => connection.is_connected().await,
+            None => true,
+        }
+    }
+
+    /// Periodically run a no-op command through `executor` and record the result
+    ///
+    /// The probe also (re)establishes the SSH connection, so the endpoint
+    /// reports healthy without waiting for the first tool call.
+    pub fn spawn_probe(
+        self: Arc<Self>,
+        executor: Arc<dyn Executor>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        tokio::spawn(
+            async move {
+                let mut ticker = tokio::time::interval(interval);
+                loop {
+                    ticker.tick().await;
+
+                    let result = match executor.prepare(false).await {
+                        Ok(()) => executor.exec("true", PROBE_TIMEOUT).await,
+                        Err(e) => Err(e),
+                    };
+                    let ok = match result {
+                        Ok(output) => output.success(),
+                        Err(e) => {
+                            warn!("Health probe failed: {}", e);
+                            false
+                        }
+                    };
+                    self.probe_ok.store(ok, Ordering::SeqCst);
+                }
+            }
+            .in_current_span(),
+        )
+    }
+}
+
+/// Serve health checks on `listener` until the task is dropped
+pub async fn serve(listener: TcpListener, state: Arc<HealthState>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                debug!("Health check from {}", peer);
+                let state = state.clone();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = respond(stream, &state).await {
+                            debug!("Health check connection error: {}", e);
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+            Err(e) => warn!("Failed to accept health check connection: {}", e),
+        }
+    }
+}
+
+/// Read the request head and write the status response
+async fn respond(mut stream: TcpStream, state: &HealthState) -> std::io::Result<()> {
+    // The request itself doesn't matter, but it is read so clients don't see
+    // a reset from closing with unread data
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    let _ = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        while request.len() < MAX_REQUEST_BYTES && !request.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+    })
+    .await;
+
+    let (status, body) = if state.is_healthy().await {
+        ("200 OK", "ok\n")
+    } else {
+        ("503 Service Unavailable", "unavailable\n")
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::SshConfig;
+
+    async fn get(state: Arc<HealthState>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, state));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        server.abort();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_healthy_without_connection() {
+        let response = get(Arc::new(HealthState::new(None))).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nok\n"));
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_when_disconnected() {
+        let manager = SshConnectionManager::new(SshConfig::new("localhost", "user")).await;
+        let state = Arc::new(HealthState::new(Some(Arc::new(manager))));
+
+        let response = get(state).await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_after_failed_probe() {
+        let state = HealthState::new(None);
+        state.probe_ok.store(false, Ordering::SeqCst);
+        assert!(!state.is_healthy().await);
+    }
+}
@@ -9,13 +9,43 @@
 //! - Execute shell commands on remote SSH servers (`exec` tool)
 //! - Support for `sudo` command execution with password (`sudo-exec` tool)
 //! - Persistent SSH connection with auto-reconnect
+//! - Local execution mode (`--local`) for running against this machine without SSH
+//! - Optional HTTP health endpoint (`--health-addr`) for liveness/readiness probes
 //! - Configurable command timeout
 //! - Command length limits for safety
+//! - Allow/deny command policy file (`--policy-file`)
+//! - Concurrency limit with a bounded queue (`--max-concurrent`)
+//! - Structured parsing of known command output, extensible with custom parsers
+//! - Output delivered as progress notifications while a command runs, for clients that ask
+//! - One SSH connection shared between server instances (`--control-path`)
 //!
 //! # MCP Tools
 //!
 //! - `exec` - Execute a shell command on the remote SSH server
 //! - `sudo-exec` - Execute a command with sudo privileges (can be disabled with `--disable-sudo`)
+//! - `session-info` - Report connection details and the remote locale
+//! - `command-history` - List recently executed commands and their exit codes
+//! - `remove` - Delete remote files over SFTP, with glob expansion
+//! - `append-file` - Append text to a remote file over SFTP
+//! - `edit-file` - Apply a regex substitution to a remote file over SFTP
+//! - `upload` - Write a local file or base64 content to a remote file over SFTP
+//! - `download` - Read a remote file over SFTP, as base64 or text
+//! - `read-file` - Read the start of a remote file over SFTP, base64-encoded
+//! - `list-dir` - List a remote directory over SFTP as structured entries
+//! - `chmod` - Set the mode of a remote file over SFTP
+//! - `chown` - Change the owner and group of a remote file
+//! - `exec-diff` - Run a command and diff its output against the previous run
+//! - `broadcast-exec` - Run a command on every configured host concurrently
+//! - `queue-status` - Report running and queued commands
+//! - `cancel-all` - Abort every in-flight command and file operation
+//! - `drop-elevation` - Close the su shell and run commands as the login user
+//! - `connect` - Open the SSH session explicitly (`--enable-connection-tools`)
+//! - `disconnect` - Close the SSH session (`--enable-connection-tools`)
+//! - `list-processes` - List running processes as structured entries
+//! - `kill-process` - Send a signal to a process
+//! - `list-ports` - List listening ports as structured entries
+//! - `wait-for` - Re-run a command until its result meets a condition
+//! - `run-command` - Run the `--only-command` template; the only tool in that mode
 //!
 //! # Example Usage (CLI)
 //!
@@ -30,18 +60,34 @@
 //!   --host=YOUR_HOST --user=root --password=pass
 //! ```
 
+pub mod cancel;
 pub mod config;
+#[cfg(unix)]
+pub mod control;
 pub mod error;
+pub mod executor;
+pub mod health;
+pub mod local;
+pub mod policy;
+pub mod queue;
 pub mod server;
 pub mod ssh;
+pub mod stream;
 pub mod tools;
 
 // Re-exports for convenience
 pub use config::{Args, Config};
 pub use error::{Result, SshMcpError};
+pub use executor::Executor;
+pub use local::LocalExecutor;
+pub use policy::Policy;
 pub use server::SshMcpServer;
 pub use ssh::{
     escape_command_for_shell, escape_for_shell, sanitize_command, sanitize_password,
-    wrap_sudo_command, CommandOutput, SshConfig, SshConnectionManager, SshHandler,
+    strip_sudo_lecture, wrap_sudo_command, CommandOutput, HostKeyPolicy, SshConfig,
+    SshConnectionManager, SshHandler,
+};
+pub use tools::{
+    AppendFileParams, BroadcastExecParams, CommandHistoryParams, ExecDiffParams, ExecParams,
+    KillProcessParams, ListProcessesParams, RemoveParams, SudoExecParams, WaitForParams,
 };
-pub use tools::{ExecParams, SudoExecParams};
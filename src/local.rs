@@ -0,0 +1,247 @@
+//! Local command execution
+//!
+//! Runs commands on the machine hosting the MCP server via `tokio::process`,
+//! used by `--local` mode in place of an SSH connection. Output collection
+//! mirrors the SSH path: stdout/stderr are captured separately, the per-call
+//! byte limit is enforced while reading, and timed out commands are killed.
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+use crate::error::{Result, SshMcpError};
+use crate::ssh::CommandOutput;
+use crate::stream::push_streamed;
+
+/// Executes commands on the local host
+#[derive(Debug, Clone, Default)]
+pub struct LocalExecutor {
+    /// Hard limit on bytes read from a single command (None = unlimited)
+    max_output_bytes: Option<usize>,
+
+    /// Locale exported as LANG/LC_ALL for executed commands
+    locale: Option<String>,
+}
+
+impl LocalExecutor {
+    /// Create a new local executor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the hard limit on bytes read from a single command
+    pub fn with_max_output_bytes(mut self, limit: usize) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// Set the locale (LANG/LC_ALL) for executed commands
+    pub fn with_locale(mut self, locale: impl Into<String>) -> Self {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    /// Execute a command through the local shell
+    ///
+    /// The command runs under `sh -c` (`cmd /C` on Windows). The child process
+    /// is killed if the timeout elapses or the byte limit is exceeded.
+    pub async fn exec_command(
+        &self,
+        command: &str,
+        timeout_duration: Duration,
+    ) -> Result<CommandOutput> {
+        self.run(command, None, timeout_duration).await
+    }
+
+    /// Execute a command with `stdin` written to its standard input
+    ///
+    /// The input is written, then closed, while the output is read.
+    pub async fn exec_command_with_stdin(
+        &self,
+        command: &str,
+        stdin: &[u8],
+        timeout_duration: Duration,
+    ) -> Result<CommandOutput> {
+        self.run(command, Some(stdin), timeout_duration).await
+    }
+
+    async fn run(
+        &self,
+        command: &str,
+        stdin: Option<&[u8]>,
+        timeout_duration: Duration,
+    ) -> Result<CommandOutput> {
+        let mut cmd = shell_command(command);
+        if let Some(ref locale) = self.locale {
+            cmd.env("LANG", locale).env("LC_ALL", locale);
+        }
+
+        let mut child = cmd
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| SshMcpError::connection("Failed to capture stdout"))?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| SshMcpError::connection("Failed to capture stderr"))?;
+
+        // The pipe is closed once the task has written everything and drops it
+        if let (Some(data), Some(mut input)) = (stdin, child.stdin.take()) {
+            let data = data.to_vec();
+            tokio::spawn(async move {
+                if let Err(e) = input.write_all(&data).await {
+                    debug!("Failed to write stdin: {}", e);
+                }
+            });
+        }
+
+        let limit = self.max_output_bytes;
+        let collect = async {
+            let mut output = CommandOutput::new();
+            let mut bytes_read = 0;
+            let mut stdout_buf = [0u8; 8192];
+            let mut stderr_buf = [0u8; 8192];
+            let mut stdout_done = false;
+            let mut stderr_done = false;
+
+            while !(stdout_done && stderr_done) {
+                let exceeded = tokio::select! {
+                    n = stdout.read(&mut stdout_buf), if !stdout_done => match n? {
+                        0 => {
+                            stdout_done = true;
+                            false
+                        }
+                        n => push_streamed(&mut output.stdout, &stdout_buf[..n], &mut bytes_read, limit),
+                    },
+                    n = stderr.read(&mut stderr_buf), if !stderr_done => match n? {
+                        0 => {
+                            stderr_done = true;
+                            false
+                        }
+                        n => push_streamed(&mut output.stderr, &stderr_buf[..n], &mut bytes_read, limit),
+                    },
+                };
+
+                if exceeded {
+                    warn!(
+                        "Command output exceeded {} bytes, killing process",
+                        bytes_read
+                    );
+                    output.byte_limit_exceeded = true;
+                    let _ = child.start_kill();
+                    break;
+                }
+            }
+
+            let status = child.wait().await?;
+            output.exit_code = status.code().map(|code| code as u32);
+
+            debug!(
+                "Local command completed: exit_code={:?}, stdout_len={}, stderr_len={}",
+                output.exit_code,
+                output.stdout.len(),
+                output.stderr.len()
+            );
+
+            Ok(output)
+        };
+
+        match timeout(timeout_duration, collect).await {
+            Ok(result) => result,
+            // Dropping the future drops the child, which kills it
+            Err(_) => {
+                warn!(
+                    "Local command timed out after {}ms, killing process",
+                    timeout_duration.as_millis()
+                );
+                Err(SshMcpError::Timeout(timeout_duration.as_millis() as u64))
+            }
+        }
+    }
+}
+
+/// Build the platform shell invocation for a command string
+fn shell_command(command: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    }
+    #[cfg(not(windows))]
+    {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_exec_captures_output() {
+        let executor = LocalExecutor::new();
+        let output = executor
+            .exec_command("echo out; echo err >&2; exit 3", Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert_eq!(output.exit_code, Some(3));
+        assert!(!output.byte_limit_exceeded);
+    }
+
+    #[tokio::test]
+    async fn test_local_exec_timeout() {
+        let executor = LocalExecutor::new();
+        let result = executor
+            .exec_command("sleep 5", Duration::from_millis(100))
+            .await;
+
+        assert!(matches!(result, Err(SshMcpError::Timeout(100))));
+    }
+
+    #[tokio::test]
+    async fn test_local_exec_byte_limit() {
+        let executor = LocalExecutor::new().with_max_output_bytes(1024);
+        let output = executor
+            .exec_command("yes", Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        assert!(output.byte_limit_exceeded);
+        assert_eq!(output.stdout.len(), 1024);
+    }
+
+    #[tokio::test]
+    async fn test_local_exec_with_stdin() {
+        let executor = LocalExecutor::new();
+        let input = "b\na\nc\n".repeat(50_000);
+        let output = executor
+            .exec_command_with_stdin("sort | uniq -c", input.as_bytes(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(
+            output.stdout.split_whitespace().collect::<Vec<_>>(),
+            vec!["50000", "a", "50000", "b", "50000", "c"]
+        );
+    }
+}
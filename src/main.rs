@@ -4,35 +4,59 @@
 //! It parses CLI arguments, validates configuration, starts the MCP server
 //! on stdio transport, and handles graceful shutdown.
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use clap::Parser;
 use rmcp::service::ServiceExt;
-use tracing::{error, info, Level};
+use tokio::net::TcpListener;
+use tracing::{error, info, info_span, warn, Instrument};
 use tracing_subscriber::FmtSubscriber;
 
 use ssh_mcp::config::{Args, Config};
 use ssh_mcp::error::Result;
+use ssh_mcp::health::{self, HealthState};
 use ssh_mcp::server::SshMcpServer;
+use ssh_mcp::ssh::HostKeyPolicy;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Parse CLI arguments
+    let args = Args::parse();
+
     // Initialize tracing/logging to stderr (stdout is for MCP JSON-RPC)
     FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
+        .with_max_level(args.log_level)
         .with_target(false)
         .with_writer(std::io::stderr)
         .init();
 
-    // Parse CLI arguments
-    let args = Args::parse();
-
     // Validate and create config
     let config = Config::from_args(args)?;
 
+    // Every log line, including those of spawned tasks, carries the label
+    let span = info_span!("ssh_mcp", label = %config.label());
+    run(config).instrument(span).await
+}
+
+/// Start the server and serve MCP on stdio until the transport closes
+async fn run(config: Config) -> Result<()> {
+    if config.probe_auth {
+        return probe_auth(config).await;
+    }
+
     info!("SSH MCP Server v{} starting...", env!("CARGO_PKG_VERSION"));
-    info!(
-        "Connecting to {}@{}:{}",
-        config.user, config.host, config.port
-    );
+    if config.local {
+        info!("Local mode: commands run on this machine, SSH is not used");
+    } else {
+        info!(
+            "Connecting to {}@{}:{}",
+            config.user, config.host, config.port
+        );
+        if config.host_key_policy == HostKeyPolicy::AcceptAny {
+            warn!("Host key verification is disabled (--host-key-policy=insecure); any server is trusted");
+        }
+    }
     info!(
         "Timeout: {}ms, Max chars: {}",
         config.timeout_ms,
@@ -45,41 +69,62 @@ async fn main() -> Result<()> {
         info!("sudo-exec tool is disabled");
     }
 
+    let health_addr = config.health_addr;
+    let health_probe_interval = config.health_probe_interval;
+
     // Create MCP server
     let server = SshMcpServer::new(config).await?;
+    server.run_welcome_command().await;
+
+    // Start the health endpoint, if configured
+    if let Some(addr) = health_addr {
+        let listener = TcpListener::bind(addr).await?;
+        let state = Arc::new(HealthState::new(server.try_connection().cloned()));
+        if let Some(secs) = health_probe_interval {
+            state
+                .clone()
+                .spawn_probe(server.executor(), Duration::from_secs(secs));
+        }
+        tokio::spawn(health::serve(listener, state).in_current_span());
+        info!("Health endpoint listening on http://{}", addr);
+    }
 
     info!("SSH MCP Server running on stdio");
 
-    // Create a clone for the shutdown handler
+    // Create clones for the shutdown handler and the cleanup on normal exit
     let server_for_shutdown = server.clone();
+    let server_for_cleanup = server.clone();
 
     // Spawn a task to handle shutdown signals
-    let shutdown_handle = tokio::spawn(async move {
-        // Wait for Ctrl+C or SIGTERM
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
-                info!("Received SIGINT (Ctrl+C), shutting down...");
-            }
-            _ = async {
-                #[cfg(unix)]
-                {
-                    let mut sigterm = tokio::signal::unix::signal(
-                        tokio::signal::unix::SignalKind::terminate()
-                    ).expect("Failed to register SIGTERM handler");
-                    sigterm.recv().await;
+    let shutdown_handle = tokio::spawn(
+        async move {
+            // Wait for Ctrl+C or SIGTERM
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Received SIGINT (Ctrl+C), shutting down...");
                 }
-                #[cfg(not(unix))]
-                {
-                    std::future::pending::<()>().await;
+                _ = async {
+                    #[cfg(unix)]
+                    {
+                        let mut sigterm = tokio::signal::unix::signal(
+                            tokio::signal::unix::SignalKind::terminate()
+                        ).expect("Failed to register SIGTERM handler");
+                        sigterm.recv().await;
+                    }
+                    #[cfg(not(unix))]
+                    {
+                        std::future::pending::<()>().await;
+                    }
+                } => {
+                    info!("Received SIGTERM, shutting down...");
                 }
-            } => {
-                info!("Received SIGTERM, shutting down...");
             }
-        }
 
-        // Cleanup
-        server_for_shutdown.shutdown().await;
-    });
+            // Cleanup
+            server_for_shutdown.shutdown().await;
+        }
+        .in_current_span(),
+    );
 
     // Start the MCP server on stdio transport
     // Note: rmcp's stdio() returns a transport that connects stdin/stdout for JSON-RPC
@@ -97,10 +142,44 @@ async fn main() -> Result<()> {
         }
     }
 
-    // Cancel the shutdown handler if we exit normally
+    // Cancel the shutdown handler if we exit normally, and clean up here
     shutdown_handle.abort();
+    server_for_cleanup.shutdown().await;
 
     info!("SSH MCP Server stopped");
 
     Ok(())
 }
+
+/// Print the authentication methods the server offers (`--probe-auth`)
+///
+/// No MCP transport runs in this mode, so the report goes to stdout.
+async fn probe_auth(config: Config) -> Result<()> {
+    let target = format!("{}@{}:{}", config.user, config.host, config.port);
+    let (has_password, has_key) = (config.password.is_some(), config.key.is_some());
+
+    let server = SshMcpServer::new(config).await?;
+    let Some(connection) = server.try_connection() else {
+        return Err(ssh_mcp::SshMcpError::config(
+            "--probe-auth needs an SSH connection",
+        ));
+    };
+    let probe = connection.probe_auth().await?;
+
+    if probe.none_accepted {
+        println!("{} accepts the user without any credentials", target);
+    } else if probe.methods.is_empty() {
+        println!("{} offers no authentication methods", target);
+    } else {
+        println!(
+            "{} offers authentication methods: {}",
+            target,
+            probe.methods.join(", ")
+        );
+    }
+    if let Some(hint) = probe.hint(has_password, has_key) {
+        println!("Hint: {}", hint);
+    }
+
+    Ok(())
+}
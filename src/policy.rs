@@ -0,0 +1,415 @@
+//! Command policy
+//!
+//! Allow/deny rules loaded from `--policy-file` and checked before every
+//! `exec`/`sudo-exec` command. The file holds one rule per line:
+//!
+//! ```text
+//! # Inspect services, but never change their state
+//! allow systemctl status *
+//! deny  systemctl stop *
+//! allow ls *
+//! default deny
+//! ```
+//!
+//! Rules are matched against the command split into words the way a shell
+//! would (quotes and extra whitespace don't matter). Each pattern word
+//! matches one command word and may contain `*`, `?` and `[...]` wildcards;
+//! a pattern word that is just `*` at the end matches any remaining words,
+//! including none. Compound commands (`;`, `&&`, `||`, `|`, `&`, subshells
+//! and command substitutions) are split up and every part must be allowed.
+//!
+//! The first matching rule wins. Commands no rule matches get the `default`
+//! action, which is `deny` unless the file says otherwise. An allow-list
+//! with `default deny` is the robust setup: with `default allow`, a denied
+//! program can still be reached indirectly, e.g. through `sh -c` or `env`.
+
+use std::fmt;
+
+use crate::error::{Result, SshMcpError};
+use crate::tools::paths::wildcard_match;
+
+/// What a rule does with the commands it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Let the command run
+    Allow,
+    /// Reject the command
+    Deny,
+}
+
+impl fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyAction::Allow => write!(f, "allow"),
+            PolicyAction::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// A single line of the policy file
+#[derive(Debug, Clone)]
+pub struct PolicyRule {
+    /// Action taken when the rule matches
+    pub action: PolicyAction,
+
+    /// Pattern words matched against the command words
+    pub pattern: Vec<String>,
+
+    /// Line number in the policy file (for error messages)
+    pub line: usize,
+}
+
+impl PolicyRule {
+    /// Check whether the rule matches a tokenized simple command
+    fn matches(&self, argv: &[String]) -> bool {
+        let mut words = argv.iter();
+        for (i, pattern) in self.pattern.iter().enumerate() {
+            // A trailing `*` swallows the rest of the command
+            if pattern == "*" && i == self.pattern.len() - 1 {
+                return true;
+            }
+            match words.next() {
+                Some(word) if wildcard_match(pattern, word) => {}
+                _ => return false,
+            }
+        }
+        words.next().is_none()
+    }
+}
+
+impl fmt::Display for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {} {}",
+            self.line,
+            self.action,
+            self.pattern.join(" ")
+        )
+    }
+}
+
+/// Parsed policy file
+#[derive(Debug, Clone)]
+pub struct Policy {
+    rules: Vec<PolicyRule>,
+    default: PolicyAction,
+}
+
+impl Policy {
+    /// Parse policy file contents
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        let mut default = PolicyAction::Deny;
+
+        for (index, raw) in contents.lines().enumerate() {
+            let line = index + 1;
+            let text = raw.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let (keyword, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+            let rest = rest.trim();
+            match keyword {
+                "allow" | "deny" => {
+                    let pattern = tokenize(rest)
+                        .map_err(|e| policy_error(line, &e))?
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>();
+                    if pattern.is_empty() {
+                        return Err(policy_error(line, "rule has no command pattern"));
+                    }
+                    let action = if keyword == "allow" {
+                        PolicyAction::Allow
+                    } else {
+                        PolicyAction::Deny
+                    };
+                    rules.push(PolicyRule {
+                        action,
+                        pattern,
+                        line,
+                    });
+                }
+                "default" => {
+                    default = match rest {
+                        "allow" => PolicyAction::Allow,
+                        "deny" => PolicyAction::Deny,
+                        _ => {
+                            return Err(policy_error(line, "default must be 'allow' or 'deny'"));
+                        }
+                    };
+                }
+                other => {
+                    return Err(policy_error(
+                        line,
+                        &format!(
+                            "unknown keyword '{}' (expected allow, deny or default)",
+                            other
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(Self { rules, default })
+    }
+
+    /// Check a command against the policy
+    ///
+    /// Returns an error naming the rule that denied the command, or stating
+    /// that the default action applied.
+    pub fn authorize_command(&self, command: &str) -> Result<()> {
+        let commands = tokenize(command).map_err(SshMcpError::invalid_params)?;
+
+        for argv in commands {
+            let argv = command_words(argv);
+            if argv.is_empty() {
+                continue;
+            }
+
+            match self.rules.iter().find(|rule| rule.matches(&argv)) {
+                Some(rule) if rule.action == PolicyAction::Deny => {
+                    return Err(SshMcpError::PolicyDenied(format!(
+                        "'{}' matched rule ({})",
+                        argv.join(" "),
+                        rule
+                    )));
+                }
+                Some(_) => {}
+                None if self.default == PolicyAction::Deny => {
+                    return Err(SshMcpError::PolicyDenied(format!(
+                        "'{}' matched no allow rule (default deny)",
+                        argv.join(" ")
+                    )));
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn policy_error(line: usize, message: &str) -> SshMcpError {
+    SshMcpError::config(format!("Policy file line {}: {}", line, message))
+}
+
+/// Shell words that may precede the command name without being the command
+const LEADING_KEYWORDS: &[&str] = &[
+    "!", "{", "}", "if", "then", "else", "elif", "fi", "do", "done", "while", "until",
+];
+
+/// Drop leading `NAME=value` assignments and shell keywords from a simple
+/// command, leaving the program name first
+fn command_words(argv: Vec<String>) -> Vec<String> {
+    let is_prefix = |word: &String| {
+        LEADING_KEYWORDS.contains(&word.as_str())
+            || word.split_once('=').is_some_and(|(name, _)| {
+                !name.is_empty()
+                    && !name.starts_with(|c: char| c.is_ascii_digit())
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            })
+    };
+    argv.into_iter().skip_while(is_prefix).collect()
+}
+
+/// Split a command line into simple commands and their words
+///
+/// Follows POSIX shell quoting: single quotes are literal, double quotes
+/// allow `\` escapes, and a backslash outside quotes escapes the next
+/// character. Unquoted `;`, `&`, `|`, `(`, `)` and newlines end a command, as
+/// do `$(` and backticks (also inside double quotes) so that substituted
+/// commands are checked on their own.
+pub fn tokenize(command: &str) -> std::result::Result<Vec<Vec<String>>, String> {
+    let mut commands = Vec::new();
+    let mut argv: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    // Open `$(` / backtick substitutions started inside double quotes
+    let mut substitutions = 0usize;
+    let mut backticks = false;
+    let mut chars = command.chars().peekable();
+
+    // Finish the current word and, if `end` is set, the current command
+    let mut flush = |argv: &mut Vec<String>, word: &mut String, in_word: &mut bool, end: bool| {
+        if *in_word {
+            argv.push(std::mem::take(word));
+            *in_word = false;
+        }
+        if end && !argv.is_empty() {
+            commands.push(std::mem::take(argv));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some('`') => {
+                            backticks = !backticks;
+                            in_word &= !word.is_empty();
+                            flush(&mut argv, &mut word, &mut in_word, true);
+                        }
+                        Some('$') if chars.peek() == Some(&'(') => {
+                            chars.next();
+                            substitutions += 1;
+                            in_word &= !word.is_empty();
+                            flush(&mut argv, &mut word, &mut in_word, true);
+                        }
+                        Some(')') if substitutions > 0 => {
+                            substitutions -= 1;
+                            flush(&mut argv, &mut word, &mut in_word, true);
+                        }
+                        // Inside a substitution the text is a command again
+                        Some(c) if substitutions > 0 || backticks => {
+                            if c.is_whitespace() {
+                                flush(&mut argv, &mut word, &mut in_word, false);
+                            } else if matches!(c, ';' | '&' | '|') {
+                                flush(&mut argv, &mut word, &mut in_word, true);
+                            } else {
+                                in_word = true;
+                                word.push(c);
+                            }
+                        }
+                        Some(c) => {
+                            in_word = true;
+                            word.push(c);
+                        }
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    // A backslash-newline is a line continuation
+                    if c != '\n' {
+                        in_word = true;
+                        word.push(c);
+                    }
+                }
+            }
+            ';' | '&' | '|' | '(' | ')' | '`' | '\n' => {
+                flush(&mut argv, &mut word, &mut in_word, true)
+            }
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                flush(&mut argv, &mut word, &mut in_word, true);
+            }
+            c if c.is_whitespace() => flush(&mut argv, &mut word, &mut in_word, false),
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    flush(&mut argv, &mut word, &mut in_word, true);
+
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+        # services
+        allow systemctl status *
+        deny  systemctl stop *
+        allow ls *
+        allow echo *
+    ";
+
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("systemctl   stop  'nginx web'").unwrap(),
+            vec![words(&["systemctl", "stop", "nginx web"])]
+        );
+        assert_eq!(
+            tokenize("ls && rm -rf /tmp/x; echo \"a\\\"b\"").unwrap(),
+            vec![
+                words(&["ls"]),
+                words(&["rm", "-rf", "/tmp/x"]),
+                words(&["echo", "a\"b"])
+            ]
+        );
+        assert_eq!(
+            tokenize("echo \"$(systemctl stop nginx)\"").unwrap(),
+            vec![words(&["echo"]), words(&["systemctl", "stop", "nginx"])]
+        );
+        assert_eq!(
+            tokenize("echo \"`id -u; reboot`\"").unwrap(),
+            vec![words(&["echo"]), words(&["id", "-u"]), words(&["reboot"])]
+        );
+        assert!(tokenize("echo 'oops").is_err());
+    }
+
+    #[test]
+    fn test_authorize_command() {
+        let policy = Policy::parse(POLICY).unwrap();
+
+        assert!(policy.authorize_command("systemctl status nginx").is_ok());
+        assert!(policy.authorize_command("ls").is_ok());
+        assert!(policy.authorize_command("ls -la /tmp | echo done").is_ok());
+
+        let err = policy
+            .authorize_command("systemctl  stop   nginx")
+            .unwrap_err();
+        assert!(err.to_string().contains("line 4: deny systemctl stop *"));
+
+        // Unlisted commands fall through to the default deny
+        let err = policy.authorize_command("reboot").unwrap_err();
+        assert!(err.to_string().contains("default deny"));
+
+        // Every part of a compound command is checked
+        assert!(policy.authorize_command("ls; reboot").is_err());
+        assert!(policy.authorize_command("echo $(reboot)").is_err());
+        assert!(policy.authorize_command("FOO=1 systemctl stop x").is_err());
+        assert!(policy.authorize_command("{ systemctl stop x; }").is_err());
+        assert!(policy.authorize_command("echo \"(a)\"").is_ok());
+    }
+
+    #[test]
+    fn test_default_allow() {
+        let policy = Policy::parse("deny rm *\ndefault allow").unwrap();
+        assert!(policy.authorize_command("reboot").is_ok());
+        assert!(policy.authorize_command("rm -rf /").is_err());
+        // `*` at the end also matches no further words
+        assert!(policy.authorize_command("rm").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = Policy::parse("allow ls\npermit rm").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+        assert!(Policy::parse("deny").is_err());
+        assert!(Policy::parse("default maybe").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Command concurrency limit and queue
+//!
+//! Commands take a slot before they run. With `--max-concurrent` set, at most
+//! that many run at once and up to `--max-queued` more wait for a slot; any
+//! further command is rejected straight away with the current load and an
+//! estimated wait, so the caller can back off instead of piling on. The
+//! estimate comes from a moving average of recent command durations.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::error::{Result, SshMcpError};
+
+/// Default number of commands allowed to wait for a slot
+pub const DEFAULT_MAX_QUEUED: usize = 10;
+
+/// Snapshot of the queue reported by the `queue-status` tool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueueStatus {
+    /// Commands currently running
+    pub running: usize,
+
+    /// Commands waiting for a slot
+    pub queued: usize,
+
+    /// Concurrency limit (None = unlimited)
+    pub max_concurrent: Option<usize>,
+
+    /// Most commands allowed to wait (only meaningful with a limit)
+    pub max_queued: usize,
+
+    /// Expected wait for a command submitted now, once durations are known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_wait_ms: Option<u64>,
+}
+
+/// Limits how many commands run at once and how many may wait
+#[derive(Debug)]
+pub struct CommandQueue {
+    /// Slots for running commands (None = unlimited)
+    slots: Option<Semaphore>,
+    max_concurrent: Option<usize>,
+    max_queued: usize,
+    running: AtomicUsize,
+    queued: AtomicUsize,
+
+    /// Moving average of command durations in milliseconds
+    average_ms: AtomicU64,
+
+    /// Number of commands that have finished
+    completed: AtomicU64,
+}
+
+/// A running command's slot, released when dropped
+#[derive(Debug)]
+pub struct QueueSlot<'a> {
+    queue: &'a CommandQueue,
+    started: Instant,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+/// Counts a command as queued while it waits, even if the wait is abandoned
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl CommandQueue {
+    /// Create a queue running at most `max_concurrent` commands at once
+    /// (None = unlimited), with up to `max_queued` more waiting
+    pub fn new(max_concurrent: Option<usize>, max_queued: usize) -> Self {
+        Self {
+            slots: max_concurrent.map(Semaphore::new),
+            max_concurrent,
+            max_queued,
+            running: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            average_ms: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a slot for a command, waiting in the queue if all are busy
+    ///
+    /// Fails without waiting when the queue is already full.
+    pub async fn acquire(&self) -> Result<QueueSlot<'_>> {
+        let permit = match self.slots {
+            Some(ref slots) => Some(match slots.try_acquire() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    let queued = self.queued.fetch_add(1, Ordering::SeqCst);
+                    let waiting = Waiting(&self.queued);
+                    if queued >= self.max_queued {
+                        drop(waiting);
+                        return Err(self.busy_error(queued));
+                    }
+                    let permit = slots
+                        .acquire()
+                        .await
+                        .map_err(|_| SshMcpError::busy("command queue is closed"))?;
+                    drop(waiting);
+                    permit
+                }
+            }),
+            None => None,
+        };
+
+        self.running.fetch_add(1, Ordering::SeqCst);
+        Ok(QueueSlot {
+            queue: self,
+            started: Instant::now(),
+            _permit: permit,
+        })
+    }
+
+    /// Report the current load
+    pub fn status(&self) -> QueueStatus {
+        let running = self.running.load(Ordering::SeqCst);
+        let queued = self.queued.load(Ordering::SeqCst);
+        let estimated_wait_ms = match self.max_concurrent {
+            Some(max) if running >= max => self.estimate_wait_ms(queued),
+            _ => Some(0),
+        };
+
+        QueueStatus {
+            running,
+            queued,
+            max_concurrent: self.max_concurrent,
+            max_queued: self.max_queued,
+            estimated_wait_ms,
+        }
+    }
+
+    /// Estimate how long a command behind `ahead` waiting ones would wait
+    ///
+    /// Every average command duration frees `max_concurrent` slots, so the
+    /// command starts after the rounds needed to serve everyone ahead of it.
+    fn estimate_wait_ms(&self, ahead: usize) -> Option<u64> {
+        if self.completed.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let max = self.max_concurrent?.max(1) as u64;
+        let rounds = (ahead as u64 + 1).div_ceil(max);
+        Some(
+            self.average_ms
+                .load(Ordering::SeqCst)
+                .saturating_mul(rounds),
+        )
+    }
+
+    fn busy_error(&self, queued: usize) -> SshMcpError {
+        let running = self.running.load(Ordering::SeqCst);
+        let wait = match self.estimate_wait_ms(queued) {
+            Some(ms) => format!("estimated wait {}s", ms.div_ceil(1000)),
+            None => "estimated wait unknown".to_string(),
+        };
+        SshMcpError::busy(format!(
+            "{} commands running and {} queued (queue limit {}); {}. Retry later.",
+            running, queued, self.max_queued, wait
+        ))
+    }
+
+    /// Fold a finished command's duration into the moving average
+    fn record_duration(&self, ms: u64) {
+        let completed = self.completed.fetch_add(1, Ordering::SeqCst);
+        let _ = self
+            .average_ms
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |average| {
+                Some(if completed == 0 {
+                    ms
+                } else {
+                    // Weight the newest sample by 1/8
+                    (average.saturating_mul(7).saturating_add(ms)) / 8
+                })
+            });
+    }
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        let ms = u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.queue.running.fetch_sub(1, Ordering::SeqCst);
+        self.queue.record_duration(ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unlimited_queue() {
+        let queue = CommandQueue::new(None, 0);
+        let a = queue.acquire().await.unwrap();
+        let _b = queue.acquire().await.unwrap();
+        assert_eq!(queue.status().running, 2);
+        assert_eq!(queue.status().max_concurrent, None);
+
+        drop(a);
+        assert_eq!(queue.status().running, 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects() {
+        let queue = CommandQueue::new(Some(1), 0);
+        let slot = queue.acquire().await.unwrap();
+
+        let err = queue.acquire().await.unwrap_err();
+        assert!(matches!(err, SshMcpError::Busy(_)));
+        assert!(err.to_string().contains("estimated wait unknown"));
+        assert_eq!(queue.status().queued, 0);
+
+        drop(slot);
+        assert!(queue.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queued_command_waits_for_slot() {
+        let queue = std::sync::Arc::new(CommandQueue::new(Some(1), 1));
+        let slot = queue.acquire().await.unwrap();
+
+        let waiter = {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let _slot = queue.acquire().await.unwrap();
+            })
+        };
+        while queue.status().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(queue.acquire().await.is_err());
+
+        drop(slot);
+        waiter.await.unwrap();
+        let status = queue.status();
+        assert_eq!((status.running, status.queued), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_wait_leaves_queue() {
+        let queue = CommandQueue::new(Some(1), 1);
+        let _slot = queue.acquire().await.unwrap();
+
+        let wait = queue.acquire();
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(10), wait)
+                .await
+                .is_err()
+        );
+        assert_eq!(queue.status().queued, 0);
+    }
+
+    #[test]
+    fn test_estimated_wait() {
+        let queue = CommandQueue::new(Some(2), 10);
+        assert_eq!(queue.estimate_wait_ms(0), None);
+
+        queue.record_duration(1000);
+        assert_eq!(queue.estimate_wait_ms(0), Some(1000));
+        assert_eq!(queue.estimate_wait_ms(1), Some(1000));
+        assert_eq!(queue.estimate_wait_ms(2), Some(2000));
+
+        queue.record_duration(9000);
+        assert_eq!(queue.average_ms.load(Ordering::SeqCst), 2000);
+    }
+}
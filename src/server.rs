@@ -1,22 +1,129 @@
 //! MCP Server implementation
 //!
 //! This module provides the main MCP server that integrates SSH connection
-//! management with the `exec` and `sudo-exec` tools.
+//! management with the `exec`, `sudo-exec` and `session-info` tools.
 
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use rmcp::{
     handler::server::ServerHandler,
     model::*,
-    service::{RequestContext, RoleServer},
+    service::{Peer, RequestContext, RoleServer},
     ErrorData as McpError,
 };
-use tracing::{debug, error, info};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn, Instrument};
 
-use crate::config::Config;
+use crate::cancel::Operations;
+use crate::config::{Config, KeyPermCheck};
+#[cfg(unix)]
+use crate::control::{self, connect_or_listen, control_target, Role};
 use crate::error::{Result, SshMcpError};
-use crate::ssh::{sanitize_command, wrap_sudo_command, SshConfig, SshConnectionManager};
+use crate::executor::{ExecFuture, Executor};
+use crate::local::LocalExecutor;
+use crate::policy::Policy;
+use crate::queue::CommandQueue;
+use crate::ssh::env::{export_prefix, validate_env, NO_PAGER_EXPORTS};
+use crate::ssh::sanitize::{
+    validate_read_duration, validate_umask, with_read_window, READ_WINDOW_EXIT_CODE,
+};
+use crate::ssh::sftp::{
+    append_file, create_parent_dirs, download_file, expand_glob, list_dir, read_file_head,
+    read_regular_file, replace_file, resolve_allowed, set_mode, upload_file, SftpHandle,
+    DEFAULT_READ_FILE_BYTES, MAX_UPLOAD_BYTES,
+};
+use crate::ssh::{
+    has_executable_statement, is_permission_denied, redact_secret, sanitize_command_with_trim,
+    strip_sudo_lecture, wrap_sudo_command_pty, wrap_sudo_command_with_newline, CommandOutput,
+    SshConfig, SshConnectionManager, SUDO_PROMPT_SENTINEL,
+};
+use crate::stream::{with_sink, OutputSink};
+use crate::tools::diff::{unified_diff, DEFAULT_DIFF_CONTEXT, MAX_DIFF_BYTES, MAX_DIFF_KEYS};
+use crate::tools::edit::{compile_pattern, substitute, BACKUP_SUFFIX, MAX_EDIT_FILE_BYTES};
+use crate::tools::exit_codes::ExitClass;
+use crate::tools::history::CommandHistory;
+use crate::tools::output::{collapse_repeats, elide_middle, sha256_hex};
+use crate::tools::parsers::{OutputParser, ParserRegistry};
+use crate::tools::paths::{is_glob, is_path_allowed};
+use crate::tools::perms::{chown_command, format_mode, parse_mode};
+use crate::tools::ports::{parse_ports_output, NO_PORT_TOOL_MESSAGE, PORTS_COMMAND};
+use crate::tools::processes::{kill_command, normalize_signal, parse_ps_output, PS_COMMAND};
+use crate::tools::results::{
+    BoundedOutputs, ResultStore, MAX_STORED_RESULTS, MAX_STORED_RESULT_BYTES,
+};
+use crate::tools::session::{parse_locale_probe, LOCALE_PROBE_COMMAND, WHOAMI_COMMAND};
+use crate::tools::template::CommandTemplate;
+use crate::tools::wait::{
+    validate_wait, WaitCondition, DEFAULT_WAIT_INTERVAL_MS, DEFAULT_WAIT_TIMEOUT_MS,
+    MIN_WAIT_ATTEMPT_MS,
+};
+use crate::tools::{
+    AppendFileParams, BroadcastExecParams, ChmodParams, ChownParams, CommandHistoryParams,
+    DownloadParams, EditFileParams, ExecDiffParams, ExecParams, KillProcessParams, ListDirParams,
+    ListPortsParams, ListProcessesParams, ReadFileParams, RemoveParams, UploadParams,
+    WaitForParams,
+};
+
+/// Default description of the exec tool (see `--exec-description`)
+pub const DEFAULT_EXEC_DESCRIPTION: &str = "Execute a shell command on the remote SSH server and return the output. Pass either command or command_base64.";
+
+/// Default description of the sudo-exec tool (see `--sudo-description`)
+pub const DEFAULT_SUDO_DESCRIPTION: &str = "Execute a shell command on the remote SSH server using sudo. Will use sudo password if provided, otherwise assumes passwordless sudo.";
+
+/// Most characters of `--welcome-command` output added to the instructions
+const MAX_WELCOME_CHARS: usize = 2000;
+
+/// Longest `--shutdown-command` may take, so it can't hold up shutdown
+const SHUTDOWN_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time allowed beyond a read window for the command to be stopped and its
+/// output collected, when the window outlasts the command timeout
+const READ_WINDOW_GRACE: Duration = Duration::from_secs(5);
+
+/// Per-call options of the exec tool
+#[derive(Debug, Clone, Copy, Default)]
+struct ExecOptions<'a> {
+    /// Run the command on a terminal this many columns wide
+    term_width: Option<u16>,
+
+    /// Octal umask applied before the command
+    umask: Option<&'a str>,
+
+    /// Treat stderr output as failure (None = `--fail-on-stderr`)
+    fail_on_stderr: Option<bool>,
+
+    /// Collapse repeated lines (None = `--collapse-repeats`)
+    dedupe: Option<bool>,
+
+    /// Report the SHA-256 of stdout
+    include_hash: Option<bool>,
+
+    /// Run the command inside this container
+    container: Option<&'a str>,
+
+    /// Read output for this long, then stop the command
+    read_duration_ms: Option<u64>,
+
+    /// Where output is sent while the command runs
+    output: Option<&'a OutputSink>,
+
+    /// Keep stdout on the server and report its result id
+    keep_output: Option<bool>,
+
+    /// Feed the stored output with this result id to the command's stdin
+    stdin_from: Option<&'a str>,
+
+    /// Return the assembled command instead of running it
+    dry_run: Option<bool>,
+}
 
 /// SSH MCP Server
 ///
@@ -27,14 +134,52 @@ pub struct SshMcpServer {
     /// Server configuration
     config: Config,
 
-    /// SSH connection manager
-    connection: Arc<SshConnectionManager>,
+    /// Command execution backend
+    executor: Arc<dyn Executor>,
+
+    /// SSH connection manager (None in local mode or with a custom executor)
+    connection: Option<Arc<SshConnectionManager>>,
+
+    /// Recently executed commands
+    history: Arc<CommandHistory>,
+
+    /// Last output of each exec-diff key, the least recently run dropped
+    /// first
+    previous_outputs: Arc<BoundedOutputs>,
+
+    /// Recent exec output, for stdin_from
+    results: Arc<ResultStore>,
 
     /// Command execution timeout
     timeout: Duration,
 
     /// Maximum command length
     max_chars: Option<usize>,
+
+    /// Allow/deny rules commands must pass (None = everything allowed)
+    policy: Option<Arc<Policy>>,
+
+    /// Concurrency limit and queue shared by command tools
+    queue: Arc<CommandQueue>,
+
+    /// Output of the welcome command, once it has run successfully
+    welcome: Arc<OnceLock<String>>,
+
+    /// Set once shutdown has begun, so the shutdown command runs only once
+    shutting_down: Arc<AtomicBool>,
+
+    /// Held while appending or editing, so writes from concurrent calls never
+    /// interleave or overwrite each other
+    append_lock: Arc<tokio::sync::Mutex<()>>,
+
+    /// Hosts besides the primary one that broadcast-exec runs on
+    broadcast_targets: Vec<(String, Arc<dyn Executor>)>,
+
+    /// Commands and file operations in flight, for cancel-all
+    operations: Arc<Operations>,
+
+    /// Parsers turning the output of known exec commands into structured data
+    parsers: ParserRegistry,
 }
 
 impl SshMcpServer {
@@ -42,7 +187,148 @@ impl SshMcpServer {
     ///
     /// This sets up the SSH connection manager based on the provided configuration.
     /// Connection is not established until a tool is actually used.
+    /// In local mode no SSH connection manager is created.
     pub async fn new(config: Config) -> Result<Self> {
+        let policy = match config.policy_file {
+            Some(ref path) => Some(Policy::parse(&tokio::fs::read_to_string(path).await?)?),
+            None => None,
+        };
+
+        let mut server = if config.local {
+            let mut executor = LocalExecutor::new();
+            if let Some(limit) = config.max_output_bytes {
+                executor = executor.with_max_output_bytes(limit);
+            }
+            if let Some(ref locale) = config.remote_locale {
+                executor = executor.with_locale(locale);
+            }
+            Self::with_executor(config, Arc::new(executor))
+        } else {
+            Self::ssh_server(config).await?
+        };
+
+        if let Some(policy) = policy {
+            server = server.with_policy(policy);
+        }
+        Ok(server)
+    }
+
+    /// Set up the SSH connections, or join the `--control-path` master
+    async fn ssh_server(config: Config) -> Result<Self> {
+        // A master already connected to the host runs our commands
+        #[cfg(unix)]
+        let mut control_listener = None;
+        #[cfg(unix)]
+        if let Some(path) = config.control_path.clone().filter(|_| !config.probe_auth) {
+            let target = control_target(&config.user, &config.host, config.port);
+            match connect_or_listen(&path, &target).await? {
+                Role::Client(client) => return Ok(Self::with_executor(config, Arc::new(client))),
+                Role::Master(listener) => control_listener = Some((listener, target)),
+            }
+        }
+
+        let connection = Arc::new(Self::connection_manager(&config).await?);
+        // --probe-auth only looks at the offered methods
+        if config.require_elevation && !config.probe_auth {
+            Self::require_elevation(&connection).await?;
+        }
+        connection.spawn_idle_refresh();
+        #[cfg(unix)]
+        if let Some((listener, target)) = control_listener {
+            control::serve(listener, connection.clone(), target);
+        }
+
+        let mut targets = Vec::with_capacity(config.broadcast_hosts.len());
+        for (host, port) in &config.broadcast_hosts {
+            let mut host_config = config.clone();
+            host_config.host = host.clone();
+            host_config.port = *port;
+            let manager = Arc::new(Self::connection_manager(&host_config).await?);
+            manager.spawn_idle_refresh();
+            targets.push((format!("{}:{}", host, port), manager));
+        }
+
+        let mut server = Self::with_executor(config, connection.clone());
+        server.connection = Some(connection);
+        for (label, manager) in targets {
+            server = server.with_broadcast_target(label, manager);
+        }
+        Ok(server)
+    }
+
+    /// Connect and open the su root shell, failing if either doesn't work
+    /// (`--require-elevation`)
+    async fn require_elevation(connection: &SshConnectionManager) -> Result<()> {
+        connection.ensure_connected().await?;
+        connection.elevate_with_retries().await.map_err(|e| {
+            let reason = match e {
+                SshMcpError::ElevationFailed(reason) => reason,
+                other => other.to_string(),
+            };
+            SshMcpError::elevation_failed(format!(
+                "{}. Not starting, since --require-elevation is set",
+                reason
+            ))
+        })?;
+        info!("Elevated at startup (--require-elevation)");
+        Ok(())
+    }
+
+    /// Check exec and sudo-exec commands against a command policy
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Add a host for broadcast-exec to run commands on, besides the primary one
+    pub fn with_broadcast_target(
+        mut self,
+        host: impl Into<String>,
+        executor: Arc<dyn Executor>,
+    ) -> Self {
+        self.broadcast_targets.push((host.into(), executor));
+        self
+    }
+
+    /// Parse the stdout of exec commands starting with `prefix` into the
+    /// structured result, replacing any parser registered for that prefix
+    pub fn with_parser(mut self, prefix: impl Into<String>, parser: Arc<dyn OutputParser>) -> Self {
+        self.parsers.register(prefix, parser);
+        self
+    }
+
+    /// Create a server running commands through a custom executor
+    ///
+    /// Connection settings in `config` are not used to build a backend.
+    pub fn with_executor(config: Config, executor: Arc<dyn Executor>) -> Self {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let max_chars = config.max_chars;
+
+        let history = Arc::new(CommandHistory::new(config.history_size));
+        let queue = Arc::new(CommandQueue::new(config.max_concurrent, config.max_queued));
+
+        Self {
+            config,
+            executor,
+            connection: None,
+            history,
+            previous_outputs: Arc::new(BoundedOutputs::new(MAX_DIFF_KEYS, MAX_DIFF_BYTES)),
+            results: Arc::new(ResultStore::new()),
+            timeout,
+            max_chars,
+            policy: None,
+            queue,
+            welcome: Arc::new(OnceLock::new()),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            append_lock: Arc::new(tokio::sync::Mutex::new(())),
+            broadcast_targets: Vec::new(),
+            operations: Arc::new(Operations::new()),
+            parsers: ParserRegistry::with_builtins(),
+        }
+    }
+
+    /// Build the SSH connection manager from the server configuration
+    async fn connection_manager(config: &Config) -> Result<SshConnectionManager> {
         // Build SSH configuration
         let mut ssh_config = SshConfig::new(&config.host, &config.user).with_port(config.port);
 
@@ -56,6 +342,7 @@ impl SshMcpServer {
             let key_content = tokio::fs::read_to_string(key_path)
                 .await
                 .map_err(SshMcpError::Io)?;
+            check_key_permissions(key_path, config.key_perm_check)?;
             ssh_config = ssh_config.with_private_key(&key_content);
         }
 
@@ -68,40 +355,284 @@ impl SshMcpServer {
             ssh_config = ssh_config.with_sudo_password(sudo_password);
         }
 
-        // Create connection manager
-        let connection = Arc::new(SshConnectionManager::new(ssh_config).await);
+        ssh_config = ssh_config
+            .with_elevation_command(&config.elevation_command)
+            .with_pty_cols(config.pty_cols);
 
-        let timeout = Duration::from_millis(config.timeout_ms);
-        let max_chars = config.max_chars;
+        if let Some(limit) = config.max_output_bytes {
+            ssh_config = ssh_config.with_max_output_bytes(limit);
+        }
 
-        Ok(Self {
-            config,
-            connection,
-            timeout,
-            max_chars,
-        })
+        if let Some(ref locale) = config.remote_locale {
+            ssh_config = ssh_config.with_locale(locale);
+        }
+
+        ssh_config = ssh_config.with_tcp_nodelay(config.tcp_nodelay);
+        if let Some(size) = config.window_size {
+            ssh_config = ssh_config.with_window_size(size);
+        }
+        if let Some(size) = config.max_packet_size {
+            ssh_config = ssh_config.with_max_packet_size(size);
+        }
+
+        if let Some(secs) = config.max_connections_lifetime {
+            ssh_config = ssh_config.with_max_session_age(Duration::from_secs(secs));
+        }
+
+        if let Some(commands) = config.max_connection_commands {
+            ssh_config = ssh_config.with_max_session_commands(commands);
+        }
+
+        if let Some(secs) = config.idle_refresh {
+            ssh_config = ssh_config.with_idle_refresh(Duration::from_secs(secs));
+        }
+
+        if let Some(threshold) = config.circuit_threshold {
+            let cooldown = Duration::from_secs(config.circuit_cooldown);
+            ssh_config = ssh_config.with_circuit_breaker(threshold, cooldown);
+        }
+
+        ssh_config = ssh_config
+            .with_reconnect_attempts(config.reconnect_attempts)
+            .with_elevation_retries(config.elevation_retries)
+            .with_auth_order(config.auth_order.clone())
+            .with_output_drain(Duration::from_millis(config.output_drain_ms))
+            .with_reconnect_jitter(config.reconnect_jitter);
+        if let Some(ref proxy) = config.socks_proxy {
+            ssh_config = ssh_config.with_socks_proxy(proxy.clone());
+        }
+        if let Some(max) = config.max_sftp_handles {
+            ssh_config = ssh_config.with_max_sftp_handles(max);
+        }
+        ssh_config = ssh_config.with_host_key_policy(config.host_key_policy.clone());
+
+        // Create connection manager
+        Ok(SshConnectionManager::new(ssh_config).await)
     }
 
     /// Get a reference to the SSH connection manager
+    ///
+    /// # Panics
+    ///
+    /// Panics in local mode or when a custom executor is used, where there
+    /// is no SSH connection; use [`try_connection`](Self::try_connection)
+    /// when either is possible.
     pub fn connection(&self) -> &Arc<SshConnectionManager> {
-        &self.connection
+        self.try_connection()
+            .expect("no SSH connection in local mode or with a custom executor")
+    }
+
+    /// Get a reference to the SSH connection manager, if there is one
+    ///
+    /// Returns `None` in local mode or when a custom executor is used.
+    pub fn try_connection(&self) -> Option<&Arc<SshConnectionManager>> {
+        self.connection.as_ref()
+    }
+
+    /// Get the backend commands are executed through
+    pub fn executor(&self) -> Arc<dyn Executor> {
+        self.executor.clone()
     }
 
     /// Close the server and cleanup resources
+    ///
+    /// `--shutdown-command` runs after in-flight operations are cancelled
+    /// and before the connections close, on the first call only.
     pub async fn shutdown(&self) {
         info!("Shutting down SSH MCP Server...");
-        self.connection.close().await;
+        let cancelled = self.operations.cancel_all();
+        if cancelled > 0 {
+            info!("Cancelled {} in-flight operations", cancelled);
+        }
+        if !self.shutting_down.swap(true, Ordering::SeqCst) {
+            self.run_shutdown_command().await;
+        }
+        self.close_connections().await;
+    }
+
+    /// Run `--shutdown-command`, giving up after [`SHUTDOWN_COMMAND_TIMEOUT`]
+    ///
+    /// The outcome is only logged; shutdown goes ahead either way.
+    async fn run_shutdown_command(&self) {
+        let Some(ref command) = self.config.shutdown_command else {
+            return;
+        };
+
+        info!("Running shutdown command: {}", command);
+        let timeout = self.timeout.min(SHUTDOWN_COMMAND_TIMEOUT);
+        let run = async {
+            self.executor.prepare(false).await?;
+            self.executor.exec(command, timeout).await
+        };
+        match tokio::time::timeout(timeout, run).await {
+            Ok(Ok(output)) if output.success() => info!("Shutdown command finished"),
+            Ok(Ok(output)) => warn!(
+                "Shutdown command exited with {:?}: {}",
+                output.exit_code,
+                output.combined_output().trim()
+            ),
+            Ok(Err(e)) => warn!("Shutdown command failed: {}", e),
+            Err(_) => warn!(
+                "Shutdown command did not finish within {}s, closing anyway",
+                timeout.as_secs()
+            ),
+        }
+    }
+
+    /// Close the connections to every host
+    ///
+    /// The next command reconnects. Closing the session is what stops remote
+    /// processes of cancelled commands, which would otherwise keep running.
+    async fn close_connections(&self) {
+        if let Err(e) = self.executor.close().await {
+            error!("Failed to close executor: {}", e);
+        }
+        for (host, executor) in &self.broadcast_targets {
+            if let Err(e) = executor.close().await {
+                error!("Failed to close connection to {}: {}", host, e);
+            }
+        }
+    }
+
+    /// Run `--welcome-command` and keep its output for the instructions
+    ///
+    /// Meant to be called once before serving. A failing command is logged
+    /// and the instructions stay static.
+    pub async fn run_welcome_command(&self) {
+        let Some(ref command) = self.config.welcome_command else {
+            return;
+        };
+
+        let result = match self.executor.prepare(false).await {
+            Ok(()) => self.executor.exec(command, self.timeout).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(output) if output.success() => {
+                let text = output.stdout.trim();
+                let mut welcome: String = text.chars().take(MAX_WELCOME_CHARS).collect();
+                if welcome.len() < text.len() {
+                    welcome.push_str("...");
+                }
+                if !welcome.is_empty() {
+                    let _ = self.welcome.set(welcome);
+                }
+            }
+            Ok(output) => warn!(
+                "Welcome command exited with {:?}, using static instructions",
+                output.exit_code
+            ),
+            Err(e) => warn!("Welcome command failed, using static instructions: {}", e),
+        }
     }
 
     /// Execute a command (used by exec tool)
+    ///
+    /// With `env`, how each variable was delivered is reported in the
+    /// result's `_meta`. `term_width` runs the command on a terminal that
+    /// many columns wide, and `umask` sets the file mode mask for files the
+    /// command creates. `fail_on_stderr` and `dedupe` override
+    /// `--fail-on-stderr` and `--collapse-repeats`, and `container` runs the
+    /// command in a container through `--container-runtime`. With
+    /// `read_duration_ms` the command is stopped once that window ends.
+    /// With `--auto-sudo-on-denied`, a command refused for lack of privileges
+    /// is run again through sudo and the elevated result returned if it works.
+    /// `dry_run` stops once the command is assembled and returns it instead.
     async fn execute_command(
         &self,
         command: &str,
+        env: &[(String, String)],
+        options: ExecOptions<'_>,
     ) -> std::result::Result<CallToolResult, McpError> {
         debug!("exec tool called with command: {}", command);
+        let ExecOptions {
+            term_width,
+            umask,
+            fail_on_stderr,
+            dedupe,
+            include_hash,
+            container,
+            read_duration_ms,
+            output,
+            keep_output,
+            stdin_from,
+            dry_run,
+        } = options;
+
+        if term_width == Some(0) {
+            return Ok(CallToolResult::error(vec![Content::text(
+                "Error: term_width must be at least 1",
+            )]));
+        }
+
+        if let Some(Err(e)) = read_duration_ms.map(validate_read_duration) {
+            return Ok(CallToolResult::error(vec![Content::text(format!(
+                "Error: {}",
+                e
+            ))]));
+        }
+
+        let stdin = match stdin_from {
+            Some(_) if !env.is_empty() || term_width.is_some() || container.is_some() => {
+                return Ok(CallToolResult::error(vec![Content::text(
+                    "Error: stdin_from cannot be combined with env, term_width or container",
+                )]));
+            }
+            Some(id) => match self.results.get(id) {
+                Some(stdin) => Some(stdin),
+                None => {
+                    return Ok(CallToolResult::error(vec![Content::text(format!(
+                        "Error: Unknown result_id {:?}; only the last {} exec results are kept",
+                        id, MAX_STORED_RESULTS
+                    ))]));
+                }
+            },
+            None => None,
+        };
+
+        if let Some(Err(e)) = umask.map(validate_umask) {
+            return Ok(CallToolResult::error(vec![Content::text(format!(
+                "Error: {}",
+                e
+            ))]));
+        }
+
+        if let Some(container) = container {
+            let checked = if env.is_empty() {
+                self.config.container_runtime.validate_container(container)
+            } else {
+                Err(SshMcpError::invalid_params(
+                    "env cannot be combined with container; export the variables in the command instead",
+                ))
+            };
+            if let Err(e) = checked {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        }
+
+        if !env.is_empty() {
+            // Variables like PATH or LD_PRELOAD would change what an allowed
+            // command actually runs
+            let checked = if self.policy.is_some() {
+                Err(SshMcpError::invalid_params(
+                    "env cannot be used while a command policy is loaded",
+                ))
+            } else {
+                validate_env(env)
+            };
+            if let Err(e) = checked {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        }
 
         // Sanitize the command
-        let sanitized = match sanitize_command(command, self.max_chars) {
+        let sanitized = match self.sanitize(command) {
             Ok(cmd) => cmd,
             Err(e) => {
                 error!("Command sanitization failed: {}", e);
@@ -112,60 +643,256 @@ impl SshMcpServer {
             }
         };
 
-        // Ensure connection is established
-        if let Err(e) = self.connection.ensure_connected().await {
-            error!("Failed to ensure SSH connection: {}", e);
+        if let Err(e) = self.authorize_command(&sanitized) {
+            warn!("{}", e);
             return Ok(CallToolResult::error(vec![Content::text(format!(
-                "SSH connection error: {}",
+                "Error: {}",
                 e
             ))]));
         }
 
-        // If su elevation is configured and available, ensure we're elevated
-        if self.connection.get_su_password().is_some() {
-            if let Err(e) = self.connection.ensure_elevated().await {
-                debug!("Elevation failed, will run as normal user: {}", e);
+        // Interactive tools get a PTY unless the caller picked a width. A
+        // terminal would echo stdin back, so piped input never gets one.
+        let term_width = term_width.or_else(|| {
+            (stdin.is_none()
+                && self
+                    .config
+                    .pty_commands
+                    .iter()
+                    .any(|pattern| pattern.is_match(&sanitized)))
+            .then_some(self.config.pty_cols)
+        });
+
+        // The policy and history see the command as given, without the umask
+        // or container wrapper. The umask is set in a subshell, as the su
+        // shell would otherwise keep it for later commands; `)` goes on its
+        // own line so a trailing comment can't swallow it. On a PTY pagers
+        // would wait for a key press.
+        let to_run = match umask {
+            Some(umask) => format!("(umask {}; {}\n)", umask, sanitized),
+            None => sanitized.clone(),
+        };
+        let to_run = if term_width.is_some() {
+            format!("{}{}", NO_PAGER_EXPORTS, to_run)
+        } else {
+            self.without_pager(&to_run)
+        };
+        // Inside the container, so the process stopped is the command itself
+        let to_run = match read_duration_ms {
+            Some(ms) => with_read_window(&to_run, ms),
+            None => to_run,
+        };
+        let to_run = match container {
+            Some(container) => {
+                self.config
+                    .container_runtime
+                    .wrap(container, &to_run, term_width.is_some())
+            }
+            None => to_run,
+        };
+
+        let elevate = self.config.elevate_exec;
+        if dry_run.unwrap_or(false) {
+            // Variables the server refuses as env requests are exported
+            // this way, so that's the form shown
+            let sent = format!("{}{}", export_prefix(env), to_run);
+            let su_shell = elevate
+                && self.config.su_password.is_some()
+                && env.is_empty()
+                && term_width.is_none()
+                && stdin.is_none();
+            let details = serde_json::json!({
+                "env": env.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+                "pty_cols": term_width,
+                "su_shell": su_shell,
+                "stdin_from": stdin_from,
+            });
+            return Ok(self.dry_run_result(&sent, details));
+        }
+
+        // Wait for a free slot (or report that the server is saturated)
+        let _slot = match self.queue.acquire().await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("{}", e);
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
             }
+        };
+
+        // Ensure connection is established (and elevated, if configured)
+        if let Err(e) = self.executor.prepare(elevate).await {
+            error!("Failed to ensure SSH connection: {}", e);
+            return Ok(CallToolResult::error(vec![Content::text(format!(
+                "SSH connection error: {}",
+                e
+            ))]));
         }
 
+        // A read window is meant to run out, so it never trips the timeout
+        let timeout = match read_duration_ms {
+            Some(ms) => self
+                .timeout
+                .max(Duration::from_millis(ms) + READ_WINDOW_GRACE),
+            None => self.timeout,
+        };
+
         // Execute the command
-        match self.connection.exec_command(&sanitized, self.timeout).await {
-            Ok(output) => {
-                // Combine stdout and stderr for the response
-                let mut result_text = output.stdout;
-                if !output.stderr.is_empty() {
-                    if !result_text.is_empty() {
-                        result_text.push_str("\n--- stderr ---\n");
+        let run = async {
+            if let Some(ref stdin) = stdin {
+                let exec = self
+                    .executor
+                    .exec_with_stdin(&to_run, stdin.as_bytes(), timeout);
+                (self.operations.run(exec).await, None)
+            } else if elevate && env.is_empty() && term_width.is_none() {
+                let exec = self.executor.exec(&to_run, timeout);
+                (self.operations.run(exec).await, None)
+            } else {
+                let exec = if elevate {
+                    self.executor
+                        .exec_with_env(&to_run, env, term_width, timeout)
+                } else {
+                    self.executor
+                        .exec_as_user(&to_run, env, term_width, timeout)
+                };
+                match self.operations.run(exec).await {
+                    Ok((output, methods)) if !env.is_empty() => (Ok(output), Some(methods)),
+                    Ok((output, _)) => (Ok(output), None),
+                    Err(e) => (Err(e), None),
+                }
+            }
+        };
+        let (result, methods) = match output {
+            Some(sink) => with_sink(sink.clone(), run).await,
+            None => run.await,
+        };
+        self.record_history("exec", &sanitized, &result);
+
+        // Retry a permission failure the way sudo-exec would run it
+        let mut auto_elevated = false;
+        let result = match result {
+            Ok(output)
+                if self.config.auto_sudo_on_denied
+                    && !self.config.disable_sudo
+                    && env.is_empty()
+                    && stdin.is_none()
+                    && is_permission_denied(&output) =>
+            {
+                info!("Command was denied, retrying with sudo: {}", sanitized);
+                let (_, retry) = self.exec_sudo(&to_run, self.timeout).await;
+                self.record_history("sudo-exec", &sanitized, &retry);
+                match retry {
+                    Ok(retry) if retry.success() => {
+                        auto_elevated = true;
+                        Ok(retry)
                     }
-                    result_text.push_str(&output.stderr);
+                    _ => Ok(output),
                 }
+            }
+            result => result,
+        };
 
-                // Check for error exit code
-                if output.exit_code.map(|code| code != 0).unwrap_or(false) {
-                    Ok(CallToolResult::error(vec![Content::text(result_text)]))
-                } else {
-                    Ok(CallToolResult::success(vec![Content::text(result_text)]))
+        let fail_on_stderr = fail_on_stderr.unwrap_or(self.config.fail_on_stderr);
+        let mut structured = serde_json::Map::new();
+        let mut stopped = false;
+        let mut result = match result {
+            Ok(mut output) => {
+                // Stopped at the end of its read window, as asked
+                if read_duration_ms.is_some() && output.exit_code == Some(READ_WINDOW_EXIT_CODE) {
+                    output.exit_code = None;
+                    stopped = true;
+                }
+                // Stored, hashed and parsed as received, before any
+                // post-processing
+                if keep_output.unwrap_or(false) {
+                    match self.results.insert(output.stdout.clone()) {
+                        Some(id) => {
+                            structured.insert("result_id".to_string(), id.into());
+                        }
+                        None => {
+                            structured.insert(
+                                "result_note".to_string(),
+                                format!(
+                                    "stdout not kept: larger than {} bytes",
+                                    MAX_STORED_RESULT_BYTES
+                                )
+                                .into(),
+                            );
+                        }
+                    }
+                }
+                if include_hash.unwrap_or(false) {
+                    structured.insert(
+                        "stdout_sha256".to_string(),
+                        sha256_hex(output.stdout.as_bytes()).into(),
+                    );
                 }
+                if output.exit_code == Some(0) {
+                    if let Some((prefix, parser)) = self.parsers.find(&sanitized) {
+                        if let Some(parsed) = parser.parse(&output.stdout) {
+                            structured.insert("parser".to_string(), prefix.into());
+                            structured.insert("parsed".to_string(), parsed);
+                        }
+                    }
+                }
+                if dedupe.unwrap_or(self.config.collapse_repeats) {
+                    output.stdout = collapse_repeats(&output.stdout);
+                    output.stderr = collapse_repeats(&output.stderr);
+                }
+                self.command_result(output, &sanitized, fail_on_stderr)
             }
             Err(e) => {
                 error!("Command execution failed: {}", e);
-                Ok(CallToolResult::error(vec![Content::text(format!(
-                    "Error: {}",
-                    e
-                ))]))
+                CallToolResult::error(vec![Content::text(format!("Error: {}", e))])
+            }
+        };
+        if let Some(methods) = methods {
+            let methods: serde_json::Map<String, serde_json::Value> = methods
+                .into_iter()
+                .map(|(name, method)| (name, serde_json::json!(method)))
+                .collect();
+            let mut meta = result.meta.take().unwrap_or_default();
+            meta.0.insert("env".to_string(), methods.into());
+            result.meta = Some(meta);
+        }
+        if let Some(ms) = read_duration_ms.filter(|_| stopped) {
+            result.content.push(Content::text(format!(
+                "[read window of {}ms ended, command was stopped]",
+                ms
+            )));
+        }
+        if auto_elevated {
+            structured.insert("auto_elevated".to_string(), true.into());
+        }
+        if !structured.is_empty() {
+            // Keep the exit_class set by command_result
+            if let Some(serde_json::Value::Object(classified)) = result.structured_content.take() {
+                structured.extend(classified);
             }
+            result.structured_content = Some(structured.into());
+        }
+        if let Some(warning) = self
+            .unprivileged_warning()
+            .filter(|_| elevate && !auto_elevated && stdin.is_none())
+        {
+            result.content.push(Content::text(warning));
         }
+        Ok(self.echo_command(result, &sanitized))
     }
 
-    /// Execute a command with sudo (used by sudo-exec tool)
-    async fn execute_sudo_command(
+    /// Run a command on every configured host at once (used by broadcast-exec tool)
+    ///
+    /// Each host takes its own queue slot, and a host that fails to connect or
+    /// run the command is reported in its entry without affecting the others.
+    async fn execute_broadcast(
         &self,
         command: &str,
     ) -> std::result::Result<CallToolResult, McpError> {
-        debug!("sudo-exec tool called with command: {}", command);
+        debug!("broadcast-exec tool called with command: {}", command);
 
-        // Sanitize the command
-        let sanitized = match sanitize_command(command, self.max_chars) {
+        let sanitized = match self.sanitize(command) {
             Ok(cmd) => cmd,
             Err(e) => {
                 error!("Command sanitization failed: {}", e);
@@ -176,195 +903,4354 @@ impl SshMcpServer {
             }
         };
 
-        // Ensure connection is established
-        if let Err(e) = self.connection.ensure_connected().await {
-            error!("Failed to ensure SSH connection: {}", e);
+        if let Err(e) = self.authorize_command(&sanitized) {
+            warn!("{}", e);
             return Ok(CallToolResult::error(vec![Content::text(format!(
-                "SSH connection error: {}",
+                "Error: {}",
                 e
             ))]));
         }
 
-        // Wrap the command with sudo
-        let sudo_password = self.connection.get_sudo_password();
-        let wrapped_command = wrap_sudo_command(&sanitized, sudo_password);
-        debug!(
-            "Wrapped sudo command (password hidden): sudo -n sh -c '...' or printf '...' | sudo ..."
-        );
+        let primary = if self.config.local {
+            "local".to_string()
+        } else {
+            format!("{}:{}", self.config.host, self.config.port)
+        };
+        let targets = std::iter::once((primary, self.executor.clone()))
+            .chain(self.broadcast_targets.iter().cloned());
 
-        // Execute the wrapped command
-        match self
-            .connection
-            .exec_command(&wrapped_command, self.timeout)
-            .await
-        {
-            Ok(output) => {
-                // Combine stdout and stderr for the response
-                let mut result_text = output.stdout;
-                if !output.stderr.is_empty() {
-                    if !result_text.is_empty() {
-                        result_text.push_str("\n--- stderr ---\n");
-                    }
-                    result_text.push_str(&output.stderr);
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, (host, executor)) in targets.enumerate() {
+            let queue = self.queue.clone();
+            let operations = self.operations.clone();
+            let command = self.without_pager(&sanitized);
+            let timeout = self.timeout;
+            let elevate = self.config.elevate_exec;
+            tasks.spawn(
+                async move {
+                    let result = match queue.acquire().await {
+                        Ok(_slot) => match executor.prepare(elevate).await {
+                            Ok(()) => {
+                                let exec =
+                                    exec_as_configured(&*executor, &command, elevate, timeout);
+                                operations.run(exec).await
+                            }
+                            Err(e) => Err(e),
+                        },
+                        Err(e) => Err(e),
+                    };
+                    (index, host, result)
                 }
+                .in_current_span(),
+            );
+        }
 
-                // Check for error exit code
-                if output.exit_code.map(|code| code != 0).unwrap_or(false) {
-                    Ok(CallToolResult::error(vec![Content::text(result_text)]))
-                } else {
-                    Ok(CallToolResult::success(vec![Content::text(result_text)]))
-                }
-            }
-            Err(e) => {
-                error!("Sudo command execution failed: {}", e);
-                Ok(CallToolResult::error(vec![Content::text(format!(
-                    "Error: {}",
-                    e
-                ))]))
+        let mut results = Vec::with_capacity(tasks.len());
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok(result) => results.push(result),
+                Err(e) => error!("Broadcast task failed: {}", e),
             }
         }
-    }
+        results.sort_by_key(|(index, _, _)| *index);
 
-    /// Build exec tool definition
-    fn exec_tool() -> Tool {
-        let schema = serde_json::json!({
-            "type": "object",
-            "properties": {
-                "command": {
-                    "type": "string",
-                    "description": "Shell command to execute on the remote SSH server"
+        let mut failed = false;
+        let entries: Vec<serde_json::Value> = results
+            .into_iter()
+            .map(|(_, host, result)| {
+                self.record_history(&format!("broadcast-exec {}", host), &sanitized, &result);
+                match result {
+                    Ok(output) => {
+                        failed |= output.exit_code != Some(0);
+                        serde_json::json!({
+                            "host": host,
+                            "stdout": output.stdout,
+                            "stderr": output.stderr,
+                            "exit_code": output.exit_code,
+                        })
+                    }
+                    Err(e) => {
+                        failed = true;
+                        warn!("broadcast-exec on {} failed: {}", host, e);
+                        serde_json::json!({ "host": host, "error": e.to_string() })
+                    }
                 }
-            },
-            "required": ["command"]
-        });
+            })
+            .collect();
 
-        // Convert Value to JsonObject (Map<String, Value>)
-        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+        let result = serde_json::json!({ "results": entries });
+        Ok(if failed {
+            CallToolResult::structured_error(result)
+        } else {
+            CallToolResult::structured(result)
+        })
+    }
+
+    /// Run a command and diff its output against the last run under `key` (used by exec-diff tool)
+    async fn execute_diff(
+        &self,
+        params: ExecDiffParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("exec-diff tool called with key: {}", params.key);
+
+        let sanitized = match self.sanitize(&params.command) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                error!("Command sanitization failed: {}", e);
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+
+        if let Err(e) = self.authorize_command(&sanitized) {
+            warn!("{}", e);
+            return Ok(CallToolResult::error(vec![Content::text(format!(
+                "Error: {}",
+                e
+            ))]));
+        }
+
+        // Wait for a free slot (or report that the server is saturated)
+        let _slot = match self.queue.acquire().await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("{}", e);
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+
+        let elevate = self.config.elevate_exec;
+        if let Err(e) = self.executor.prepare(elevate).await {
+            error!("Failed to ensure SSH connection: {}", e);
+            return Ok(CallToolResult::error(vec![Content::text(format!(
+                "SSH connection error: {}",
+                e
+            ))]));
+        }
+
+        let to_run = self.without_pager(&sanitized);
+        let exec = exec_as_configured(&*self.executor, &to_run, elevate, self.timeout);
+        let result = self.operations.run(exec).await;
+        self.record_history("exec-diff", &sanitized, &result);
+
+        // A command that could not run has no output to compare, so the
+        // stored output is left untouched
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Command execution failed: {}", e);
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+
+        let exit_code = output.exit_code;
+        let current = self.output_text(output);
+        let previous = self.previous_outputs.insert(&params.key, current.clone());
+
+        let mut result = serde_json::json!({
+            "key": params.key,
+            "exit_code": exit_code,
+        });
+        if let Some(warning) = self.unprivileged_warning() {
+            result["warning"] = warning.into();
+        }
+        match previous {
+            Some(previous) => {
+                let diff = unified_diff(
+                    &previous,
+                    &current,
+                    &format!("{} (previous)", params.key),
+                    &format!("{} (current)", params.key),
+                    params.context.unwrap_or(DEFAULT_DIFF_CONTEXT),
+                );
+                result["changed"] = (!diff.is_empty()).into();
+                result["diff"] = diff.into();
+            }
+            None => {
+                result["changed"] = false.into();
+                result["first_run"] = true.into();
+                result["output"] = current.into();
+            }
+        }
+
+        Ok(CallToolResult::structured(result))
+    }
+
+    /// Warning for a command that ran unprivileged because su elevation is
+    /// failing, so the caller does not mistake its output for root's
+    fn unprivileged_warning(&self) -> Option<String> {
+        self.executor.elevation_warning().map(|error| {
+            format!(
+                "Warning: su elevation failed ({}); the command ran as {} without root privileges",
+                error, self.config.user
+            )
+        })
+    }
+
+    /// Sanitize a tool command, rejecting comment-only commands if configured
+    fn sanitize(&self, command: &str) -> Result<String> {
+        let sanitized =
+            sanitize_command_with_trim(command, self.max_chars, self.config.trim_command).map_err(
+                |e| match e {
+                    // Many users don't know a limit applies unless they set one
+                    SshMcpError::InvalidParams(msg)
+                        if self.config.max_chars_defaulted
+                            && msg.starts_with("Command is too long") =>
+                    {
+                        SshMcpError::invalid_params(format!(
+                            "{}. This is the default limit; restart the server with \
+                             --maxChars <n> to raise it or --maxChars none to disable it",
+                            msg
+                        ))
+                    }
+                    e => e,
+                },
+            )?;
+        if self.config.reject_empty_effect && !has_executable_statement(&sanitized) {
+            return Err(SshMcpError::invalid_params(
+                "command contains no executable statement",
+            ));
+        }
+        Ok(sanitized)
+    }
+
+    /// Check a sanitized command against the policy file, if one is loaded
+    fn authorize_command(&self, command: &str) -> Result<()> {
+        match self.policy {
+            Some(ref policy) => policy.authorize_command(command),
+            None => Ok(()),
+        }
+    }
+
+    /// Execute a command with sudo (used by sudo-exec tool)
+    ///
+    /// With `dry_run` the sudo-wrapped command is returned instead.
+    async fn execute_sudo_command(
+        &self,
+        command: &str,
+        dry_run: bool,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("sudo-exec tool called with command: {}", command);
+
+        // Sanitize the command
+        let sanitized = match self.sanitize(command) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                error!("Command sanitization failed: {}", e);
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+
+        if let Err(e) = self.authorize_command(&sanitized) {
+            warn!("{}", e);
+            return Ok(CallToolResult::error(vec![Content::text(format!(
+                "Error: {}",
+                e
+            ))]));
+        }
+
+        if dry_run {
+            let sent = self.wrap_sudo(&self.without_pager(&sanitized));
+            let details = serde_json::json!({
+                "sudo_pty": self.config.sudo_pty && self.config.sudo_password.is_some(),
+            });
+            return Ok(self.dry_run_result(&sent, details));
+        }
+
+        // Wait for a free slot (or report that the server is saturated)
+        let _slot = match self.queue.acquire().await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("{}", e);
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+
+        // Ensure connection is established
+        if let Err(e) = self.executor.prepare(false).await {
+            error!("Failed to ensure SSH connection: {}", e);
+            return Ok(CallToolResult::error(vec![Content::text(format!(
+                "SSH connection error: {}",
+                e
+            ))]));
+        }
+
+        let (wrapped_command, result) = self
+            .exec_sudo(&self.without_pager(&sanitized), self.timeout)
+            .await;
+        self.record_history("sudo-exec", &sanitized, &result);
+
+        let result = match result {
+            Ok(output) => self.command_result(output, &sanitized, self.config.fail_on_stderr),
+            Err(e) => {
+                error!("Sudo command execution failed: {}", e);
+                CallToolResult::error(vec![Content::text(format!("Error: {}", e))])
+            }
+        };
+        Ok(self.echo_command(result, &wrapped_command))
+    }
+
+    /// `command` with pagers turned off when `--disable-pager` is set
+    fn without_pager(&self, command: &str) -> String {
+        if self.config.disable_pager {
+            format!("{}{}", NO_PAGER_EXPORTS, command)
+        } else {
+            command.to_string()
+        }
+    }
+
+    /// A sanitized command wrapped with sudo the way `exec_sudo` sends it
+    ///
+    /// Without `--sudo-pty` the password, if any, is part of the result.
+    fn wrap_sudo(&self, sanitized: &str) -> String {
+        match self.config.sudo_password {
+            Some(_) if self.config.sudo_pty => wrap_sudo_command_pty(sanitized),
+            ref password => wrap_sudo_command_with_newline(
+                sanitized,
+                password.as_deref(),
+                self.config.sudo_password_newline,
+            ),
+        }
+    }
+
+    /// Wrap a command with sudo and execute it
+    ///
+    /// Returns the wrapped command along with the result, whose stderr has
+    /// the sudo lecture removed.
+    async fn exec_sudo(
+        &self,
+        sanitized: &str,
+        timeout: Duration,
+    ) -> (String, Result<CommandOutput>) {
+        let wrapped_command = self.wrap_sudo(sanitized);
+        let result = match self.config.sudo_password.as_deref() {
+            Some(password) if self.config.sudo_pty => {
+                // Feed the password only once sudo prints the sentinel prompt
+                debug!("Wrapped sudo command for PTY: {}", wrapped_command);
+                let exec = self.executor.exec_prompted(
+                    &wrapped_command,
+                    SUDO_PROMPT_SENTINEL,
+                    password,
+                    timeout,
+                );
+                self.operations.run(exec).await
+            }
+            _ => {
+                debug!(
+                    "Wrapped sudo command (password hidden): sudo -n sh -c '...' or printf '...' | sudo ..."
+                );
+                let exec = self.executor.exec(&wrapped_command, timeout);
+                self.operations.run(exec).await
+            }
+        };
+
+        let result = result.map(|mut output| {
+            // Drop the first-use lecture so it doesn't pollute the result
+            output.stderr = strip_sudo_lecture(&output.stderr);
+            output
+        });
+        (wrapped_command, result)
+    }
+
+    /// Open an SFTP session for a file tool, along with its connection
+    ///
+    /// Fails with the tool's error result when there is no SSH connection
+    /// or the session cannot be opened.
+    async fn open_sftp(
+        &self,
+    ) -> std::result::Result<(&Arc<SshConnectionManager>, SftpHandle), CallToolResult> {
+        let Some(ref connection) = self.connection else {
+            return Err(CallToolResult::error(vec![Content::text(
+                "Error: file tools require an SSH connection",
+            )]));
+        };
+
+        match connection.sftp().await {
+            Ok(sftp) => Ok((connection, sftp)),
+            Err(e) => {
+                error!("Failed to open SFTP session: {}", e);
+                Err(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        }
+    }
+
+    /// Run `f` on `path` with `~` expanded, over a new SFTP session
+    ///
+    /// Globs are rejected, but the path is not checked against
+    /// `--allowed-paths`; see [`Self::with_resolved_sftp_path`]. `f` runs as
+    /// a cancellable operation and the session is closed once it returns.
+    /// Any error becomes the tool's error result.
+    async fn with_sftp_path<T, F, Fut>(
+        &self,
+        tool: &str,
+        path: &str,
+        f: F,
+    ) -> std::result::Result<T, CallToolResult>
+    where
+        F: FnOnce(Arc<SftpHandle>, String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        if is_glob(path) {
+            return Err(CallToolResult::error(vec![Content::text(format!(
+                "Error: {} does not expand globs: {}",
+                tool, path
+            ))]));
+        }
+
+        let (connection, sftp) = self.open_sftp().await?;
+        let sftp = Arc::new(sftp);
+        let operation = async {
+            let path = connection.expand_home(&sftp, path).await?;
+            f(sftp.clone(), path).await
+        };
+        let result = self.operations.run(operation).await;
+        let _ = sftp.close().await;
+        result.map_err(|e| CallToolResult::error(vec![Content::text(format!("Error: {}", e))]))
+    }
+
+    /// Run `f` on the resolved `path` of a single-file tool
+    ///
+    /// Like [`Self::with_sftp_path`], but `f` only runs once the path is
+    /// resolved within `--allowed-paths`.
+    async fn with_resolved_sftp_path<T, F, Fut>(
+        &self,
+        tool: &str,
+        path: &str,
+        f: F,
+    ) -> std::result::Result<T, CallToolResult>
+    where
+        F: FnOnce(Arc<SftpHandle>, String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let allowed = &self.config.allowed_paths;
+        self.with_sftp_path(tool, path, |sftp, path| async move {
+            let resolved = resolve_allowed(&sftp, &path, allowed).await?;
+            f(sftp, resolved).await
+        })
+        .await
+    }
+
+    /// Delete remote files, expanding globs (used by remove tool)
+    async fn remove_files(&self, path: &str) -> std::result::Result<CallToolResult, McpError> {
+        debug!("remove tool called with path: {}", path);
+
+        let (connection, sftp) = match self.open_sftp().await {
+            Ok(opened) => opened,
+            Err(error) => return Ok(error),
+        };
+
+        // Check the pattern's directory before listing it, then every match
+        let allowed = &self.config.allowed_paths;
+        let matches = match connection.expand_home(&sftp, path).await {
+            Ok(path) => match resolve_allowed(&sftp, &path, allowed).await {
+                Ok(pattern) => expand_glob(&sftp, &pattern, self.config.max_glob_matches).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        let matches = match matches {
+            Ok(matches) => matches,
+            Err(e) => {
+                let _ = sftp.close().await;
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+
+        let mut results = Vec::with_capacity(matches.len());
+        let mut failed = false;
+        for file in matches {
+            let outcome = match resolve_allowed(&sftp, &file, allowed).await {
+                Ok(resolved) => {
+                    let remove = async { Ok(sftp.remove_file(resolved).await?) };
+                    self.operations.run(remove).await
+                }
+                Err(e) => Err(e),
+            };
+
+            match outcome {
+                Ok(()) => results.push(serde_json::json!({ "path": file, "removed": true })),
+                Err(e) => {
+                    failed = true;
+                    results.push(serde_json::json!({
+                        "path": file,
+                        "removed": false,
+                        "error": e.to_string(),
+                    }));
+                }
+            }
+        }
+        let _ = sftp.close().await;
+
+        let result = serde_json::json!({ "results": results });
+        Ok(if failed {
+            CallToolResult::structured_error(result)
+        } else {
+            CallToolResult::structured(result)
+        })
+    }
+
+    /// Append text to a remote file (used by append-file tool)
+    ///
+    /// Besides SFTP append mode, appends are serialized here: a large write
+    /// spans several SFTP requests, which could otherwise interleave with
+    /// another call's.
+    async fn append_to_file(
+        &self,
+        params: AppendFileParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("append-file tool called with path: {}", params.path);
+
+        if let Some(max) = self.config.max_append_bytes {
+            if params.content.len() > max {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: content is {} bytes, more than the limit of {} (see --max-append-bytes)",
+                    params.content.len(),
+                    max
+                ))]));
+            }
+        }
+
+        let content = params.content.as_bytes();
+        let result = self
+            .with_resolved_sftp_path("append-file", &params.path, |sftp, resolved| async move {
+                let _guard = self.append_lock.lock().await;
+                append_file(&sftp, &resolved, content).await
+            })
+            .await;
+
+        Ok(match result {
+            Ok(size) => CallToolResult::structured(serde_json::json!({
+                "path": params.path,
+                "appended": params.content.len(),
+                "size": size,
+            })),
+            Err(error) => error,
+        })
+    }
+
+    /// Run the `--only-command` template (used by run-command tool)
+    ///
+    /// The rendered command then runs like an `exec` call without options.
+    async fn run_command(
+        &self,
+        template: &CommandTemplate,
+        args: &serde_json::Map<String, serde_json::Value>,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        let command = match template.render(args) {
+            Ok(command) => command,
+            Err(e) => {
+                warn!("run-command rejected: {}", e);
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+        self.execute_command(&command, &[], ExecOptions::default())
+            .await
+    }
+
+    /// Apply a regex substitution to a remote file (used by edit-file tool)
+    ///
+    /// The file is read over SFTP, edited here and written back in one
+    /// piece with [`replace_file`]; it is left untouched when nothing
+    /// matches.
+    async fn edit_file(
+        &self,
+        params: EditFileParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("edit-file tool called with path: {}", params.path);
+
+        let pattern = match compile_pattern(&params.pattern) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+
+        let params = &params;
+        let pattern = &pattern;
+        let result = self
+            .with_resolved_sftp_path("edit-file", &params.path, |sftp, resolved| async move {
+                let _guard = self.append_lock.lock().await;
+                let data = read_regular_file(&sftp, &resolved, MAX_EDIT_FILE_BYTES).await?;
+                let content = String::from_utf8(data).map_err(|_| {
+                    SshMcpError::invalid_params(format!("{} is not UTF-8 text", params.path))
+                })?;
+                let (edited, count) = substitute(&content, pattern, &params.replacement);
+                // Replacing a match with itself leaves nothing to write
+                if edited == content {
+                    return Ok((count, false, None));
+                }
+                let backup = params
+                    .backup
+                    .then(|| format!("{}{}", resolved, BACKUP_SUFFIX));
+                replace_file(&sftp, &resolved, edited.as_bytes(), backup.as_deref()).await?;
+                Ok((count, true, backup))
+            })
+            .await;
+
+        Ok(match result {
+            Ok((replacements, changed, backup)) => CallToolResult::structured(serde_json::json!({
+                "path": params.path,
+                "replacements": replacements,
+                "changed": changed,
+                "backup": backup,
+            })),
+            Err(error) => error,
+        })
+    }
+
+    /// Write a local file or base64 content to a remote file (used by
+    /// upload tool)
+    ///
+    /// Parent directories are only created on request. With
+    /// `--allowed-paths` they must lie within the jail, and those created
+    /// for a path that then resolves outside of it are removed again.
+    async fn upload(&self, params: UploadParams) -> std::result::Result<CallToolResult, McpError> {
+        debug!("upload tool called with path: {}", params.remote_path);
+
+        let mode = match params.mode.as_deref().map(parse_mode).transpose() {
+            Ok(mode) => mode,
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+        let data = match params
+            .content(MAX_UPLOAD_BYTES, &self.config.allowed_local_paths)
+            .await
+        {
+            Ok(data) => data,
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+
+        // The parent directories have to exist before the path can be
+        // resolved, so it is checked here rather than by the helper
+        let params = &params;
+        let data = &data;
+        let allowed = &self.config.allowed_paths;
+        let result = self
+            .with_sftp_path("upload", &params.remote_path, |sftp, path| async move {
+                let created = if params.create_dirs {
+                    if !is_path_allowed(&path, allowed) {
+                        return Err(SshMcpError::invalid_params(format!(
+                            "Path is outside the allowed paths: {}",
+                            params.remote_path
+                        )));
+                    }
+                    create_parent_dirs(&sftp, &path).await?
+                } else {
+                    Vec::new()
+                };
+                let resolved = match resolve_allowed(&sftp, &path, allowed).await {
+                    Ok(resolved) => resolved,
+                    Err(e) => {
+                        for dir in created.iter().rev() {
+                            let _ = sftp.remove_dir(dir).await;
+                        }
+                        return Err(e);
+                    }
+                };
+                let mode = upload_file(&sftp, &resolved, data, mode).await?;
+                Ok((created, mode))
+            })
+            .await;
+
+        Ok(match result {
+            Ok((created, mode)) => CallToolResult::structured(serde_json::json!({
+                "path": params.remote_path,
+                "bytes_written": data.len(),
+                "mode": format_mode(mode),
+                "created_dirs": created,
+            })),
+            Err(error) => error,
+        })
+    }
+
+    /// Read a remote file over SFTP (used by download tool)
+    ///
+    /// The content is returned base64-encoded, or as text with
+    /// `--download-as-text` when it is valid UTF-8.
+    async fn download(
+        &self,
+        params: DownloadParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("download tool called with path: {}", params.remote_path);
+
+        let max = match (self.config.max_download_bytes, params.max_bytes) {
+            (Some(limit), Some(requested)) => Some(limit.min(requested)),
+            (limit, requested) => limit.or(requested),
+        };
+
+        let result = self
+            .with_resolved_sftp_path(
+                "download",
+                &params.remote_path,
+                |sftp, resolved| async move { download_file(&sftp, &resolved, max).await },
+            )
+            .await;
+
+        Ok(match result {
+            Ok((size, data)) => {
+                let text = if self.config.download_as_text {
+                    String::from_utf8(data).map_err(|e| e.into_bytes())
+                } else {
+                    Err(data)
+                };
+                let (encoding, content) = match text {
+                    Ok(text) => ("utf-8", text),
+                    Err(data) => ("base64", BASE64.encode(data)),
+                };
+                CallToolResult::structured(serde_json::json!({
+                    "path": params.remote_path,
+                    "size": size,
+                    "encoding": encoding,
+                    "content": content,
+                }))
+            }
+            Err(error) => error,
+        })
+    }
+
+    /// Read the start of a remote file over SFTP (used by read-file tool)
+    ///
+    /// No shell is involved, so any file name works and binary content
+    /// arrives intact. `--max-download-bytes` caps `max_bytes`.
+    async fn read_file(
+        &self,
+        params: ReadFileParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("read-file tool called with path: {}", params.path);
+
+        let requested = params.max_bytes.unwrap_or(DEFAULT_READ_FILE_BYTES);
+        let max = match self.config.max_download_bytes {
+            Some(limit) => requested.min(limit),
+            None => requested,
+        };
+
+        let result = self
+            .with_resolved_sftp_path("read-file", &params.path, |sftp, resolved| async move {
+                read_file_head(&sftp, &resolved, max).await
+            })
+            .await;
+
+        Ok(match result {
+            Ok((size, data, truncated)) => CallToolResult::structured(serde_json::json!({
+                "path": params.path,
+                "size": size,
+                "bytes": data.len(),
+                "truncated": truncated,
+                "content_base64": BASE64.encode(data),
+            })),
+            Err(error) => error,
+        })
+    }
+
+    /// List a remote directory over SFTP (used by list-dir tool)
+    ///
+    /// Returns one structured entry per file instead of `ls -la` text,
+    /// whose layout differs between hosts and locales.
+    async fn list_dir(
+        &self,
+        params: ListDirParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("list-dir tool called with path: {}", params.path);
+
+        let include_hidden = params.include_hidden;
+        let allowed = &self.config.allowed_paths;
+        let result = self
+            .with_resolved_sftp_path("list-dir", &params.path, |sftp, resolved| async move {
+                list_dir(&sftp, &resolved, include_hidden, allowed).await
+            })
+            .await;
+
+        Ok(match result {
+            Ok(entries) => CallToolResult::structured(serde_json::json!({
+                "path": params.path,
+                "entries": entries,
+            })),
+            Err(error) => error,
+        })
+    }
+
+    /// Set the mode of a remote file over SFTP (used by chmod tool)
+    async fn chmod(&self, params: ChmodParams) -> std::result::Result<CallToolResult, McpError> {
+        debug!(
+            "chmod tool called with path: {} mode: {}",
+            params.path, params.mode
+        );
+
+        let mode = match parse_mode(&params.mode) {
+            Ok(mode) => mode,
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+
+        let result = self
+            .with_resolved_sftp_path("chmod", &params.path, |sftp, resolved| async move {
+                set_mode(&sftp, &resolved, mode).await
+            })
+            .await;
+
+        Ok(match result {
+            Ok((before, after)) => CallToolResult::structured(serde_json::json!({
+                "path": params.path,
+                "previous_mode": before.permissions.map(format_mode),
+                "mode": after.permissions.map(format_mode),
+                "uid": after.uid,
+                "gid": after.gid,
+            })),
+            Err(error) => error,
+        })
+    }
+
+    /// Change the owner and group of a remote file (used by chown tool)
+    ///
+    /// The path is checked over SFTP like the other file tools, then
+    /// changed with `chown` on the remote host; see [`Self::change_owner`].
+    async fn chown(&self, params: ChownParams) -> std::result::Result<CallToolResult, McpError> {
+        debug!(
+            "chown tool called with path: {} owner: {} group: {:?}",
+            params.path, params.owner, params.group
+        );
+
+        let resolved =
+            self.with_resolved_sftp_path("chown", &params.path, |_, resolved| async move {
+                Ok(resolved)
+            })
+            .await;
+
+        Ok(match resolved {
+            Ok(resolved) => self.change_owner(&params, &resolved).await,
+            Err(error) => error,
+        })
+    }
+
+    /// Run `chown` on an already resolved path
+    ///
+    /// Giving files away needs root, so unless commands already run as root
+    /// (as the root user or in the su shell) it goes through sudo, when sudo
+    /// is enabled.
+    async fn change_owner(&self, params: &ChownParams, resolved: &str) -> CallToolResult {
+        let command = match chown_command(resolved, &params.owner, params.group.as_deref()) {
+            Ok(command) => command,
+            Err(e) => return CallToolResult::error(vec![Content::text(format!("Error: {}", e))]),
+        };
+
+        // Elevate first where su is configured, so its outcome decides on sudo
+        if let Err(e) = self.executor.prepare(self.config.elevate_exec).await {
+            return CallToolResult::error(vec![Content::text(format!(
+                "SSH connection error: {}",
+                e
+            ))]);
+        }
+        let sudo = !self.runs_as_root() && !self.config.disable_sudo;
+
+        let mut report = serde_json::json!({
+            "path": params.path,
+            "owner": params.owner,
+            "group": params.group,
+            "sudo": sudo,
+        });
+        match self.run_tool_command("chown", &command, sudo).await {
+            Ok(output) if output.success() => {
+                report["changed"] = true.into();
+                CallToolResult::structured(report)
+            }
+            Ok(output) => {
+                let denied = is_permission_denied(&output);
+                let message = self.output_text(output).trim().to_string();
+                report["changed"] = false.into();
+                report["error"] = if denied {
+                    format!(
+                        "Changing the owner of {} was denied ({}). This needs root: connect \
+                         as root, set --su-password, or allow sudo for {}",
+                        params.path, message, self.config.user
+                    )
+                } else {
+                    message
+                }
+                .into();
+                CallToolResult::structured_error(report)
+            }
+            Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {}", e))]),
+        }
+    }
+
+    /// Whether commands already run as root, without sudo
+    fn runs_as_root(&self) -> bool {
+        self.config.user == "root"
+            || (self.config.elevate_exec
+                && self
+                    .connection
+                    .as_ref()
+                    .is_some_and(|connection| connection.is_elevated()))
+    }
+
+    /// Report connection details and the detected locale (used by session-info tool)
+    async fn session_info(&self) -> std::result::Result<CallToolResult, McpError> {
+        debug!("session-info tool called");
+
+        let mut info = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "mode": if self.config.local { "local" } else { "ssh" },
+        });
+
+        if let Some(ref connection) = self.connection {
+            info["host"] = self.config.host.clone().into();
+            info["port"] = self.config.port.into();
+            info["user"] = self.config.user.clone().into();
+            info["connected"] = connection.is_connected().await.into();
+            info["elevated"] = connection.is_elevated().into();
+            if connection.is_elevation_dropped() {
+                info["elevation_dropped"] = true.into();
+            }
+            info["sftp_handles"] = connection.open_sftp_handles().into();
+            if let Some(circuit) = connection.circuit() {
+                info["circuit"] = serde_json::json!({
+                    "state": circuit.state(),
+                    "failures": circuit.failures(),
+                });
+            }
+        }
+
+        info["locale"] = match self.executor.prepare(false).await {
+            Ok(()) => match self.executor.exec(LOCALE_PROBE_COMMAND, self.timeout).await {
+                Ok(output) => parse_locale_probe(&output.stdout),
+                Err(e) => serde_json::json!({ "error": e.to_string() }),
+            },
+            Err(e) => serde_json::json!({ "error": e.to_string() }),
+        };
+        if let Some(ref locale) = self.config.remote_locale {
+            info["locale"]["configured"] = locale.clone().into();
+        }
+
+        // Read after the locale probe, which connects if needed
+        if let Some(timings) = self
+            .connection
+            .as_ref()
+            .and_then(|connection| connection.last_connect_timings())
+        {
+            info["connect_timings"] = serde_json::json!(timings);
+        }
+        if let Some(method) = self
+            .connection
+            .as_ref()
+            .and_then(|connection| connection.auth_method())
+        {
+            info["auth_method"] = method.to_string().into();
+        }
+
+        Ok(CallToolResult::structured(info))
+    }
+
+    /// List running processes (used by list-processes tool)
+    async fn list_processes(
+        &self,
+        params: ListProcessesParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("list-processes tool called with filter: {:?}", params.name);
+
+        let output = match self
+            .run_tool_command("list-processes", PS_COMMAND, false)
+            .await
+        {
+            Ok(output) if output.success() => output,
+            Ok(output) => return Ok(self.command_result(output, PS_COMMAND, false)),
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+
+        let processes = parse_ps_output(&output.stdout, params.name.as_deref());
+        Ok(CallToolResult::structured(serde_json::json!({
+            "count": processes.len(),
+            "processes": processes,
+        })))
+    }
+
+    /// List listening ports (used by list-ports tool)
+    async fn list_ports(
+        &self,
+        params: ListPortsParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("list-ports tool called");
+
+        let sudo = params.sudo.unwrap_or(false);
+        let output = match self
+            .run_tool_command("list-ports", PORTS_COMMAND, sudo)
+            .await
+        {
+            Ok(output) if output.success() => output,
+            Ok(output)
+                if output.stderr.contains(NO_PORT_TOOL_MESSAGE)
+                    || output.stdout.contains(NO_PORT_TOOL_MESSAGE) =>
+            {
+                return Ok(CallToolResult::error(vec![Content::text(
+                    "Error: neither ss nor netstat is installed on the remote host \
+                     (install iproute2 or net-tools)",
+                )]))
+            }
+            Ok(output) => return Ok(self.command_result(output, PORTS_COMMAND, false)),
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+
+        let ports = parse_ports_output(&output.stdout);
+        Ok(CallToolResult::structured(serde_json::json!({
+            "count": ports.len(),
+            "ports": ports,
+        })))
+    }
+
+    /// Send a signal to a process (used by kill-process tool)
+    async fn kill_process(
+        &self,
+        params: KillProcessParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!(
+            "kill-process tool called for pid {} with signal {:?}",
+            params.pid, params.signal
+        );
+
+        let built = normalize_signal(params.signal.as_deref().unwrap_or("TERM"))
+            .and_then(|signal| Ok((kill_command(params.pid, &signal)?, signal)));
+        let (command, signal) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+
+        let sudo = params.sudo.unwrap_or(false);
+        let result = self.run_tool_command("kill-process", &command, sudo).await;
+        Ok(match result {
+            Ok(output) => {
+                let mut report = serde_json::json!({
+                    "pid": params.pid,
+                    "signal": signal,
+                    "sent": output.success(),
+                });
+                if output.success() {
+                    CallToolResult::structured(report)
+                } else {
+                    // e.g. "kill: (4242) - No such process"
+                    report["error"] = self.output_text(output).trim().to_string().into();
+                    CallToolResult::structured_error(report)
+                }
+            }
+            Err(e) => CallToolResult::error(vec![Content::text(format!("Error: {}", e))]),
+        })
+    }
+
+    /// Re-run a command until its result meets a condition (used by wait-for tool)
+    ///
+    /// Each attempt runs like the process tools' commands, through the
+    /// policy, queue and history, with no more than the time left before the
+    /// deadline (but at least [`MIN_WAIT_ATTEMPT_MS`]). An attempt that times
+    /// out has just not met the condition; one that cannot run at all, e.g.
+    /// because the connection is down and cannot be re-established, ends the
+    /// wait rather than being retried until the timeout.
+    async fn wait_for(
+        &self,
+        params: WaitForParams,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        debug!("wait-for tool called with command: {}", params.command);
+
+        let interval_ms = params.interval_ms.unwrap_or(DEFAULT_WAIT_INTERVAL_MS);
+        let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_WAIT_TIMEOUT_MS);
+        let command = match validate_wait(interval_ms, timeout_ms)
+            .and_then(|()| self.sanitize(&params.command))
+        {
+            Ok(command) => command,
+            Err(e) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]))
+            }
+        };
+        let condition = WaitCondition::new(params.expect_exit_code, params.expect_output_contains);
+
+        let start = std::time::Instant::now();
+        let deadline = start + Duration::from_millis(timeout_ms);
+        let mut attempts = 0;
+        let outcome = loop {
+            attempts += 1;
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            let attempt_timeout = self
+                .timeout
+                .min(remaining.max(Duration::from_millis(MIN_WAIT_ATTEMPT_MS)));
+            let output = match self
+                .run_tool_command_with_timeout("wait-for", &command, false, attempt_timeout)
+                .await
+            {
+                Ok(output) if condition.is_met(&output) => break Ok((true, Some(output))),
+                Ok(output) => Some(output),
+                // A probe that hangs, e.g. against a service that is down
+                Err(SshMcpError::Timeout(_)) => None,
+                Err(e) => break Err(e),
+            };
+
+            // The last attempt runs right at the deadline
+            let now = std::time::Instant::now();
+            if now >= deadline {
+                break Ok((false, output));
+            }
+            let pause = Duration::from_millis(interval_ms).min(deadline - now);
+            let slept = self
+                .operations
+                .run(async {
+                    tokio::time::sleep(pause).await;
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = slept {
+                break Err(e);
+            }
+        };
+
+        let waited_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        info!(
+            "wait-for finished after {} attempts in {}ms",
+            attempts, waited_ms
+        );
+        let mut result = serde_json::json!({
+            "attempts": attempts,
+            "waited_ms": waited_ms,
+        });
+        match outcome {
+            Ok((met, Some(output))) => {
+                result["met"] = met.into();
+                result["exit_code"] = output.exit_code.into();
+                result["output"] = self.output_text(output).into();
+                if met {
+                    return Ok(CallToolResult::structured(result));
+                }
+            }
+            Ok((met, None)) => {
+                result["met"] = met.into();
+                result["timed_out"] = true.into();
+            }
+            Err(e) => {
+                result["met"] = false.into();
+                result["error"] = e.to_string().into();
+            }
+        }
+        Ok(CallToolResult::structured_error(result))
+    }
+
+    /// Run a command built by a tool rather than given by the caller
+    ///
+    /// It goes through the same policy check, queue and history as exec, so
+    /// these tools cannot be used to get around a command policy. With
+    /// `sudo` it is wrapped like a sudo-exec command.
+    async fn run_tool_command(
+        &self,
+        tool: &str,
+        command: &str,
+        sudo: bool,
+    ) -> Result<CommandOutput> {
+        self.run_tool_command_with_timeout(tool, command, sudo, self.timeout)
+            .await
+    }
+
+    /// Like [`Self::run_tool_command`], but with its own command timeout
+    async fn run_tool_command_with_timeout(
+        &self,
+        tool: &str,
+        command: &str,
+        sudo: bool,
+        timeout: Duration,
+    ) -> Result<CommandOutput> {
+        if sudo && self.config.disable_sudo {
+            return Err(SshMcpError::invalid_params(
+                "sudo is disabled on this server (--disable-sudo)",
+            ));
+        }
+        self.authorize_command(command)?;
+        let _slot = self.queue.acquire().await?;
+        let elevate = !sudo && self.config.elevate_exec;
+        self.executor.prepare(elevate).await?;
+
+        let to_run = self.without_pager(command);
+        let result = if sudo {
+            self.exec_sudo(&to_run, timeout).await.1
+        } else {
+            let exec = exec_as_configured(&*self.executor, &to_run, elevate, timeout);
+            self.operations.run(exec).await
+        };
+        self.record_history(tool, command, &result);
+        result
+    }
+
+    /// Report running and queued commands (used by queue-status tool)
+    fn queue_status(&self) -> CallToolResult {
+        debug!("queue-status tool called");
+
+        CallToolResult::structured(serde_json::json!(self.queue.status()))
+    }
+
+    /// Abort every in-flight operation (used by cancel-all tool)
+    ///
+    /// Connections are closed when anything was cancelled, so remote
+    /// processes stop as well instead of running on unobserved.
+    async fn cancel_all(&self) -> CallToolResult {
+        let cancelled = self.operations.cancel_all();
+        info!("cancel-all tool cancelled {} operations", cancelled);
+        if cancelled > 0 {
+            self.close_connections().await;
+        }
+
+        CallToolResult::structured(serde_json::json!({ "cancelled": cancelled }))
+    }
+
+    /// Close the su shell and confirm who commands run as now (used by
+    /// drop-elevation tool)
+    async fn drop_elevation(&self) -> std::result::Result<CallToolResult, McpError> {
+        debug!("drop-elevation tool called");
+
+        let Some(ref connection) = self.connection else {
+            return Ok(CallToolResult::error(vec![Content::text(
+                "Error: drop-elevation requires an SSH connection",
+            )]));
+        };
+
+        let was_elevated = connection.drop_elevation().await;
+        let mut result = serde_json::json!({
+            "was_elevated": was_elevated,
+            "elevated": connection.is_elevated(),
+        });
+        // Ask the remote side, so the result confirms the drop took effect
+        let user = match connection.prepare(false).await {
+            Ok(()) => connection.exec(WHOAMI_COMMAND, self.timeout).await,
+            Err(e) => Err(e),
+        };
+        match user {
+            Ok(output) if output.success() => {
+                result["user"] = output.stdout.trim().into();
+            }
+            Ok(output) => result["error"] = output.combined_output().trim().into(),
+            Err(e) => result["error"] = e.to_string().into(),
+        }
+
+        Ok(CallToolResult::structured(result))
+    }
+
+    /// Open the SSH session now instead of on the first command (used by
+    /// connect tool)
+    ///
+    /// Connection and authentication errors are returned as they are, so
+    /// the client can see why the session could not be opened.
+    async fn connect(&self) -> std::result::Result<CallToolResult, McpError> {
+        debug!("connect tool called");
+
+        let Some(ref connection) = self.connection else {
+            return Ok(CallToolResult::error(vec![Content::text(
+                "Error: connect requires an SSH connection",
+            )]));
+        };
+
+        match connection.ensure_connected().await {
+            Ok(()) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "connected to {}@{}:{}",
+                self.config.user, self.config.host, self.config.port
+            ))])),
+            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
+                "Error: {}",
+                e
+            ))])),
+        }
+    }
+
+    /// Close the SSH session (used by disconnect tool)
+    ///
+    /// The next command, or a connect call, opens a new session.
+    async fn disconnect(&self) -> std::result::Result<CallToolResult, McpError> {
+        debug!("disconnect tool called");
+
+        let Some(ref connection) = self.connection else {
+            return Ok(CallToolResult::error(vec![Content::text(
+                "Error: disconnect requires an SSH connection",
+            )]));
+        };
+
+        connection.close().await;
+        Ok(CallToolResult::success(vec![Content::text("disconnected")]))
+    }
+
+    /// Record a finished command in the history (without any wrapping)
+    fn record_history(&self, tool: &str, command: &str, result: &Result<CommandOutput>) {
+        match result {
+            Ok(output) => self.history.record(tool, command, output.exit_code, None),
+            Err(e) => self
+                .history
+                .record(tool, command, None, Some(e.to_string())),
+        }
+    }
+
+    /// List recently executed commands (used by command-history tool)
+    fn command_history(&self, limit: Option<usize>) -> CallToolResult {
+        debug!("command-history tool called");
+
+        CallToolResult::structured(serde_json::json!({
+            "capacity": self.history.capacity(),
+            "entries": self.history.recent(limit),
+        }))
+    }
+
+    /// Convert command output into a tool result
+    ///
+    /// stdout and stderr are combined into a single text block, and a non-zero
+    /// exit code marks the result as an error. With `fail_on_stderr`, so does
+    /// any output on stderr. Exit codes in `--exit-code-map` are reported as
+    /// mapped for `command`, with the class under `exit_class`.
+    fn command_result(
+        &self,
+        mut output: CommandOutput,
+        command: &str,
+        fail_on_stderr: bool,
+    ) -> CallToolResult {
+        let class = output
+            .exit_code
+            .and_then(|code| self.config.exit_code_map.classify(command, code));
+        let exit_failed = match class {
+            Some(class) => class == ExitClass::Error,
+            None => output.exit_code.is_some_and(|code| code != 0),
+        };
+        let failed = exit_failed || (fail_on_stderr && !output.stderr.is_empty());
+        let exit_code = output.exit_code;
+        if let Some(max_lines) = self.config.max_output_lines {
+            let (head, tail) = (self.config.output_head_lines, self.config.output_tail_lines);
+            for stream in [&mut output.stdout, &mut output.stderr, &mut output.extended] {
+                if let Cow::Owned(elided) = elide_middle(stream, max_lines, head, tail) {
+                    *stream = elided;
+                }
+            }
+        }
+        let mut contents = if self.config.separate_content {
+            self.output_contents(output)
+        } else {
+            vec![Content::text(self.output_text(output))]
+        };
+        if let (Some(ExitClass::Warning), Some(code)) = (class, exit_code) {
+            contents.push(Content::text(format!(
+                "[warning: exit code {} (see --exit-code-map)]",
+                code
+            )));
+        }
+
+        // Check for error exit code
+        let mut result = if failed {
+            CallToolResult::error(contents)
+        } else {
+            CallToolResult::success(contents)
+        };
+        if let Some(class) = class {
+            result.structured_content = Some(serde_json::json!({ "exit_class": class }));
+        }
+        result
+    }
+
+    /// Split the output into one content item per stream (`--separate-content`)
+    ///
+    /// Each item names its stream (`stdout`, `stderr` or `extended`) under
+    /// `stream` in its `_meta`. Empty streams are left out, but there is
+    /// always at least one item.
+    fn output_contents(&self, output: CommandOutput) -> Vec<Content> {
+        let streams = [
+            ("stdout", output.stdout),
+            ("stderr", output.stderr),
+            ("extended", output.extended),
+        ];
+        let mut contents: Vec<Content> = streams
+            .into_iter()
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(stream, text)| stream_content(stream, text))
+            .collect();
+
+        if output.byte_limit_exceeded {
+            contents.push(Content::text(self.byte_limit_notice()));
+        }
+        if contents.is_empty() {
+            contents.push(stream_content("stdout", String::new()));
+        }
+        contents
+    }
+
+    /// Note appended when a command was stopped at the byte limit
+    fn byte_limit_notice(&self) -> String {
+        format!(
+            "[output truncated: exceeded {} byte limit, command was stopped]",
+            self.config.max_output_bytes.unwrap_or_default()
+        )
+    }
+
+    /// Combine stdout and stderr into the text shown to the caller
+    fn output_text(&self, output: CommandOutput) -> String {
+        let mut result_text = output.stdout;
+        if !output.stderr.is_empty() {
+            if !result_text.is_empty() {
+                result_text.push_str("\n--- stderr ---\n");
+            }
+            result_text.push_str(&output.stderr);
+        }
+
+        if !output.extended.is_empty() {
+            if !result_text.is_empty() {
+                result_text.push('\n');
+            }
+            result_text.push_str("--- extended data ---\n");
+            result_text.push_str(&output.extended);
+        }
+
+        if output.byte_limit_exceeded {
+            result_text.push('\n');
+            result_text.push_str(&self.byte_limit_notice());
+        }
+
+        result_text
+    }
+
+    /// Attach the command sent to the executor to the result's `_meta`
+    ///
+    /// Only active with `--echo-command`. Passwords are redacted so the value
+    /// can be copied and rerun on the host without leaking secrets.
+    fn echo_command(&self, mut result: CallToolResult, sent: &str) -> CallToolResult {
+        if !self.config.echo_command {
+            return result;
+        }
+
+        let mut meta = result.meta.take().unwrap_or_default();
+        meta.0
+            .insert("command".to_string(), self.redact_secrets(sent).into());
+        result.meta = Some(meta);
+        result
+    }
+
+    /// Result of a `dry_run` call: the command that would have been sent
+    ///
+    /// `details` describes how it would have been sent, and gets the
+    /// command, with passwords redacted, added under `command`.
+    fn dry_run_result(&self, sent: &str, mut details: serde_json::Value) -> CallToolResult {
+        let command = self.redact_secrets(sent);
+        details["dry_run"] = true.into();
+        details["command"] = command.clone().into();
+        let mut result = CallToolResult::success(vec![Content::text(command)]);
+        result.structured_content = Some(details);
+        result
+    }
+
+    /// `command` with every configured password replaced by asterisks
+    fn redact_secrets(&self, command: &str) -> String {
+        let secrets = [
+            self.config.sudo_password.as_deref(),
+            self.config.su_password.as_deref(),
+            self.config.password.as_deref(),
+        ];
+        secrets
+            .into_iter()
+            .flatten()
+            .fold(command.to_string(), |command, secret| {
+                redact_secret(&command, secret)
+            })
+    }
+
+    /// Server instructions, followed by the welcome command output if any
+    ///
+    /// An explicit `--label` prefixes them, so the agent can tell instances
+    /// apart.
+    fn instructions(&self) -> String {
+        let target = if self.config.local {
+            "the local host".to_string()
+        } else {
+            format!(
+                "{}@{}:{}",
+                self.config.user, self.config.host, self.config.port
+            )
+        };
+        let mut instructions = format!(
+            "SSH MCP Server v{} - Execute commands on {}",
+            env!("CARGO_PKG_VERSION"),
+            target
+        );
+        if let Some(ref label) = self.config.label {
+            instructions = format!("[{}] {}", label, instructions);
+        }
+
+        if let Some(welcome) = self.welcome.get() {
+            instructions.push_str("\n\nHost information:\n");
+            instructions.push_str(welcome);
+        }
+        instructions
+    }
+
+    /// Protocol version advertised to clients (see `--protocol-version`)
+    fn protocol_version(&self) -> ProtocolVersion {
+        match self.config.protocol_version.as_deref() {
+            Some("2024-11-05") => ProtocolVersion::V_2024_11_05,
+            Some("2025-03-26") => ProtocolVersion::V_2025_03_26,
+            Some("2025-06-18") => ProtocolVersion::V_2025_06_18,
+            _ => ProtocolVersion::LATEST,
+        }
+    }
+
+    /// Build exec tool definition, optionally overriding its description
+    fn exec_tool(description: Option<&str>) -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "Shell command to execute on the remote SSH server"
+                },
+                "env": {
+                    "type": "object",
+                    "additionalProperties": { "type": "string" },
+                    "description": "Environment variables for the command. Sent as SSH env requests where the server's AcceptEnv allows it, otherwise exported before the command; _meta.env reports which was used."
+                },
+                "term_width": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "maximum": 65535,
+                    "description": "Run the command on a terminal this many columns wide (e.g. 200) so ps, docker ps and similar tools don't truncate columns. stderr is merged into stdout."
+                },
+                "dedupe": {
+                    "type": "boolean",
+                    "description": "Collapse runs of identical consecutive lines (e.g. from ping or progress output) into '<line> (repeated N times)'. Defaults to the server's --collapse-repeats setting."
+                },
+                "read_duration_ms": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "Read output for this many milliseconds, then stop the command and return what it printed, e.g. to watch `tail -f` or `journalctl -f` for a while. Not an error, unlike the command timeout, which is extended to cover the window."
+                },
+                "container": {
+                    "type": "string",
+                    "description": "Run the command inside this container on the remote host (docker exec, podman exec or nsenter, see --container-runtime; nsenter takes a PID). Cannot be combined with env."
+                },
+                "include_hash": {
+                    "type": "boolean",
+                    "description": "Add the SHA-256 of stdout as stdout_sha256 to the structured result, to tell cheaply whether output changed between runs (default: false)"
+                },
+                "fail_on_stderr": {
+                    "type": "boolean",
+                    "description": "Treat any stderr output as a failure even if the command exits 0. Defaults to the server's --fail-on-stderr setting."
+                },
+                "umask": {
+                    "type": "string",
+                    "pattern": "^0?[0-7]{1,3}$",
+                    "description": "Octal file mode mask for files the command creates (e.g. \"077\" so they are readable by the owner only)"
+                },
+                "command_base64": {
+                    "type": "string",
+                    "description": "The command as base64-encoded UTF-8, instead of command. Prefer this when the command contains many quotes, backslashes or other characters that are hard to escape in JSON."
+                },
+                "keep_output": {
+                    "type": "boolean",
+                    "description": "Keep stdout on the server and add its result_id to the structured result, for a later call's stdin_from. The last 16 kept outputs are available."
+                },
+                "stdin_from": {
+                    "type": "string",
+                    "description": "result_id of an earlier exec call made with keep_output; its stdout is fed to this command's standard input on the server, so large output can be piped without sending it back (e.g. run 'find / -name \"*.log\"', then 'xargs du -ch' with stdin_from). Runs as the login user; cannot be combined with env, term_width or container."
+                },
+                "dry_run": {
+                    "type": "boolean",
+                    "description": "Don't run anything; return the exact command that would be sent after sanitizing, umask, pager, container and env handling, with passwords redacted. Useful to debug quoting (default: false)."
+                }
+            }
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "exec",
+            description.unwrap_or(DEFAULT_EXEC_DESCRIPTION).to_string(),
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build sudo-exec tool definition, optionally overriding its description
+    fn sudo_exec_tool(description: Option<&str>) -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "Shell command to execute with sudo on the remote SSH server"
+                },
+                "dry_run": {
+                    "type": "boolean",
+                    "description": "Don't run anything; return the exact sudo-wrapped command that would be sent, with the password redacted (default: false)."
+                }
+            },
+            "required": ["command"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "sudo-exec",
+            description.unwrap_or(DEFAULT_SUDO_DESCRIPTION).to_string(),
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build remove tool definition
+    fn remove_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Remote file to delete. The last component may be a glob (e.g. /var/log/*.log) to delete every match."
+                }
+            },
+            "required": ["path"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "remove",
+            "Delete remote files over SFTP. Globs in the file name are expanded on the server and each match is reported separately.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build append-file tool definition
+    fn append_file_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Remote file to append to. Created if it does not exist."
+                },
+                "content": {
+                    "type": "string",
+                    "description": "Text appended verbatim. Include a trailing newline for line-based logs."
+                }
+            },
+            "required": ["path", "content"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "append-file",
+            "Append text to a remote file over SFTP and return its new size. Concurrent appends never interleave, unlike `echo >>` through exec.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build upload tool definition
+    fn upload_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "remote_path": {
+                    "type": "string",
+                    "description": "Remote file to write. An existing file is replaced."
+                },
+                "content_base64": {
+                    "type": "string",
+                    "description": "File content, base64-encoded. Provide this or local_path."
+                },
+                "local_path": {
+                    "type": "string",
+                    "description": "File on the machine running this MCP server to upload instead of content_base64. Only files within --allowed-local-paths may be read."
+                },
+                "mode": {
+                    "type": "string",
+                    "description": "Octal mode for the remote file, such as 644 or 0755 (default: keep the mode of an existing file, 0644 for a new one)."
+                },
+                "create_dirs": {
+                    "type": "boolean",
+                    "description": "Create missing parent directories (default: false)."
+                }
+            },
+            "required": ["remote_path"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "upload",
+            "Write a file to the remote host over SFTP, from base64 content or a local file, and return the bytes written. The file is written to a temporary name and renamed into place, so it is never left half-written.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build list-dir tool definition
+    fn list_dir_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Remote directory to list."
+                },
+                "include_hidden": {
+                    "type": "boolean",
+                    "description": "Include entries whose names start with a dot (default: false)."
+                }
+            },
+            "required": ["path"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "list-dir",
+            "List a remote directory over SFTP. Returns entries sorted by name with name, size, is_dir, is_symlink, mode and mtime, and the resolved target of symlinks. Prefer this over parsing ls output.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build read-file tool definition
+    fn read_file_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Remote file to read. Any file name works; no shell quoting needed."
+                },
+                "max_bytes": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Return at most this many bytes from the start of the file (default: 1048576)."
+                }
+            },
+            "required": ["path"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "read-file",
+            "Read a remote file over SFTP and return its bytes base64-encoded, with its size and whether the content was truncated at max_bytes. Safer than exec with cat for odd file names and binary files.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build download tool definition
+    fn download_tool(as_text: bool) -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "remote_path": {
+                    "type": "string",
+                    "description": "Remote file to read."
+                },
+                "max_bytes": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Refuse files larger than this many bytes. Can only lower the server's limit."
+                }
+            },
+            "required": ["remote_path"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        let description = if as_text {
+            "Read a remote file over SFTP. Returns its size, and its content as text when it is valid UTF-8 (encoding \"utf-8\") or base64-encoded otherwise (encoding \"base64\"). Files over the size limit are refused."
+        } else {
+            "Read a remote file over SFTP. Returns its size and its content base64-encoded. Files over the size limit are refused."
+        };
+        Tool::new("download", description, Arc::new(schema_obj))
+    }
+
+    /// Build run-command tool definition, with one parameter per placeholder
+    fn run_command_tool(template: &CommandTemplate) -> Tool {
+        let placeholders = template.placeholders();
+        let properties: serde_json::Map<String, serde_json::Value> = placeholders
+            .iter()
+            .map(|name| {
+                let schema = serde_json::json!({
+                    "type": "string",
+                    "description": format!("Value for {{{}}}, passed as a single argument", name)
+                });
+                (name.to_string(), schema)
+            })
+            .collect();
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": placeholders,
+            "additionalProperties": false
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "run-command",
+            format!(
+                "Run `{}` on the remote host, with each {{placeholder}} filled in from the parameter of the same name. No other commands can be run. Values must not start with '-'.",
+                template
+            ),
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build edit-file tool definition
+    fn edit_file_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Remote file to edit."
+                },
+                "pattern": {
+                    "type": "string",
+                    "description": "Regular expression to replace (Rust regex syntax). Use (?m) for ^ and $ to match at line boundaries."
+                },
+                "replacement": {
+                    "type": "string",
+                    "description": "Replacement for every match. $1 or ${name} insert capture groups; $$ is a literal $. No shell escaping is needed."
+                },
+                "backup": {
+                    "type": "boolean",
+                    "description": "Keep the original file as <path>.bak (default: false)"
+                }
+            },
+            "required": ["path", "pattern", "replacement"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "edit-file",
+            "Replace every match of a regular expression in a remote file over SFTP and return the number of replacements. Safer than sed -i through exec: nothing passes through a shell, and the file is written in one piece.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build chmod tool definition
+    fn chmod_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Remote file or directory."
+                },
+                "mode": {
+                    "type": "string",
+                    "description": "Octal mode such as 644, 0755 or 2775. Symbolic modes like u+x are not accepted."
+                }
+            },
+            "required": ["path", "mode"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "chmod",
+            "Set the permission mode of a remote file or directory over SFTP. Returns the previous and new mode and the numeric owner and group.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build chown tool definition
+    fn chown_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Remote file or directory."
+                },
+                "owner": {
+                    "type": "string",
+                    "description": "New owner: a user name or numeric uid."
+                },
+                "group": {
+                    "type": "string",
+                    "description": "New group: a group name or numeric gid (default: unchanged)."
+                }
+            },
+            "required": ["path", "owner"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "chown",
+            "Change the owner and optionally the group of a remote file or directory. Runs through sudo or the su shell when the login user is not root.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build broadcast-exec tool definition
+    fn broadcast_exec_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "Shell command to execute on every configured host"
+                }
+            },
+            "required": ["command"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "broadcast-exec",
+            "Run the same shell command on every configured host concurrently. Returns host, stdout, stderr and exit_code per host, or error for hosts that could not run it; one host failing does not stop the others.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build exec-diff tool definition
+    fn exec_diff_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "key": {
+                    "type": "string",
+                    "description": "Name to store the output under; the next call with the same key is diffed against it"
+                },
+                "command": {
+                    "type": "string",
+                    "description": "Shell command to execute on the remote SSH server"
+                },
+                "context": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Number of unchanged lines shown around each change (default: 3)"
+                }
+            },
+            "required": ["key", "command"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "exec-diff",
+            "Execute a shell command and compare its output with the previous run under the same key. Returns changed and a unified diff instead of the full output, for spotting drift between checks.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build list-processes tool definition
+    fn list_processes_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": {
+                    "type": "string",
+                    "description": "Only list processes whose name or command line contains this text"
+                }
+            }
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "list-processes",
+            "List running processes as structured entries with pid, ppid, user, cpu and mem (percent), name and command line, optionally filtered by a name substring. Use this instead of parsing ps output, e.g. to find the pid of a runaway process.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build list-ports tool definition
+    fn list_ports_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "sudo": {
+                    "type": "boolean",
+                    "description": "List through sudo, so the processes owning other users' sockets show (default: false)"
+                }
+            }
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "list-ports",
+            "List listening TCP ports and bound UDP ports as structured entries with proto, local_addr, local_port, state, pid and process. Uses ss, or netstat where ss is missing. Process details of sockets owned by other users need root: use sudo, or the su shell when configured.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build kill-process tool definition
+    fn kill_process_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "pid": {
+                    "type": "integer",
+                    "minimum": 2,
+                    "description": "Process ID to signal (see list-processes)"
+                },
+                "signal": {
+                    "type": "string",
+                    "description": "Signal name such as TERM, KILL or HUP (with or without SIG), or a number (default: TERM)"
+                },
+                "sudo": {
+                    "type": "boolean",
+                    "description": "Send the signal through sudo, for processes owned by other users (default: false)"
+                }
+            },
+            "required": ["pid"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "kill-process",
+            "Send a signal to a process by pid (TERM by default). Prefer TERM and only use KILL when the process ignores it.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build wait-for tool definition
+    fn wait_for_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "Shell command to run on every attempt, e.g. 'systemctl is-active nginx' or 'curl -sf http://localhost:8080/health'"
+                },
+                "expect_exit_code": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Exit code the command has to return (default: 0 unless expect_output_contains is given)"
+                },
+                "expect_output_contains": {
+                    "type": "string",
+                    "description": "Text the command's stdout or stderr has to contain"
+                },
+                "interval_ms": {
+                    "type": "integer",
+                    "minimum": 100,
+                    "description": "Pause between attempts in milliseconds (default: 1000)"
+                },
+                "timeout_ms": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "maximum": 3600000,
+                    "description": "How long to keep trying in milliseconds (default: 60000)"
+                }
+            },
+            "required": ["command"]
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "wait-for",
+            "Re-run a command until it meets a condition (an exit code and/or text in its output) or the timeout elapses, e.g. to wait until a service is up. Returns whether the condition was met, the number of attempts, how long it waited and the last output. Use this instead of polling with repeated exec calls.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build session-info tool definition
+    fn session_info_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {}
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "session-info",
+            "Report connection details (host, user, connection and elevation state, authentication method) and the remote locale, including a warning when it is not UTF-8.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build queue-status tool definition
+    fn queue_status_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {}
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "queue-status",
+            "Report how many commands are running and queued, the concurrency limit and the estimated wait for a new command. Check this and back off when the server is saturated.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build cancel-all tool definition
+    fn cancel_all_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {}
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "cancel-all",
+            "Abort every command and file operation in flight and report how many were cancelled. Connections are reset so remote processes stop too. Use this to stop everything at once when things have gone wrong.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build drop-elevation tool definition
+    fn drop_elevation_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {}
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "drop-elevation",
+            "Close the root shell opened through su and run later exec commands as the login user, without reconnecting. Reports whether a root shell was open and the user commands now run as. sudo-exec is not affected.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build connect tool definition
+    fn connect_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {}
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "connect",
+            "Open the SSH session now rather than on the first command. Returns the user, host and port connected to; if the connection or authentication fails, the error says why. Does nothing when already connected.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build disconnect tool definition
+    fn disconnect_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {}
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "disconnect",
+            "Close the SSH session, including the root shell opened through su. The next command or connect call opens a new session.",
+            Arc::new(schema_obj),
+        )
+    }
+
+    /// Build command-history tool definition
+    fn command_history_tool() -> Tool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "limit": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "Maximum number of most recent commands to return"
+                }
+            }
+        });
+
+        // Convert Value to JsonObject (Map<String, Value>)
+        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+
+        Tool::new(
+            "command-history",
+            "List recently executed commands with their exit codes (output is not stored), oldest first.",
+            Arc::new(schema_obj),
+        )
+    }
+}
+
+impl ServerHandler for SshMcpServer {
+    /// Return server information
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            protocol_version: self.protocol_version(),
+            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            server_info: Implementation::from_build_env(),
+            instructions: Some(self.instructions()),
+        }
+    }
+
+    /// List available tools
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> std::result::Result<ListToolsResult, McpError> {
+        debug!("list_tools called");
+
+        if let Some(ref template) = self.config.only_command {
+            return Ok(ListToolsResult {
+                tools: vec![Self::run_command_tool(template)],
+                next_cursor: None,
+                meta: Default::default(),
+            });
+        }
+
+        let mut tools = vec![Self::exec_tool(self.config.exec_description.as_deref())];
+
+        // Add sudo-exec tool if enabled
+        if !self.config.disable_sudo {
+            tools.push(Self::sudo_exec_tool(
+                self.config.sudo_description.as_deref(),
+            ));
+        }
+
+        tools.push(Self::exec_diff_tool());
+        if !self.broadcast_targets.is_empty() {
+            tools.push(Self::broadcast_exec_tool());
+        }
+        tools.push(Self::session_info_tool());
+        tools.push(Self::queue_status_tool());
+        tools.push(Self::cancel_all_tool());
+        tools.push(Self::list_processes_tool());
+        tools.push(Self::kill_process_tool());
+        tools.push(Self::list_ports_tool());
+        tools.push(Self::wait_for_tool());
+
+        if self.config.history_size > 0 {
+            tools.push(Self::command_history_tool());
+        }
+
+        // File tools work over SFTP and need an SSH connection
+        if self.connection.is_some() {
+            if self.config.su_password.is_some() {
+                tools.push(Self::drop_elevation_tool());
+            }
+            if self.config.enable_connection_tools {
+                tools.push(Self::connect_tool());
+                tools.push(Self::disconnect_tool());
+            }
+            tools.push(Self::remove_tool());
+            tools.push(Self::append_file_tool());
+            tools.push(Self::upload_tool());
+            tools.push(Self::download_tool(self.config.download_as_text));
+            tools.push(Self::read_file_tool());
+            tools.push(Self::list_dir_tool());
+            tools.push(Self::edit_file_tool());
+            tools.push(Self::chmod_tool());
+            tools.push(Self::chown_tool());
+        }
+
+        Ok(ListToolsResult {
+            tools,
+            next_cursor: None,
+            meta: Default::default(),
+        })
+    }
+
+    /// Call a tool
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> std::result::Result<CallToolResult, McpError> {
+        let tool_name: &str = request.name.as_ref();
+        debug!("call_tool called: {:?}", tool_name);
+
+        let args = request.arguments.unwrap_or_default();
+
+        // Nothing but the template runs in --only-command mode
+        if let Some(ref template) = self.config.only_command {
+            return match tool_name {
+                "run_command" | "run-command" => self.run_command(template, &args).await,
+                _ => Err(McpError::invalid_params(
+                    format!(
+                        "Unknown tool: {} (this server only has run-command)",
+                        tool_name
+                    ),
+                    None,
+                )),
+            };
+        }
+
+        // Route to the appropriate tool
+        match tool_name {
+            "exec" => {
+                let params: ExecParams = serde_json::from_value(serde_json::Value::Object(args))
+                    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+                let command = params
+                    .command()
+                    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+                let env: Vec<(String, String)> = params.env.into_iter().flatten().collect();
+
+                // Clients that send a progress token get the output as it
+                // arrives, unless --stream=false
+                let streaming = context
+                    .meta
+                    .get_progress_token()
+                    .filter(|_| self.config.stream)
+                    .map(|token| forward_output(context.peer.clone(), token));
+                let result = self
+                    .execute_command(
+                        &command,
+                        &env,
+                        ExecOptions {
+                            term_width: params.term_width,
+                            umask: params.umask.as_deref(),
+                            fail_on_stderr: params.fail_on_stderr,
+                            dedupe: params.dedupe,
+                            include_hash: params.include_hash,
+                            container: params.container.as_deref(),
+                            read_duration_ms: params.read_duration_ms,
+                            output: streaming.as_ref().map(|(sink, _)| sink),
+                            keep_output: params.keep_output,
+                            stdin_from: params.stdin_from.as_deref(),
+                            dry_run: params.dry_run,
+                        },
+                    )
+                    .await;
+                // Updates must not arrive after the result they belong to
+                if let Some((sink, forwarder)) = streaming {
+                    drop(sink);
+                    let _ = forwarder.await;
+                }
+                result
+            }
+            "sudo_exec" | "sudo-exec" => {
+                // Check if sudo is enabled
+                if self.config.disable_sudo {
+                    return Err(McpError::invalid_params("sudo-exec tool is disabled", None));
+                }
+
+                // Extract command from arguments
+                let command = args
+                    .get("command")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| {
+                        McpError::invalid_params("Missing required parameter: command", None)
+                    })?;
+
+                let dry_run = args
+                    .get("dry_run")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+
+                self.execute_sudo_command(command, dry_run).await
+            }
+            "list_processes" | "list-processes" => {
+                let params: ListProcessesParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.list_processes(params).await
+            }
+            "list_ports" | "list-ports" => {
+                let params: ListPortsParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.list_ports(params).await
+            }
+            "kill_process" | "kill-process" => {
+                let params: KillProcessParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.kill_process(params).await
+            }
+            "wait_for" | "wait-for" => {
+                let params: WaitForParams = serde_json::from_value(serde_json::Value::Object(args))
+                    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.wait_for(params).await
+            }
+            "exec_diff" | "exec-diff" => {
+                let params: ExecDiffParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.execute_diff(params).await
+            }
+            "broadcast_exec" | "broadcast-exec" => {
+                let params: BroadcastExecParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.execute_broadcast(&params.command).await
+            }
+            "session_info" | "session-info" => self.session_info().await,
+            "queue_status" | "queue-status" => Ok(self.queue_status()),
+            "cancel_all" | "cancel-all" => Ok(self.cancel_all().await),
+            "drop_elevation" | "drop-elevation" => self.drop_elevation().await,
+            "connect" => self.connect().await,
+            "disconnect" => self.disconnect().await,
+            "command_history" | "command-history" => {
+                let params: CommandHistoryParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                Ok(self.command_history(params.limit))
+            }
+            "remove" => {
+                let params: RemoveParams = serde_json::from_value(serde_json::Value::Object(args))
+                    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.remove_files(&params.path).await
+            }
+            "append_file" | "append-file" => {
+                let params: AppendFileParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.append_to_file(params).await
+            }
+            "edit_file" | "edit-file" => {
+                let params: EditFileParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.edit_file(params).await
+            }
+            "upload" => {
+                let params: UploadParams = serde_json::from_value(serde_json::Value::Object(args))
+                    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.upload(params).await
+            }
+            "read_file" | "read-file" => {
+                let params: ReadFileParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.read_file(params).await
+            }
+            "list_dir" | "list-dir" => {
+                let params: ListDirParams = serde_json::from_value(serde_json::Value::Object(args))
+                    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.list_dir(params).await
+            }
+            "download" => {
+                let params: DownloadParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.download(params).await
+            }
+            "chmod" => {
+                let params: ChmodParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.chmod(params).await
+            }
+            "chown" => {
+                let params: ChownParams =
+                    serde_json::from_value(serde_json::Value::Object(args))
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+                self.chown(params).await
+            }
+            _ => Err(McpError::invalid_params(
+                format!("Unknown tool: {}", tool_name),
+                None,
+            )),
+        }
+    }
+}
+
+/// Check that a private key file is not accessible to group or others
+///
+/// OpenSSH refuses such keys outright; `check` picks between refusing,
+/// warning and ignoring. Only Unix has the mode bits to check.
+fn check_key_permissions(path: &Path, check: KeyPermCheck) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if check == KeyPermCheck::Ignore {
+            return Ok(());
+        }
+        let mode = std::fs::metadata(path)?.permissions().mode() & 0o777;
+        if mode & 0o077 == 0 {
+            return Ok(());
+        }
+
+        let problem = format!(
+            "Permissions {:04o} for private key {} are too open; it should be \
+             readable only by you (chmod 600 {})",
+            mode,
+            path.display(),
+            path.display()
+        );
+        if check == KeyPermCheck::Error {
+            return Err(SshMcpError::config(format!(
+                "{}, or pass --key-perm-check warn to use it anyway",
+                problem
+            )));
+        }
+        warn!("{}", problem);
+    }
+    #[cfg(not(unix))]
+    let _ = (path, check);
+
+    Ok(())
+}
+
+/// Send output from the returned sink to the client as progress
+/// notifications for `token`
+///
+/// Each notification carries the text printed since the previous one as its
+/// message, and the number of bytes so far as its progress. The task ends
+/// once every sender is dropped and the queued output has been sent.
+fn forward_output(peer: Peer<RoleServer>, token: ProgressToken) -> (OutputSink, JoinHandle<()>) {
+    let (sink, mut chunks) = mpsc::unbounded_channel::<String>();
+    let forwarder = tokio::spawn(async move {
+        let mut sent = 0;
+        while let Some(mut text) = chunks.recv().await {
+            // Batch what piled up while the last notification was sent
+            while let Ok(more) = chunks.try_recv() {
+                text.push_str(&more);
+            }
+            sent += text.len();
+            let update = ProgressNotificationParam {
+                progress_token: token.clone(),
+                progress: sent as f64,
+                total: None,
+                message: Some(text),
+            };
+            if let Err(e) = peer.notify_progress(update).await {
+                debug!("Failed to send output update: {}", e);
+            }
+        }
+    });
+    (sink, forwarder)
+}
+
+/// Run a command in the su root shell when `elevate` is set and the shell
+/// is open, otherwise as the login user
+///
+/// This is how exec runs commands under `--no-elevation-on-exec`, shared by
+/// the other tools that run commands on the caller's behalf.
+fn exec_as_configured<'a>(
+    executor: &'a dyn Executor,
+    command: &'a str,
+    elevate: bool,
+    timeout: Duration,
+) -> ExecFuture<'a, CommandOutput> {
+    if elevate {
+        return executor.exec(command, timeout);
+    }
+    Box::pin(async move {
+        executor
+            .exec_as_user(command, &[], None, timeout)
+            .await
+            .map(|(output, _)| output)
+    })
+}
+
+/// Text content labeled with the output stream it came from
+fn stream_content(stream: &str, text: String) -> Content {
+    let mut meta = Meta::new();
+    meta.0.insert("stream".to_string(), stream.into());
+    RawContent::Text(RawTextContent {
+        text,
+        meta: Some(meta),
+    })
+    .no_annotation()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Args;
+    use crate::ssh::EnvMethod;
+    use crate::stream::push_streamed;
+    use clap::Parser;
+    use std::sync::Mutex;
+
+    /// Executor returning canned output and recording the commands it was given
+    struct MockExecutor {
+        output: CommandOutput,
+        sudo_output: Option<CommandOutput>,
+        commands: Mutex<Vec<String>>,
+    }
+
+    impl MockExecutor {
+        fn new(output: CommandOutput) -> Arc<Self> {
+            Arc::new(Self {
+                output,
+                sudo_output: None,
+                commands: Mutex::new(Vec::new()),
+            })
+        }
+
+        /// Like `new`, but commands run through sudo get `sudo_output`
+        fn with_sudo_output(output: CommandOutput, sudo_output: CommandOutput) -> Arc<Self> {
+            Arc::new(Self {
+                output,
+                sudo_output: Some(sudo_output),
+                commands: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn commands(&self) -> Vec<String> {
+            self.commands.lock().unwrap().clone()
+        }
+    }
+
+    impl Executor for MockExecutor {
+        fn exec<'a>(
+            &'a self,
+            command: &'a str,
+            timeout: Duration,
+        ) -> ExecFuture<'a, CommandOutput> {
+            self.commands.lock().unwrap().push(command.to_string());
+            let output = match &self.sudo_output {
+                Some(sudo_output) if command.contains("sudo ") => sudo_output.clone(),
+                _ => self.output.clone(),
+            };
+            // Runs until the command timeout, as a stuck command would
+            if command == "hang" {
+                return Box::pin(async move {
+                    tokio::time::sleep(timeout).await;
+                    Err(SshMcpError::Timeout(timeout.as_millis() as u64))
+                });
+            }
+            Box::pin(async move { Ok(output) })
+        }
+
+        fn exec_prompted<'a>(
+            &'a self,
+            command: &'a str,
+            prompt: &'a str,
+            _response: &'a str,
+            _timeout: Duration,
+        ) -> ExecFuture<'a, CommandOutput> {
+            self.commands
+                .lock()
+                .unwrap()
+                .push(format!("{} <{}>", command, prompt));
+            let output = self.output.clone();
+            Box::pin(async move { Ok(output) })
+        }
+
+        fn exec_with_stdin<'a>(
+            &'a self,
+            command: &'a str,
+            stdin: &'a [u8],
+            _timeout: Duration,
+        ) -> ExecFuture<'a, CommandOutput> {
+            self.commands.lock().unwrap().push(format!(
+                "{} <<< {:?}",
+                command,
+                String::from_utf8_lossy(stdin)
+            ));
+            let output = self.output.clone();
+            Box::pin(async move { Ok(output) })
+        }
+
+        fn exec_as_user<'a>(
+            &'a self,
+            command: &'a str,
+            _env: &'a [(String, String)],
+            _term_width: Option<u16>,
+            _timeout: Duration,
+        ) -> ExecFuture<'a, (CommandOutput, Vec<(String, EnvMethod)>)> {
+            self.commands
+                .lock()
+                .unwrap()
+                .push(format!("{} <as user>", command));
+            let output = self.output.clone();
+            Box::pin(async move { Ok((output, Vec::new())) })
+        }
+    }
+
+    fn test_config(extra: &[&str]) -> Config {
+        let args = Args::parse_from(["ssh-mcp", "--local"].iter().chain(extra));
+        Config::from_args(args).unwrap()
+    }
+
+    fn result_text(result: &CallToolResult) -> String {
+        result
+            .content
+            .iter()
+            .filter_map(|c| c.as_text().map(|t| t.text.clone()))
+            .collect::<Vec<_>>()
+            .join("")
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_success() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "hello\n".to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let result = server
+            .execute_command("  echo hello  ", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(result_text(&result), "hello\n");
+        assert_eq!(executor.commands(), vec!["echo hello"]);
+    }
+
+    #[tokio::test]
+    async fn test_extended_data_kept_out_of_stdout() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "out".to_string(),
+            extended: "side channel".to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let result = server
+            .execute_command("run", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            result_text(&result),
+            "out\n--- extended data ---\nside channel"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_nonzero_exit_is_error() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "partial".to_string(),
+            stderr: "boom".to_string(),
+            exit_code: Some(2),
+            ..Default::default()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let result = server
+            .execute_command("false", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(result_text(&result), "partial\n--- stderr ---\nboom");
+    }
+
+    #[tokio::test]
+    async fn test_separate_content() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "partial".to_string(),
+            stderr: "boom".to_string(),
+            exit_code: Some(2),
+            ..Default::default()
+        });
+        let config = test_config(&["--separate-content"]);
+        let server = SshMcpServer::with_executor(config, executor);
+
+        let result = server
+            .execute_command("false", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let items: Vec<_> = result
+            .content
+            .iter()
+            .map(|c| {
+                let text = c.as_text().unwrap();
+                (
+                    text.meta.as_ref().unwrap().0["stream"].clone(),
+                    text.text.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                ("stdout".into(), "partial".to_string()),
+                ("stderr".into(), "boom".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_rejects_long_command() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&["--maxChars=5"]), executor.clone());
+
+        let result = server
+            .execute_command("echo too long", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(executor.commands().is_empty());
+        // A limit the user set needs no explanation
+        assert!(!result_text(&result).contains("default limit"));
+
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        let result = server
+            .execute_command(&"x".repeat(1001), &[], ExecOptions::default())
+            .await
+            .unwrap();
+        let text = result_text(&result);
+        assert!(text.contains("max 1000 characters"));
+        assert!(text.contains("This is the default limit"));
+        assert!(text.contains("--maxChars none"));
+    }
+
+    #[tokio::test]
+    async fn test_reject_empty_effect() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server =
+            SshMcpServer::with_executor(test_config(&["--reject-empty-effect"]), executor.clone());
+
+        let result = server
+            .execute_command("# check the disk", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("no executable statement"));
+
+        server
+            .execute_command("# check the disk\ndf -h", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["# check the disk\ndf -h"]);
+
+        // Off by default
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        server
+            .execute_command("# noop", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["# noop"]);
+    }
+
+    #[tokio::test]
+    async fn test_no_trim_command_keeps_whitespace() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server =
+            SshMcpServer::with_executor(test_config(&["--no-trim-command"]), executor.clone());
+
+        server
+            .execute_command("cat <<EOF\n  x\nEOF\n", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["cat <<EOF\n  x\nEOF\n"]);
+
+        let result = server
+            .execute_command("  \n", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_execute_sudo_command_wraps_command() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        server
+            .execute_sudo_command("apt update", false)
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["sudo -n sh -c 'apt update'"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_sudo_command_without_password_newline() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--sudo-password=p%s", "--sudo-password-newline=false"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        server.execute_sudo_command("id", false).await.unwrap();
+        assert_eq!(
+            executor.commands(),
+            vec!["printf '%s' 'p%s' | sudo -p \"\" -S sh -c 'id'"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_sudo_command_pty_waits_for_prompt() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--sudo-pty", "--sudo-password=secret"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        server
+            .execute_sudo_command("apt update", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            executor.commands(),
+            vec![format!(
+                "sudo -p '{0}' sh -c 'apt update' <{0}>",
+                SUDO_PROMPT_SENTINEL
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_echo_command_redacts_password() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--echo-command", "--sudo-password=s3cret"]);
+        let server = SshMcpServer::with_executor(config, executor);
+
+        let result = server
+            .execute_sudo_command("apt update", false)
+            .await
+            .unwrap();
+        let meta = result.meta.expect("command should be echoed");
+        assert_eq!(
+            meta.0["command"],
+            "printf '%s\\n' '******' | sudo -p \"\" -S sh -c 'apt update'"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_echo_command_disabled_by_default() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let result = server
+            .execute_command("uptime", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert!(result.meta.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--sudo-password=s3cret"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        let env = [("LANG".to_string(), "C".to_string())];
+        let options = ExecOptions {
+            umask: Some("077"),
+            dry_run: Some(true),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("echo 'it works'", &env, options)
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        let command = "export LANG='C'; (umask 077; echo 'it works'\n)";
+        assert_eq!(result_text(&result), command);
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["dry_run"], true);
+        assert_eq!(structured["command"], command);
+        assert_eq!(structured["env"], serde_json::json!(["LANG"]));
+
+        let result = server
+            .execute_sudo_command("apt update", true)
+            .await
+            .unwrap();
+        assert_eq!(
+            result_text(&result),
+            "printf '%s\\n' '******' | sudo -p \"\" -S sh -c 'apt update'"
+        );
+        assert_eq!(result.structured_content.unwrap()["sudo_pty"], false);
+
+        // Nothing ran and nothing was recorded
+        assert!(executor.commands().is_empty());
+        let history = server.command_history(None).structured_content.unwrap();
+        assert_eq!(history["entries"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_policy_denies_command() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let policy = Policy::parse("allow systemctl status *\ndeny systemctl stop *").unwrap();
+        let server =
+            SshMcpServer::with_executor(test_config(&[]), executor.clone()).with_policy(policy);
+
+        let result = server
+            .execute_sudo_command("systemctl  stop nginx", false)
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("line 2: deny systemctl stop *"));
+
+        server
+            .execute_command("systemctl status nginx", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["systemctl status nginx"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_diff_against_previous_run() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "active\n".to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        let params = || ExecDiffParams {
+            key: "nginx".to_string(),
+            command: "systemctl is-active nginx".to_string(),
+            context: None,
+        };
+
+        let first = server.execute_diff(params()).await.unwrap();
+        let first = first.structured_content.unwrap();
+        assert_eq!(first["first_run"], true);
+        assert_eq!(first["changed"], false);
+        assert_eq!(first["output"], "active\n");
+
+        let second = server.execute_diff(params()).await.unwrap();
+        let second = second.structured_content.unwrap();
+        assert_eq!(second["changed"], false);
+        assert_eq!(second["diff"], "");
+        assert!(second.get("first_run").is_none());
+
+        server
+            .previous_outputs
+            .insert("nginx", "inactive\n".to_string());
+        let third = server.execute_diff(params()).await.unwrap();
+        let third = third.structured_content.unwrap();
+        assert_eq!(third["changed"], true);
+        assert_eq!(
+            third["diff"],
+            "--- nginx (previous)\n+++ nginx (current)\n@@ -1,1 +1,1 @@\n-inactive\n+active\n"
+        );
+
+        assert_eq!(executor.commands().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_busy_server_rejects_command() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--max-concurrent=1", "--max-queued=0"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        let slot = server.queue.acquire().await.unwrap();
+        let result = server
+            .execute_command("uptime", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("Server busy"));
+        assert!(executor.commands().is_empty());
+
+        let status = server.queue_status().structured_content.unwrap();
+        assert_eq!(status["running"], 1);
+        assert_eq!(status["max_concurrent"], 1);
+
+        drop(slot);
+        server
+            .execute_command("uptime", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["uptime"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_with_env() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        let env = vec![("GREETING".to_string(), "it's".to_string())];
+
+        let result = server
+            .execute_command("echo $GREETING", &env, ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            executor.commands(),
+            vec!["export GREETING='it'\"'\"'s'; echo $GREETING"]
+        );
+        assert_eq!(result.meta.unwrap().0["env"]["GREETING"], "shell");
+
+        let env = vec![("BAD NAME".to_string(), "x".to_string())];
+        let result = server
+            .execute_command("true", &env, ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_elevation_warns_in_result() {
+        struct Unprivileged;
+
+        impl Executor for Unprivileged {
+            fn exec<'a>(
+                &'a self,
+                _command: &'a str,
+                _timeout: Duration,
+            ) -> ExecFuture<'a, CommandOutput> {
+                Box::pin(async { Ok(CommandOutput::new()) })
+            }
+
+            fn elevation_warning(&self) -> Option<String> {
+                Some("su: Authentication failure".to_string())
+            }
+        }
+
+        let config = test_config(&["--user=deploy"]);
+        let server = SshMcpServer::with_executor(config, Arc::new(Unprivileged));
+        let result = server
+            .execute_command("id -u", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        let warning = result.content.last().unwrap().as_text().unwrap();
+        assert!(warning.text.contains("su: Authentication failure"));
+        assert!(warning.text.contains("ran as deploy"));
+    }
+
+    #[tokio::test]
+    async fn test_exec_streams_output() {
+        struct Printing;
+
+        impl Executor for Printing {
+            fn exec<'a>(
+                &'a self,
+                _command: &'a str,
+                _timeout: Duration,
+            ) -> ExecFuture<'a, CommandOutput> {
+                Box::pin(async {
+                    let mut output = CommandOutput::new();
+                    let mut bytes_read = 0;
+                    for line in ["step 1\n", "step 2\n"] {
+                        push_streamed(&mut output.stdout, line.as_bytes(), &mut bytes_read, None);
+                    }
+                    output.exit_code = Some(0);
+                    Ok(output)
+                })
+            }
+        }
+
+        let server = SshMcpServer::with_executor(test_config(&[]), Arc::new(Printing));
+        let (sink, mut chunks) = mpsc::unbounded_channel();
+        let options = ExecOptions {
+            output: Some(&sink),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("deploy", &[], options)
+            .await
+            .unwrap();
+        drop(sink);
+        assert_eq!(result_text(&result), "step 1\nstep 2\n");
+        assert_eq!(chunks.recv().await.unwrap(), "step 1\n");
+        assert_eq!(chunks.recv().await.unwrap(), "step 2\n");
+        assert!(chunks.recv().await.is_none());
+
+        // Without a sink the same command only returns its result
+        let result = server
+            .execute_command("deploy", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result_text(&result), "step 1\nstep 2\n");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_aborts_running_command() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let running = {
+            let server = server.clone();
+            tokio::spawn(async move {
+                server
+                    .execute_command("hang", &[], ExecOptions::default())
+                    .await
+            })
+        };
+        while server.operations.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let result = server.cancel_all().await;
+        assert_eq!(
+            result.structured_content,
+            Some(serde_json::json!({ "cancelled": 1 }))
+        );
+
+        let result = running.await.unwrap().unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("Operation cancelled"));
+
+        let result = server.cancel_all().await;
+        assert_eq!(
+            result.structured_content,
+            Some(serde_json::json!({ "cancelled": 0 }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drop_elevation_requires_connection() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let result = server.drop_elevation().await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("requires an SSH connection"));
+        assert!(executor.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upload_requires_connection() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let params: UploadParams = serde_json::from_value(serde_json::json!({
+            "remote_path": "/etc/motd",
+            "content_base64": "aGVsbG8K",
+        }))
+        .unwrap();
+        let result = server.upload(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("require an SSH connection"));
+    }
+
+    #[tokio::test]
+    async fn test_download_requires_connection() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let params: DownloadParams =
+            serde_json::from_value(serde_json::json!({ "remote_path": "/etc/hosts" })).unwrap();
+        let result = server.download(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("require an SSH connection"));
+    }
+
+    #[tokio::test]
+    async fn test_list_dir_requires_connection() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params: ListDirParams =
+            serde_json::from_value(serde_json::json!({ "path": "/var/log" })).unwrap();
+        assert!(!params.include_hidden);
+        let result = server.list_dir(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("require an SSH connection"));
+        assert!(executor.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_file_requires_connection() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params: ReadFileParams = serde_json::from_value(serde_json::json!({
+            "path": "/var/log/it's here.log",
+            "max_bytes": 4096,
+        }))
+        .unwrap();
+        let result = server.read_file(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("require an SSH connection"));
+        assert!(executor.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_tools() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let mut server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        let result = server.connect().await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("requires an SSH connection"));
+
+        // Nothing listens on port 1, so the connection error is returned
+        let config = SshConfig::new("127.0.0.1", "admin").with_port(1);
+        server.connection = Some(Arc::new(SshConnectionManager::new(config).await));
+        let result = server.connect().await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).starts_with("Error: "));
+
+        let result = server.disconnect().await.unwrap();
+        assert_ne!(result.is_error, Some(true));
+        assert_eq!(result_text(&result), "disconnected");
+        assert!(!server.connection.as_ref().unwrap().is_connected().await);
+        assert!(executor.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_edit_file_requires_connection() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let params: EditFileParams = serde_json::from_value(serde_json::json!({
+            "path": "/etc/ssh/sshd_config",
+            "pattern": "^#?Port .*",
+            "replacement": "Port 2222",
+        }))
+        .unwrap();
+        assert!(!params.backup);
+        let result = server.edit_file(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("require an SSH connection"));
+    }
+
+    #[tokio::test]
+    async fn test_chmod_and_chown_require_connection() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let params = ChmodParams {
+            path: "/srv/app".to_string(),
+            mode: "755".to_string(),
+        };
+        let result = server.chmod(params).await.unwrap();
+        assert!(result_text(&result).contains("require an SSH connection"));
+
+        let params = ChownParams {
+            path: "/srv/app".to_string(),
+            owner: "www-data".to_string(),
+            group: None,
+        };
+        let result = server.chown(params).await.unwrap();
+        assert!(result_text(&result).contains("require an SSH connection"));
+    }
+
+    #[tokio::test]
+    async fn test_change_owner() {
+        let params = ChownParams {
+            path: "~/app".to_string(),
+            owner: "www-data".to_string(),
+            group: Some("www-data".to_string()),
+        };
+
+        // A non-root login user goes through sudo
+        let denied = CommandOutput {
+            stderr: "chown: changing ownership of '/home/deploy/app': Operation not permitted\n"
+                .to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        let executor = MockExecutor::with_sudo_output(denied.clone(), CommandOutput::new());
+        let server =
+            SshMcpServer::with_executor(test_config(&["--user", "deploy"]), executor.clone());
+        let result = server.change_owner(&params, "/home/deploy/app").await;
+        assert_ne!(result.is_error, Some(true));
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["sudo"], true);
+        assert_eq!(report["changed"], true);
+        let commands = executor.commands();
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].contains("sudo "));
+        assert!(commands[0].contains("chown -h -- www-data:www-data"));
+
+        // Without sudo a denial explains that root is needed
+        let executor = MockExecutor::new(denied);
+        let config = test_config(&["--user", "deploy", "--disable-sudo"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+        let result = server.change_owner(&params, "/home/deploy/app").await;
+        assert_eq!(result.is_error, Some(true));
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["sudo"], false);
+        assert!(report["error"]
+            .as_str()
+            .unwrap()
+            .contains("This needs root"));
+        assert!(!executor.commands()[0].contains("sudo "));
+
+        // Invalid names never reach the remote host
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        let params = ChownParams {
+            owner: "root;reboot".to_string(),
+            ..params
+        };
+        let result = server.change_owner(&params, "/srv/app").await;
+        assert!(result_text(&result).contains("Invalid owner"));
+        assert!(executor.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_processes() {
+        let output = CommandOutput {
+            stdout: "  812     1 www-data  12.5  3.4 nginx  nginx: worker process
+                      4242   812 deploy     0.0  0.0 sleep  sleep 600
+"
+            .to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        };
+        let executor = MockExecutor::new(output);
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params = ListProcessesParams {
+            name: Some("nginx".to_string()),
+        };
+        let result = server.list_processes(params).await.unwrap();
+        let listing = result.structured_content.unwrap();
+        assert_eq!(listing["count"], 1);
+        assert_eq!(listing["processes"][0]["pid"], 812);
+        assert_eq!(listing["processes"][0]["user"], "www-data");
+        assert_eq!(executor.commands(), vec![PS_COMMAND]);
+    }
+
+    #[tokio::test]
+    async fn test_list_ports() {
+        let output = CommandOutput {
+            stdout: "Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      4096   0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=801,fd=3))
+"
+            .to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        };
+        let executor = MockExecutor::new(output);
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let result = server
+            .list_ports(ListPortsParams { sudo: None })
+            .await
+            .unwrap();
+        let listing = result.structured_content.unwrap();
+        assert_eq!(listing["count"], 1);
+        assert_eq!(listing["ports"][0]["local_port"], 22);
+        assert_eq!(listing["ports"][0]["process"], "sshd");
+        assert_eq!(executor.commands(), vec![PORTS_COMMAND]);
+
+        let missing = MockExecutor::new(CommandOutput {
+            stderr: format!("{}\n", NO_PORT_TOOL_MESSAGE),
+            exit_code: Some(127),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), missing);
+        let result = server
+            .list_ports(ListPortsParams { sudo: None })
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("neither ss nor netstat"));
+
+        // Through the su shell the message arrives on stdout
+        let elevated = MockExecutor::new(CommandOutput {
+            stdout: format!("{}\n", NO_PORT_TOOL_MESSAGE),
+            exit_code: Some(127),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), elevated);
+        let result = server
+            .list_ports(ListPortsParams { sudo: None })
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("install iproute2 or net-tools"));
+    }
+
+    #[tokio::test]
+    async fn test_kill_process() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params = KillProcessParams {
+            pid: 4242,
+            signal: Some("sigkill".to_string()),
+            sudo: None,
+        };
+        let result = server.kill_process(params).await.unwrap();
+        assert_eq!(result.structured_content.unwrap()["signal"], "KILL");
+        assert_eq!(executor.commands(), vec!["kill -KILL 4242"]);
+
+        let failing = MockExecutor::new(CommandOutput {
+            stderr: "kill: (4242) - No such process".to_string(),
+            exit_code: Some(1),
+            ..CommandOutput::new()
+        });
+        let failing_server = SshMcpServer::with_executor(test_config(&[]), failing);
+        let params = KillProcessParams {
+            pid: 4242,
+            signal: None,
+            sudo: None,
+        };
+        let result = failing_server.kill_process(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["sent"], false);
+        assert_eq!(report["error"], "kill: (4242) - No such process");
+
+        let params = KillProcessParams {
+            pid: 1,
+            signal: None,
+            sudo: None,
+        };
+        let result = server.kill_process(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "active\n".to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params = WaitForParams {
+            command: "systemctl is-active nginx".to_string(),
+            expect_exit_code: None,
+            expect_output_contains: Some("active".to_string()),
+            interval_ms: None,
+            timeout_ms: None,
+        };
+        let result = server.wait_for(params).await.unwrap();
+        assert_eq!(result.is_error, Some(false));
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["met"], true);
+        assert_eq!(report["attempts"], 1);
+        assert_eq!(report["output"], "active\n");
+        assert_eq!(executor.commands(), vec!["systemctl is-active nginx"]);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_times_out() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "activating\n".to_string(),
+            exit_code: Some(3),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params = WaitForParams {
+            command: "systemctl is-active nginx".to_string(),
+            expect_exit_code: None,
+            expect_output_contains: None,
+            interval_ms: Some(100),
+            timeout_ms: Some(250),
+        };
+        let result = server.wait_for(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["met"], false);
+        assert_eq!(report["exit_code"], 3);
+        assert!(report["waited_ms"].as_u64().unwrap() >= 250);
+        let attempts = report["attempts"].as_u64().unwrap();
+        assert!(attempts >= 3);
+        assert_eq!(executor.commands().len() as u64, attempts);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_bounds_hanging_attempts() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params = WaitForParams {
+            command: "hang".to_string(),
+            expect_exit_code: None,
+            expect_output_contains: None,
+            interval_ms: Some(100),
+            timeout_ms: Some(300),
+        };
+        let result = server.wait_for(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["met"], false);
+        assert_eq!(report["timed_out"], true);
+        assert!(report.get("error").is_none());
+        // One attempt, cut off after MIN_WAIT_ATTEMPT_MS rather than the
+        // 60s command timeout
+        assert_eq!(report["attempts"], 1);
+        let waited_ms = report["waited_ms"].as_u64().unwrap();
+        assert!(
+            (MIN_WAIT_ATTEMPT_MS..5_000).contains(&waited_ms),
+            "{}",
+            waited_ms
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kill_process_with_sudo() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params = KillProcessParams {
+            pid: 4242,
+            signal: Some("9".to_string()),
+            sudo: Some(true),
+        };
+        let result = server.kill_process(params).await.unwrap();
+        assert_eq!(result.structured_content.unwrap()["sent"], true);
+        let commands = executor.commands();
+        assert!(commands[0].contains("sudo"));
+        assert!(commands[0].contains("kill -9 4242"));
+
+        let server = SshMcpServer::with_executor(test_config(&["--disable-sudo"]), executor);
+        let params = KillProcessParams {
+            pid: 4242,
+            signal: None,
+            sudo: Some(true),
+        };
+        let result = server.kill_process(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("sudo is disabled"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_dedupe() {
+        let output = CommandOutput {
+            stdout: "64 bytes\n64 bytes\n64 bytes\ndone\n".to_string(),
+            ..CommandOutput::new()
+        };
+
+        let server =
+            SshMcpServer::with_executor(test_config(&[]), MockExecutor::new(output.clone()));
+        let result = server
+            .execute_command(
+                "ping -c 3 host",
+                &[],
+                ExecOptions {
+                    dedupe: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(result_text(&result), "64 bytes (repeated 3 times)\ndone\n");
+
+        let config = test_config(&["--collapse-repeats"]);
+        let server = SshMcpServer::with_executor(config, MockExecutor::new(output));
+        let result = server
+            .execute_command(
+                "ping -c 3 host",
+                &[],
+                ExecOptions {
+                    dedupe: Some(false),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(result_text(&result), "64 bytes\n64 bytes\n64 bytes\ndone\n");
+    }
 
-        Tool::new(
-            "exec",
-            "Execute a shell command on the remote SSH server and return the output.",
-            Arc::new(schema_obj),
-        )
+    #[tokio::test]
+    async fn test_no_elevation_on_exec() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--no-elevation-on-exec"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        server
+            .execute_command("id -u", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        server.execute_sudo_command("id -u", false).await.unwrap();
+        assert_eq!(
+            executor.commands(),
+            vec!["id -u <as user>", "sudo -n sh -c 'id -u'"]
+        );
     }
 
-    /// Build sudo-exec tool definition
-    fn sudo_exec_tool() -> Tool {
-        let schema = serde_json::json!({
-            "type": "object",
-            "properties": {
-                "command": {
-                    "type": "string",
-                    "description": "Shell command to execute with sudo on the remote SSH server"
-                }
-            },
-            "required": ["command"]
+    #[tokio::test]
+    async fn test_no_elevation_on_exec_covers_other_tools() {
+        let executor = MockExecutor::new(CommandOutput {
+            exit_code: Some(0),
+            ..CommandOutput::new()
         });
+        let config = test_config(&["--no-elevation-on-exec"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
 
-        // Convert Value to JsonObject (Map<String, Value>)
-        let schema_obj = schema.as_object().cloned().unwrap_or_default();
+        let params = ExecDiffParams {
+            key: "uid".to_string(),
+            command: "id -u".to_string(),
+            context: None,
+        };
+        server.execute_diff(params).await.unwrap();
+        let params = ListProcessesParams { name: None };
+        server.list_processes(params).await.unwrap();
+        assert_eq!(
+            executor.commands(),
+            vec![
+                "id -u <as user>".to_string(),
+                format!("{} <as user>", PS_COMMAND)
+            ]
+        );
+    }
 
-        Tool::new(
-            "sudo-exec",
-            "Execute a shell command on the remote SSH server using sudo. Will use sudo password if provided, otherwise assumes passwordless sudo.",
-            Arc::new(schema_obj),
-        )
+    #[tokio::test]
+    async fn test_execute_command_include_hash() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "x\nx\n".to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor);
+
+        let result = server
+            .execute_command("uptime", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.structured_content, None);
+
+        // The hash covers stdout as received, not the collapsed text
+        let options = ExecOptions {
+            include_hash: Some(true),
+            dedupe: Some(true),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("uptime", &[], options)
+            .await
+            .unwrap();
+        assert_eq!(result_text(&result), "x (repeated 2 times)\n");
+        assert_eq!(
+            result.structured_content.unwrap()["stdout_sha256"],
+            sha256_hex(b"x\nx\n")
+        );
     }
-}
 
-impl ServerHandler for SshMcpServer {
-    /// Return server information
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::LATEST,
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some(format!(
-                "SSH MCP Server v{} - Execute commands on {}@{}:{}",
-                env!("CARGO_PKG_VERSION"),
-                self.config.user,
-                self.config.host,
-                self.config.port,
-            )),
+    #[tokio::test]
+    async fn test_execute_command_parsers() {
+        struct Lines;
+        impl OutputParser for Lines {
+            fn parse(&self, stdout: &str) -> Option<serde_json::Value> {
+                Some(stdout.lines().count().into())
+            }
         }
-    }
 
-    /// List available tools
-    async fn list_tools(
-        &self,
-        _request: Option<PaginatedRequestParam>,
-        _context: RequestContext<RoleServer>,
-    ) -> std::result::Result<ListToolsResult, McpError> {
-        debug!("list_tools called");
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "total used free\nMem: 100 40 60\n".to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor)
+            .with_parser("cat /etc/hosts", Arc::new(Lines));
 
-        let mut tools = vec![Self::exec_tool()];
+        let options = ExecOptions {
+            include_hash: Some(true),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("free -b", &[], options)
+            .await
+            .unwrap();
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["parser"], "free");
+        assert_eq!(structured["parsed"]["mem"]["used"], 40);
+        assert!(structured.get("stdout_sha256").is_some());
 
-        // Add sudo-exec tool if enabled
-        if !self.config.disable_sudo {
-            tools.push(Self::sudo_exec_tool());
+        let result = server
+            .execute_command("cat /etc/hosts", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.structured_content.unwrap()["parsed"], 2);
+
+        // Piped or unknown commands keep only the raw text
+        for command in ["free | head -1", "uptime"] {
+            let result = server
+                .execute_command(command, &[], ExecOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(result.structured_content, None);
         }
+    }
 
-        Ok(ListToolsResult {
-            tools,
-            next_cursor: None,
-            meta: Default::default(),
-        })
+    #[tokio::test]
+    async fn test_exec_stdin_from() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "/var/log/a.log\n".to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let options = ExecOptions {
+            keep_output: Some(true),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("find /var/log -name '*.log'", &[], options)
+            .await
+            .unwrap();
+        let structured = result.structured_content.unwrap();
+        let id = structured["result_id"].as_str().unwrap();
+
+        let options = ExecOptions {
+            stdin_from: Some(id),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("xargs du -ch", &[], options)
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(
+            executor.commands().last().unwrap(),
+            "xargs du -ch <<< \"/var/log/a.log\\n\""
+        );
+
+        let options = ExecOptions {
+            stdin_from: Some("r999"),
+            ..Default::default()
+        };
+        let result = server.execute_command("wc -l", &[], options).await.unwrap();
+        assert!(result_text(&result).contains("Unknown result_id"));
+
+        let env = vec![("A".to_string(), "1".to_string())];
+        let options = ExecOptions {
+            stdin_from: Some(id),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("wc -l", &env, options)
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(executor.commands().len(), 2);
     }
 
-    /// Call a tool
-    async fn call_tool(
-        &self,
-        request: CallToolRequestParam,
-        _context: RequestContext<RoleServer>,
-    ) -> std::result::Result<CallToolResult, McpError> {
-        let tool_name: &str = request.name.as_ref();
-        debug!("call_tool called: {:?}", tool_name);
+    #[tokio::test]
+    async fn test_fail_on_stderr() {
+        let output = CommandOutput {
+            stdout: "built".to_string(),
+            stderr: "warning: unused variable".to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        };
 
-        let args = request.arguments.unwrap_or_default();
+        let server =
+            SshMcpServer::with_executor(test_config(&[]), MockExecutor::new(output.clone()));
+        let result = server
+            .execute_command("make", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        let result = server
+            .execute_command(
+                "make",
+                &[],
+                ExecOptions {
+                    fail_on_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
 
-        // Route to the appropriate tool
-        match tool_name {
-            "exec" => {
-                // Extract command from arguments
-                let command = args
-                    .get("command")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| {
-                        McpError::invalid_params("Missing required parameter: command", None)
-                    })?;
+        let config = test_config(&["--fail-on-stderr"]);
+        let server = SshMcpServer::with_executor(config, MockExecutor::new(output));
+        let result = server
+            .execute_command("make", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let result = server
+            .execute_command(
+                "make",
+                &[],
+                ExecOptions {
+                    fail_on_stderr: Some(false),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+    }
 
-                self.execute_command(command).await
-            }
-            "sudo_exec" | "sudo-exec" => {
-                // Check if sudo is enabled
-                if self.config.disable_sudo {
-                    return Err(McpError::invalid_params("sudo-exec tool is disabled", None));
-                }
+    #[tokio::test]
+    async fn test_exit_code_map() {
+        let output = |code| CommandOutput {
+            stdout: "out".to_string(),
+            exit_code: Some(code),
+            ..CommandOutput::new()
+        };
+        let config = test_config(&["--exit-code-map=rsync:24=success,grep:1=warning,2=error"]);
 
-                // Extract command from arguments
-                let command = args
-                    .get("command")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| {
-                        McpError::invalid_params("Missing required parameter: command", None)
-                    })?;
+        let server = SshMcpServer::with_executor(config.clone(), MockExecutor::new(output(24)));
+        let result = server
+            .execute_command("rsync -a src/ dst/", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(result.structured_content.unwrap()["exit_class"], "success");
+        // The mapping is per command
+        let result = server
+            .execute_command("cp -a src/ dst/", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(result.structured_content, None);
 
-                self.execute_sudo_command(command).await
-            }
-            _ => Err(McpError::invalid_params(
-                format!("Unknown tool: {}", tool_name),
-                None,
-            )),
+        let server = SshMcpServer::with_executor(config, MockExecutor::new(output(1)));
+        let result = server
+            .execute_command("grep -q x file", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        assert!(result_text(&result).contains("[warning: exit code 1 (see --exit-code-map)]"));
+        assert_eq!(result.structured_content.unwrap()["exit_class"], "warning");
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_with_umask() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        server
+            .execute_command(
+                "touch secret",
+                &[],
+                ExecOptions {
+                    umask: Some("077"),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["(umask 077; touch secret\n)"]);
+
+        let result = server
+            .execute_command(
+                "touch x",
+                &[],
+                ExecOptions {
+                    umask: Some("0999"),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("umask"));
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_read_window() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "line 1\nline 2\n".to_string(),
+            exit_code: Some(124),
+            ..CommandOutput::new()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let options = ExecOptions {
+            read_duration_ms: Some(2_000),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("tail -f /var/log/app.log", &[], options)
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(
+            executor.commands(),
+            vec!["timeout 2.000 sh -c 'tail -f /var/log/app.log'"]
+        );
+        let text = result_text(&result);
+        assert!(text.starts_with("line 1\nline 2\n"));
+        assert!(text.contains("[read window of 2000ms ended, command was stopped]"));
+
+        // Exit code 124 is only special with a read window
+        let result = server
+            .execute_command("tail -f /var/log/app.log", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+
+        let options = ExecOptions {
+            read_duration_ms: Some(0),
+            ..Default::default()
+        };
+        let result = server
+            .execute_command("tail -f x", &[], options)
+            .await
+            .unwrap();
+        assert!(result_text(&result).contains("read_duration_ms must be between"));
+    }
+
+    #[tokio::test]
+    async fn test_auto_sudo_on_denied() {
+        let denied = CommandOutput {
+            stderr: "cat: /etc/shadow: Permission denied\n".to_string(),
+            exit_code: Some(1),
+            ..CommandOutput::new()
+        };
+        let elevated = CommandOutput {
+            stdout: "root:*:19000::::::\n".to_string(),
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        };
+        let executor = MockExecutor::with_sudo_output(denied.clone(), elevated);
+        let config = test_config(&["--auto-sudo-on-denied"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        let result = server
+            .execute_command("cat /etc/shadow", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(result_text(&result), "root:*:19000::::::\n");
+        assert_eq!(
+            result.structured_content,
+            Some(serde_json::json!({ "auto_elevated": true }))
+        );
+        assert_eq!(
+            executor.commands(),
+            vec!["cat /etc/shadow", "sudo -n sh -c 'cat /etc/shadow'"]
+        );
+
+        // A failed retry returns the original error
+        let executor = MockExecutor::with_sudo_output(denied.clone(), denied.clone());
+        let config = test_config(&["--auto-sudo-on-denied"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+        let result = server
+            .execute_command("cat /etc/shadow", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("Permission denied"));
+        assert!(result.structured_content.is_none());
+        assert_eq!(executor.commands().len(), 2);
+
+        // Off by default, and never with sudo disabled
+        for extra in [&[][..], &["--auto-sudo-on-denied", "--disable-sudo"][..]] {
+            let executor = MockExecutor::new(denied.clone());
+            let server = SshMcpServer::with_executor(test_config(extra), executor.clone());
+            let result = server
+                .execute_command("cat /etc/shadow", &[], ExecOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(result.is_error, Some(true));
+            assert_eq!(executor.commands(), vec!["cat /etc/shadow"]);
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn test_execute_command_in_container() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let options = ExecOptions {
+            container: Some("web"),
+            umask: Some("077"),
+            ..Default::default()
+        };
+        server
+            .execute_command("cat /etc/hostname", &[], options)
+            .await
+            .unwrap();
+        let commands = executor.commands();
+        assert!(commands[0].starts_with("if command -v docker >/dev/null 2>&1; then "));
+        assert!(commands[0]
+            .contains("; then docker exec web sh -c '(umask 077; cat /etc/hostname\n)'; else "));
+
+        let options = ExecOptions {
+            container: Some("web; reboot"),
+            ..Default::default()
+        };
+        let result = server.execute_command("id", &[], options).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("Invalid container"));
+
+        let env = vec![("A".to_string(), "1".to_string())];
+        let options = ExecOptions {
+            container: Some("web"),
+            ..Default::default()
+        };
+        let result = server.execute_command("id", &env, options).await.unwrap();
+        assert!(result_text(&result).contains("env cannot be combined with container"));
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_pty_commands() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        for command in ["ls -l", "top -bn1", "sudo -l"] {
+            server
+                .execute_command(command, &[], ExecOptions::default())
+                .await
+                .unwrap();
+        }
+        // An explicit width wins over the default one
+        let options = ExecOptions {
+            term_width: Some(120),
+            ..Default::default()
+        };
+        server.execute_command("htop", &[], options).await.unwrap();
+        assert_eq!(
+            executor.commands(),
+            vec![
+                "ls -l".to_string(),
+                format!("export COLUMNS=80; {}top -bn1", NO_PAGER_EXPORTS),
+                format!("export COLUMNS=80; {}sudo -l", NO_PAGER_EXPORTS),
+                format!("export COLUMNS=120; {}htop", NO_PAGER_EXPORTS),
+            ]
+        );
+
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--pty-commands=", "--pty-cols=100"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+        server
+            .execute_command("top -bn1", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["top -bn1"]);
+    }
+
+    #[tokio::test]
+    async fn test_long_output_keeps_head_and_tail() {
+        let stdout: String = (1..=50).map(|n| format!("{}\n", n)).collect();
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: stdout.clone(),
+            stderr: "error: disk full\n".to_string(),
+            exit_code: Some(1),
+            ..CommandOutput::new()
+        });
+        let config = test_config(&[
+            "--max-output-lines=10",
+            "--output-head-lines=2",
+            "--output-tail-lines=3",
+        ]);
+        let server = SshMcpServer::with_executor(config, executor);
+
+        let options = ExecOptions {
+            include_hash: Some(true),
+            ..Default::default()
+        };
+        let result = server.execute_command("make", &[], options).await.unwrap();
+        assert_eq!(
+            result_text(&result),
+            "1\n2\n... [45 lines omitted] ...\n48\n49\n50\n\n--- stderr ---\nerror: disk full\n"
+        );
+        // The hash covers the full output
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["stdout_sha256"], sha256_hex(stdout.as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn test_run_command_template() {
+        let executor = MockExecutor::new(CommandOutput {
+            exit_code: Some(0),
+            ..CommandOutput::new()
+        });
+        let config = test_config(&["--only-command=systemctl {action} {service}"]);
+        let template = config.only_command.clone().unwrap();
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        let args = serde_json::json!({ "action": "restart", "service": "nginx; reboot" });
+        let result = server
+            .run_command(&template, args.as_object().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+
+        let args = serde_json::json!({ "action": "--help", "service": "nginx" });
+        let result = server
+            .run_command(&template, args.as_object().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            executor.commands(),
+            vec!["systemctl 'restart' 'nginx; reboot'"]
+        );
+
+        let tool = SshMcpServer::run_command_tool(&template);
+        assert_eq!(tool.name, "run-command");
+        assert_eq!(
+            tool.input_schema["required"],
+            serde_json::json!(["action", "service"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disable_pager() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let config = test_config(&["--disable-pager"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        server
+            .execute_command("git log -3", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        server
+            .execute_sudo_command("systemctl status nginx", false)
+            .await
+            .unwrap();
+        let commands = executor.commands();
+        assert_eq!(commands[0], format!("{}git log -3", NO_PAGER_EXPORTS));
+        assert_eq!(
+            commands[1],
+            format!("sudo -n sh -c '{}systemctl status nginx'", NO_PAGER_EXPORTS)
+        );
+        // History shows the commands as given
+        let history = server.command_history(None).structured_content.unwrap();
+        assert_eq!(history["entries"][0]["command"], "git log -3");
+
+        // Without a terminal pagers stay untouched by default
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        server
+            .execute_command("git log -3", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(executor.commands(), vec!["git log -3"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_with_term_width() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let result = server
+            .execute_command(
+                "ps aux",
+                &[],
+                ExecOptions {
+                    term_width: Some(200),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            executor.commands(),
+            vec![format!("export COLUMNS=200; {}ps aux", NO_PAGER_EXPORTS)]
+        );
+        assert!(result.meta.is_none());
+
+        let result = server
+            .execute_command(
+                "ps",
+                &[],
+                ExecOptions {
+                    term_width: Some(0),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reports_every_host() {
+        let primary = MockExecutor::new(CommandOutput {
+            stdout: "ok\n".to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        });
+        let broken = MockExecutor::new(CommandOutput {
+            stderr: "not found\n".to_string(),
+            exit_code: Some(127),
+            ..Default::default()
+        });
+        let server = SshMcpServer::with_executor(test_config(&[]), primary.clone())
+            .with_broadcast_target("web2:22", broken.clone());
 
-    // Note: Real tests would require a mock SSH server or testcontainers
-    // These are placeholder tests
+        let result = server.execute_broadcast("uptime").await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let results = &result.structured_content.unwrap()["results"];
+        assert_eq!(results[0]["host"], "local");
+        assert_eq!(results[0]["stdout"], "ok\n");
+        assert_eq!(results[1]["host"], "web2:22");
+        assert_eq!(results[1]["exit_code"], 127);
+        assert_eq!(primary.commands(), vec!["uptime"]);
+        assert_eq!(broken.commands(), vec!["uptime"]);
+    }
+
+    #[tokio::test]
+    async fn test_history_excludes_sudo_wrapping() {
+        let executor = MockExecutor::new(CommandOutput {
+            exit_code: Some(0),
+            ..Default::default()
+        });
+        let server = SshMcpServer::with_executor(test_config(&["--history-size=2"]), executor);
+
+        server
+            .execute_command("uptime", &[], ExecOptions::default())
+            .await
+            .unwrap();
+        server
+            .execute_sudo_command("apt update", false)
+            .await
+            .unwrap();
+
+        let entries = server.history.recent(None);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].tool, "exec");
+        assert_eq!(entries[1].tool, "sudo-exec");
+        assert_eq!(entries[1].command, "apt update");
+        assert_eq!(entries[1].exit_code, Some(0));
+    }
 
     #[test]
     fn test_server_info() {
@@ -372,17 +5258,144 @@ mod tests {
         assert!(!env!("CARGO_PKG_VERSION").is_empty());
     }
 
+    #[test]
+    fn test_label_prefixes_instructions() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        let instructions = server.get_info().instructions.unwrap();
+        assert!(instructions.starts_with("SSH MCP Server v"));
+
+        let server =
+            SshMcpServer::with_executor(test_config(&["--label=prod-db"]), executor.clone());
+        let instructions = server.get_info().instructions.unwrap();
+        assert!(instructions.starts_with("[prod-db] SSH MCP Server v"));
+    }
+
+    #[tokio::test]
+    async fn test_welcome_command_extends_instructions() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "Linux web01 6.1.0\nweb01\n".to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        });
+        let config = test_config(&["--welcome-command=uname -a; hostname"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        server.run_welcome_command().await;
+        assert_eq!(executor.commands(), vec!["uname -a; hostname"]);
+        let instructions = server.get_info().instructions.unwrap();
+        assert!(instructions.ends_with("Host information:\nLinux web01 6.1.0\nweb01"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_command_runs_once() {
+        let executor = MockExecutor::new(CommandOutput {
+            exit_code: Some(0),
+            ..Default::default()
+        });
+        let config = test_config(&["--shutdown-command=rm -rf /tmp/agent-work"]);
+        let server = SshMcpServer::with_executor(config, executor.clone());
+
+        server.shutdown().await;
+        server.clone().shutdown().await;
+        assert_eq!(executor.commands(), vec!["rm -rf /tmp/agent-work"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_welcome_command_keeps_static_instructions() {
+        let executor = MockExecutor::new(CommandOutput {
+            stdout: "partial".to_string(),
+            exit_code: Some(127),
+            ..Default::default()
+        });
+        let config = test_config(&["--welcome-command=hostnamectl"]);
+        let server = SshMcpServer::with_executor(config, executor);
+
+        server.run_welcome_command().await;
+        let instructions = server.get_info().instructions.unwrap();
+        assert!(!instructions.contains("Host information"));
+    }
+
+    #[test]
+    fn test_protocol_version_pinning() {
+        let executor = MockExecutor::new(CommandOutput::new());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+        assert_eq!(server.get_info().protocol_version, ProtocolVersion::LATEST);
+
+        for version in crate::config::SUPPORTED_PROTOCOL_VERSIONS {
+            let flag = format!("--protocol-version={}", version);
+            let server = SshMcpServer::with_executor(test_config(&[&flag]), executor.clone());
+            assert_eq!(server.get_info().protocol_version.to_string(), *version);
+        }
+    }
+
     #[test]
     fn test_exec_tool_definition() {
-        let tool = SshMcpServer::exec_tool();
+        let tool = SshMcpServer::exec_tool(None);
         assert_eq!(tool.name.as_ref(), "exec");
-        assert!(tool.description.is_some());
+        assert_eq!(tool.description.as_deref(), Some(DEFAULT_EXEC_DESCRIPTION));
     }
 
     #[test]
     fn test_sudo_exec_tool_definition() {
-        let tool = SshMcpServer::sudo_exec_tool();
+        let tool = SshMcpServer::sudo_exec_tool(None);
         assert_eq!(tool.name.as_ref(), "sudo-exec");
-        assert!(tool.description.is_some());
+        assert_eq!(tool.description.as_deref(), Some(DEFAULT_SUDO_DESCRIPTION));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_key_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let key = std::env::temp_dir().join("ssh-mcp-test-key-permissions");
+        std::fs::write(&key, "key").unwrap();
+        let chmod =
+            |mode| std::fs::set_permissions(&key, std::fs::Permissions::from_mode(mode)).unwrap();
+
+        chmod(0o600);
+        assert!(check_key_permissions(&key, KeyPermCheck::Error).is_ok());
+
+        chmod(0o644);
+        let err = check_key_permissions(&key, KeyPermCheck::Error).unwrap_err();
+        assert!(err.to_string().contains("Permissions 0644"));
+        assert!(err.to_string().contains("chmod 600"));
+        assert!(check_key_permissions(&key, KeyPermCheck::Warn).is_ok());
+        assert!(check_key_permissions(&key, KeyPermCheck::Ignore).is_ok());
+
+        let _ = std::fs::remove_file(&key);
+    }
+
+    #[test]
+    fn test_append_file_tool_definition() {
+        let tool = SshMcpServer::append_file_tool();
+        assert_eq!(tool.name.as_ref(), "append-file");
+        assert_eq!(
+            tool.input_schema["required"],
+            serde_json::json!(["path", "content"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_append_file_requires_ssh() {
+        let executor = MockExecutor::new(CommandOutput::default());
+        let server = SshMcpServer::with_executor(test_config(&[]), executor.clone());
+
+        let params = AppendFileParams {
+            path: "/tmp/log".to_string(),
+            content: "line\n".to_string(),
+        };
+        let result = server.append_to_file(params).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(executor.commands().is_empty());
+    }
+
+    #[test]
+    fn test_tool_description_override() {
+        let tool = SshMcpServer::exec_tool(Some("Prefer read-only commands."));
+        assert_eq!(
+            tool.description.as_deref(),
+            Some("Prefer read-only commands.")
+        );
     }
 }
@@ -0,0 +1,154 @@
+//! Circuit breaker for connection attempts
+//!
+//! When a host is down or rejecting us, every tool call would otherwise pay
+//! the full connect timeout again. After `threshold` consecutive failed
+//! connection attempts the breaker opens: calls fail straight away with the
+//! last error until the cooldown has passed. The next call then tries the
+//! host once more (half-open), and either closes the breaker or opens it for
+//! another cooldown.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::{Result, SshMcpError};
+
+/// Whether connection attempts are let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Attempts go through as usual
+    Closed,
+    /// Attempts fail immediately until the cooldown has passed
+    Open,
+    /// The cooldown has passed; the next attempt decides
+    HalfOpen,
+}
+
+/// Counts consecutive connection failures and opens after too many
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<Failures>,
+}
+
+#[derive(Debug, Default)]
+struct Failures {
+    count: u32,
+    opened_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl CircuitBreaker {
+    /// Open after `threshold` consecutive failures, for `cooldown` each time
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            inner: Mutex::new(Failures::default()),
+        }
+    }
+
+    /// Current state of the breaker
+    pub fn state(&self) -> CircuitState {
+        let failures = self.inner.lock().unwrap();
+        match failures.opened_at {
+            None => CircuitState::Closed,
+            Some(opened) if opened.elapsed() < self.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Consecutive failures so far
+    pub fn failures(&self) -> u32 {
+        self.inner.lock().unwrap().count
+    }
+
+    /// Fail fast while the breaker is open
+    pub fn check(&self) -> Result<()> {
+        let failures = self.inner.lock().unwrap();
+        let Some(opened) = failures.opened_at else {
+            return Ok(());
+        };
+        let remaining = self.cooldown.saturating_sub(opened.elapsed());
+        if remaining.is_zero() {
+            return Ok(());
+        }
+
+        Err(SshMcpError::connection(format!(
+            "circuit open, host unhealthy: {} consecutive connection failures (last: {}). \
+             Not trying again for another {}s",
+            failures.count,
+            failures.last_error.as_deref().unwrap_or("unknown error"),
+            remaining.as_secs().max(1)
+        )))
+    }
+
+    /// Record the outcome of a connection attempt
+    ///
+    /// A success closes the breaker. A failure that reaches the threshold
+    /// opens it, as does any failure of the attempt made once half-open.
+    pub fn record<T>(&self, result: &Result<T>) {
+        let mut failures = self.inner.lock().unwrap();
+        match result {
+            Ok(_) => *failures = Failures::default(),
+            Err(e) => {
+                failures.count = failures.count.saturating_add(1);
+                failures.last_error = Some(e.to_string());
+                if failures.count >= self.threshold {
+                    failures.opened_at = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed() -> Result<()> {
+        Err(SshMcpError::connection("Connection refused"))
+    }
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let circuit = CircuitBreaker::new(3, Duration::from_secs(60));
+        for _ in 0..2 {
+            circuit.record(&failed());
+            assert!(circuit.check().is_ok());
+        }
+        assert_eq!(circuit.state(), CircuitState::Closed);
+
+        circuit.record(&failed());
+        assert_eq!(circuit.state(), CircuitState::Open);
+        let err = circuit.check().unwrap_err().to_string();
+        assert!(err.contains("circuit open, host unhealthy"));
+        assert!(err.contains("3 consecutive"));
+        assert!(err.contains("Connection refused"));
+    }
+
+    #[test]
+    fn test_success_resets() {
+        let circuit = CircuitBreaker::new(2, Duration::from_secs(60));
+        circuit.record(&failed());
+        circuit.record(&Ok(()));
+        assert_eq!(circuit.failures(), 0);
+        circuit.record(&failed());
+        assert!(circuit.check().is_ok());
+    }
+
+    #[test]
+    fn test_half_open_after_cooldown() {
+        let circuit = CircuitBreaker::new(1, Duration::ZERO);
+        circuit.record(&failed());
+        // The cooldown is over at once, so the next attempt goes through
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+        assert!(circuit.check().is_ok());
+
+        circuit.record(&Ok(()));
+        assert_eq!(circuit.state(), CircuitState::Closed);
+    }
+}
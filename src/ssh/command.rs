@@ -3,18 +3,24 @@
 //! Provides the `CommandOutput` struct and `exec_command` functionality
 //! for executing commands over an SSH connection with timeout support.
 
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
-use russh::ChannelMsg;
+use russh::client;
+use russh::{Channel, ChannelMsg};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
 use tokio::time::timeout;
 use tracing::{debug, error, warn};
 
 use super::connection::SshConnectionManager;
+use super::env::{export_prefix, scoped_exports, EnvMethod, NO_PAGER_EXPORTS};
 use super::sanitize::escape_command_for_shell;
 use crate::error::{Result, SshMcpError};
+use crate::stream::push_streamed;
 
 /// Output from a command execution
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CommandOutput {
     /// Standard output from the command
     pub stdout: String,
@@ -22,8 +28,15 @@ pub struct CommandOutput {
     /// Standard error from the command
     pub stderr: String,
 
+    /// Extended data of types other than stderr, kept apart so it cannot
+    /// corrupt stdout (rarely used by servers)
+    pub extended: String,
+
     /// Exit code of the command (if available)
     pub exit_code: Option<u32>,
+
+    /// Whether reading stopped early because the per-call byte limit was hit
+    pub byte_limit_exceeded: bool,
 }
 
 impl CommandOutput {
@@ -49,6 +62,172 @@ impl CommandOutput {
     }
 }
 
+/// How long the forced-command probe may take after connecting
+const FORCED_COMMAND_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Explanation shared by the errors reported for a forced command
+const FORCED_COMMAND_HINT: &str = "The account appears to be restricted to a forced command \
+(command=\"...\" in authorized_keys or ForceCommand in sshd_config), so the server runs that \
+command instead of the requested one. Use a key or account without a forced command, or make \
+the forced command run $SSH_ORIGINAL_COMMAND.";
+
+/// Most characters of unexpected probe output quoted in the error
+const FORCED_COMMAND_SAMPLE_CHARS: usize = 200;
+
+/// Explanation for an account whose login shell refuses to run anything
+pub(crate) const NO_SHELL_HINT: &str = "The account appears to have no usable login shell \
+(e.g. /usr/sbin/nologin or /bin/false), so the server cannot run commands for it. Give the \
+account a shell such as /bin/sh (chsh -s /bin/sh <user>) or connect as another user.";
+
+/// Message `nologin` prints instead of starting a shell
+const NOLOGIN_MESSAGE: &str = "account is currently not available";
+
+/// Whether a shell or command ended the way a shell-less account does
+///
+/// `nologin` prints its refusal and exits non-zero; `/bin/false` exits
+/// non-zero, or the channel closes without an exit status, with nothing
+/// printed at all.
+pub(crate) fn looks_shell_less(printed: &str, exit_code: Option<u32>) -> bool {
+    printed.to_ascii_lowercase().contains(NOLOGIN_MESSAGE)
+        || (printed.trim().is_empty() && exit_code != Some(0))
+}
+
+/// Per-command completion marker for the su shell
+///
+/// The su shell is a long-lived interactive shell, so the end of a command's
+/// output is found by printing a marker after it. The marker is a 128-bit
+/// random token generated for each command; it is split in two inside the
+/// `printf` that prints it, so it never appears literally in the input sent
+/// to the shell. It is only accepted at the start of a line and followed by
+/// `:<exit status>` and a line break, which means output that happens to
+/// contain the token elsewhere (or a truncated copy) does not end collection.
+/// A stale marker left by an earlier, abandoned command never matches either.
+pub(crate) struct Sentinel {
+    token: String,
+}
+
+impl Sentinel {
+    /// Generate a new random marker
+    pub(crate) fn new() -> Self {
+        Self {
+            token: format!("SSHMCP{:032x}", rand::random::<u128>()),
+        }
+    }
+
+    /// Shell line that prints the marker and the previous command's exit status
+    pub(crate) fn shell_line(&self) -> String {
+        let (head, tail) = self.token.split_at(self.token.len() / 2);
+        format!("printf '\\n%s%s:%s\\n' '{}' '{}' \"$?\"", head, tail)
+    }
+
+    /// Locate the marker line in the collected output
+    ///
+    /// Returns the length of the command output preceding it (without the
+    /// line break the marker line starts with) and the reported exit status.
+    pub(crate) fn find(&self, buffer: &str, from: usize) -> Option<(usize, Option<u32>)> {
+        let mut from = from.min(buffer.len());
+        while !buffer.is_char_boundary(from) {
+            from -= 1;
+        }
+
+        buffer[from..]
+            .match_indices(&self.token)
+            .map(|(pos, _)| from + pos)
+            .find_map(|pos| {
+                let before = &buffer[..pos];
+                if !(before.is_empty() || before.ends_with('\n')) {
+                    return None;
+                }
+
+                let rest = buffer[pos + self.token.len()..].strip_prefix(':')?;
+                let line_end = rest.find('\n')?;
+                let status = rest[..line_end].trim_end_matches('\r');
+                if !status.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+
+                let output = before
+                    .strip_suffix('\n')
+                    .map(|b| b.strip_suffix('\r').unwrap_or(b))
+                    .unwrap_or(before);
+                Some((output.len(), status.parse().ok()))
+            })
+    }
+}
+
+/// Outcome of reading su shell output up to a [`Sentinel`]
+pub(crate) enum SentinelRead {
+    /// The marker arrived; `output` is everything printed before it
+    Complete {
+        output: String,
+        exit_code: Option<u32>,
+    },
+    /// The byte limit was hit before the marker arrived
+    LimitExceeded { output: String, bytes_read: usize },
+    /// The deadline passed before the marker arrived
+    TimedOut,
+}
+
+/// Read from an interactive shell channel until `sentinel` is printed
+pub(crate) async fn read_until_sentinel(
+    channel: &mut Channel<client::Msg>,
+    sentinel: &Sentinel,
+    deadline: tokio::time::Instant,
+    limit: Option<usize>,
+) -> Result<SentinelRead> {
+    let mut buffer = String::new();
+    let mut bytes_read = 0;
+
+    loop {
+        if tokio::time::Instant::now() > deadline {
+            return Ok(SentinelRead::TimedOut);
+        }
+
+        let wait_result = tokio::time::timeout(Duration::from_millis(500), channel.wait()).await;
+
+        match wait_result {
+            Ok(Some(ChannelMsg::Data { data })) => {
+                // Only the tail can hold a marker line that wasn't complete before;
+                // leave room for the line break, status digits and separators
+                let scan_from = buffer.len().saturating_sub(sentinel.token.len() + 16);
+                let exceeded = push_limited(&mut buffer, &data, &mut bytes_read, limit);
+
+                if let Some((end, exit_code)) = sentinel.find(&buffer, scan_from) {
+                    buffer.truncate(end);
+                    return Ok(SentinelRead::Complete {
+                        output: buffer.replace("\r\n", "\n"),
+                        exit_code,
+                    });
+                }
+
+                if exceeded {
+                    return Ok(SentinelRead::LimitExceeded {
+                        output: buffer.replace("\r\n", "\n"),
+                        bytes_read,
+                    });
+                }
+            }
+            Ok(Some(ChannelMsg::Close)) => {
+                return Err(SshMcpError::connection(
+                    "Channel closed during command execution",
+                ));
+            }
+            Ok(Some(_)) => {
+                // Ignore other messages
+            }
+            Ok(None) => {
+                return Err(SshMcpError::connection(
+                    "Channel ended during command execution",
+                ));
+            }
+            Err(_) => {
+                // Timeout on wait, continue loop
+                continue;
+            }
+        }
+    }
+}
+
 impl SshConnectionManager {
     /// Execute a command over SSH
     ///
@@ -72,126 +251,328 @@ impl SshConnectionManager {
         command: &str,
         timeout_duration: Duration,
     ) -> Result<CommandOutput> {
-        // Ensure we're connected
-        self.ensure_connected().await?;
+        self.exec_command_with_env(command, &[], None, timeout_duration)
+            .await
+            .map(|(output, _)| output)
+    }
+
+    /// Execute a command with extra environment variables
+    ///
+    /// Variables the server accepts through `env` requests (see its
+    /// `AcceptEnv`) are sent that way; the rest, and all of them in the
+    /// elevated su shell, are exported on the command line. In the su shell
+    /// that happens in a subshell, so they don't leak into later commands.
+    /// Returns how each variable was delivered, in the order given.
+    ///
+    /// With `term_width`, the command runs on a PTY of that many columns: the
+    /// su shell's terminal is resized for the duration of the command, and an
+    /// exec channel requests a PTY of its own.
+    pub async fn exec_command_with_env(
+        &self,
+        command: &str,
+        env: &[(String, String)],
+        term_width: Option<u16>,
+        timeout_duration: Duration,
+    ) -> Result<(CommandOutput, Vec<(String, EnvMethod)>)> {
+        // Ensure we're connected; the session is not recycled until we finish
+        let _in_flight = self.begin_command().await?;
 
-        // Check if we have an elevated su shell
-        if self.is_elevated() && self.has_su_channel().await {
-            debug!("Using elevated su shell for command execution");
-            return self.exec_via_su_shell(command, timeout_duration).await;
+        // Check if we have an elevated su shell. The su channel lock is held for
+        // the whole command so elevation changes wait until it has finished.
+        if self.is_elevated() {
+            let mut su_channel = self.su_channel.lock().await;
+            if su_channel.is_some() {
+                debug!("Using elevated su shell for command execution");
+                let command = scoped_exports(env, command);
+                let methods = env
+                    .iter()
+                    .map(|(name, _)| (name.clone(), EnvMethod::Shell))
+                    .collect();
+                let resized = match (term_width, su_channel.as_ref()) {
+                    (Some(cols), Some(channel)) => self.resize_pty(channel, cols).await,
+                    _ => false,
+                };
+                let result = self
+                    .exec_via_su_shell(&mut su_channel, &command, timeout_duration)
+                    .await;
+                if resized {
+                    if let Some(channel) = su_channel.as_ref() {
+                        self.resize_pty(channel, self.config.pty_cols).await;
+                    }
+                }
+                return result.map(|output| (output, methods));
+            }
         }
 
+        self.exec_on_channel(command, env, term_width, timeout_duration)
+            .await
+    }
+
+    /// Execute a command as the login user, even when an elevated su shell
+    /// is available
+    ///
+    /// Otherwise the same as [`exec_command_with_env`](Self::exec_command_with_env).
+    pub async fn exec_command_as_user(
+        &self,
+        command: &str,
+        env: &[(String, String)],
+        term_width: Option<u16>,
+        timeout_duration: Duration,
+    ) -> Result<(CommandOutput, Vec<(String, EnvMethod)>)> {
+        let _in_flight = self.begin_command().await?;
+        self.exec_on_channel(command, env, term_width, timeout_duration)
+            .await
+    }
+
+    /// Execute a command on a new exec channel, sending the variables the
+    /// server accepts as `env` requests
+    async fn exec_on_channel(
+        &self,
+        command: &str,
+        env: &[(String, String)],
+        term_width: Option<u16>,
+        timeout_duration: Duration,
+    ) -> Result<(CommandOutput, Vec<(String, EnvMethod)>)> {
+        let names: Vec<&str> = env.iter().map(|(name, _)| name.as_str()).collect();
+        let accepted = if names.is_empty() {
+            Default::default()
+        } else {
+            self.accepted_env_names(&names).await
+        };
+        let (protocol, shell): (Vec<_>, Vec<_>) = env
+            .iter()
+            .cloned()
+            .partition(|(name, _)| accepted.contains(name));
+        let methods = env
+            .iter()
+            .map(|(name, _)| {
+                let method = if accepted.contains(name) {
+                    EnvMethod::Protocol
+                } else {
+                    EnvMethod::Shell
+                };
+                (name.clone(), method)
+            })
+            .collect();
+
         // Normal exec via new channel
         debug!("Using normal exec channel for command execution");
-        self.exec_via_channel(command, timeout_duration).await
+        let command = format!("{}{}", export_prefix(&shell), command);
+        self.exec_via_channel(&command, &protocol, term_width, None, timeout_duration)
+            .await
+            .map(|output| (output, methods))
+    }
+
+    /// Change the width of a PTY channel, returning whether it succeeded
+    async fn resize_pty(&self, channel: &Channel<client::Msg>, cols: u16) -> bool {
+        match channel.window_change(cols.into(), 24, 0, 0).await {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("Failed to resize PTY to {} columns: {}", cols, e);
+                false
+            }
+        }
     }
 
     /// Execute command via the elevated su shell (PTY)
+    ///
+    /// `su_channel` is the contents of the locked `su_channel` mutex; the caller
+    /// keeps the lock for the duration of the command. If the shell becomes
+    /// unusable it is removed from the slot and elevation is cleared.
     async fn exec_via_su_shell(
         &self,
+        su_channel: &mut Option<Channel<client::Msg>>,
         command: &str,
         timeout_duration: Duration,
     ) -> Result<CommandOutput> {
-        // Take the channel from the mutex (we'll put it back after)
-        let mut channel = {
-            let mut guard = self.su_channel.lock().await;
-            guard
-                .take()
-                .ok_or_else(|| SshMcpError::connection("No su channel available"))?
+        let channel = su_channel
+            .as_mut()
+            .ok_or_else(|| SshMcpError::connection("No su channel available"))?;
+
+        // The login shell started by su resets the environment, so the locale
+        // is exported as part of the command line
+        let command = match self.config.locale {
+            Some(ref locale) => format!(
+                "export LANG='{0}' LC_ALL='{0}'; {1}",
+                escape_command_for_shell(locale),
+                command
+            ),
+            None => command.to_string(),
         };
 
-        // Send command
-        if let Err(e) = channel.data(format!("{}\n", command).as_bytes()).await {
-            // Put channel back before returning error
-            let mut guard = self.su_channel.lock().await;
-            *guard = Some(channel);
+        // Send the command followed by the completion marker on its own line,
+        // so it runs even if the command line ends in a comment or `&`
+        let rekeys_before = self.expected_rekeys();
+        let sentinel = Sentinel::new();
+        let input = format!("{}\n{}\n", command, sentinel.shell_line());
+        if let Err(e) = channel.data(input.as_bytes()).await {
             return Err(SshMcpError::connection(format!(
                 "Failed to send command: {}",
                 e
             )));
         }
 
-        // Collect output until we see a root prompt (#)
-        let mut buffer = String::new();
         let deadline = tokio::time::Instant::now() + timeout_duration;
+        let result =
+            match read_until_sentinel(channel, &sentinel, deadline, self.config.max_output_bytes)
+                .await
+            {
+                Ok(SentinelRead::Complete { output, exit_code }) => Ok(CommandOutput {
+                    stdout: output,
+                    stderr: String::new(),
+                    extended: String::new(),
+                    exit_code,
+                    byte_limit_exceeded: false,
+                }),
+                Ok(SentinelRead::LimitExceeded { output, bytes_read }) => {
+                    warn!(
+                        "su shell output exceeded {} bytes, interrupting command",
+                        bytes_read
+                    );
+                    Ok(CommandOutput {
+                        stdout: output,
+                        stderr: String::new(),
+                        extended: String::new(),
+                        exit_code: None,
+                        byte_limit_exceeded: true,
+                    })
+                }
+                Ok(SentinelRead::TimedOut) => {
+                    self.note_timeout(rekeys_before);
+                    Err(SshMcpError::Timeout(timeout_duration.as_millis() as u64))
+                }
+                Err(e) => Err(e),
+            };
 
-        let result = loop {
-            if tokio::time::Instant::now() > deadline {
-                break Err(SshMcpError::Timeout(timeout_duration.as_millis() as u64));
+        // Drop the shell if the command is still running (cut off or timed
+        // out) or the channel went away; its state is no longer predictable
+        let shell_lost = match &result {
+            Ok(output) => output.byte_limit_exceeded,
+            Err(SshMcpError::Connection(_)) | Err(SshMcpError::Timeout(_)) => true,
+            Err(_) => false,
+        };
+        if shell_lost {
+            if let Some(channel) = su_channel.take() {
+                // Interrupt the producer before closing
+                let _ = channel.data(b"\x03".as_slice()).await;
+                let _ = channel.close().await;
             }
+            self.is_elevated.store(false, Ordering::SeqCst);
+        }
 
-            let wait_result =
-                tokio::time::timeout(Duration::from_millis(500), channel.wait()).await;
-
-            match wait_result {
-                Ok(Some(msg)) => {
-                    match msg {
-                        ChannelMsg::Data { data } => {
-                            let text = String::from_utf8_lossy(&data);
-                            buffer.push_str(&text);
-
-                            // Check for root prompt - indicates command complete
-                            // Match # which indicates root prompt (may be followed by spaces, escape codes, etc)
-                            if buffer.contains('#') {
-                                // Extract output: remove the command echo and final prompt
-                                let lines: Vec<&str> = buffer.lines().collect();
-                                // First line is often the echoed command; last line is the prompt
-                                let output = if lines.len() > 2 {
-                                    lines[1..lines.len() - 1].join("\n")
-                                } else {
-                                    String::new()
-                                };
-
-                                break Ok(CommandOutput {
-                                    stdout: if output.is_empty() {
-                                        output
-                                    } else {
-                                        format!("{}\n", output)
-                                    },
-                                    stderr: String::new(),
-                                    exit_code: Some(0), // Assume success in PTY mode
-                                });
-                            }
-                        }
-                        ChannelMsg::Close => {
-                            break Err(SshMcpError::connection(
-                                "Channel closed during command execution",
-                            ));
-                        }
-                        _ => {
-                            // Ignore other messages
-                        }
-                    }
-                }
-                Ok(None) => {
-                    break Err(SshMcpError::connection(
-                        "Channel ended during command execution",
-                    ));
-                }
-                Err(_) => {
-                    // Timeout on wait, continue loop
-                    continue;
-                }
+        result
+    }
+
+    /// Turn a freshly elevated shell into a quiet command runner
+    ///
+    /// Disables terminal echo and empties the prompts, so command output read
+    /// from the shell contains nothing but what the command printed, then
+    /// waits for a marker to discard everything printed so far.
+    pub(crate) async fn prepare_su_shell(&self, channel: &mut Channel<client::Msg>) -> Result<()> {
+        let sentinel = Sentinel::new();
+        // The shell runs on a PTY, where pagers would wait for a key press
+        let setup = format!(
+            "stty -echo; PS1=''; PS2=''; unset PROMPT_COMMAND; {}\n{}\n",
+            NO_PAGER_EXPORTS,
+            sentinel.shell_line()
+        );
+        channel
+            .data(setup.as_bytes())
+            .await
+            .map_err(|e| SshMcpError::elevation_failed(format!("Failed to set up shell: {}", e)))?;
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        match read_until_sentinel(channel, &sentinel, deadline, None).await? {
+            SentinelRead::Complete { .. } => Ok(()),
+            _ => Err(SshMcpError::elevation_failed(
+                "Elevated shell did not respond to setup",
+            )),
+        }
+    }
+
+    /// Check that exec requests run the command that was sent
+    ///
+    /// With a forced command the server ignores the requested command and
+    /// runs its own, which would otherwise surface as baffling output from
+    /// every tool. The probe prints a random marker (split so that a forced
+    /// command echoing `$SSH_ORIGINAL_COMMAND` cannot reproduce it); when the
+    /// marker does not come back, something else ran.
+    pub(crate) async fn check_forced_command(&self) -> Result<()> {
+        let sentinel = Sentinel::new();
+        let output = match self
+            .exec_via_channel(
+                &sentinel.shell_line(),
+                &[],
+                None,
+                None,
+                FORCED_COMMAND_PROBE_TIMEOUT,
+            )
+            .await
+        {
+            Ok(output) => output,
+            Err(SshMcpError::Timeout(ms)) => {
+                return Err(SshMcpError::connection(format!(
+                    "A test command did not finish within {}ms. {}",
+                    ms, FORCED_COMMAND_HINT
+                )));
             }
+            Err(e) => return Err(e),
         };
 
-        // Put the channel back
-        {
-            let mut guard = self.su_channel.lock().await;
-            *guard = Some(channel);
+        if sentinel.find(&output.stdout, 0).is_some() {
+            debug!("Forced command check passed");
+            return Ok(());
         }
 
-        result
+        Err(forced_command_error(&output))
     }
 
-    /// Execute command via a new exec channel
-    async fn exec_via_channel(
+    /// Execute a command as the login user with `stdin` on its standard input
+    ///
+    /// The command always gets an exec channel of its own, since the su
+    /// shell's terminal would echo the input back.
+    pub async fn exec_command_with_stdin(
         &self,
         command: &str,
+        stdin: &[u8],
+        timeout_duration: Duration,
+    ) -> Result<CommandOutput> {
+        let _in_flight = self.begin_command().await?;
+        self.exec_via_channel(command, &[], None, Some(stdin), timeout_duration)
+            .await
+    }
+
+    /// Execute command via a new exec channel, sending `env` as env requests
+    ///
+    /// With `pty_cols`, the command runs on a PTY that many columns wide. The
+    /// terminal merges stderr into stdout and ends lines with CRLF, which is
+    /// turned back into LF. `stdin` is written, followed by EOF, while the
+    /// output is read, so a command that prints before it has read all of its
+    /// input doesn't stall.
+    pub(super) async fn exec_via_channel(
+        &self,
+        command: &str,
+        env: &[(String, String)],
+        pty_cols: Option<u16>,
+        stdin: Option<&[u8]>,
         timeout_duration: Duration,
     ) -> Result<CommandOutput> {
         // Open a new channel
+        let rekeys_before = self.expected_rekeys();
         let channel = self.open_channel().await?;
+        self.apply_locale(&channel).await;
+        for (name, value) in env {
+            if let Err(e) = channel.set_env(false, name.as_str(), value.as_str()).await {
+                debug!("Failed to set {} for command: {}", name, e);
+            }
+        }
+
+        if let Some(cols) = pty_cols {
+            channel
+                .request_pty(false, "xterm", cols.into(), 24, 0, 0, &[])
+                .await
+                .map_err(|e| SshMcpError::connection(format!("Failed to request PTY: {}", e)))?;
+        }
 
         // Execute command
         channel
@@ -199,13 +580,95 @@ impl SshConnectionManager {
             .await
             .map_err(|e| SshMcpError::connection(format!("Failed to exec command: {}", e)))?;
 
+        let writer = stdin.map(|data| {
+            let mut writer = Box::pin(channel.make_writer());
+            let data = data.to_vec();
+            tokio::spawn(async move {
+                if let Err(e) = writer.write_all(&data).await {
+                    debug!("Failed to write stdin: {}", e);
+                }
+                let _ = writer.shutdown().await;
+            })
+        });
+
         // Collect output with timeout
         let result = timeout(timeout_duration, self.collect_channel_output(channel)).await;
+        if let Some(writer) = writer {
+            writer.abort();
+        }
 
         match result {
+            Ok(output) if pty_cols.is_some() => output.map(|mut output| {
+                output.stdout = output.stdout.replace("\r\n", "\n");
+                output
+            }),
             Ok(output) => output,
             Err(_) => {
                 // Timeout occurred - attempt graceful abort
+                warn!(
+                    "Command timed out after {}ms, attempting abort",
+                    timeout_duration.as_millis()
+                );
+                self.abort_command(command).await;
+                self.note_timeout(rekeys_before);
+                Err(SshMcpError::Timeout(timeout_duration.as_millis() as u64))
+            }
+        }
+    }
+
+    /// Request the configured locale through the protocol env request
+    ///
+    /// Servers only honour names listed in AcceptEnv (LANG and LC_* on most
+    /// distributions), so a rejection is not treated as an error.
+    async fn apply_locale(&self, channel: &Channel<client::Msg>) {
+        if let Some(ref locale) = self.config.locale {
+            for name in ["LANG", "LC_ALL"] {
+                if let Err(e) = channel.set_env(false, name, locale.as_str()).await {
+                    debug!("Failed to set {} for command: {}", name, e);
+                }
+            }
+        }
+    }
+
+    /// Execute a command on a PTY channel, answering a known prompt
+    ///
+    /// Output is scanned as it streams in. The first time `prompt` appears it
+    /// is removed from the output and `response` is sent, so the answer is
+    /// delivered exactly when it is asked for rather than up front. A second
+    /// occurrence means the response was rejected and the command is aborted.
+    ///
+    /// With a PTY, stdout and stderr are merged and returned as stdout.
+    pub async fn exec_with_prompt(
+        &self,
+        command: &str,
+        prompt: &str,
+        response: &str,
+        timeout_duration: Duration,
+    ) -> Result<CommandOutput> {
+        let _in_flight = self.begin_command().await?;
+
+        let channel = self.open_channel().await?;
+        self.apply_locale(&channel).await;
+
+        channel
+            .request_pty(false, "xterm", self.config.pty_cols.into(), 24, 0, 0, &[])
+            .await
+            .map_err(|e| SshMcpError::connection(format!("Failed to request PTY: {}", e)))?;
+
+        channel
+            .exec(true, command)
+            .await
+            .map_err(|e| SshMcpError::connection(format!("Failed to exec command: {}", e)))?;
+
+        let result = timeout(
+            timeout_duration,
+            self.collect_prompted_output(channel, prompt, response),
+        )
+        .await;
+
+        match result {
+            Ok(output) => output,
+            Err(_) => {
                 warn!(
                     "Command timed out after {}ms, attempting abort",
                     timeout_duration.as_millis()
@@ -216,38 +679,166 @@ impl SshConnectionManager {
         }
     }
 
+    /// Collect PTY output, answering `prompt` with `response` once
+    async fn collect_prompted_output(
+        &self,
+        mut channel: Channel<client::Msg>,
+        prompt: &str,
+        response: &str,
+    ) -> Result<CommandOutput> {
+        let mut output = CommandOutput::new();
+        let limit = self.config.max_output_bytes;
+        let mut bytes_read = 0;
+        let mut answered = false;
+
+        while let Some(msg) = channel.wait().await {
+            let exceeded = match msg {
+                ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. } => {
+                    push_limited(&mut output.stdout, &data, &mut bytes_read, limit)
+                }
+                ChannelMsg::ExitStatus { exit_status } => {
+                    output.exit_code = Some(exit_status);
+                    false
+                }
+                ChannelMsg::Close | ChannelMsg::Eof => {
+                    break;
+                }
+                _ => false,
+            };
+
+            if let Some(pos) = output.stdout.find(prompt) {
+                output.stdout.replace_range(pos..pos + prompt.len(), "");
+
+                if answered {
+                    let _ = channel.close().await;
+                    return Err(SshMcpError::elevation_failed(
+                        "Password was rejected (prompted again after it was sent)",
+                    ));
+                }
+
+                debug!("Prompt detected, sending response");
+                channel
+                    .data(format!("{}\n", response).as_bytes())
+                    .await
+                    .map_err(|e| {
+                        SshMcpError::connection(format!("Failed to send response: {}", e))
+                    })?;
+                answered = true;
+            }
+
+            if exceeded {
+                warn!(
+                    "Command output exceeded {} bytes, closing channel",
+                    bytes_read
+                );
+                output.byte_limit_exceeded = true;
+                let _ = channel.close().await;
+                break;
+            }
+        }
+
+        // The PTY translates newlines and echoes the line break after the
+        // (hidden) response
+        output.stdout = output.stdout.replace("\r\n", "\n");
+        if answered {
+            output.stdout = output.stdout.trim_start_matches('\n').to_string();
+        }
+
+        Ok(output)
+    }
+
     /// Collect output from a channel until it closes
+    ///
+    /// Once the exit status or EOF arrives, reading goes on for the
+    /// configured drain window, so data still queued behind it (and an exit
+    /// status sent after EOF) is not lost on channels the server closes late.
     async fn collect_channel_output(
         &self,
         mut channel: russh::Channel<russh::client::Msg>,
     ) -> Result<CommandOutput> {
         let mut output = CommandOutput::new();
+        let limit = self.config.max_output_bytes;
+        let mut bytes_read = 0;
+        let mut rejected = false;
+        let drain = self.config.output_drain;
+        let mut drain_until = None;
 
-        while let Some(msg) = channel.wait().await {
-            match msg {
+        loop {
+            let msg = match drain_until {
+                None => channel.wait().await,
+                Some(deadline) => match tokio::time::timeout_at(deadline, channel.wait()).await {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        debug!("Output drain window elapsed");
+                        break;
+                    }
+                },
+            };
+            let Some(msg) = msg else {
+                break;
+            };
+
+            let exceeded = match msg {
                 ChannelMsg::Data { data } => {
-                    output.stdout.push_str(&String::from_utf8_lossy(&data));
+                    push_streamed(&mut output.stdout, &data, &mut bytes_read, limit)
                 }
                 ChannelMsg::ExtendedData { data, ext } => {
-                    // ext == 1 is typically stderr
+                    // ext == 1 is SSH_EXTENDED_DATA_STDERR, the only type
+                    // defined by the protocol
                     if ext == 1 {
-                        output.stderr.push_str(&String::from_utf8_lossy(&data));
+                        push_streamed(&mut output.stderr, &data, &mut bytes_read, limit)
                     } else {
-                        output.stdout.push_str(&String::from_utf8_lossy(&data));
+                        debug!(
+                            "Received {} bytes of extended data type {}",
+                            data.len(),
+                            ext
+                        );
+                        push_streamed(&mut output.extended, &data, &mut bytes_read, limit)
                     }
                 }
                 ChannelMsg::ExitStatus { exit_status } => {
                     output.exit_code = Some(exit_status);
+                    if !drain.is_zero() {
+                        drain_until.get_or_insert_with(|| tokio::time::Instant::now() + drain);
+                    }
+                    false
+                }
+                ChannelMsg::Failure => {
+                    // The only request on this channel wanting a reply is exec
+                    rejected = true;
+                    false
+                }
+                ChannelMsg::Eof if !drain.is_zero() => {
+                    drain_until.get_or_insert_with(|| tokio::time::Instant::now() + drain);
+                    false
                 }
                 ChannelMsg::Close | ChannelMsg::Eof => {
                     break;
                 }
                 _ => {
                     // Ignore other messages
+                    false
                 }
+            };
+
+            if exceeded {
+                warn!(
+                    "Command output exceeded {} bytes, closing channel",
+                    bytes_read
+                );
+                output.byte_limit_exceeded = true;
+                let _ = channel.close().await;
+                break;
             }
         }
 
+        if rejected && output.exit_code.is_none() {
+            return Err(SshMcpError::connection(format!(
+                "The server rejected the exec request. {}",
+                FORCED_COMMAND_HINT
+            )));
+        }
+
         // If there's stderr and a non-zero exit code, we might want to handle it
         // For now, just return the output as-is
         debug!(
@@ -304,16 +895,155 @@ impl SshConnectionManager {
     }
 }
 
+/// How far back from the byte limit truncated output looks for a line break
+/// to end on, so a long line is not cut off mid-way when avoidable
+const LINE_CUT_WINDOW: usize = 256;
+
+/// Append a chunk of channel data to `buf` while enforcing a byte budget
+///
+/// `bytes_read` is the running total across all buffers for the current call.
+/// When the chunk would cross `limit`, only the part that fits is kept and
+/// `true` is returned so the caller can stop reading. The kept part never
+/// ends inside a multibyte UTF-8 character, and ends after the last line
+/// break when one is within [`LINE_CUT_WINDOW`] bytes of the limit.
+pub(crate) fn push_limited(
+    buf: &mut String,
+    data: &[u8],
+    bytes_read: &mut usize,
+    limit: Option<usize>,
+) -> bool {
+    let Some(limit) = limit else {
+        *bytes_read += data.len();
+        buf.push_str(&String::from_utf8_lossy(data));
+        return false;
+    };
+
+    let remaining = limit.saturating_sub(*bytes_read);
+    if data.len() <= remaining {
+        buf.push_str(&String::from_utf8_lossy(data));
+        *bytes_read += data.len();
+        return false;
+    }
+
+    // Back off to the start of the character the limit falls in
+    let mut take = remaining;
+    while take > 0 && is_utf8_continuation(data[take]) {
+        take -= 1;
+    }
+    buf.push_str(&String::from_utf8_lossy(&data[..take]));
+    *bytes_read = limit;
+
+    let window = buf.len().saturating_sub(LINE_CUT_WINDOW);
+    if let Some(pos) = buf.as_bytes()[window..].iter().rposition(|&b| b == b'\n') {
+        buf.truncate(window + pos + 1);
+    }
+    true
+}
+
+/// Whether `byte` continues a multibyte UTF-8 character
+fn is_utf8_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// Build the error for a probe whose marker never came back, quoting what
+/// was printed instead
+fn forced_command_error(output: &CommandOutput) -> SshMcpError {
+    let printed = output.combined_output();
+    let printed = printed.trim();
+    if looks_shell_less(printed, output.exit_code) {
+        return SshMcpError::connection(match printed {
+            "" => format!(
+                "A test command printed nothing and failed. {}",
+                NO_SHELL_HINT
+            ),
+            _ => format!(
+                "A test command was refused: {:?}. {}",
+                printed, NO_SHELL_HINT
+            ),
+        });
+    }
+
+    let mut sample: String = printed.chars().take(FORCED_COMMAND_SAMPLE_CHARS).collect();
+    if sample.len() < printed.len() {
+        sample.push_str("...");
+    }
+
+    if sample.is_empty() {
+        SshMcpError::connection(format!(
+            "A test command printed nothing. {}",
+            FORCED_COMMAND_HINT
+        ))
+    } else {
+        SshMcpError::connection(format!(
+            "A test command printed unexpected output: {:?}. {}",
+            sample, FORCED_COMMAND_HINT
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_forced_command_error() {
+        let output = CommandOutput {
+            stdout: "backup completed\n".to_string(),
+            ..Default::default()
+        };
+        let message = forced_command_error(&output).to_string();
+        assert!(message.contains("\"backup completed\""));
+        assert!(message.contains("forced command"));
+
+        let output = CommandOutput {
+            stdout: "x".repeat(1000),
+            ..Default::default()
+        };
+        let message = forced_command_error(&output).to_string();
+        assert!(message.contains(&format!("{}...", "x".repeat(FORCED_COMMAND_SAMPLE_CHARS))));
+        assert!(!message.contains(&"x".repeat(FORCED_COMMAND_SAMPLE_CHARS + 1)));
+
+        let output = CommandOutput {
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        let message = forced_command_error(&output).to_string();
+        assert!(message.contains("printed nothing"));
+        assert!(message.contains("forced command"));
+    }
+
+    #[test]
+    fn test_shell_less_account_error() {
+        // /bin/false: nothing printed, non-zero or no exit status
+        for exit_code in [None, Some(1)] {
+            let output = CommandOutput {
+                exit_code,
+                ..Default::default()
+            };
+            let message = forced_command_error(&output).to_string();
+            assert!(message.contains("no usable login shell"), "{}", message);
+        }
+
+        let output = CommandOutput {
+            stdout: "This account is currently not available.\n".to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        let message = forced_command_error(&output).to_string();
+        assert!(message.contains("refused: \"This account is currently not available.\""));
+        assert!(message.contains("/usr/sbin/nologin"));
+
+        assert!(!looks_shell_less("backup completed", Some(1)));
+        assert!(!looks_shell_less("", Some(0)));
+    }
+
     #[test]
     fn test_command_output_success() {
         let output = CommandOutput {
             stdout: "hello".to_string(),
             stderr: String::new(),
             exit_code: Some(0),
+            ..Default::default()
         };
         assert!(output.success());
     }
@@ -324,6 +1054,7 @@ mod tests {
             stdout: String::new(),
             stderr: "error".to_string(),
             exit_code: Some(1),
+            ..Default::default()
         };
         assert!(!output.success());
     }
@@ -334,6 +1065,7 @@ mod tests {
             stdout: "hello".to_string(),
             stderr: String::new(),
             exit_code: None,
+            ..Default::default()
         };
         // No exit code should be treated as success
         assert!(output.success());
@@ -345,6 +1077,7 @@ mod tests {
             stdout: "stdout".to_string(),
             stderr: "stderr".to_string(),
             exit_code: Some(0),
+            ..Default::default()
         };
         assert_eq!(output.combined_output(), "stdout\nstderr");
     }
@@ -355,6 +1088,7 @@ mod tests {
             stdout: "stdout".to_string(),
             stderr: String::new(),
             exit_code: Some(0),
+            ..Default::default()
         };
         assert_eq!(output.combined_output(), "stdout");
     }
@@ -365,7 +1099,145 @@ mod tests {
             stdout: String::new(),
             stderr: "stderr".to_string(),
             exit_code: Some(1),
+            ..Default::default()
         };
         assert_eq!(output.combined_output(), "stderr");
     }
+
+    #[test]
+    fn test_push_limited_unlimited() {
+        let mut buf = String::new();
+        let mut read = 0;
+        assert!(!push_limited(&mut buf, b"hello", &mut read, None));
+        assert_eq!(buf, "hello");
+        assert_eq!(read, 5);
+    }
+
+    #[test]
+    fn test_push_limited_stops_at_limit() {
+        let mut buf = String::new();
+        let mut read = 0;
+        assert!(!push_limited(&mut buf, b"yes\n", &mut read, Some(6)));
+        assert!(push_limited(&mut buf, b"yes\n", &mut read, Some(6)));
+        // Cut after the last complete line rather than mid-line
+        assert_eq!(buf, "yes\n");
+        assert_eq!(read, 6);
+        // Once the budget is spent nothing else is accepted
+        assert!(push_limited(&mut buf, b"y", &mut read, Some(6)));
+        assert_eq!(buf, "yes\n");
+    }
+
+    #[test]
+    fn test_push_limited_keeps_characters_whole() {
+        // The limit falls inside the two-byte "é"
+        let mut buf = String::new();
+        let mut read = 0;
+        assert!(push_limited(
+            &mut buf,
+            "héllo".as_bytes(),
+            &mut read,
+            Some(2)
+        ));
+        assert_eq!(buf, "h");
+        assert_eq!(read, 2);
+
+        // ...and inside the four-byte "🦀", after a character in an earlier chunk
+        for limit in 2..=4 {
+            let mut buf = String::new();
+            let mut read = 0;
+            assert!(!push_limited(
+                &mut buf,
+                "ж".as_bytes(),
+                &mut read,
+                Some(limit)
+            ));
+            assert!(push_limited(
+                &mut buf,
+                "🦀".as_bytes(),
+                &mut read,
+                Some(limit)
+            ));
+            assert_eq!(buf, "ж");
+            assert!(!buf.contains(char::REPLACEMENT_CHARACTER));
+        }
+    }
+
+    #[test]
+    fn test_push_limited_line_cut_window() {
+        // A line break far from the limit is not worth losing the output for
+        let line = "ä".repeat(LINE_CUT_WINDOW);
+        let data = format!("first\n{}", line);
+        let mut buf = String::new();
+        let mut read = 0;
+        assert!(push_limited(
+            &mut buf,
+            data.as_bytes(),
+            &mut read,
+            Some(data.len() - 1)
+        ));
+        assert_eq!(buf, format!("first\n{}", "ä".repeat(LINE_CUT_WINDOW - 1)));
+
+        let data = "one\ntwo ümlaut\nthree";
+        let mut buf = String::new();
+        let mut read = 0;
+        assert!(push_limited(&mut buf, data.as_bytes(), &mut read, Some(19)));
+        assert_eq!(buf, "one\ntwo ümlaut\n");
+    }
+
+    #[test]
+    fn test_sentinel_is_random_and_not_sent_literally() {
+        let a = Sentinel::new();
+        let b = Sentinel::new();
+        assert_ne!(a.token, b.token);
+        assert_eq!(a.token.len(), "SSHMCP".len() + 32);
+        assert!(!a.shell_line().contains(&a.token));
+    }
+
+    #[test]
+    fn test_sentinel_find() {
+        let sentinel = Sentinel::new();
+        let token = &sentinel.token;
+
+        let buffer = format!("line one\r\nline two\r\n\r\n{}:3\r\n", token);
+        let (end, code) = sentinel.find(&buffer, 0).unwrap();
+        assert_eq!(&buffer[..end], "line one\r\nline two\r\n");
+        assert_eq!(code, Some(3));
+
+        // Output without a trailing newline
+        let buffer = format!("partial\n{}:0\n", token);
+        let (end, code) = sentinel.find(&buffer, 0).unwrap();
+        assert_eq!(&buffer[..end], "partial");
+        assert_eq!(code, Some(0));
+
+        // The marker line is not complete yet
+        assert!(sentinel.find(&format!("out\n{}:1", token), 0).is_none());
+    }
+
+    #[test]
+    fn test_sentinel_near_misses_are_ignored() {
+        let sentinel = Sentinel::new();
+        let token = &sentinel.token;
+
+        // Token echoed mid-line by the command itself
+        let buffer = format!("echo {}:0\n", token);
+        assert!(sentinel.find(&buffer, 0).is_none());
+
+        // Truncated token and a token with different digits
+        let buffer = format!("{}:0\n", &token[..token.len() - 1]);
+        assert!(sentinel.find(&buffer, 0).is_none());
+        let buffer = format!("{}x:0\n", token);
+        assert!(sentinel.find(&buffer, 0).is_none());
+
+        // A marker from another command
+        let other = Sentinel::new();
+        assert!(sentinel
+            .find(&format!("\n{}:0\n", other.token), 0)
+            .is_none());
+
+        // Near misses before the real marker don't hide it
+        let buffer = format!("say {0}:0\n{0}:oops\n\n{0}:7\n", token);
+        let (end, code) = sentinel.find(&buffer, 0).unwrap();
+        assert_eq!(&buffer[..end], format!("say {0}:0\n{0}:oops\n", token));
+        assert_eq!(code, Some(7));
+    }
 }
@@ -2,6 +2,65 @@
 //!
 //! Configuration for SSH connection parameters including authentication.
 
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::handler::HostKeyPolicy;
+use super::socks::SocksProxy;
+
+/// Elevation command used when none is configured
+pub const DEFAULT_ELEVATION_COMMAND: &str = "su -";
+
+/// Elevation command keeping the login user's environment (`--su-login=false`)
+pub const NON_LOGIN_ELEVATION_COMMAND: &str = "su";
+
+/// How long to keep reading a channel once its command has finished
+pub const DEFAULT_OUTPUT_DRAIN: Duration = Duration::from_millis(50);
+
+/// An SSH authentication method ssh-mcp can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// `password` authentication with the configured password
+    Password,
+
+    /// `publickey` authentication with the configured private key
+    Key,
+
+    /// `publickey` authentication with the keys of the SSH agent at
+    /// `SSH_AUTH_SOCK`; only tried when listed in the auth order
+    Agent,
+}
+
+/// Order methods are tried in when none is configured
+const DEFAULT_AUTH_ORDER: [AuthMethod; 2] = [AuthMethod::Password, AuthMethod::Key];
+
+impl FromStr for AuthMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "password" => Ok(AuthMethod::Password),
+            "key" | "publickey" => Ok(AuthMethod::Key),
+            "agent" => Ok(AuthMethod::Agent),
+            other => Err(format!(
+                "unknown authentication method '{}' (supported: password, key, agent)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuthMethod::Password => "password",
+            AuthMethod::Key => "key",
+            AuthMethod::Agent => "agent",
+        })
+    }
+}
+
 /// SSH connection configuration
 #[derive(Debug, Clone)]
 pub struct SshConfig {
@@ -25,6 +84,66 @@ pub struct SshConfig {
 
     /// Password for `sudo` commands (if different from su_password)
     pub sudo_password: Option<String>,
+
+    /// Command run in the PTY shell to obtain a root shell (default: `su -`)
+    pub elevation_command: String,
+
+    /// Hard limit on bytes read from a single command (None = unlimited)
+    pub max_output_bytes: Option<usize>,
+
+    /// Locale exported as LANG/LC_ALL for executed commands
+    pub locale: Option<String>,
+
+    /// Age after which the session is closed and reopened (None = never)
+    pub max_session_age: Option<Duration>,
+
+    /// Number of commands after which the session is reopened (None = never)
+    pub max_session_commands: Option<u64>,
+
+    /// Idle time after which the session is checked in the background and
+    /// reconnected if dead (None = never)
+    pub idle_refresh: Option<Duration>,
+
+    /// Disable Nagle's algorithm on the TCP socket (default: true)
+    pub tcp_nodelay: bool,
+
+    /// SSH channel window size in bytes (None = russh default)
+    pub window_size: Option<u32>,
+
+    /// Maximum SSH packet size in bytes (None = russh default)
+    pub max_packet_size: Option<u32>,
+
+    /// Width in columns of requested PTYs (default: 80)
+    pub pty_cols: u16,
+
+    /// Extra connection attempts after a network failure (default: 0)
+    pub reconnect_attempts: u32,
+
+    /// Randomize reconnect delays with full jitter (default: true)
+    pub reconnect_jitter: bool,
+
+    /// Extra su elevation attempts after a failure (default: 0)
+    pub elevation_retries: u32,
+
+    /// Order authentication methods are tried in (empty = password, then key)
+    pub auth_order: Vec<AuthMethod>,
+
+    /// How long to keep reading output after the exit status or EOF
+    /// (default: 50ms, zero stops at EOF)
+    pub output_drain: Duration,
+
+    /// SOCKS5 proxy the TCP connection is made through (None = direct)
+    pub socks_proxy: Option<SocksProxy>,
+
+    /// Maximum SFTP handles open at once (None = unlimited)
+    pub max_sftp_handles: Option<usize>,
+
+    /// How the server's host key is verified (default: `~/.ssh/known_hosts`)
+    pub host_key_policy: HostKeyPolicy,
+
+    /// Consecutive connection failures after which attempts fail fast, and
+    /// for how long (None = never)
+    pub circuit_breaker: Option<(u32, Duration)>,
 }
 
 impl SshConfig {
@@ -38,6 +157,25 @@ impl SshConfig {
             private_key: None,
             su_password: None,
             sudo_password: None,
+            elevation_command: DEFAULT_ELEVATION_COMMAND.to_string(),
+            max_output_bytes: None,
+            locale: None,
+            max_session_age: None,
+            max_session_commands: None,
+            idle_refresh: None,
+            tcp_nodelay: true,
+            window_size: None,
+            max_packet_size: None,
+            pty_cols: 80,
+            reconnect_attempts: 0,
+            reconnect_jitter: true,
+            elevation_retries: 0,
+            auth_order: Vec::new(),
+            output_drain: DEFAULT_OUTPUT_DRAIN,
+            socks_proxy: None,
+            max_sftp_handles: None,
+            host_key_policy: HostKeyPolicy::default(),
+            circuit_breaker: None,
         }
     }
 
@@ -59,6 +197,33 @@ impl SshConfig {
         self
     }
 
+    /// Try authentication methods in this order
+    pub fn with_auth_order(mut self, order: Vec<AuthMethod>) -> Self {
+        self.auth_order = order;
+        self
+    }
+
+    /// Authentication methods to try, in order
+    ///
+    /// Methods without credentials configured are skipped. The agent is
+    /// only used when the order lists it.
+    pub fn auth_methods(&self) -> Vec<AuthMethod> {
+        let order = if self.auth_order.is_empty() {
+            &DEFAULT_AUTH_ORDER[..]
+        } else {
+            &self.auth_order[..]
+        };
+        order
+            .iter()
+            .copied()
+            .filter(|method| match method {
+                AuthMethod::Password => self.password.is_some(),
+                AuthMethod::Key => self.private_key.is_some(),
+                AuthMethod::Agent => true,
+            })
+            .collect()
+    }
+
     /// Set su password for privilege elevation
     pub fn with_su_password(mut self, password: impl Into<String>) -> Self {
         self.su_password = Some(password.into());
@@ -70,6 +235,118 @@ impl SshConfig {
         self.sudo_password = Some(password.into());
         self
     }
+
+    /// Set the command used to obtain the elevated shell (e.g. `sudo -i`)
+    pub fn with_elevation_command(mut self, command: impl Into<String>) -> Self {
+        self.elevation_command = command.into();
+        self
+    }
+
+    /// Set the hard limit on bytes read from a single command
+    pub fn with_max_output_bytes(mut self, limit: usize) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// Set the locale (LANG/LC_ALL) for executed commands
+    pub fn with_locale(mut self, locale: impl Into<String>) -> Self {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    /// Enable or disable TCP_NODELAY on the connection socket
+    pub fn with_tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// Set the SSH channel window size in bytes
+    pub fn with_window_size(mut self, size: u32) -> Self {
+        self.window_size = Some(size);
+        self
+    }
+
+    /// Set the maximum SSH packet size in bytes
+    pub fn with_max_packet_size(mut self, size: u32) -> Self {
+        self.max_packet_size = Some(size);
+        self
+    }
+
+    /// Set the width in columns of requested PTYs
+    pub fn with_pty_cols(mut self, cols: u16) -> Self {
+        self.pty_cols = cols;
+        self
+    }
+
+    /// Retry a failed connection up to `attempts` more times with backoff
+    pub fn with_reconnect_attempts(mut self, attempts: u32) -> Self {
+        self.reconnect_attempts = attempts;
+        self
+    }
+
+    /// Retry a failed su elevation up to `retries` more times
+    pub fn with_elevation_retries(mut self, retries: u32) -> Self {
+        self.elevation_retries = retries;
+        self
+    }
+
+    /// Enable or disable full jitter on reconnect delays
+    pub fn with_reconnect_jitter(mut self, jitter: bool) -> Self {
+        self.reconnect_jitter = jitter;
+        self
+    }
+
+    /// Recycle the session once it has been open for `age`
+    pub fn with_max_session_age(mut self, age: Duration) -> Self {
+        self.max_session_age = Some(age);
+        self
+    }
+
+    /// Recycle the session after it has run `commands` commands
+    pub fn with_max_session_commands(mut self, commands: u64) -> Self {
+        self.max_session_commands = Some(commands);
+        self
+    }
+
+    /// Check the session after `idle` without commands, reconnecting if dead
+    ///
+    /// Takes effect once [`SshConnectionManager::spawn_idle_refresh`](super::SshConnectionManager::spawn_idle_refresh)
+    /// starts the background task.
+    pub fn with_idle_refresh(mut self, idle: Duration) -> Self {
+        self.idle_refresh = Some(idle);
+        self
+    }
+
+    /// Connect through a SOCKS5 proxy
+    pub fn with_socks_proxy(mut self, proxy: SocksProxy) -> Self {
+        self.socks_proxy = Some(proxy);
+        self
+    }
+
+    /// Limit the SFTP handles open at once
+    pub fn with_max_sftp_handles(mut self, max: usize) -> Self {
+        self.max_sftp_handles = Some(max);
+        self
+    }
+
+    /// Fail connection attempts fast for `cooldown` after `threshold`
+    /// consecutive failures
+    pub fn with_circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some((threshold, cooldown));
+        self
+    }
+
+    /// Set how the server's host key is verified
+    pub fn with_host_key_policy(mut self, policy: HostKeyPolicy) -> Self {
+        self.host_key_policy = policy;
+        self
+    }
+
+    /// Keep reading output for `drain` after a command finishes
+    pub fn with_output_drain(mut self, drain: Duration) -> Self {
+        self.output_drain = drain;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -88,4 +365,32 @@ mod tests {
         assert_eq!(config.password, Some("secret".to_string()));
         assert!(config.private_key.is_none());
     }
+
+    #[test]
+    fn test_auth_methods() {
+        let config = SshConfig::new("host", "admin")
+            .with_password("secret")
+            .with_private_key("key");
+        assert_eq!(
+            config.auth_methods(),
+            vec![AuthMethod::Password, AuthMethod::Key]
+        );
+
+        let config = config.with_auth_order(vec![AuthMethod::Key, AuthMethod::Password]);
+        assert_eq!(
+            config.auth_methods(),
+            vec![AuthMethod::Key, AuthMethod::Password]
+        );
+
+        let config = SshConfig::new("host", "admin").with_private_key("key");
+        assert_eq!(config.auth_methods(), vec![AuthMethod::Key]);
+    }
+
+    #[test]
+    fn test_parse_auth_method() {
+        assert_eq!("password".parse(), Ok(AuthMethod::Password));
+        assert_eq!("publickey".parse(), Ok(AuthMethod::Key));
+        assert_eq!("agent".parse(), Ok(AuthMethod::Agent));
+        assert!("hostbased".parse::<AuthMethod>().is_err());
+    }
 }
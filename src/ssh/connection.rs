@@ -3,32 +3,332 @@
 //! Provides persistent SSH connection handling with automatic reconnection,
 //! concurrent access protection, and optional privilege elevation via `su`.
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use russh::client::{self, Handle};
 use russh::keys::PrivateKeyWithHashAlg;
 use russh::Channel;
-use tokio::sync::Mutex;
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard, Semaphore};
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, debug_span, error, info, info_span, warn, Instrument};
 
-use super::config::SshConfig;
-use super::handler::SshHandler;
+use super::circuit::CircuitBreaker;
+use super::command::{looks_shell_less, NO_SHELL_HINT};
+use super::config::{AuthMethod, SshConfig};
+use super::handler::{LearnedHostKey, SshHandler};
+use super::probe::remaining_methods;
 use crate::config::CONNECTION_TIMEOUT_SECS;
 use crate::error::{Result, SshMcpError};
 
+/// How long each phase of the last connection attempt took, in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConnectTimings {
+    /// Resolving the host and opening the TCP connection
+    pub tcp_ms: u64,
+
+    /// SSH version exchange, key exchange and host key check
+    pub handshake_ms: u64,
+
+    /// User authentication
+    pub auth_ms: u64,
+
+    /// `su` elevation, if configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elevation_ms: Option<u64>,
+
+    /// Whole connection attempt
+    pub total_ms: u64,
+}
+
+/// Backoff before the first reconnect attempt
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest backoff between reconnect attempts
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How long an idle session may take to answer the liveness probe
+const IDLE_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before reconnect attempt `attempt` (0-based)
+///
+/// The backoff doubles with each attempt up to [`RECONNECT_MAX_DELAY`]. With
+/// `jitter`, a uniformly random delay between zero and the backoff is used
+/// instead ("full jitter"), which spreads out clients that lost the same
+/// server at the same moment.
+fn reconnect_delay(attempt: u32, jitter: bool) -> Duration {
+    let backoff = RECONNECT_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(RECONNECT_MAX_DELAY);
+    if jitter {
+        let ms = u64::try_from(backoff.as_millis()).unwrap_or(u64::MAX);
+        Duration::from_millis(rand::random_range(0..=ms))
+    } else {
+        backoff
+    }
+}
+
+/// Number of time-based key re-exchanges due in a session of age `age`
+///
+/// russh starts a re-exchange every `interval` (its `rekey_time_limit`) but
+/// does not report when one starts (`Handler::kex_done` only fires once it
+/// has completed, which a hung one never does), so the schedule is the best
+/// available signal for correlating a stall with a rekey. Re-exchanges that
+/// russh starts after a volume of data (`rekey_read_limit` and
+/// `rekey_write_limit`, 1 GiB by default) are not counted.
+fn rekeys_due(age: Duration, interval: Duration) -> u64 {
+    let interval = interval.as_millis().max(1);
+    u64::try_from(age.as_millis() / interval).unwrap_or(u64::MAX)
+}
+
+/// Error for a rejected authentication attempt, listing what the server
+/// would accept instead
+fn rejected(method: &str, remaining: &[String]) -> SshMcpError {
+    if remaining.is_empty() {
+        SshMcpError::auth(format!("{} authentication rejected", method))
+    } else {
+        SshMcpError::auth(format!(
+            "{} authentication rejected (server accepts: {}; see --probe-auth)",
+            method,
+            remaining.join(", ")
+        ))
+    }
+}
+
+/// Outcome of one authentication attempt that did not fail outright
+enum AuthAttempt {
+    /// The server accepted the credentials
+    Accepted,
+
+    /// The method was turned down, by the server or because its credentials
+    /// are unusable (e.g. a key that does not parse); the next one is tried
+    Rejected(SshMcpError),
+}
+
+/// Something authentication methods can be tried against
+trait AuthAttempts {
+    /// Try one method
+    ///
+    /// An `Err` is a protocol or transport failure, after which no further
+    /// methods are tried on the session.
+    async fn attempt(&mut self, method: AuthMethod) -> Result<AuthAttempt>;
+}
+
+/// Authentication attempts against an SSH session
+struct SessionAuth<'a> {
+    config: &'a SshConfig,
+    session: &'a mut Handle<SshHandler>,
+}
+
+impl AuthAttempts for SessionAuth<'_> {
+    async fn attempt(&mut self, method: AuthMethod) -> Result<AuthAttempt> {
+        let username = &self.config.username;
+        let (name, auth_result) = match method {
+            AuthMethod::Password => {
+                let password = self.config.password.as_deref().unwrap_or_default();
+                let result = self.session.authenticate_password(username, password).await;
+                ("Password", result)
+            }
+            AuthMethod::Key => {
+                let key_content = self.config.private_key.as_deref().unwrap_or_default();
+
+                // Parse the private key using russh::keys
+                let key = match russh::keys::PrivateKey::from_openssh(key_content.as_bytes()) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        return Ok(AuthAttempt::Rejected(SshMcpError::SshKey(format!(
+                            "Failed to parse private key: {}",
+                            e
+                        ))))
+                    }
+                };
+
+                // Wrap in PrivateKeyWithHashAlg (None for non-RSA or default hash)
+                let key_with_alg = PrivateKeyWithHashAlg::new(Arc::new(key), None);
+
+                let result = self
+                    .session
+                    .authenticate_publickey(username, key_with_alg)
+                    .await;
+                ("Key", result)
+            }
+            AuthMethod::Agent => return self.attempt_agent().await,
+        };
+        let auth_result = auth_result.map_err(|e| SshMcpError::auth(e.to_string()))?;
+
+        Ok(match remaining_methods(&auth_result) {
+            None => AuthAttempt::Accepted,
+            Some(methods) => AuthAttempt::Rejected(rejected(name, &methods)),
+        })
+    }
+}
+
+impl SessionAuth<'_> {
+    /// Offer each key the SSH agent holds until the server accepts one
+    #[cfg(unix)]
+    async fn attempt_agent(&mut self) -> Result<AuthAttempt> {
+        use russh::keys::agent::client::AgentClient;
+
+        let mut agent = match AgentClient::connect_env().await {
+            Ok(agent) => agent,
+            Err(e) => {
+                return Ok(AuthAttempt::Rejected(SshMcpError::auth(format!(
+                    "Could not reach the SSH agent at SSH_AUTH_SOCK: {}",
+                    e
+                ))))
+            }
+        };
+        let identities = match agent.request_identities().await {
+            Ok(identities) if identities.is_empty() => {
+                return Ok(AuthAttempt::Rejected(SshMcpError::auth(
+                    "The SSH agent holds no keys",
+                )))
+            }
+            Ok(identities) => identities,
+            Err(e) => {
+                return Ok(AuthAttempt::Rejected(SshMcpError::auth(format!(
+                    "The SSH agent did not list its keys: {}",
+                    e
+                ))))
+            }
+        };
+
+        let mut remaining = Vec::new();
+        for identity in identities {
+            let key = identity.public_key().into_owned();
+            let fingerprint = key.fingerprint(Default::default());
+            // A key the agent refuses to sign with (e.g. a declined
+            // confirmation) is skipped like a rejected one
+            match self
+                .session
+                .authenticate_publickey_with(&self.config.username, key, None, &mut agent)
+                .await
+            {
+                Ok(result) => match remaining_methods(&result) {
+                    None => return Ok(AuthAttempt::Accepted),
+                    Some(methods) => remaining = methods,
+                },
+                Err(e) => debug!("SSH agent key {} failed: {}", fingerprint, e),
+            }
+        }
+        Ok(AuthAttempt::Rejected(rejected("Agent", &remaining)))
+    }
+
+    #[cfg(not(unix))]
+    async fn attempt_agent(&mut self) -> Result<AuthAttempt> {
+        Ok(AuthAttempt::Rejected(SshMcpError::auth(
+            "SSH agent authentication is only supported on Unix",
+        )))
+    }
+}
+
+/// Try `methods` in order until one is accepted, and return it
+///
+/// A rejected method falls through to the next one, so a wrong key still
+/// lets a configured password in. A protocol error ends authentication at
+/// once. When every method is rejected, the error lists each one's outcome
+/// (a single method's rejection is returned as is).
+async fn authenticate_with(
+    methods: &[AuthMethod],
+    attempts: &mut impl AuthAttempts,
+) -> Result<AuthMethod> {
+    let mut rejections = Vec::new();
+    for &method in methods {
+        debug!("Attempting {} authentication", method);
+        let attempt = Instant::now();
+        let outcome = attempts.attempt(method).await?;
+        debug!(
+            "{} authentication attempt took {}ms",
+            method,
+            elapsed_ms(attempt)
+        );
+
+        match outcome {
+            AuthAttempt::Accepted => {
+                info!("{} authentication successful", method);
+                return Ok(method);
+            }
+            AuthAttempt::Rejected(e) => {
+                debug!("{} authentication failed: {}", method, e);
+                rejections.push((method, e));
+            }
+        }
+    }
+
+    if rejections.len() > 1 {
+        let outcomes: Vec<String> = rejections
+            .iter()
+            .map(|(method, e)| match e {
+                SshMcpError::Authentication(reason) => format!("{}: {}", method, reason),
+                other => format!("{}: {}", method, other),
+            })
+            .collect();
+        return Err(SshMcpError::auth(format!(
+            "every method was rejected ({})",
+            outcomes.join("; ")
+        )));
+    }
+    Err(rejections.pop().map(|(_, e)| e).unwrap_or_else(|| {
+        SshMcpError::auth("No authentication method available (require password or private_key)")
+    }))
+}
+
+/// Open the TCP connection to `host:port`
+///
+/// The host name is resolved first, so a name that does not resolve (most
+/// often a typo in `--host`) and a refused connection get errors of their
+/// own instead of a bare OS message.
+async fn connect_direct(host: &str, port: u16) -> Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| unresolved(host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(unresolved(host, "no addresses found"));
+    }
+
+    TcpStream::connect(addrs.as_slice())
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::ConnectionRefused => SshMcpError::connection(format!(
+                "Connection to {}:{} refused; no SSH server is listening on that port",
+                host, port
+            )),
+            _ => SshMcpError::connection(format!("Failed to connect to {}:{}: {}", host, port, e)),
+        })
+}
+
+fn unresolved(host: &str, reason: impl std::fmt::Display) -> SshMcpError {
+    SshMcpError::connection(format!(
+        "Could not resolve host name {:?} ({}); check --host for typos",
+        host, reason
+    ))
+}
+
+/// Milliseconds elapsed since `start`, saturating
+fn elapsed_ms(start: Instant) -> u64 {
+    u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
 /// SSH Connection Manager
 ///
 /// Manages a persistent SSH connection with the following features:
 /// - Automatic reconnection when connection drops
 /// - Concurrent access protection via mutex/atomic flags
 /// - Optional `su` elevation for privileged operations
+/// - Optional recycling of the session after a maximum age or command count
+/// - Optional background check of the session after an idle period
 /// - 30-second connection timeout
 pub struct SshConnectionManager {
     /// SSH configuration
-    config: SshConfig,
+    /// Made pub(crate) to allow access from command.rs
+    pub(crate) config: SshConfig,
 
     /// Active SSH session handle
     session: Arc<Mutex<Option<Handle<SshHandler>>>>,
@@ -37,11 +337,73 @@ pub struct SshConnectionManager {
     is_connecting: AtomicBool,
 
     /// Elevated shell channel (when using su)
+    ///
+    /// The lock is held for the full duration of any use of the shell
+    /// (elevation, command execution, teardown), and `is_elevated` is only
+    /// written while holding it, so concurrent callers never observe a
+    /// half-updated elevation state.
     /// Made pub(crate) to allow access from command.rs
     pub(crate) su_channel: Arc<Mutex<Option<Channel<client::Msg>>>>,
 
     /// Flag indicating whether we're running as root via su
-    is_elevated: AtomicBool,
+    /// Made pub(crate) to allow access from command.rs
+    pub(crate) is_elevated: AtomicBool,
+
+    /// When the current session was established
+    session_created: std::sync::Mutex<Option<Instant>>,
+
+    /// Commands started on the current session
+    session_commands: AtomicU64,
+
+    /// When the last command started, or the connection was made
+    last_activity: std::sync::Mutex<Option<Instant>>,
+
+    /// Held shared by every running command and exclusively while recycling,
+    /// so a session is never torn down under an in-flight command
+    command_gate: RwLock<()>,
+
+    /// Phase timings of the last successful connection
+    last_connect: std::sync::Mutex<Option<ConnectTimings>>,
+
+    /// Authentication method the last successful connection used
+    auth_method: std::sync::Mutex<Option<AuthMethod>>,
+
+    /// Environment variable names probed on this connection, and whether
+    /// the server accepts them through `env` requests
+    pub(crate) env_accepted: std::sync::Mutex<HashMap<String, bool>>,
+
+    /// Remote home directory used to expand `~` in file tool paths, looked
+    /// up once per connection
+    pub(crate) home_dir: std::sync::Mutex<Option<String>>,
+
+    /// Interval at which russh re-exchanges keys on a session
+    rekey_interval: Duration,
+
+    /// Re-exchanges already logged for the current session
+    rekeys_logged: AtomicU64,
+
+    /// Set when a command stalled across an expected re-exchange, so the
+    /// session is recycled before the next command
+    rekey_stalled: AtomicBool,
+
+    /// Why the last su elevation failed, until one succeeds
+    elevation_error: std::sync::Mutex<Option<String>>,
+
+    /// Set by [`Self::drop_elevation`] so commands and reconnects stay
+    /// unprivileged until elevation is asked for again
+    elevation_dropped: AtomicBool,
+
+    /// SFTP handles currently open
+    pub(crate) sftp_open: Arc<AtomicUsize>,
+
+    /// Slots for SFTP handles when their number is limited
+    pub(crate) sftp_slots: Option<Arc<Semaphore>>,
+
+    /// Host key accepted on the first connection; reconnects must match it
+    host_key: LearnedHostKey,
+
+    /// Stops connection attempts to a failing host for a while
+    circuit: Option<CircuitBreaker>,
 }
 
 impl SshConnectionManager {
@@ -50,12 +412,35 @@ impl SshConnectionManager {
     /// Does not establish connection immediately; call `connect()` or
     /// `ensure_connected()` to establish the connection.
     pub async fn new(config: SshConfig) -> Self {
+        let sftp_slots = config
+            .max_sftp_handles
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let circuit = config
+            .circuit_breaker
+            .map(|(threshold, cooldown)| CircuitBreaker::new(threshold, cooldown));
         Self {
             config,
             session: Arc::new(Mutex::new(None)),
             is_connecting: AtomicBool::new(false),
             su_channel: Arc::new(Mutex::new(None)),
             is_elevated: AtomicBool::new(false),
+            session_created: std::sync::Mutex::new(None),
+            session_commands: AtomicU64::new(0),
+            last_activity: std::sync::Mutex::new(None),
+            command_gate: RwLock::new(()),
+            last_connect: std::sync::Mutex::new(None),
+            auth_method: std::sync::Mutex::new(None),
+            env_accepted: std::sync::Mutex::new(HashMap::new()),
+            home_dir: std::sync::Mutex::new(None),
+            rekey_interval: client::Config::default().limits.rekey_time_limit,
+            rekeys_logged: AtomicU64::new(0),
+            rekey_stalled: AtomicBool::new(false),
+            elevation_error: std::sync::Mutex::new(None),
+            elevation_dropped: AtomicBool::new(false),
+            sftp_open: Arc::new(AtomicUsize::new(0)),
+            sftp_slots,
+            host_key: LearnedHostKey::default(),
+            circuit,
         }
     }
 
@@ -91,8 +476,8 @@ impl SshConnectionManager {
             };
         }
 
-        // Perform connection with timeout
-        let result = self.do_connect().await;
+        // Perform connection with timeout, retrying network failures
+        let result = self.connect_with_retry().await;
 
         // Reset connecting flag
         self.is_connecting.store(false, Ordering::SeqCst);
@@ -100,122 +485,228 @@ impl SshConnectionManager {
         result
     }
 
+    /// Run connection attempts until one succeeds or the retries run out
+    ///
+    /// Only network failures are retried; authentication and key errors
+    /// would fail the same way again.
+    async fn connect_with_retry(&self) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.do_connect().await {
+                Err(e @ (SshMcpError::Connection(_) | SshMcpError::Timeout(_)))
+                    if attempt < self.config.reconnect_attempts =>
+                {
+                    let delay = reconnect_delay(attempt, self.config.reconnect_jitter);
+                    attempt += 1;
+                    warn!(
+                        "Connection attempt {} failed ({}), retrying in {}ms",
+                        attempt,
+                        e,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
     /// Internal connection logic
+    ///
+    /// Each phase runs in its own tracing span and its duration is logged at
+    /// debug level, so a slow connect can be attributed to the network, the
+    /// key exchange or authentication.
     async fn do_connect(&self) -> Result<()> {
+        let span = info_span!(
+            "ssh_connect",
+            host = %self.config.host,
+            port = self.config.port
+        );
+        self.do_connect_phases().instrument(span).await
+    }
+
+    async fn do_connect_phases(&self) -> Result<()> {
         info!(
             "Connecting to SSH server {}:{}...",
             self.config.host, self.config.port
         );
 
-        let connection_timeout = Duration::from_secs(CONNECTION_TIMEOUT_SECS);
-
-        // Create russh config with defaults
-        let ssh_config = client::Config::default();
-        let ssh_config = Arc::new(ssh_config);
-
-        // Connect with timeout
-        let addr = format!("{}:{}", self.config.host, self.config.port);
-        let connect_result = timeout(
-            connection_timeout,
-            client::connect(ssh_config, addr.as_str(), SshHandler::new()),
-        )
-        .await;
-
-        let mut session = match connect_result {
-            Ok(Ok(session)) => session,
-            Ok(Err(e)) => {
-                error!("SSH connection failed: {}", e);
-                return Err(SshMcpError::connection(e.to_string()));
-            }
-            Err(_) => {
-                error!("SSH connection timeout after {}s", CONNECTION_TIMEOUT_SECS);
-                return Err(SshMcpError::connection(format!(
-                    "Connection timeout after {}s",
-                    CONNECTION_TIMEOUT_SECS
-                )));
-            }
-        };
+        let started = Instant::now();
+        let mut timings = ConnectTimings::default();
+        let mut session = self.open_transport(&mut timings).await?;
 
         // Authenticate
-        self.authenticate(&mut session).await?;
+        let phase = Instant::now();
+        self.authenticate(&mut session)
+            .instrument(debug_span!("auth", user = %self.config.username))
+            .await?;
+        timings.auth_ms = elapsed_ms(phase);
+        debug!("Authentication completed in {}ms", timings.auth_ms);
 
         // Store session
         {
             let mut session_guard = self.session.lock().await;
             *session_guard = Some(session);
         }
+        *self.session_created.lock().unwrap() = Some(Instant::now());
+        self.session_commands.store(0, Ordering::SeqCst);
+        self.touch();
+        self.rekeys_logged.store(0, Ordering::SeqCst);
+        self.rekey_stalled.store(false, Ordering::SeqCst);
+        // AcceptEnv may differ after reconnecting (e.g. to another server
+        // behind the same name)
+        self.env_accepted.lock().unwrap().clear();
+        self.home_dir.lock().unwrap().take();
 
         info!(
-            "Successfully connected to {}@{}:{}",
-            self.config.username, self.config.host, self.config.port
+            "Successfully connected to {}@{}:{} using {} authentication",
+            self.config.username,
+            self.config.host,
+            self.config.port,
+            self.auth_method()
+                .map_or_else(|| "unknown".to_string(), |method| method.to_string())
         );
 
+        // Make sure exec requests run our commands, not a forced command
+        let checked = self
+            .check_forced_command()
+            .instrument(debug_span!("forced_command_check"))
+            .await;
+        if let Err(e) = checked {
+            error!("{}", e);
+            self.close().await;
+            return Err(e);
+        }
+
         // If su_password is configured, attempt elevation
-        if self.config.su_password.is_some() {
+        if self.config.su_password.is_some() && !self.is_elevation_dropped() {
             debug!("su_password configured, attempting elevation...");
-            if let Err(e) = self.ensure_elevated().await {
-                // Don't fail connection if elevation fails, just log it
-                warn!(
-                    "Failed to elevate to root: {}. Commands will run as normal user.",
-                    e
-                );
-            }
+            let phase = Instant::now();
+            // Don't fail the connection if elevation fails; commands retry it
+            let _ = self
+                .elevate_with_retries()
+                .instrument(debug_span!("elevation"))
+                .await;
+            let elevation_ms = elapsed_ms(phase);
+            debug!("Elevation attempt took {}ms", elevation_ms);
+            timings.elevation_ms = Some(elevation_ms);
         }
 
+        timings.total_ms = elapsed_ms(started);
+        info!(
+            "Connection phases: tcp {}ms, handshake {}ms, auth {}ms, total {}ms",
+            timings.tcp_ms, timings.handshake_ms, timings.auth_ms, timings.total_ms
+        );
+        *self.last_connect.lock().unwrap() = Some(timings);
+
         Ok(())
     }
 
-    /// Authenticate with the SSH server
-    async fn authenticate(&self, session: &mut Handle<SshHandler>) -> Result<()> {
-        // Try password authentication first
-        if let Some(ref password) = self.config.password {
-            debug!(
-                "Attempting password authentication for user '{}'",
-                self.config.username
-            );
-            let auth_result = session
-                .authenticate_password(&self.config.username, password)
-                .await
-                .map_err(|e| SshMcpError::auth(e.to_string()))?;
+    /// Open the TCP connection and complete the SSH handshake
+    ///
+    /// Stops short of authentication; records the TCP and handshake phases
+    /// in `timings`.
+    pub(super) async fn open_transport(
+        &self,
+        timings: &mut ConnectTimings,
+    ) -> Result<Handle<SshHandler>> {
+        let connection_timeout = Duration::from_secs(CONNECTION_TIMEOUT_SECS);
 
-            if auth_result.success() {
-                info!("Password authentication successful");
-                return Ok(());
-            } else {
-                return Err(SshMcpError::auth("Password authentication rejected"));
-            }
+        // Create russh config with defaults, applying the transport tuning
+        let mut ssh_config = client::Config::default();
+        if let Some(size) = self.config.window_size {
+            ssh_config.window_size = size;
         }
+        if let Some(size) = self.config.max_packet_size {
+            ssh_config.maximum_packet_size = size;
+        }
+        let ssh_config = Arc::new(ssh_config);
 
-        // Try key authentication
-        if let Some(ref key_content) = self.config.private_key {
-            debug!(
-                "Attempting key authentication for user '{}'",
-                self.config.username
-            );
-
-            // Parse the private key using russh::keys
-            let key = russh::keys::PrivateKey::from_openssh(key_content.as_bytes())
-                .map_err(|e| SshMcpError::SshKey(format!("Failed to parse private key: {}", e)))?;
-
-            // Wrap in PrivateKeyWithHashAlg (None for non-RSA or default hash)
-            let key_with_alg = PrivateKeyWithHashAlg::new(Arc::new(key), None);
-
-            let auth_result = session
-                .authenticate_publickey(&self.config.username, key_with_alg)
+        // TCP connect and handshake share one timeout
+        let connect_result = timeout(connection_timeout, async {
+            let phase = Instant::now();
+            let stream = match self.config.socks_proxy {
+                Some(ref proxy) => {
+                    proxy
+                        .connect(&self.config.host, self.config.port)
+                        .instrument(debug_span!("socks_connect", proxy = %proxy.host))
+                        .await?
+                }
+                None => {
+                    connect_direct(&self.config.host, self.config.port)
+                        .instrument(debug_span!("tcp_connect"))
+                        .await?
+                }
+            };
+            if let Err(e) = stream.set_nodelay(self.config.tcp_nodelay) {
+                warn!("Failed to set TCP_NODELAY: {}", e);
+            }
+            timings.tcp_ms = elapsed_ms(phase);
+            debug!("TCP connection established in {}ms", timings.tcp_ms);
+
+            let phase = Instant::now();
+            let handler = SshHandler::new(
+                self.config.host.clone(),
+                self.config.port,
+                self.config.host_key_policy.clone(),
+            )
+            .with_learned_key(self.host_key.clone());
+            // russh only reports that the key was rejected; the handler knows why
+            let verifier = handler.clone();
+            let session = client::connect_stream(ssh_config, stream, handler)
+                .instrument(debug_span!("handshake"))
                 .await
-                .map_err(|e| SshMcpError::auth(e.to_string()))?;
+                .map_err(|e| {
+                    verifier
+                        .take_rejection()
+                        .unwrap_or_else(|| SshMcpError::connection(e.to_string()))
+                })?;
+            timings.handshake_ms = elapsed_ms(phase);
+            debug!("SSH handshake completed in {}ms", timings.handshake_ms);
+
+            Ok::<_, SshMcpError>(session)
+        })
+        .await;
 
-            if auth_result.success() {
-                info!("Key authentication successful");
-                return Ok(());
-            } else {
-                return Err(SshMcpError::auth("Key authentication rejected"));
+        match connect_result {
+            Ok(Ok(session)) => Ok(session),
+            Ok(Err(e)) => {
+                error!("SSH connection failed: {}", e);
+                Err(e)
+            }
+            Err(_) => {
+                error!("SSH connection timeout after {}s", CONNECTION_TIMEOUT_SECS);
+                Err(SshMcpError::connection(format!(
+                    "Connection timeout after {}s",
+                    CONNECTION_TIMEOUT_SECS
+                )))
             }
         }
+    }
 
-        Err(SshMcpError::auth(
-            "No authentication method available (require password or private_key)",
-        ))
+    /// Phase timings of the last successful connection, if any
+    pub fn last_connect_timings(&self) -> Option<ConnectTimings> {
+        self.last_connect.lock().unwrap().clone()
+    }
+
+    /// Authentication method the last successful connection used, if any
+    pub fn auth_method(&self) -> Option<AuthMethod> {
+        *self.auth_method.lock().unwrap()
+    }
+
+    /// Authenticate with the SSH server
+    ///
+    /// The configured methods are tried in order, and the one that succeeds
+    /// is remembered for [`Self::auth_method`].
+    async fn authenticate(&self, session: &mut Handle<SshHandler>) -> Result<()> {
+        debug!("Authenticating as user '{}'", self.config.username);
+        let mut attempts = SessionAuth {
+            config: &self.config,
+            session,
+        };
+        let method = authenticate_with(&self.config.auth_methods(), &mut attempts).await?;
+        *self.auth_method.lock().unwrap() = Some(method);
+        Ok(())
     }
 
     /// Check if the connection is active
@@ -225,11 +716,199 @@ impl SshConnectionManager {
     }
 
     /// Ensure connection is established, reconnecting if necessary
+    ///
+    /// With a circuit breaker configured, fails at once while it is open,
+    /// and records the outcome of every connection attempt.
     pub async fn ensure_connected(&self) -> Result<()> {
-        if !self.is_connected().await {
-            self.connect().await?;
+        if self.is_connected().await {
+            return Ok(());
         }
-        Ok(())
+        let Some(ref circuit) = self.circuit else {
+            return self.connect().await;
+        };
+
+        circuit.check()?;
+        let result = self.connect().await;
+        circuit.record(&result);
+        result
+    }
+
+    /// Circuit breaker for connection attempts, if configured
+    pub fn circuit(&self) -> Option<&CircuitBreaker> {
+        self.circuit.as_ref()
+    }
+
+    /// Prepare the connection for a command and mark it as in flight
+    ///
+    /// Recycles the session first if it has outlived the configured age or
+    /// command count or stalled during a key re-exchange, then makes sure it
+    /// is connected. The returned guard keeps
+    /// the session from being recycled until the command has finished.
+    pub(crate) async fn begin_command(&self) -> Result<RwLockReadGuard<'_, ()>> {
+        if self.session_expired() {
+            let _exclusive = self.command_gate.write().await;
+            // Another caller may have recycled while we waited for the gate
+            if self.session_expired() {
+                info!("SSH session is due for recycling, reconnecting");
+                self.close().await;
+            }
+        }
+
+        let guard = self.command_gate.read().await;
+        self.ensure_connected().await?;
+        self.session_commands.fetch_add(1, Ordering::SeqCst);
+        self.touch();
+        self.log_rekeys();
+        Ok(guard)
+    }
+
+    /// Record activity on the connection, postponing the idle check
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Time since the last command started or the connection was made
+    ///
+    /// `None` before any activity.
+    pub fn idle_for(&self) -> Option<Duration> {
+        self.last_activity
+            .lock()
+            .unwrap()
+            .map(|last| last.elapsed())
+    }
+
+    /// Start the background task refreshing the session when idle
+    ///
+    /// Does nothing unless `idle_refresh` is configured. The task checks the
+    /// session once it has been idle for that long, and again every interval
+    /// while it stays idle (see [`refresh_idle_session`](Self::refresh_idle_session)).
+    /// It only holds a weak reference and ends once the manager is dropped.
+    pub fn spawn_idle_refresh(self: &Arc<Self>) -> Option<JoinHandle<()>> {
+        let interval = self.config.idle_refresh?;
+        let manager = Arc::downgrade(self);
+        Some(tokio::spawn(async move {
+            let mut wait = interval;
+            loop {
+                tokio::time::sleep(wait).await;
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                let idle = manager.idle_for().unwrap_or(interval);
+                wait = if idle >= interval {
+                    manager.refresh_idle_session().await;
+                    interval
+                } else {
+                    interval - idle
+                };
+            }
+        }))
+    }
+
+    /// Check an idle session with a round trip and reconnect if it is dead
+    ///
+    /// A keepalive request only tells whether it could be queued, so a
+    /// half-open connection would pass; instead a channel is opened and
+    /// closed again, which the server has to answer within
+    /// [`IDLE_PROBE_TIMEOUT`]. Skipped while a command is running (the
+    /// session is then not idle) or when there is no session to check.
+    /// Returns whether it reconnected.
+    pub async fn refresh_idle_session(&self) -> bool {
+        let Ok(_exclusive) = self.command_gate.try_write() else {
+            self.touch();
+            return false;
+        };
+
+        let alive = {
+            let session_guard = self.session.lock().await;
+            let Some(session) = session_guard.as_ref() else {
+                return false;
+            };
+            !session.is_closed()
+                && match tokio::time::timeout(IDLE_PROBE_TIMEOUT, session.channel_open_session())
+                    .await
+                {
+                    Ok(Ok(channel)) => {
+                        let _ = channel.close().await;
+                        true
+                    }
+                    // A refusal (such as MaxSessions) is still an answer
+                    Ok(Err(russh::Error::ChannelOpenFailure(_))) => true,
+                    Ok(Err(_)) | Err(_) => false,
+                }
+        };
+        if alive {
+            debug!("Idle SSH session is alive");
+            return false;
+        }
+
+        warn!("Idle SSH session is dead, reconnecting before the next command");
+        self.close().await;
+        if let Err(e) = self.ensure_connected().await {
+            // The next command retries the connection as usual
+            warn!("Reconnecting the idle SSH session failed: {}", e);
+        }
+        true
+    }
+
+    /// Key re-exchanges due on the current session so far
+    pub(crate) fn expected_rekeys(&self) -> u64 {
+        match *self.session_created.lock().unwrap() {
+            Some(created) => rekeys_due(created.elapsed(), self.rekey_interval),
+            None => 0,
+        }
+    }
+
+    /// Log at debug level each re-exchange that became due since the last
+    /// command, so a later stall can be correlated with it
+    fn log_rekeys(&self) {
+        let due = self.expected_rekeys();
+        let logged = self.rekeys_logged.swap(due, Ordering::SeqCst);
+        if due > logged {
+            debug!(
+                "SSH key re-exchange #{} due (every {}s); a stall around now may be a rekey hang",
+                due,
+                self.rekey_interval.as_secs()
+            );
+        }
+    }
+
+    /// Handle a command timeout, given the re-exchanges due when it started
+    ///
+    /// Some older servers hang during key re-exchange, and then every channel
+    /// stays silent until the command times out. When a re-exchange became
+    /// due while the command ran, the session is assumed stuck and is
+    /// recycled before the next command instead of failing it the same way.
+    /// Only the time-based schedule is known (see [`rekeys_due`]), so a hang
+    /// in a re-exchange triggered by data volume is not recognized.
+    pub(crate) fn note_timeout(&self, rekeys_before: u64) {
+        let due = self.expected_rekeys();
+        if due > rekeys_before {
+            warn!(
+                "Command timed out across SSH key re-exchange #{}; the server may have \
+                 stalled during rekey, reconnecting before the next command",
+                due
+            );
+            self.rekey_stalled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Check whether the current session has exceeded its age or command
+    /// limit, or is suspected stuck in a key re-exchange
+    fn session_expired(&self) -> bool {
+        let Some(created) = *self.session_created.lock().unwrap() else {
+            return false;
+        };
+
+        let too_old = self
+            .config
+            .max_session_age
+            .is_some_and(|age| created.elapsed() >= age);
+        let too_busy = self
+            .config
+            .max_session_commands
+            .is_some_and(|max| self.session_commands.load(Ordering::SeqCst) >= max);
+
+        too_old || too_busy || self.rekey_stalled.load(Ordering::SeqCst)
     }
 
     /// Get a reference to the session for operations
@@ -288,15 +967,20 @@ impl SshConnectionManager {
 
     /// Ensure we have an elevated shell via `su`
     ///
-    /// This starts an interactive PTY session, runs `su -`, sends the password,
-    /// and waits for the root prompt (#).
+    /// This starts an interactive PTY session, runs the configured elevation
+    /// command (`su -` by default, or e.g. `sudo -i`), sends the password when
+    /// prompted, and waits for the root prompt (#).
+    ///
+    /// Asking for elevation undoes [`Self::drop_elevation`].
     pub async fn ensure_elevated(&self) -> Result<()> {
+        // Hold the su channel lock for the whole elevation so that concurrent
+        // elevations, commands and password changes are serialized
+        let mut channel_guard = self.su_channel.lock().await;
+        self.elevation_dropped.store(false, Ordering::SeqCst);
+
         // Already elevated?
-        if self.is_elevated.load(Ordering::SeqCst) {
-            let channel_guard = self.su_channel.lock().await;
-            if channel_guard.is_some() {
-                return Ok(());
-            }
+        if channel_guard.is_some() {
+            return Ok(());
         }
 
         // Need su_password
@@ -306,6 +990,53 @@ impl SshConnectionManager {
             .clone()
             .ok_or_else(|| SshMcpError::elevation_failed("No su_password configured"))?;
 
+        match self.open_su_shell(&su_password).await {
+            Ok(elevated_channel) => {
+                // Store the elevated channel
+                *channel_guard = Some(elevated_channel);
+                self.is_elevated.store(true, Ordering::SeqCst);
+                info!("Successfully elevated to root via su");
+                Ok(())
+            }
+            Err(e) => {
+                self.is_elevated.store(false, Ordering::SeqCst);
+                if !self.drop_closed_session().await {
+                    return Err(e);
+                }
+                let reason = match e {
+                    SshMcpError::ElevationFailed(reason) => reason,
+                    other => other.to_string(),
+                };
+                warn!("SSH connection dropped during elevation: {}", reason);
+                Err(SshMcpError::elevation_failed(format!(
+                    "SSH connection dropped ({}); it will be re-established on the \
+                     next command",
+                    reason
+                )))
+            }
+        }
+    }
+
+    /// Forget the stored session if the connection behind it has closed
+    ///
+    /// The next [`ensure_connected`](Self::ensure_connected) then connects
+    /// from scratch instead of handing out a dead handle. Returns whether the
+    /// session was dropped.
+    async fn drop_closed_session(&self) -> bool {
+        let mut session_guard = self.session.lock().await;
+        if !session_guard
+            .as_ref()
+            .is_some_and(|session| session.is_closed())
+        {
+            return false;
+        }
+        session_guard.take();
+        *self.session_created.lock().unwrap() = None;
+        true
+    }
+
+    /// Open a PTY shell and elevate it with the configured command
+    async fn open_su_shell(&self, su_password: &str) -> Result<Channel<client::Msg>> {
         // Open a channel for PTY shell
         let channel = self
             .open_channel()
@@ -319,7 +1050,7 @@ impl SshConnectionManager {
             .request_pty(
                 true, // want_reply
                 "xterm",
-                80,  // cols
+                u32::from(self.config.pty_cols),
                 24,  // rows
                 0,   // pixel width
                 0,   // pixel height
@@ -335,30 +1066,108 @@ impl SshConnectionManager {
             SshMcpError::elevation_failed(format!("Failed to request shell: {}", e))
         })?;
 
-        debug!("Shell requested, starting su elevation...");
+        debug!(
+            "Shell requested, starting elevation with '{}'...",
+            self.config.elevation_command
+        );
 
-        // Send "su -\n" command
-        channel.data(b"su -\n".as_slice()).await.map_err(|e| {
-            SshMcpError::elevation_failed(format!("Failed to send su command: {}", e))
-        })?;
+        // Send the elevation command ("su -" unless overridden)
+        let elevation_command = format!("{}\n", self.config.elevation_command);
+        channel
+            .data(elevation_command.as_bytes())
+            .await
+            .map_err(|e| {
+                SshMcpError::elevation_failed(format!("Failed to send elevation command: {}", e))
+            })?;
 
         // Wait for password prompt and respond
-        let elevation_result = self.handle_su_elevation(channel, &su_password).await;
+        let mut elevated_channel = self.handle_su_elevation(channel, su_password).await?;
+        self.prepare_su_shell(&mut elevated_channel).await?;
+        Ok(elevated_channel)
+    }
 
-        match elevation_result {
-            Ok(elevated_channel) => {
-                // Store the elevated channel
-                let mut channel_guard = self.su_channel.lock().await;
-                *channel_guard = Some(elevated_channel);
-                self.is_elevated.store(true, Ordering::SeqCst);
-                info!("Successfully elevated to root via su");
-                Ok(())
+    /// Elevate via su, retrying up to `elevation_retries` more times
+    ///
+    /// Every failure is retried, since a transient PAM error looks the same
+    /// as a rejected password. The outcome is remembered for
+    /// [`elevation_error`](Self::elevation_error).
+    pub async fn elevate_with_retries(&self) -> Result<()> {
+        let mut attempt = 0;
+        let result = loop {
+            match self.ensure_elevated().await {
+                Err(e) if attempt < self.config.elevation_retries => {
+                    let delay = reconnect_delay(attempt, self.config.reconnect_jitter);
+                    attempt += 1;
+                    warn!(
+                        "Elevation attempt {} failed ({}), retrying in {}ms",
+                        attempt,
+                        e,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => break result,
             }
-            Err(e) => {
-                self.is_elevated.store(false, Ordering::SeqCst);
-                Err(e)
+        };
+
+        let mut error = self.elevation_error.lock().unwrap();
+        match result {
+            Ok(()) => *error = None,
+            Err(ref e) => {
+                warn!(
+                    "Failed to elevate to root: {}. Commands will run as normal user.",
+                    e
+                );
+                *error = Some(e.to_string());
+            }
+        }
+        result
+    }
+
+    /// Close the elevated su shell and run later commands as the login user
+    ///
+    /// The SSH connection stays open. Commands and reconnects no longer
+    /// elevate on their own until [`Self::ensure_elevated`] is called.
+    /// Returns whether an elevated shell was open.
+    pub async fn drop_elevation(&self) -> bool {
+        // Waits for a command running in the su shell to finish
+        let mut channel_guard = self.su_channel.lock().await;
+        self.elevation_dropped.store(true, Ordering::SeqCst);
+        self.is_elevated.store(false, Ordering::SeqCst);
+        self.elevation_error.lock().unwrap().take();
+        match channel_guard.take() {
+            Some(channel) => {
+                let _ = channel.eof().await;
+                info!("Dropped su elevation, commands run as the login user");
+                true
             }
+            None => false,
+        }
+    }
+
+    /// Whether elevation was dropped with [`Self::drop_elevation`]
+    pub fn is_elevation_dropped(&self) -> bool {
+        self.elevation_dropped.load(Ordering::SeqCst)
+    }
+
+    /// Why su elevation is failing, if it was configured and has not
+    /// succeeded since the last attempt
+    pub fn elevation_error(&self) -> Option<String> {
+        self.elevation_error.lock().unwrap().clone()
+    }
+
+    /// Error for a shell channel that went away during elevation
+    ///
+    /// A login shell that quits before the elevation command could even ask
+    /// for a password usually means the account has no usable shell.
+    fn su_channel_ended(&self, how: &str, printed: &str, password_sent: bool) -> SshMcpError {
+        if !password_sent && looks_shell_less(printed, None) {
+            return SshMcpError::elevation_failed(format!(
+                "Shell channel {} before '{}' could run. {}",
+                how, self.config.elevation_command, NO_SHELL_HINT
+            ));
         }
+        SshMcpError::elevation_failed(format!("Channel {} before elevation completed", how))
     }
 
     /// Handle the interactive su elevation process
@@ -393,45 +1202,53 @@ impl SshConnectionManager {
                             buffer.push_str(&text);
                             debug!("su buffer: {}", buffer.replace('\n', "\\n"));
 
-                            // Check for password prompt
-                            if !password_sent && buffer.to_lowercase().contains("password") {
-                                debug!("Password prompt detected, sending password...");
-                                channel
-                                    .data(format!("{}\n", password).as_bytes())
-                                    .await
-                                    .map_err(|e| {
-                                        SshMcpError::elevation_failed(format!(
-                                            "Failed to send password: {}",
-                                            e
-                                        ))
-                                    })?;
-                                password_sent = true;
-                                // Clear buffer to avoid re-matching password prompt
-                                buffer.clear();
-                            }
-
-                            // Check for root prompt after password sent
-                            if password_sent && buffer.contains('#') {
-                                debug!("Root prompt detected, elevation successful");
-                                return Ok(channel);
-                            }
-
-                            // Check for authentication failure
-                            if buffer.to_lowercase().contains("authentication failure")
-                                || buffer.to_lowercase().contains("incorrect password")
-                                || buffer.to_lowercase().contains("su: failed")
-                                || buffer.to_lowercase().contains("su: authentication")
-                            {
-                                return Err(SshMcpError::elevation_failed(format!(
-                                    "su authentication failed: {}",
-                                    buffer
-                                )));
+                            match classify_su_output(&buffer, password_sent) {
+                                SuOutput::PasswordPrompt => {
+                                    debug!("Password prompt detected, sending password...");
+                                    channel
+                                        .data(format!("{}\n", password).as_bytes())
+                                        .await
+                                        .map_err(|e| {
+                                            SshMcpError::elevation_failed(format!(
+                                                "Failed to send password: {}",
+                                                e
+                                            ))
+                                        })?;
+                                    password_sent = true;
+                                    // Clear buffer to avoid re-matching password prompt
+                                    buffer.clear();
+                                }
+                                SuOutput::RootPrompt => {
+                                    if password_sent {
+                                        debug!("Root prompt detected, elevation successful");
+                                    } else {
+                                        debug!(
+                                            "Root prompt detected without password prompt, \
+                                             account has no password set"
+                                        );
+                                    }
+                                    return Ok(channel);
+                                }
+                                SuOutput::AuthFailure if password_sent => {
+                                    return Err(SshMcpError::elevation_failed(format!(
+                                        "'{}' authentication failed: {}",
+                                        self.config.elevation_command, buffer
+                                    )));
+                                }
+                                SuOutput::AuthFailure => {
+                                    return Err(SshMcpError::elevation_failed(format!(
+                                        "'{}' failed without prompting for a password; \
+                                         the target account is likely locked, has \
+                                         no usable password, or is not permitted \
+                                         to elevate: {}",
+                                        self.config.elevation_command, buffer
+                                    )));
+                                }
+                                SuOutput::Pending => {}
                             }
                         }
                         ChannelMsg::Close => {
-                            return Err(SshMcpError::elevation_failed(
-                                "Channel closed before elevation completed",
-                            ));
+                            return Err(self.su_channel_ended("closed", &buffer, password_sent));
                         }
                         _ => {
                             // Ignore other messages
@@ -440,9 +1257,7 @@ impl SshConnectionManager {
                 }
                 Ok(None) => {
                     // Channel ended
-                    return Err(SshMcpError::elevation_failed(
-                        "Channel ended before elevation completed",
-                    ));
+                    return Err(self.su_channel_ended("ended", &buffer, password_sent));
                 }
                 Err(_) => {
                     // Timeout on wait, continue loop
@@ -509,11 +1324,75 @@ impl SshConnectionManager {
                     .await;
             }
         }
+        *self.session_created.lock().unwrap() = None;
 
         info!("SSH connection closed");
     }
 }
 
+/// Classification of the interactive `su` output seen so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SuOutput {
+    /// `su` is asking for the password
+    PasswordPrompt,
+    /// A root prompt is showing, elevation completed
+    RootPrompt,
+    /// `su` rejected the attempt
+    AuthFailure,
+    /// Nothing conclusive yet, keep reading
+    Pending,
+}
+
+/// Classify the buffered `su` output
+///
+/// The same rules cover `sudo -i` and `sudo su -`: sudo's password prompt
+/// contains "password" and its rejections ("Sorry, try again", "not in the
+/// sudoers file") are recognised as failures. Authentication failures are
+/// checked first so that a failure message is never
+/// mistaken for a prompt. Before the password is sent, a root prompt is only
+/// accepted at the end of the last line (prompt position), which covers accounts
+/// with an empty password where `su` never asks for one, while ignoring `#`
+/// characters that may appear in a login banner.
+fn classify_su_output(buffer: &str, password_sent: bool) -> SuOutput {
+    let lowered = buffer.to_lowercase();
+
+    if lowered.contains("authentication failure")
+        || lowered.contains("incorrect password")
+        || lowered.contains("su: failed")
+        || lowered.contains("su: authentication")
+        || lowered.contains("account is locked")
+        || lowered.contains("account has expired")
+        || lowered.contains("permission denied")
+        || lowered.contains("sorry, try again")
+        || lowered.contains("not in the sudoers file")
+        || lowered.contains("is not allowed to execute")
+        || lowered.contains("a password is required")
+    {
+        return SuOutput::AuthFailure;
+    }
+
+    if password_sent {
+        if buffer.contains('#') {
+            return SuOutput::RootPrompt;
+        }
+        return SuOutput::Pending;
+    }
+
+    if lowered.contains("password") {
+        return SuOutput::PasswordPrompt;
+    }
+
+    let at_root_prompt = buffer
+        .lines()
+        .last()
+        .is_some_and(|line| line.trim_end().ends_with('#'));
+    if at_root_prompt {
+        return SuOutput::RootPrompt;
+    }
+
+    SuOutput::Pending
+}
+
 impl std::fmt::Debug for SshConnectionManager {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SshConnectionManager")
@@ -529,6 +1408,8 @@ impl std::fmt::Debug for SshConnectionManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ssh::handler::HostKeyPolicy;
+    use crate::ssh::EnvMethod;
 
     #[tokio::test]
     async fn test_connection_manager_creation() {
@@ -540,6 +1421,116 @@ mod tests {
 
         assert!(!manager.is_connected().await);
         assert!(!manager.is_elevated());
+        assert_eq!(manager.auth_method(), None);
+    }
+
+    /// Server double: the key does not parse, and only `password` is right
+    struct FakeAuth {
+        password: &'static str,
+        key_error: Option<fn() -> SshMcpError>,
+        tried: Vec<AuthMethod>,
+    }
+
+    impl AuthAttempts for FakeAuth {
+        async fn attempt(&mut self, method: AuthMethod) -> Result<AuthAttempt> {
+            self.tried.push(method);
+            match method {
+                AuthMethod::Key => match self.key_error {
+                    Some(error) => Err(error()),
+                    None => Ok(AuthAttempt::Rejected(SshMcpError::SshKey(
+                        "Failed to parse private key: invalid PEM".to_string(),
+                    ))),
+                },
+                AuthMethod::Password if self.password == "right" => Ok(AuthAttempt::Accepted),
+                AuthMethod::Password => Ok(AuthAttempt::Rejected(rejected("Password", &[]))),
+                AuthMethod::Agent => Ok(AuthAttempt::Rejected(rejected("Agent", &[]))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_key_falls_back_to_password() {
+        let methods = [AuthMethod::Key, AuthMethod::Password];
+        let mut server = FakeAuth {
+            password: "right",
+            key_error: None,
+            tried: Vec::new(),
+        };
+        let method = authenticate_with(&methods, &mut server).await.unwrap();
+        assert_eq!(method, AuthMethod::Password);
+        assert_eq!(server.tried, methods);
+
+        // With every method rejected, each outcome is reported
+        let mut server = FakeAuth {
+            password: "wrong",
+            key_error: None,
+            tried: Vec::new(),
+        };
+        let err = authenticate_with(&methods, &mut server).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Authentication failed: every method was rejected (key: SSH key error: Failed \
+             to parse private key: invalid PEM; password: Password authentication rejected)"
+        );
+
+        let err = authenticate_with(&[AuthMethod::Key], &mut server)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Failed to parse private key"));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_error_ends_authentication() {
+        let mut server = FakeAuth {
+            password: "right",
+            key_error: Some(|| SshMcpError::auth("Disconnected")),
+            tried: Vec::new(),
+        };
+        let methods = [AuthMethod::Key, AuthMethod::Password];
+        let err = authenticate_with(&methods, &mut server).await.unwrap_err();
+        assert!(err.to_string().contains("Disconnected"));
+        assert_eq!(server.tried, [AuthMethod::Key]);
+
+        let err = authenticate_with(&[], &mut server).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("No authentication method available"));
+    }
+
+    #[tokio::test]
+    async fn test_drop_elevation() {
+        let config = SshConfig::new("localhost", "testuser").with_su_password("secret");
+        let manager = SshConnectionManager::new(config).await;
+
+        assert!(!manager.drop_elevation().await);
+        assert!(manager.is_elevation_dropped());
+        assert!(!manager.is_elevated());
+        assert!(manager.elevation_error().is_none());
+
+        // Asking for elevation again clears the flag, even when it fails
+        assert!(manager.ensure_elevated().await.is_err());
+        assert!(!manager.is_elevation_dropped());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_fails_fast() {
+        // Nothing listens on port 1, so every attempt is refused
+        let config = SshConfig::new("127.0.0.1", "testuser")
+            .with_port(1)
+            .with_circuit_breaker(2, Duration::from_secs(60));
+        let manager = SshConnectionManager::new(config).await;
+
+        for _ in 0..2 {
+            let err = manager.ensure_connected().await.unwrap_err();
+            assert!(!err.to_string().contains("circuit open"));
+        }
+        let circuit = manager.circuit().unwrap();
+        assert_eq!(circuit.failures(), 2);
+
+        let err = manager.ensure_connected().await.unwrap_err();
+        assert!(err.to_string().contains("circuit open, host unhealthy"));
+        // The short-circuited call was not another attempt
+        assert_eq!(circuit.failures(), 2);
     }
 
     #[tokio::test]
@@ -551,4 +1542,480 @@ mod tests {
         let result = manager.open_channel().await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_idle_refresh() {
+        let manager = Arc::new(SshConnectionManager::new(SshConfig::new("localhost", "u")).await);
+        assert!(manager.spawn_idle_refresh().is_none());
+        assert_eq!(manager.idle_for(), None);
+
+        let config = SshConfig::new("localhost", "u").with_idle_refresh(Duration::from_secs(300));
+        let manager = Arc::new(SshConnectionManager::new(config).await);
+        let task = manager.spawn_idle_refresh().unwrap();
+
+        // Nothing to refresh without a session
+        assert!(!manager.refresh_idle_session().await);
+
+        // A running command means the connection is not idle
+        let gate = manager.command_gate.read().await;
+        assert!(!manager.refresh_idle_session().await);
+        assert!(manager.idle_for().unwrap() < Duration::from_secs(1));
+        drop(gate);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_connect_direct_errors() {
+        let err = connect_direct("no-such-host.invalid", 22)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Could not resolve host name \"no-such-host.invalid\""));
+
+        // A port that was just free refuses connections
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let err = connect_direct("127.0.0.1", port).await.unwrap_err();
+        assert!(err.to_string().contains("refused"), "{}", err);
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(0, false), Duration::from_millis(500));
+        assert_eq!(reconnect_delay(3, false), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(20, false), RECONNECT_MAX_DELAY);
+        assert_eq!(reconnect_delay(u32::MAX, false), RECONNECT_MAX_DELAY);
+
+        for attempt in [0, 3, 20] {
+            let backoff = reconnect_delay(attempt, false);
+            assert!(reconnect_delay(attempt, true) <= backoff);
+        }
+    }
+
+    #[test]
+    fn test_connect_timings_serialize() {
+        let timings = ConnectTimings {
+            tcp_ms: 3,
+            handshake_ms: 40,
+            auth_ms: 120,
+            elevation_ms: None,
+            total_ms: 163,
+        };
+        assert_eq!(
+            serde_json::to_value(&timings).unwrap(),
+            serde_json::json!({ "tcp_ms": 3, "handshake_ms": 40, "auth_ms": 120, "total_ms": 163 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_expiry() {
+        let config = SshConfig::new("localhost", "testuser")
+            .with_max_session_age(Duration::from_secs(3600))
+            .with_max_session_commands(2);
+        let manager = SshConnectionManager::new(config).await;
+
+        // No session yet, nothing to recycle
+        assert!(!manager.session_expired());
+
+        *manager.session_created.lock().unwrap() = Some(Instant::now());
+        manager.session_commands.store(1, Ordering::SeqCst);
+        assert!(!manager.session_expired());
+
+        manager.session_commands.store(2, Ordering::SeqCst);
+        assert!(manager.session_expired());
+
+        // Only possible once the monotonic clock has run for over an hour
+        let Some(created) = Instant::now().checked_sub(Duration::from_secs(3601)) else {
+            return;
+        };
+        manager.session_commands.store(0, Ordering::SeqCst);
+        *manager.session_created.lock().unwrap() = Some(created);
+        assert!(manager.session_expired());
+    }
+
+    #[test]
+    fn test_rekeys_due() {
+        let hour = Duration::from_secs(3600);
+        assert_eq!(rekeys_due(Duration::from_secs(3599), hour), 0);
+        assert_eq!(rekeys_due(hour, hour), 1);
+        assert_eq!(rekeys_due(Duration::from_secs(7300), hour), 2);
+        assert_eq!(rekeys_due(hour, Duration::ZERO), 3_600_000);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_across_rekey_recycles_session() {
+        let manager = SshConnectionManager::new(SshConfig::new("localhost", "testuser")).await;
+        let interval = manager.rekey_interval;
+
+        *manager.session_created.lock().unwrap() = Some(Instant::now());
+        manager.note_timeout(manager.expected_rekeys());
+        assert!(!manager.session_expired());
+
+        // A re-exchange became due while the command was running; only
+        // possible once the monotonic clock has run for longer than that
+        let Some(created) = Instant::now().checked_sub(interval + Duration::from_secs(1)) else {
+            return;
+        };
+        *manager.session_created.lock().unwrap() = Some(created);
+        manager.note_timeout(0);
+        assert!(manager.session_expired());
+    }
+
+    /// SSH server on localhost that runs shells and exec requests in a
+    /// local `sh`, for tests that need real channels
+    mod shell_server {
+        use std::collections::HashMap;
+        use std::process::Stdio;
+        use std::sync::Arc;
+
+        use russh::keys::ssh_key::rand_core::OsRng;
+        use russh::keys::{Algorithm, PrivateKey};
+        use russh::server::{self, Auth, Msg, Session};
+        use russh::{Channel, ChannelId, CryptoVec, Pty};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+        use tokio::process::{ChildStdin, Command};
+
+        /// Start accepting connections, returning the port
+        pub(super) async fn start() -> u16 {
+            let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+            let config = Arc::new(server::Config {
+                keys: vec![key],
+                auth_rejection_time: std::time::Duration::ZERO,
+                ..Default::default()
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let handler = ShellHandler::default();
+                    if let Ok(session) = server::run_stream(config.clone(), stream, handler).await {
+                        tokio::spawn(session);
+                    }
+                }
+            });
+            port
+        }
+
+        /// Accepts any password and feeds each shell channel's data to its `sh`
+        #[derive(Default)]
+        struct ShellHandler {
+            stdin: HashMap<ChannelId, ChildStdin>,
+        }
+
+        impl ShellHandler {
+            /// Run `sh`, or `sh -c command`, sending its output to `channel`
+            fn spawn_sh(
+                &mut self,
+                channel: ChannelId,
+                command: Option<&[u8]>,
+                session: &mut Session,
+            ) -> Result<(), russh::Error> {
+                let mut sh = Command::new("sh");
+                if let Some(command) = command {
+                    sh.arg("-c")
+                        .arg(String::from_utf8_lossy(command).into_owned());
+                }
+                let mut child = sh
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()?;
+                let stdin = child.stdin.take().unwrap();
+                if command.is_none() {
+                    self.stdin.insert(channel, stdin);
+                }
+                let mut stdout = child.stdout.take().unwrap();
+
+                let handle = session.handle();
+                session.channel_success(channel)?;
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n @ 1..) = stdout.read(&mut buf).await {
+                        let data = CryptoVec::from_slice(&buf[..n]);
+                        if handle.data(channel, data).await.is_err() {
+                            break;
+                        }
+                    }
+                    let status = child.wait().await.ok().and_then(|s| s.code());
+                    let _ = handle
+                        .exit_status_request(channel, status.unwrap_or(255) as u32)
+                        .await;
+                    let _ = handle.eof(channel).await;
+                    let _ = handle.close(channel).await;
+                });
+                Ok(())
+            }
+        }
+
+        impl server::Handler for ShellHandler {
+            type Error = russh::Error;
+
+            async fn auth_password(
+                &mut self,
+                _user: &str,
+                _password: &str,
+            ) -> Result<Auth, Self::Error> {
+                Ok(Auth::Accept)
+            }
+
+            async fn channel_open_session(
+                &mut self,
+                _channel: Channel<Msg>,
+                _session: &mut Session,
+            ) -> Result<bool, Self::Error> {
+                Ok(true)
+            }
+
+            #[allow(clippy::too_many_arguments)]
+            async fn pty_request(
+                &mut self,
+                channel: ChannelId,
+                _term: &str,
+                _col_width: u32,
+                _row_height: u32,
+                _pix_width: u32,
+                _pix_height: u32,
+                _modes: &[(Pty, u32)],
+                session: &mut Session,
+            ) -> Result<(), Self::Error> {
+                session.channel_success(channel)
+            }
+
+            async fn shell_request(
+                &mut self,
+                channel: ChannelId,
+                session: &mut Session,
+            ) -> Result<(), Self::Error> {
+                self.spawn_sh(channel, None, session)
+            }
+
+            async fn exec_request(
+                &mut self,
+                channel: ChannelId,
+                data: &[u8],
+                session: &mut Session,
+            ) -> Result<(), Self::Error> {
+                self.spawn_sh(channel, Some(data), session)
+            }
+
+            async fn data(
+                &mut self,
+                channel: ChannelId,
+                data: &[u8],
+                _session: &mut Session,
+            ) -> Result<(), Self::Error> {
+                if let Some(stdin) = self.stdin.get_mut(&channel) {
+                    let _ = stdin.write_all(data).await;
+                }
+                Ok(())
+            }
+
+            async fn channel_eof(
+                &mut self,
+                channel: ChannelId,
+                _session: &mut Session,
+            ) -> Result<(), Self::Error> {
+                // Closing its input ends the shell
+                self.stdin.remove(&channel);
+                Ok(())
+            }
+
+            async fn channel_close(
+                &mut self,
+                channel: ChannelId,
+                _session: &mut Session,
+            ) -> Result<(), Self::Error> {
+                self.stdin.remove(&channel);
+                Ok(())
+            }
+        }
+    }
+
+    /// Run a command that reports whether it ran in the stand-in su shell
+    async fn run_marked(manager: Arc<SshConnectionManager>) -> Result<String> {
+        manager
+            .exec_command(
+                "sleep 0.2; echo ${IN_SU_SHELL:-exec}",
+                Duration::from_secs(5),
+            )
+            .await
+            .map(|output| output.stdout.trim().to_string())
+    }
+
+    /// Connect to the shell server with a stand-in su shell in place
+    ///
+    /// Commands typed into the shell can be told apart from those on exec
+    /// channels by `IN_SU_SHELL`.
+    async fn connect_with_su_shell() -> Arc<SshConnectionManager> {
+        let port = shell_server::start().await;
+        let config = SshConfig::new("127.0.0.1", "testuser")
+            .with_port(port)
+            .with_password("testpass")
+            .with_host_key_policy(HostKeyPolicy::AcceptAny);
+        let manager = Arc::new(SshConnectionManager::new(config).await);
+        manager.connect().await.unwrap();
+
+        let channel = manager.open_channel().await.unwrap();
+        channel.request_shell(true).await.unwrap();
+        channel.data(b"IN_SU_SHELL=yes\n".as_slice()).await.unwrap();
+        *manager.su_channel.lock().await = Some(channel);
+        manager.is_elevated.store(true, Ordering::SeqCst);
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_su_shell_env_is_scoped_to_the_command() {
+        let manager = connect_with_su_shell().await;
+        let command = "echo ${SSH_MCP_SCOPED:-unset} ${IN_SU_SHELL:-exec}";
+
+        let env = vec![("SSH_MCP_SCOPED".to_string(), "set".to_string())];
+        let (output, methods) = manager
+            .exec_command_with_env(command, &env, None, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.trim(), "set yes");
+        assert_eq!(methods[0].1, EnvMethod::Shell);
+
+        // The next command in the same shell no longer sees it
+        let output = manager
+            .exec_command(command, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.trim(), "unset yes");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_set_su_password_and_exec() {
+        let manager = connect_with_su_shell().await;
+
+        // Clearing the password must wait for the command holding the shell
+        let first = tokio::spawn(run_marked(manager.clone()));
+        timeout(Duration::from_secs(5), async {
+            while manager.su_channel.try_lock().is_ok() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the command never took the su shell");
+
+        let mut tasks = Vec::new();
+        for i in 0..6 {
+            let manager = manager.clone();
+            tasks.push(tokio::spawn(async move {
+                if i % 2 == 0 {
+                    manager.set_su_password(None).await.map(|()| None)
+                } else {
+                    run_marked(manager).await.map(Some)
+                }
+            }));
+        }
+
+        let (first, results) = timeout(Duration::from_secs(10), async {
+            let first = first.await.expect("task panicked");
+            let mut results = Vec::new();
+            for task in tasks {
+                results.push(task.await.expect("task panicked"));
+            }
+            (first, results)
+        })
+        .await
+        .expect("set_su_password and exec deadlocked");
+
+        assert_eq!(first.unwrap(), "yes");
+        for result in results {
+            if let Some(marker) = result.unwrap() {
+                assert!(marker == "yes" || marker == "exec", "{}", marker);
+            }
+        }
+        assert!(!manager.is_elevated());
+        assert!(!manager.has_su_channel().await);
+        assert_eq!(run_marked(manager).await.unwrap(), "exec");
+    }
+
+    #[test]
+    fn test_classify_su_output_password_prompt() {
+        assert_eq!(
+            classify_su_output("$ su -\r\nPassword: ", false),
+            SuOutput::PasswordPrompt
+        );
+    }
+
+    #[test]
+    fn test_classify_su_output_root_prompt_after_password() {
+        assert_eq!(
+            classify_su_output("\r\nroot@host:~# ", true),
+            SuOutput::RootPrompt
+        );
+        assert_eq!(classify_su_output("\r\n", true), SuOutput::Pending);
+    }
+
+    #[test]
+    fn test_classify_su_output_no_password_set() {
+        // su succeeds straight away when the account has an empty password
+        assert_eq!(
+            classify_su_output("$ su -\r\nroot@host:~# ", false),
+            SuOutput::RootPrompt
+        );
+        // A '#' in a banner is not a prompt
+        assert_eq!(
+            classify_su_output("# Welcome to host\r\n$ su -\r\n", false),
+            SuOutput::Pending
+        );
+    }
+
+    #[test]
+    fn test_classify_su_output_non_login_su() {
+        // Plain su stays in the login user's directory, and a root shell
+        // without a PS1 of its own shows bash's default prompt
+        assert_eq!(
+            classify_su_output("admin@host:/srv/app$ su\r\nPassword: ", false),
+            SuOutput::PasswordPrompt
+        );
+        assert_eq!(
+            classify_su_output("\r\nroot@host:/srv/app# ", true),
+            SuOutput::RootPrompt
+        );
+        assert_eq!(
+            classify_su_output("\r\nbash-5.2# ", true),
+            SuOutput::RootPrompt
+        );
+    }
+
+    #[test]
+    fn test_classify_su_output_locked_account() {
+        assert_eq!(
+            classify_su_output("$ su -\r\nsu: Authentication failure\r\n", false),
+            SuOutput::AuthFailure
+        );
+        assert_eq!(
+            classify_su_output("su: Authentication failure\r\n$ ", true),
+            SuOutput::AuthFailure
+        );
+    }
+
+    #[test]
+    fn test_classify_su_output_sudo_elevation() {
+        assert_eq!(
+            classify_su_output("$ sudo -i\r\n[sudo] password for admin: ", false),
+            SuOutput::PasswordPrompt
+        );
+        assert_eq!(
+            classify_su_output("\r\nSorry, try again.\r\n[sudo] password for admin: ", true),
+            SuOutput::AuthFailure
+        );
+        assert_eq!(
+            classify_su_output("admin is not in the sudoers file.\r\n", false),
+            SuOutput::AuthFailure
+        );
+        // NOPASSWD rule: straight to the root prompt
+        assert_eq!(
+            classify_su_output("$ sudo -i\r\nroot@host:~# ", false),
+            SuOutput::RootPrompt
+        );
+    }
 }
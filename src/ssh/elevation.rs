@@ -2,11 +2,16 @@
 //!
 //! This module provides utilities for:
 //! - Wrapping commands with `sudo` for privilege escalation
+//! - Stripping the first-use sudo lecture from command output
+//! - Redacting passwords from commands before they are shown
+//! - Recognising failures that sudo would fix (`--auto-sudo-on-denied`)
 //! - Escaping passwords and commands for safe shell execution
 //!
 //! The elevation logic for `su` shells is implemented directly in
 //! [`SshConnectionManager`](super::connection::SshConnectionManager).
 
+use super::CommandOutput;
+
 /// Wraps a command for execution with sudo privileges.
 ///
 /// # Arguments
@@ -31,6 +36,30 @@
 /// assert_eq!(cmd, "printf '%s\\n' 'mypassword' | sudo -p \"\" -S sh -c 'apt update'");
 /// ```
 pub fn wrap_sudo_command(command: &str, password: Option<&str>) -> String {
+    wrap_sudo_command_with_newline(command, password, true)
+}
+
+/// Wraps a command for sudo, choosing whether the piped password ends in a newline.
+///
+/// Identical to [`wrap_sudo_command`] except that with `newline` set to false
+/// the password is piped without the trailing `\n`, for PAM setups that take
+/// the raw stdin contents as the password. The password is always passed as
+/// an argument to a fixed `printf` format, so `%` and `\` in it are sent
+/// literally.
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::ssh::elevation::wrap_sudo_command_with_newline;
+///
+/// let cmd = wrap_sudo_command_with_newline("id", Some("100%s"), false);
+/// assert_eq!(cmd, "printf '%s' '100%s' | sudo -p \"\" -S sh -c 'id'");
+/// ```
+pub fn wrap_sudo_command_with_newline(
+    command: &str,
+    password: Option<&str>,
+    newline: bool,
+) -> String {
     let escaped_command = escape_for_shell(command);
 
     match password {
@@ -41,15 +70,168 @@ pub fn wrap_sudo_command(command: &str, password: Option<&str>) -> String {
         Some(pwd) => {
             // Password provided — pipe it into sudo using printf. This avoids complex
             // PTY/stdin handling on the SSH channel and is simpler and more reliable.
+            // The password is an argument, never part of the format string
             let escaped_pwd = escape_for_shell(pwd);
+            let format = if newline { "%s\\n" } else { "%s" };
             format!(
-                "printf '%s\\n' '{}' | sudo -p \"\" -S sh -c '{}'",
-                escaped_pwd, escaped_command
+                "printf '{}' '{}' | sudo -p \"\" -S sh -c '{}'",
+                format, escaped_pwd, escaped_command
             )
         }
     }
 }
 
+/// Replaces every occurrence of `secret` in `command` with `******`.
+///
+/// Both the raw value and its shell-escaped form (as produced by
+/// [`wrap_sudo_command`]) are redacted. An empty secret leaves the command
+/// unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::ssh::elevation::{redact_secret, wrap_sudo_command};
+///
+/// let cmd = wrap_sudo_command("id", Some("it's"));
+/// assert_eq!(
+///     redact_secret(&cmd, "it's"),
+///     "printf '%s\\n' '******' | sudo -p \"\" -S sh -c 'id'"
+/// );
+/// ```
+pub fn redact_secret(command: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        return command.to_string();
+    }
+
+    command
+        .replace(&escape_for_shell(secret), "******")
+        .replace(secret, "******")
+}
+
+/// Prompt sudo is told to print when it needs a password on a PTY.
+///
+/// The value is distinctive enough not to occur in normal command output, so
+/// seeing it in the stream reliably means sudo is waiting for the password.
+pub const SUDO_PROMPT_SENTINEL: &str = "[ssh-mcp-sudo-password-prompt]";
+
+/// Wraps a command for sudo on a PTY with the sentinel password prompt.
+///
+/// Unlike [`wrap_sudo_command`], the password is not part of the command
+/// line; the caller watches the output for [`SUDO_PROMPT_SENTINEL`] and sends
+/// the password only when sudo asks for it.
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::ssh::elevation::wrap_sudo_command_pty;
+///
+/// let cmd = wrap_sudo_command_pty("apt update");
+/// assert_eq!(cmd, "sudo -p '[ssh-mcp-sudo-password-prompt]' sh -c 'apt update'");
+/// ```
+pub fn wrap_sudo_command_pty(command: &str) -> String {
+    format!(
+        "sudo -p '{}' sh -c '{}'",
+        SUDO_PROMPT_SENTINEL,
+        escape_for_shell(command)
+    )
+}
+
+/// Lines of the lecture `sudo` prints the first time an account uses it.
+const SUDO_LECTURE_LINES: &[&str] = &[
+    "We trust you have received the usual lecture from the local System",
+    "Administrator. It usually boils down to these three things:",
+    "#1) Respect the privacy of others.",
+    "#2) Think before you type.",
+    "#3) With great power comes great responsibility.",
+    "For security reasons, the password you type will not be visible.",
+];
+
+/// Messages commands print when they lack the privileges to do something
+const PERMISSION_DENIED_MESSAGES: &[&str] = &[
+    "permission denied",
+    "operation not permitted",
+    "eacces",
+    "must be root",
+    "must be run as root",
+    "are you root",
+    "requires root privileges",
+];
+
+/// Checks whether a failed command was refused for lack of privileges.
+///
+/// The command must have exited with a non-zero code and said so on stderr
+/// (or on stdout, where a PTY merges both).
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::ssh::elevation::is_permission_denied;
+/// use ssh_mcp::ssh::CommandOutput;
+///
+/// let output = CommandOutput {
+///     stderr: "cat: /etc/shadow: Permission denied\n".to_string(),
+///     exit_code: Some(1),
+///     ..CommandOutput::new()
+/// };
+/// assert!(is_permission_denied(&output));
+/// ```
+pub fn is_permission_denied(output: &CommandOutput) -> bool {
+    if output.success() {
+        return false;
+    }
+    [&output.stderr, &output.stdout].iter().any(|text| {
+        let text = text.to_ascii_lowercase();
+        PERMISSION_DENIED_MESSAGES
+            .iter()
+            .any(|message| text.contains(message))
+    })
+}
+
+/// Removes the first-use sudo lecture from command output.
+///
+/// On a fresh account the first `sudo` invocation prints a lecture to stderr
+/// before running the command. It carries no information about the command
+/// itself, so it is stripped along with the blank lines surrounding it.
+/// Output without the lecture is returned unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::ssh::elevation::strip_sudo_lecture;
+///
+/// let stderr = "\nWe trust you have received the usual lecture from the local System\n\
+///               Administrator. It usually boils down to these three things:\n\n\
+///               \x20   #1) Respect the privacy of others.\n\
+///               \x20   #2) Think before you type.\n\
+///               \x20   #3) With great power comes great responsibility.\n\n\
+///               warning: something\n";
+/// assert_eq!(strip_sudo_lecture(stderr), "warning: something\n");
+/// ```
+pub fn strip_sudo_lecture(output: &str) -> String {
+    if !output.contains(SUDO_LECTURE_LINES[0]) {
+        return output.to_string();
+    }
+
+    let mut result = String::with_capacity(output.len());
+    let mut in_lecture = false;
+
+    for line in output.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if SUDO_LECTURE_LINES.contains(&trimmed) {
+            in_lecture = true;
+            continue;
+        }
+        // Blank lines separate the lecture paragraphs
+        if trimmed.is_empty() && (in_lecture || result.is_empty()) {
+            continue;
+        }
+        in_lecture = false;
+        result.push_str(line);
+    }
+
+    result
+}
+
 /// Escapes a string for safe use in single-quoted shell contexts.
 ///
 /// Replaces single quotes with the pattern `'\''` which:
@@ -75,6 +257,8 @@ pub fn escape_for_shell(s: &str) -> String {
 /// A valid password:
 /// - Is not empty after trimming
 /// - Does not contain null bytes
+/// - Does not contain line breaks, since `sudo -S` and the `su` prompt read
+///   the password up to the first one and would receive it truncated
 ///
 /// # Arguments
 /// * `password` - The password to validate
@@ -82,7 +266,7 @@ pub fn escape_for_shell(s: &str) -> String {
 /// # Returns
 /// `true` if the password is valid, `false` otherwise.
 pub fn is_valid_password(password: &str) -> bool {
-    !password.trim().is_empty() && !password.contains('\0')
+    !password.trim().is_empty() && !password.contains(['\0', '\n', '\r'])
 }
 
 /// Sanitizes a password by trimming whitespace.
@@ -144,6 +328,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_wrap_sudo_command_with_percent_in_password() {
+        // printf must never see the password as its format string
+        let result = wrap_sudo_command("id", Some("100%s%d\\n"));
+        assert_eq!(
+            result,
+            "printf '%s\\n' '100%s%d\\n' | sudo -p \"\" -S sh -c 'id'"
+        );
+    }
+
+    #[test]
+    fn test_wrap_sudo_command_without_newline() {
+        let result = wrap_sudo_command_with_newline("id", Some("50%off"), false);
+        assert_eq!(result, "printf '%s' '50%off' | sudo -p \"\" -S sh -c 'id'");
+
+        let result = wrap_sudo_command_with_newline("id", None, false);
+        assert_eq!(result, "sudo -n sh -c 'id'");
+    }
+
     #[test]
     fn test_escape_for_shell_no_quotes() {
         assert_eq!(escape_for_shell("hello world"), "hello world");
@@ -169,6 +372,63 @@ mod tests {
         assert!(!is_valid_password(""));
         assert!(!is_valid_password("   "));
         assert!(!is_valid_password("has\0null"));
+        assert!(!is_valid_password("two\nlines"));
+        assert!(!is_valid_password("carriage\rreturn"));
+    }
+
+    /// Run the printf half of a wrapped sudo command and return the bytes it
+    /// would feed to `sudo -S`
+    #[cfg(unix)]
+    fn piped_password(password: &str, newline: bool) -> Vec<u8> {
+        let wrapped = wrap_sudo_command_with_newline("true", Some(password), newline);
+        let (printf, _) = wrapped.split_once(" | sudo ").unwrap();
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(printf)
+            .output()
+            .unwrap()
+            .stdout
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_piped_password_matrix() {
+        let passwords = [
+            "plain",
+            "100%",
+            "%s%d%n%%",
+            "%b\\n",
+            "back\\slash",
+            "\\\\double",
+            "trailing\\",
+            "it's",
+            "'quoted'",
+            "\"double\"",
+            "''",
+            "$HOME `id` $(id)",
+            "!history",
+            "-n",
+            "--",
+            "tab\there",
+            "ünïcödé",
+            " spaced ",
+        ];
+
+        for password in passwords {
+            assert!(is_valid_password(password), "{:?}", password);
+            assert_eq!(
+                piped_password(password, true),
+                format!("{}\n", password).into_bytes(),
+                "password {:?}",
+                password
+            );
+            assert_eq!(
+                piped_password(password, false),
+                password.as_bytes(),
+                "password {:?}",
+                password
+            );
+        }
     }
 
     #[test]
@@ -185,4 +445,84 @@ mod tests {
         assert_eq!(sanitize_password(Some("   ")), None);
         assert_eq!(sanitize_password(None), None);
     }
+
+    #[test]
+    fn test_strip_sudo_lecture() {
+        let stderr = "\n\
+            We trust you have received the usual lecture from the local System\n\
+            Administrator. It usually boils down to these three things:\n\
+            \n\
+            \x20   #1) Respect the privacy of others.\n\
+            \x20   #2) Think before you type.\n\
+            \x20   #3) With great power comes great responsibility.\n\
+            \n\
+            For security reasons, the password you type will not be visible.\n\
+            \n\
+            E: Could not open lock file\n";
+        assert_eq!(strip_sudo_lecture(stderr), "E: Could not open lock file\n");
+    }
+
+    #[test]
+    fn test_strip_sudo_lecture_without_lecture() {
+        let stderr = "\nline one\n\nline two\n";
+        assert_eq!(strip_sudo_lecture(stderr), stderr);
+    }
+
+    #[test]
+    fn test_strip_sudo_lecture_only_lecture() {
+        let stderr = "We trust you have received the usual lecture from the local System\n\
+            Administrator. It usually boils down to these three things:\n\n";
+        assert_eq!(strip_sudo_lecture(stderr), "");
+    }
+
+    #[test]
+    fn test_is_permission_denied() {
+        let output = |stdout: &str, stderr: &str, exit_code| CommandOutput {
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            exit_code,
+            ..Default::default()
+        };
+        assert!(is_permission_denied(&output(
+            "",
+            "bash: /root/x: Permission denied",
+            Some(126)
+        )));
+        assert!(is_permission_denied(&output(
+            "",
+            "kill: (1) - Operation not permitted",
+            Some(1)
+        )));
+        assert!(is_permission_denied(&output(
+            "E: This command must be run as root\r\n",
+            "",
+            Some(100)
+        )));
+        assert!(!is_permission_denied(&output(
+            "",
+            "No such file or directory",
+            Some(1)
+        )));
+        // Successful commands may mention denials in their output
+        assert!(!is_permission_denied(&output(
+            "0 permission denied events\n",
+            "",
+            Some(0)
+        )));
+    }
+
+    #[test]
+    fn test_wrap_sudo_command_pty() {
+        let result = wrap_sudo_command_pty("echo 'hi'");
+        assert_eq!(
+            result,
+            "sudo -p '[ssh-mcp-sudo-password-prompt]' sh -c 'echo '\"'\"'hi'\"'\"''"
+        );
+    }
+
+    #[test]
+    fn test_redact_secret() {
+        assert_eq!(redact_secret("echo hunter2", "hunter2"), "echo ******");
+        assert_eq!(redact_secret("echo hunter2", ""), "echo hunter2");
+    }
 }
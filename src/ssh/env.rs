@@ -0,0 +1,204 @@
+//! Environment variables for executed commands
+//!
+//! sshd only applies `env` requests for names listed in its `AcceptEnv`
+//! setting and silently drops the rest. Names are therefore probed once per
+//! connection: accepted ones are sent through the protocol, everything else
+//! is exported at the start of the command line instead.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::connection::SshConnectionManager;
+use super::sanitize::escape_command_for_shell;
+use crate::error::{Result, SshMcpError};
+
+/// How long the AcceptEnv probe may take
+const ENV_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How an environment variable reached the command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvMethod {
+    /// Sent with an SSH `env` request (accepted by the server's AcceptEnv)
+    Protocol,
+
+    /// Exported by the shell before the command
+    Shell,
+}
+
+/// Check that a name can be used as a shell variable
+pub fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Validate environment variables requested for a command
+pub fn validate_env(env: &[(String, String)]) -> Result<()> {
+    for (name, value) in env {
+        if !is_valid_env_name(name) {
+            return Err(SshMcpError::invalid_params(format!(
+                "Invalid environment variable name: {:?}",
+                name
+            )));
+        }
+        if value.contains('\0') {
+            return Err(SshMcpError::invalid_params(format!(
+                "Environment variable {} contains a null byte",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Exports that make pagers print straight through instead of waiting for
+/// a key press on a terminal
+///
+/// Put before commands that run on a PTY (and before every command with
+/// `--disable-pager`), since `git log`, `systemctl status` and `man` only
+/// start a pager when their output is a terminal.
+pub const NO_PAGER_EXPORTS: &str =
+    "export PAGER=cat GIT_PAGER=cat SYSTEMD_PAGER=cat MANPAGER=cat; ";
+
+/// Build `export NAME='value'; ` statements for the given variables
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::ssh::env::export_prefix;
+///
+/// let env = vec![("GREETING".to_string(), "it's".to_string())];
+/// assert_eq!(export_prefix(&env), "export GREETING='it'\"'\"'s'; ");
+/// ```
+pub fn export_prefix(env: &[(String, String)]) -> String {
+    env.iter()
+        .map(|(name, value)| format!("export {}='{}'; ", name, escape_command_for_shell(value)))
+        .collect()
+}
+
+/// Run `command` in a subshell that exports the given variables first
+///
+/// For the persistent su shell, where plain exports would stay set for
+/// every later command. The closing parenthesis goes on its own line, so a
+/// command ending in a comment still closes the subshell. Without variables
+/// the command is returned unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::ssh::env::scoped_exports;
+///
+/// let env = vec![("TZ".to_string(), "UTC".to_string())];
+/// assert_eq!(scoped_exports(&env, "date"), "(export TZ='UTC'; date\n)");
+/// assert_eq!(scoped_exports(&[], "date"), "date");
+/// ```
+pub fn scoped_exports(env: &[(String, String)], command: &str) -> String {
+    if env.is_empty() {
+        return command.to_string();
+    }
+    format!("({}{}\n)", export_prefix(env), command)
+}
+
+impl SshConnectionManager {
+    /// Return which of `names` the server accepts through `env` requests
+    ///
+    /// Names not seen on this connection yet are probed together: each is
+    /// sent with a random value and `env` is run to see which values arrived.
+    /// A failed probe is not cached, so the names fall back to the shell and
+    /// are probed again next time.
+    pub(crate) async fn accepted_env_names(&self, names: &[&str]) -> HashSet<String> {
+        let unknown: Vec<&str> = {
+            let cache = self.env_accepted.lock().unwrap();
+            names
+                .iter()
+                .copied()
+                .filter(|name| !cache.contains_key(*name))
+                .collect()
+        };
+
+        if !unknown.is_empty() {
+            let token = format!("ssh-mcp-probe-{:016x}", rand::random::<u64>());
+            let probe: Vec<(String, String)> = unknown
+                .iter()
+                .map(|name| (name.to_string(), token.clone()))
+                .collect();
+
+            match self
+                .exec_via_channel("env", &probe, None, None, ENV_PROBE_TIMEOUT)
+                .await
+            {
+                Ok(output) => {
+                    let mut cache = self.env_accepted.lock().unwrap();
+                    for name in unknown {
+                        let expected = format!("{}={}", name, token);
+                        let accepted = output.stdout.lines().any(|line| line == expected);
+                        debug!("Server AcceptEnv for {}: {}", name, accepted);
+                        cache.insert(name.to_string(), accepted);
+                    }
+                }
+                Err(e) => debug!("AcceptEnv probe failed: {}", e),
+            }
+        }
+
+        let cache = self.env_accepted.lock().unwrap();
+        names
+            .iter()
+            .filter(|name| cache.get(**name).copied().unwrap_or(false))
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_env_name() {
+        assert!(is_valid_env_name("LANG"));
+        assert!(is_valid_env_name("_private1"));
+        assert!(!is_valid_env_name(""));
+        assert!(!is_valid_env_name("1ABC"));
+        assert!(!is_valid_env_name("A-B"));
+        assert!(!is_valid_env_name("A B"));
+        assert!(!is_valid_env_name("X;rm"));
+    }
+
+    #[test]
+    fn test_validate_env() {
+        let ok = vec![("TZ".to_string(), "UTC".to_string())];
+        assert!(validate_env(&ok).is_ok());
+
+        let bad_name = vec![("$(id)".to_string(), "x".to_string())];
+        assert!(validate_env(&bad_name).is_err());
+
+        let bad_value = vec![("A".to_string(), "a\0b".to_string())];
+        assert!(validate_env(&bad_value).is_err());
+    }
+
+    #[test]
+    fn test_export_prefix() {
+        assert_eq!(export_prefix(&[]), "");
+
+        let env = vec![
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "$HOME".to_string()),
+        ];
+        assert_eq!(export_prefix(&env), "export A='1'; export B='$HOME'; ");
+    }
+
+    #[test]
+    fn test_scoped_exports() {
+        let env = vec![("A".to_string(), "1".to_string())];
+        assert_eq!(
+            scoped_exports(&env, "make # build"),
+            "(export A='1'; make # build\n)"
+        );
+    }
+}
@@ -2,24 +2,419 @@
 //!
 //! Implements the `russh::client::Handler` trait to handle SSH connection events.
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use russh::keys::ssh_key::Fingerprint;
+use russh::keys::{HashAlg, PublicKey};
+use tracing::{debug, info, warn};
+
+use crate::error::{Result, SshMcpError};
+
+/// How the server's host key is verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostKeyPolicy {
+    /// Require a matching entry in this known_hosts file
+    KnownHosts(PathBuf),
+
+    /// Require one of these SHA256 fingerprints (`--host-fingerprint`)
+    Fingerprints(Vec<Fingerprint>),
+
+    /// Record each host's key on first use and require it afterwards
+    /// (`--host-key-policy=tofu`)
+    Tofu(HostKeyStore),
+
+    /// Accept any key (`--host-key-policy=insecure` or `--no-host-key-check`)
+    AcceptAny,
+}
+
+impl Default for HostKeyPolicy {
+    fn default() -> Self {
+        HostKeyPolicy::KnownHosts(default_known_hosts())
+    }
+}
+
+/// `~/.ssh/known_hosts` of the user running the server
+pub fn default_known_hosts() -> PathBuf {
+    ssh_dir().join("known_hosts")
+}
+
+/// `~/.ssh/ssh-mcp-host-keys.json`, where TOFU keys go by default
+pub fn default_host_key_store() -> PathBuf {
+    ssh_dir().join("ssh-mcp-host-keys.json")
+}
+
+/// `~/.ssh` of the user running the server
+fn ssh_dir() -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    home.join(".ssh")
+}
+
+/// How `--host-key-policy` checks host keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HostKeyMode {
+    /// Require a known_hosts entry (or a `--host-fingerprint`)
+    #[default]
+    Strict,
+
+    /// Trust the first key seen for a host and require it afterwards
+    Tofu,
+
+    /// Accept any key
+    Insecure,
+}
+
+impl FromStr for HostKeyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "strict" => Ok(HostKeyMode::Strict),
+            "tofu" => Ok(HostKeyMode::Tofu),
+            "insecure" => Ok(HostKeyMode::Insecure),
+            other => Err(format!(
+                "unknown host key policy '{}' (supported: strict, tofu, insecure)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HostKeyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HostKeyMode::Strict => "strict",
+            HostKeyMode::Tofu => "tofu",
+            HostKeyMode::Insecure => "insecure",
+        })
+    }
+}
+
+/// JSON file of host keys trusted on first use
+///
+/// Maps each host, as `host` or `[host]:port`, to its key in OpenSSH
+/// format (`ssh-ed25519 AAAA...`). The file is rewritten whole through a
+/// temporary file, so a crash never leaves it half-written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostKeyStore {
+    path: PathBuf,
+}
+
+impl HostKeyStore {
+    /// Use the store at `path`, which is created on the first write
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Location of the store
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Key recorded for `host`, if any
+    pub fn get(&self, host: &str) -> Result<Option<PublicKey>> {
+        let Some(key) = self.load()?.remove(host) else {
+            return Ok(None);
+        };
+        PublicKey::from_openssh(&key).map(Some).map_err(|e| {
+            SshMcpError::connection(format!(
+                "Invalid key for {} in {}: {}",
+                host,
+                self.path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Record `key` for `host`, replacing any earlier key
+    pub fn insert(&self, host: &str, key: &PublicKey) -> Result<()> {
+        let encoded = key.to_openssh().map_err(|e| {
+            SshMcpError::connection(format!("Failed to encode the host key: {}", e))
+        })?;
+        let mut keys = self.load()?;
+        keys.insert(host.to_string(), encoded);
+        self.save(&keys)
+    }
+
+    /// Check `key` against the one recorded for `host`, recording it if
+    /// there is none yet
+    pub fn verify(&self, host: &str, key: &PublicKey) -> Result<()> {
+        let actual = key.fingerprint(HashAlg::Sha256);
+        match self.get(host)? {
+            Some(ref stored) if stored == key => {
+                debug!("Host key {} for {} matches the store", actual, host);
+                Ok(())
+            }
+            Some(stored) => Err(SshMcpError::HostKeyChanged {
+                host: host.to_string(),
+                expected: stored.fingerprint(HashAlg::Sha256).to_string(),
+                actual: actual.to_string(),
+                hint: format!(
+                    "If the change is expected, remove {} from {}",
+                    host,
+                    self.path.display()
+                ),
+            }),
+            None => {
+                self.insert(host, key)?;
+                info!(
+                    "Trusting {} key {} for {} on first use (stored in {})",
+                    key.algorithm(),
+                    actual,
+                    host,
+                    self.path.display()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Every recorded key, by host; a missing file has none
+    fn load(&self) -> Result<BTreeMap<String, String>> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(self.store_error(e)),
+        };
+        serde_json::from_str(&text).map_err(|e| self.store_error(e))
+    }
+
+    /// Replace the file with `keys`
+    fn save(&self, keys: &BTreeMap<String, String>) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| self.store_error(e))?;
+        }
+        let json = serde_json::to_string_pretty(keys).map_err(|e| self.store_error(e))?;
+        let temp = self
+            .path
+            .with_extension(format!("{:08x}.tmp", rand::random::<u32>()));
+        std::fs::write(&temp, json + "\n")
+            .and_then(|()| std::fs::rename(&temp, &self.path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&temp);
+                self.store_error(e)
+            })
+    }
+
+    fn store_error(&self, e: impl fmt::Display) -> SshMcpError {
+        SshMcpError::connection(format!("Host key store {}: {}", self.path.display(), e))
+    }
+}
+
+/// Parse a `SHA256:...` host key fingerprint, as printed by `ssh-keygen -l`
+///
+/// Base64 padding is accepted and ignored.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::ssh::handler::parse_fingerprint;
+///
+/// let fingerprint = "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU";
+/// assert_eq!(parse_fingerprint(fingerprint).unwrap().to_string(), fingerprint);
+/// assert!(parse_fingerprint("MD5:16:27:ac:a5").is_err());
+/// ```
+pub fn parse_fingerprint(fingerprint: &str) -> std::result::Result<Fingerprint, String> {
+    let trimmed = fingerprint.trim().trim_end_matches('=');
+    match trimmed.parse::<Fingerprint>() {
+        Ok(parsed) if parsed.algorithm() == HashAlg::Sha256 => Ok(parsed),
+        _ => Err(format!(
+            "invalid host key fingerprint {:?} (expected SHA256:<base64>, as printed by \
+             ssh-keygen -lf)",
+            fingerprint
+        )),
+    }
+}
+
+/// Outcome of looking a host key up in a known_hosts file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    /// An entry for the host has this key
+    Known,
+
+    /// There is no entry for the host
+    Unknown,
+
+    /// The host has entries, on these lines, but none with this key
+    Changed { lines: Vec<usize> },
+}
+
+/// Look `key` up for `host`:`port` in the known_hosts file at `path`
+///
+/// A missing file counts as one without entries. Hosts on a port other
+/// than 22 are looked up as `[host]:port`, and hashed entries are matched
+/// like plain ones.
+pub fn check_known_hosts(
+    host: &str,
+    port: u16,
+    key: &PublicKey,
+    path: &Path,
+) -> Result<HostKeyStatus> {
+    let entries = russh::keys::known_host_keys_path(host, port, path).map_err(|e| {
+        SshMcpError::connection(format!("Failed to read {}: {}", path.display(), e))
+    })?;
+
+    if entries.is_empty() {
+        return Ok(HostKeyStatus::Unknown);
+    }
+    if entries.iter().any(|(_, known)| known == key) {
+        return Ok(HostKeyStatus::Known);
+    }
+    Ok(HostKeyStatus::Changed {
+        lines: entries.into_iter().map(|(line, _)| line).collect(),
+    })
+}
+
 /// SSH client handler for russh
 ///
 /// This handler is used by russh to process SSH events such as server key
-/// verification. Currently, it accepts all server keys (suitable for automated
-/// connections where host key verification is handled separately).
+/// verification. Keys are checked against known_hosts according to the
+/// [`HostKeyPolicy`]; a rejected key aborts the handshake, and the reason is
+/// kept for [`SshHandler::take_rejection`], since russh itself only reports
+/// an unknown key.
+///
+/// The first accepted key is also remembered in a [`LearnedHostKey`] that
+/// outlives the connection, and every reconnect must present that same key,
+/// whatever the policy and the known_hosts file say by then.
 #[derive(Debug, Clone)]
-pub struct SshHandler;
+pub struct SshHandler {
+    host: String,
+    port: u16,
+    policy: HostKeyPolicy,
+    learned: LearnedHostKey,
+    rejection: Arc<Mutex<Option<SshMcpError>>>,
+}
+
+/// Host key accepted on the first connection, shared by later handlers
+pub type LearnedHostKey = Arc<Mutex<Option<PublicKey>>>;
 
 impl SshHandler {
-    /// Create a new SSH handler
-    pub fn new() -> Self {
-        Self
+    /// Create a new SSH handler verifying the key of `host`:`port`
+    pub fn new(host: impl Into<String>, port: u16, policy: HostKeyPolicy) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            policy,
+            learned: LearnedHostKey::default(),
+            rejection: Arc::new(Mutex::new(None)),
+        }
     }
-}
 
-impl Default for SshHandler {
-    fn default() -> Self {
-        Self::new()
+    /// Check the key against, and remember it in, `learned`
+    ///
+    /// Pass the same slot to the handler of every connection to one host.
+    pub fn with_learned_key(mut self, learned: LearnedHostKey) -> Self {
+        self.learned = learned;
+        self
+    }
+
+    /// Why the server's key was rejected, if it was
+    ///
+    /// Clones of the handler share this, so a clone kept before the
+    /// handshake can read it afterwards.
+    pub fn take_rejection(&self) -> Option<SshMcpError> {
+        self.rejection.lock().unwrap().take()
+    }
+
+    /// Name of the host as known_hosts lists it
+    fn host_port(&self) -> Cow<'_, str> {
+        match self.port {
+            22 => Cow::Borrowed(&self.host),
+            port => Cow::Owned(format!("[{}]:{}", self.host, port)),
+        }
+    }
+
+    /// Check `key` against the learned key and the policy, then learn it
+    fn verify(&self, key: &PublicKey) -> Result<()> {
+        let mut learned = self.learned.lock().unwrap();
+        if let Some(ref first) = *learned {
+            if first != key {
+                return Err(SshMcpError::HostKeyChanged {
+                    host: self.host_port().into_owned(),
+                    expected: first.fingerprint(HashAlg::Sha256).to_string(),
+                    actual: key.fingerprint(HashAlg::Sha256).to_string(),
+                    hint: "The key changed since the first connection of this run, so \
+                           reconnects are refused until the server is restarted."
+                        .to_string(),
+                });
+            }
+        }
+        self.verify_policy(key)?;
+        if learned.is_none() {
+            *learned = Some(key.clone());
+        }
+        Ok(())
+    }
+
+    /// Check `key` against the policy, explaining any rejection
+    fn verify_policy(&self, key: &PublicKey) -> Result<()> {
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        let host_port = self.host_port();
+        let path = match self.policy {
+            HostKeyPolicy::KnownHosts(ref path) => path,
+            HostKeyPolicy::Fingerprints(ref allowed) => {
+                if allowed.contains(&fingerprint) {
+                    debug!("Host key {} for {} is allowed", fingerprint, host_port);
+                    return Ok(());
+                }
+                return Err(SshMcpError::connection(format!(
+                    "Host key of {} has fingerprint {}, which is not one of the \
+                     --host-fingerprint values. Someone may be intercepting the connection, \
+                     or the host key changed; check it with `ssh-keygen -lf` on the host",
+                    host_port, fingerprint
+                )));
+            }
+            HostKeyPolicy::Tofu(ref store) => return store.verify(&host_port, key),
+            HostKeyPolicy::AcceptAny => return Ok(()),
+        };
+
+        match check_known_hosts(&self.host, self.port, key, path)? {
+            HostKeyStatus::Known => {
+                debug!("Host key {} for {} is known", fingerprint, host_port);
+                Ok(())
+            }
+            HostKeyStatus::Unknown => {
+                let keyscan = match self.port {
+                    22 => format!("ssh-keyscan {}", self.host),
+                    port => format!("ssh-keyscan -p {} {}", port, self.host),
+                };
+                Err(SshMcpError::connection(format!(
+                    "Host {} is not in {}. The server offered the {} key {}. If that is the \
+                     right fingerprint, add the host with `{} >> {}` (or connect once with \
+                     ssh), point --known-hosts at a file that lists it, trust the first key \
+                     with --host-key-policy=tofu, or disable the check with \
+                     --no-host-key-check",
+                    host_port,
+                    path.display(),
+                    key.algorithm(),
+                    fingerprint,
+                    keyscan,
+                    path.display()
+                )))
+            }
+            HostKeyStatus::Changed { lines } => {
+                let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+                Err(SshMcpError::connection(format!(
+                    "HOST KEY CHANGED for {}: the server offered the {} key {}, which does \
+                     not match {} (line {}). Someone may be intercepting the connection, or \
+                     the host was reinstalled. If the change is expected, remove the old \
+                     entry with `ssh-keygen -R '{}' -f {}` and add the new key",
+                    host_port,
+                    key.algorithm(),
+                    fingerprint,
+                    path.display(),
+                    lines.join(", "),
+                    host_port,
+                    path.display()
+                )))
+            }
+        }
     }
 }
 
@@ -28,15 +423,19 @@ impl russh::client::Handler for SshHandler {
 
     /// Verify the server's host key
     ///
-    /// # Security Note
-    /// Currently accepts all server keys for simplicity in automated environments.
-    /// In production, you may want to implement proper host key verification
-    /// (check against known_hosts file or fingerprint whitelist).
+    /// Returning `false` makes russh abort the handshake.
     async fn check_server_key(
         &mut self,
-        _server_public_key: &russh::keys::PublicKey,
-    ) -> Result<bool, Self::Error> {
-        Ok(true)
+        server_public_key: &russh::keys::PublicKey,
+    ) -> std::result::Result<bool, Self::Error> {
+        match self.verify(server_public_key) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                warn!("Rejected host key: {}", e);
+                *self.rejection.lock().unwrap() = Some(e);
+                Ok(false)
+            }
+        }
     }
 }
 
@@ -44,14 +443,189 @@ impl russh::client::Handler for SshHandler {
 mod tests {
     use super::*;
 
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+    const OTHER_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIBHVWmkKN8P1S0R7nVa04jA2pHsF7ycs4u8cZdTVi3qc";
+
+    fn known_hosts(contents: &str) -> (PathBuf, PublicKey) {
+        let path =
+            std::env::temp_dir().join(format!("ssh-mcp-known-hosts-{:08x}", rand::random::<u32>()));
+        std::fs::write(&path, contents).unwrap();
+        let key = russh::keys::parse_public_key_base64(KEY).unwrap();
+        (path, key)
+    }
+
     #[test]
     fn test_handler_creation() {
-        let handler = SshHandler::new();
+        let handler = SshHandler::new("example.com", 22, HostKeyPolicy::AcceptAny);
         assert!(format!("{:?}", handler).contains("SshHandler"));
+        assert!(handler.take_rejection().is_none());
+    }
+
+    #[test]
+    fn test_default_policy_uses_known_hosts() {
+        let HostKeyPolicy::KnownHosts(path) = HostKeyPolicy::default() else {
+            panic!("expected a known_hosts policy");
+        };
+        assert!(path.ends_with(".ssh/known_hosts"));
+    }
+
+    #[test]
+    fn test_check_known_hosts() {
+        let (path, key) = known_hosts(&format!(
+            "# comment\nexample.com ssh-ed25519 {} root@example\n\
+             [example.com]:2222 ssh-ed25519 {} root@example\n",
+            KEY, OTHER_KEY
+        ));
+
+        let status = check_known_hosts("example.com", 22, &key, &path).unwrap();
+        assert_eq!(status, HostKeyStatus::Known);
+        let status = check_known_hosts("example.com", 2222, &key, &path).unwrap();
+        assert_eq!(status, HostKeyStatus::Changed { lines: vec![3] });
+        let status = check_known_hosts("other.example.com", 22, &key, &path).unwrap();
+        assert_eq!(status, HostKeyStatus::Unknown);
+
+        // A missing file knows no hosts
+        let missing = path.with_extension("missing");
+        let status = check_known_hosts("example.com", 22, &key, &missing).unwrap();
+        assert_eq!(status, HostKeyStatus::Unknown);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_verify_explains_rejections() {
+        let (path, key) = known_hosts(&format!(
+            "[example.com]:2222 ssh-ed25519 {} root@example\n",
+            OTHER_KEY
+        ));
+        let policy = HostKeyPolicy::KnownHosts(path.clone());
+
+        let handler = SshHandler::new("example.com", 2222, policy.clone());
+        let message = handler.verify(&key).unwrap_err().to_string();
+        assert!(
+            message.contains("HOST KEY CHANGED for [example.com]:2222"),
+            "{}",
+            message
+        );
+        assert!(message.contains("SHA256:"));
+        assert!(message.contains("line 1"));
+
+        let handler = SshHandler::new("new.example.com", 22, policy);
+        let message = handler.verify(&key).unwrap_err().to_string();
+        assert!(message.contains("is not in"), "{}", message);
+        assert!(message.contains("ssh-keyscan new.example.com >>"));
+
+        let handler = SshHandler::new("new.example.com", 22, HostKeyPolicy::AcceptAny);
+        assert!(handler.verify(&key).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint_whitelist() {
+        let key = russh::keys::parse_public_key_base64(KEY).unwrap();
+        let other = russh::keys::parse_public_key_base64(OTHER_KEY).unwrap();
+        let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+        let allowed = parse_fingerprint(&format!("{}=", fingerprint)).unwrap();
+
+        let policy = HostKeyPolicy::Fingerprints(vec![allowed]);
+        let handler = SshHandler::new("example.com", 22, policy.clone());
+        assert!(handler.verify(&key).is_ok());
+
+        let handler = SshHandler::new("example.com", 22, policy);
+        let message = handler.verify(&other).unwrap_err().to_string();
+        assert!(message.contains("not one of the --host-fingerprint values"));
+        assert!(message.contains(&other.fingerprint(HashAlg::Sha256).to_string()));
+
+        for bad in [
+            "",
+            "SHA256:",
+            "SHA256:not base64!",
+            "abc",
+            &fingerprint[7..],
+        ] {
+            assert!(parse_fingerprint(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_reconnect_requires_learned_key() {
+        let key = russh::keys::parse_public_key_base64(KEY).unwrap();
+        let other = russh::keys::parse_public_key_base64(OTHER_KEY).unwrap();
+        let learned = LearnedHostKey::default();
+        let handler =
+            |policy| SshHandler::new("example.com", 22, policy).with_learned_key(learned.clone());
+
+        // A rejected key is not learned
+        let allowed = key.fingerprint(HashAlg::Sha256);
+        let pinned = HostKeyPolicy::Fingerprints(vec![allowed]);
+        assert!(handler(pinned.clone()).verify(&other).is_err());
+        assert!(learned.lock().unwrap().is_none());
+
+        assert!(handler(pinned).verify(&key).is_ok());
+        assert_eq!(learned.lock().unwrap().as_ref(), Some(&key));
+
+        // Even a policy accepting anything can't swap the key mid-run
+        assert!(handler(HostKeyPolicy::AcceptAny).verify(&key).is_ok());
+        let message = handler(HostKeyPolicy::AcceptAny)
+            .verify(&other)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("since the first connection"),
+            "{}",
+            message
+        );
+        assert!(message.contains(&allowed.to_string()));
     }
 
     #[test]
-    fn test_handler_default() {
-        let _handler: SshHandler = Default::default();
+    fn test_parse_host_key_mode() {
+        assert_eq!("strict".parse(), Ok(HostKeyMode::Strict));
+        assert_eq!(" tofu".parse(), Ok(HostKeyMode::Tofu));
+        assert_eq!("insecure".parse(), Ok(HostKeyMode::Insecure));
+        assert!("accept-new".parse::<HostKeyMode>().is_err());
+        assert_eq!(HostKeyMode::default().to_string(), "strict");
+    }
+
+    #[test]
+    fn test_trust_on_first_use() {
+        let key = russh::keys::parse_public_key_base64(KEY).unwrap();
+        let other = russh::keys::parse_public_key_base64(OTHER_KEY).unwrap();
+        let dir = std::env::temp_dir().join(format!("ssh-mcp-tofu-{:08x}", rand::random::<u32>()));
+        let store = HostKeyStore::new(dir.join("host-keys.json"));
+        let policy = HostKeyPolicy::Tofu(store.clone());
+
+        // The first key is recorded, in a store that didn't exist yet
+        let handler = SshHandler::new("example.com", 2222, policy.clone());
+        assert!(handler.verify(&key).is_ok());
+        assert_eq!(store.get("[example.com]:2222").unwrap(), Some(key.clone()));
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(store.path()).unwrap()).unwrap();
+        assert!(json["[example.com]:2222"]
+            .as_str()
+            .unwrap()
+            .starts_with("ssh-ed25519 "));
+
+        // A new run accepts the same key and refuses another
+        let handler = SshHandler::new("example.com", 2222, policy.clone());
+        assert!(handler.verify(&key).is_ok());
+        let handler = SshHandler::new("example.com", 2222, policy.clone());
+        let err = handler.verify(&other).unwrap_err();
+        assert!(matches!(err, SshMcpError::HostKeyChanged { .. }));
+        let message = err.to_string();
+        assert!(message.contains(&key.fingerprint(HashAlg::Sha256).to_string()));
+        assert!(message.contains(&other.fingerprint(HashAlg::Sha256).to_string()));
+        assert!(message.contains("host-keys.json"));
+
+        // Other hosts are learned separately
+        let handler = SshHandler::new("other.example.com", 22, policy);
+        assert!(handler.verify(&other).is_ok());
+        assert_eq!(store.get("other.example.com").unwrap(), Some(other));
+
+        std::fs::write(store.path(), "not json").unwrap();
+        assert!(store.get("example.com").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
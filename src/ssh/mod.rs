@@ -3,17 +3,32 @@
 //! This module provides persistent SSH connection handling with automatic
 //! reconnection, authentication, and session management.
 
+pub mod circuit;
 pub mod command;
 pub mod config;
 pub mod connection;
 pub mod elevation;
+pub mod env;
 pub mod handler;
+pub mod probe;
 pub mod sanitize;
+pub mod sftp;
+pub mod socks;
 
 // Re-exports
+pub use circuit::{CircuitBreaker, CircuitState};
 pub use command::CommandOutput;
-pub use config::SshConfig;
-pub use connection::SshConnectionManager;
-pub use elevation::{escape_for_shell, sanitize_password, wrap_sudo_command};
-pub use handler::SshHandler;
-pub use sanitize::{escape_command_for_shell, sanitize_command};
+pub use config::{AuthMethod, SshConfig};
+pub use connection::{ConnectTimings, SshConnectionManager};
+pub use elevation::{
+    escape_for_shell, is_permission_denied, redact_secret, sanitize_password, strip_sudo_lecture,
+    wrap_sudo_command, wrap_sudo_command_pty, wrap_sudo_command_with_newline, SUDO_PROMPT_SENTINEL,
+};
+pub use env::EnvMethod;
+pub use handler::{HostKeyPolicy, SshHandler};
+pub use probe::AuthProbe;
+pub use sanitize::{
+    escape_command_for_shell, has_executable_statement, sanitize_command,
+    sanitize_command_with_trim, validate_read_duration, validate_umask, with_read_window,
+};
+pub use socks::SocksProxy;
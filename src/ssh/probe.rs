@@ -0,0 +1,148 @@
+//! Authentication method probe (`--probe-auth`)
+//!
+//! Servers list the authentication methods they accept whenever an attempt
+//! fails. Attempting the `none` method reveals that list without sending any
+//! credentials, which explains an "authentication rejected" error, e.g. a
+//! server that only accepts keys when a password was configured.
+
+use russh::client::AuthResult;
+use serde::Serialize;
+use tracing::debug;
+
+use super::connection::{ConnectTimings, SshConnectionManager};
+use crate::error::{Result, SshMcpError};
+
+/// Authentication methods offered by the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthProbe {
+    /// Method names as sent by the server (`publickey`, `password`, ...)
+    pub methods: Vec<String>,
+
+    /// Whether the server let the user in without any credentials
+    pub none_accepted: bool,
+}
+
+impl AuthProbe {
+    /// Explain a mismatch between the configured credentials and the methods
+    /// the server offers, if there is one
+    pub fn hint(&self, has_password: bool, has_key: bool) -> Option<String> {
+        if self.none_accepted {
+            return None;
+        }
+
+        let offers = |method: &str| self.methods.iter().any(|m| m == method);
+        if has_password && !offers("password") {
+            let mut hint = "--password is set, but the server does not accept password \
+                            authentication"
+                .to_string();
+            if offers("keyboard-interactive") {
+                hint.push_str(
+                    " (it offers keyboard-interactive, which ssh-mcp does not use); \
+                     enable PasswordAuthentication on the server",
+                );
+            }
+            if offers("publickey") {
+                hint.push_str("; use --key instead");
+            }
+            return Some(hint);
+        }
+        if has_key && !has_password && !offers("publickey") {
+            return Some(
+                "--key is set, but the server does not accept public key authentication"
+                    .to_string(),
+            );
+        }
+        None
+    }
+}
+
+/// Names of the methods the server still accepts after a failed attempt
+///
+/// Returns `None` when the attempt succeeded.
+pub(super) fn remaining_methods(result: &AuthResult) -> Option<Vec<String>> {
+    match result {
+        AuthResult::Success => None,
+        AuthResult::Failure {
+            remaining_methods, ..
+        } => Some(
+            remaining_methods
+                .iter()
+                .map(|method| <&str>::from(method).to_string())
+                .collect(),
+        ),
+    }
+}
+
+impl SshConnectionManager {
+    /// Connect and report the authentication methods the server offers
+    ///
+    /// Only the `none` method is attempted, so no credentials are sent. The
+    /// connection is closed afterwards and does not become the managed
+    /// session.
+    pub async fn probe_auth(&self) -> Result<AuthProbe> {
+        let mut session = self.open_transport(&mut ConnectTimings::default()).await?;
+
+        let result = session
+            .authenticate_none(&self.config.username)
+            .await
+            .map_err(|e| SshMcpError::auth(e.to_string()));
+        let _ = session
+            .disconnect(russh::Disconnect::ByApplication, "", "")
+            .await;
+
+        let probe = match remaining_methods(&result?) {
+            Some(methods) => AuthProbe {
+                methods,
+                none_accepted: false,
+            },
+            None => AuthProbe {
+                methods: Vec::new(),
+                none_accepted: true,
+            },
+        };
+        debug!("Server offers authentication methods: {:?}", probe.methods);
+        Ok(probe)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(methods: &[&str]) -> AuthProbe {
+        AuthProbe {
+            methods: methods.iter().map(|m| m.to_string()).collect(),
+            none_accepted: false,
+        }
+    }
+
+    #[test]
+    fn test_hint_password_not_offered() {
+        let hint = probe(&["publickey"]).hint(true, false).unwrap();
+        assert!(hint.contains("does not accept password"));
+        assert!(hint.contains("use --key"));
+
+        let hint = probe(&["publickey", "keyboard-interactive"])
+            .hint(true, false)
+            .unwrap();
+        assert!(hint.contains("keyboard-interactive"));
+    }
+
+    #[test]
+    fn test_hint_key_not_offered() {
+        let hint = probe(&["password"]).hint(false, true).unwrap();
+        assert!(hint.contains("public key"));
+    }
+
+    #[test]
+    fn test_no_hint_when_method_offered() {
+        assert_eq!(probe(&["publickey", "password"]).hint(true, false), None);
+        assert_eq!(probe(&["publickey"]).hint(false, true), None);
+
+        let open = AuthProbe {
+            methods: Vec::new(),
+            none_accepted: true,
+        };
+        assert_eq!(open.hint(true, true), None);
+    }
+}
@@ -31,24 +31,93 @@ use crate::error::{Result, SshMcpError};
 /// assert!(result.is_err());
 /// ```
 pub fn sanitize_command(command: &str, max_chars: Option<usize>) -> Result<String> {
-    let trimmed = command.trim();
+    sanitize_command_with_trim(command, max_chars, true)
+}
 
-    if trimmed.is_empty() {
+/// Sanitize a command, optionally keeping its surrounding whitespace
+///
+/// Like [`sanitize_command`], but with `trim` set to false leading and
+/// trailing whitespace is kept (and counted against `max_chars`), for
+/// payloads where it is significant such as here-documents. A command made
+/// up only of whitespace is rejected either way.
+///
+/// # Examples
+/// ```
+/// use ssh_mcp::ssh::sanitize::sanitize_command_with_trim;
+///
+/// let cmd = sanitize_command_with_trim("cat <<EOF\n  indented\nEOF\n", None, false).unwrap();
+/// assert_eq!(cmd, "cat <<EOF\n  indented\nEOF\n");
+///
+/// assert!(sanitize_command_with_trim(" \n ", None, false).is_err());
+/// ```
+pub fn sanitize_command_with_trim(
+    command: &str,
+    max_chars: Option<usize>,
+    trim: bool,
+) -> Result<String> {
+    if command.trim().is_empty() {
         return Err(SshMcpError::invalid_params("Command cannot be empty"));
     }
 
+    let command = if trim { command.trim() } else { command };
+
     // Check length limit
     if let Some(max) = max_chars {
-        if trimmed.len() > max {
+        if command.len() > max {
             return Err(SshMcpError::invalid_params(format!(
                 "Command is too long (max {} characters, got {})",
                 max,
-                trimmed.len()
+                command.len()
             )));
         }
     }
 
-    Ok(trimmed.to_string())
+    Ok(command.to_string())
+}
+
+/// Check whether a command contains anything besides blank lines and comments
+///
+/// A command made up only of `#` comments passes [`sanitize_command`] but
+/// runs nothing. This is a line-based check: any line that is not blank and
+/// does not start with `#` counts as a statement.
+///
+/// # Example
+/// ```
+/// use ssh_mcp::ssh::sanitize::has_executable_statement;
+///
+/// assert!(has_executable_statement("# list files\nls -la"));
+/// assert!(!has_executable_statement("# restart nginx here\n  # later"));
+/// ```
+pub fn has_executable_statement(command: &str) -> bool {
+    command.lines().any(|line| {
+        let line = line.trim_start();
+        !line.is_empty() && !line.starts_with('#')
+    })
+}
+
+/// Validate a umask given as up to three octal digits, optionally
+/// preceded by `0`
+///
+/// # Example
+/// ```
+/// use ssh_mcp::ssh::sanitize::validate_umask;
+///
+/// assert!(validate_umask("027").is_ok());
+/// assert!(validate_umask("u=rwx").is_err());
+/// ```
+pub fn validate_umask(umask: &str) -> Result<()> {
+    let digits = umask
+        .strip_prefix('0')
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(umask);
+    if (1..=3).contains(&digits.len()) && digits.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        Ok(())
+    } else {
+        Err(SshMcpError::invalid_params(format!(
+            "Invalid umask {:?}: expected an octal value such as 022 or 0077",
+            umask
+        )))
+    }
 }
 
 /// Escape a command for use in shell contexts (like pkill -f)
@@ -70,10 +139,90 @@ pub fn escape_command_for_shell(command: &str) -> String {
     command.replace('\'', "'\"'\"'")
 }
 
+/// Longest read window a command may be given (one hour)
+pub const MAX_READ_DURATION_MS: u64 = 3_600_000;
+
+/// Exit code `timeout` reports when it stopped the command
+pub const READ_WINDOW_EXIT_CODE: u32 = 124;
+
+/// Wrap a command so it is stopped once it has run for `read_duration_ms`
+///
+/// Meant for commands that never finish on their own, like `tail -f` or
+/// `journalctl -f`: the output is read for the window and the remote
+/// `timeout` then terminates exactly the process it started, exiting with
+/// [`READ_WINDOW_EXIT_CODE`]. This is separate from the command timeout,
+/// which treats running out of time as a failure.
+///
+/// # Example
+/// ```
+/// use ssh_mcp::ssh::sanitize::with_read_window;
+///
+/// assert_eq!(
+///     with_read_window("tail -f /var/log/syslog", 2_500),
+///     "timeout 2.500 sh -c 'tail -f /var/log/syslog'"
+/// );
+/// ```
+pub fn with_read_window(command: &str, read_duration_ms: u64) -> String {
+    format!(
+        "timeout {}.{:03} sh -c '{}'",
+        read_duration_ms / 1000,
+        read_duration_ms % 1000,
+        escape_command_for_shell(command)
+    )
+}
+
+/// Check a read window given in milliseconds
+pub fn validate_read_duration(read_duration_ms: u64) -> Result<()> {
+    if read_duration_ms == 0 || read_duration_ms > MAX_READ_DURATION_MS {
+        return Err(SshMcpError::invalid_params(format!(
+            "read_duration_ms must be between 1 and {}",
+            MAX_READ_DURATION_MS
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_validate_umask() {
+        for ok in ["0", "7", "22", "077", "0027", "0777"] {
+            assert!(validate_umask(ok).is_ok(), "{}", ok);
+        }
+        for bad in ["", "8", "0800", "1777", "00777", "-1", "077; id", "u=rw"] {
+            assert!(validate_umask(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_with_read_window() {
+        assert_eq!(
+            with_read_window("journalctl -f", 30_000),
+            "timeout 30.000 sh -c 'journalctl -f'"
+        );
+        assert_eq!(
+            with_read_window("tail -f 'a b'", 50),
+            "timeout 0.050 sh -c 'tail -f '\"'\"'a b'\"'\"''"
+        );
+
+        assert!(validate_read_duration(1).is_ok());
+        assert!(validate_read_duration(MAX_READ_DURATION_MS).is_ok());
+        assert!(validate_read_duration(0).is_err());
+        assert!(validate_read_duration(MAX_READ_DURATION_MS + 1).is_err());
+    }
+
+    #[test]
+    fn test_has_executable_statement() {
+        assert!(has_executable_statement("ls"));
+        assert!(has_executable_statement("ls # list"));
+        assert!(has_executable_statement("# first\n\n  uptime"));
+        assert!(!has_executable_statement("# do nothing"));
+        assert!(!has_executable_statement("  # indented\n\n#second"));
+        assert!(!has_executable_statement(""));
+    }
+
     #[test]
     fn test_sanitize_command_valid() {
         let result = sanitize_command("ls -la", Some(1000));
@@ -147,4 +296,16 @@ mod tests {
         let escaped = escape_command_for_shell("");
         assert_eq!(escaped, "");
     }
+
+    #[test]
+    fn test_sanitize_command_without_trim() {
+        let result = sanitize_command_with_trim("  echo hi\n", None, false).unwrap();
+        assert_eq!(result, "  echo hi\n");
+
+        // Surrounding whitespace counts towards the limit when kept
+        assert!(sanitize_command_with_trim("  ls  ", Some(4), false).is_err());
+        assert!(sanitize_command_with_trim("  ls  ", Some(4), true).is_ok());
+
+        assert!(sanitize_command_with_trim("\t\n", None, false).is_err());
+    }
 }
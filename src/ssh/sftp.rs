@@ -0,0 +1,852 @@
+//! SFTP file operations
+//!
+//! Opens SFTP sessions over the existing SSH connection and provides the
+//! glob expansion shared by the file tools.
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+
+use super::connection::SshConnectionManager;
+use crate::config::CONNECTION_TIMEOUT_SECS;
+use crate::error::{Result, SshMcpError};
+use crate::tools::paths::{expand_home, glob_match, is_glob, is_path_allowed, split_path};
+use crate::tools::perms::format_mode;
+
+/// Largest file the upload tool writes (64 MiB)
+pub const MAX_UPLOAD_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Bytes the read-file tool returns when the call gives no `max_bytes`
+pub const DEFAULT_READ_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Mode of uploaded files when none is given
+pub const DEFAULT_UPLOAD_MODE: u32 = 0o644;
+
+/// One entry of a directory listing from the list-dir tool
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+
+    /// Permission bits as four octal digits
+    pub mode: Option<String>,
+
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: Option<u32>,
+
+    /// Where a symlink points, resolved on the server when it can be
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl From<russh_sftp::client::error::Error> for SshMcpError {
+    fn from(err: russh_sftp::client::error::Error) -> Self {
+        SshMcpError::Sftp(err.to_string())
+    }
+}
+
+/// An open SFTP session, counted against `--max-sftp-handles`
+///
+/// Dereferences to the [`SftpSession`]. Dropping it closes the session and
+/// frees its slot, however the file operation ended, including on errors
+/// and cancellation.
+pub struct SftpHandle {
+    session: SftpSession,
+    open: Arc<AtomicUsize>,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl SftpHandle {
+    fn new(
+        session: SftpSession,
+        open: Arc<AtomicUsize>,
+        slot: Option<OwnedSemaphorePermit>,
+    ) -> Self {
+        open.fetch_add(1, Ordering::SeqCst);
+        Self {
+            session,
+            open,
+            _slot: slot,
+        }
+    }
+}
+
+impl Deref for SftpHandle {
+    type Target = SftpSession;
+
+    fn deref(&self) -> &SftpSession {
+        &self.session
+    }
+}
+
+impl Drop for SftpHandle {
+    fn drop(&mut self) {
+        // The session itself closes when dropped right after this
+        self.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wait up to `wait` for a free SFTP handle slot
+async fn acquire_slot(slots: &Arc<Semaphore>, wait: Duration) -> Result<OwnedSemaphorePermit> {
+    if let Ok(slot) = slots.clone().try_acquire_owned() {
+        return Ok(slot);
+    }
+
+    debug!("Every SFTP handle is in use, waiting for one to close");
+    match tokio::time::timeout(wait, slots.clone().acquire_owned()).await {
+        Ok(Ok(slot)) => Ok(slot),
+        _ => Err(SshMcpError::sftp(format!(
+            "Every SFTP handle stayed in use for {}s; retry once other file operations \
+             finish or raise --max-sftp-handles",
+            wait.as_secs()
+        ))),
+    }
+}
+
+impl SshConnectionManager {
+    /// Open an SFTP session on a new channel of the current connection
+    ///
+    /// With `--max-sftp-handles` reached, waits up to the connection timeout
+    /// for another handle to close.
+    pub async fn sftp(&self) -> Result<SftpHandle> {
+        let slot = match self.sftp_slots {
+            Some(ref slots) => {
+                let wait = Duration::from_secs(CONNECTION_TIMEOUT_SECS);
+                Some(acquire_slot(slots, wait).await?)
+            }
+            None => None,
+        };
+
+        self.ensure_connected().await?;
+
+        let channel = self.open_channel().await?;
+        channel
+            .request_subsystem(true, "sftp")
+            .await
+            .map_err(|e| SshMcpError::sftp(format!("Failed to start SFTP subsystem: {}", e)))?;
+
+        debug!("SFTP subsystem started");
+        let session = SftpSession::new(channel.into_stream()).await?;
+        Ok(SftpHandle::new(session, self.sftp_open.clone(), slot))
+    }
+
+    /// Number of SFTP handles currently open
+    pub fn open_sftp_handles(&self) -> usize {
+        self.sftp_open.load(Ordering::SeqCst)
+    }
+
+    /// Expand a leading `~` in a file tool path to the remote home directory
+    ///
+    /// The home directory is where SFTP sessions start, so it is found by
+    /// canonicalizing `.` and cached until the next reconnect.
+    pub async fn expand_home(&self, sftp: &SftpSession, path: &str) -> Result<String> {
+        // Only look the home directory up when there is something to expand
+        if expand_home(path, "").is_none() {
+            return Ok(path.to_string());
+        }
+
+        let cached = self.home_dir.lock().unwrap().clone();
+        let home = match cached {
+            Some(home) => home,
+            None => {
+                let home = sftp.canonicalize(".").await?;
+                debug!("Remote home directory: {}", home);
+                *self.home_dir.lock().unwrap() = Some(home.clone());
+                home
+            }
+        };
+        Ok(expand_home(path, &home).unwrap_or_else(|| path.to_string()))
+    }
+}
+
+/// Expand a remote path into the list of paths it refers to
+///
+/// Paths without glob metacharacters are returned unchanged. Globs are only
+/// supported in the final component (`/var/log/*.log`); the directory is read
+/// over SFTP and the matching entry names are returned sorted. Expansion fails
+/// if nothing matches or more than `max_matches` entries match.
+pub async fn expand_glob(
+    sftp: &SftpSession,
+    path: &str,
+    max_matches: usize,
+) -> Result<Vec<String>> {
+    if !is_glob(path) {
+        return Ok(vec![path.to_string()]);
+    }
+
+    let (dir, pattern) = split_path(path);
+    if is_glob(dir) {
+        return Err(SshMcpError::invalid_params(format!(
+            "Glob patterns are only supported in the last path component: {}",
+            path
+        )));
+    }
+
+    let mut matches: Vec<String> = sftp
+        .read_dir(dir)
+        .await?
+        .map(|entry| entry.file_name())
+        .filter(|name| glob_match(pattern, name))
+        .map(|name| match dir {
+            "/" => format!("/{}", name),
+            "." => name,
+            _ => format!("{}/{}", dir, name),
+        })
+        .collect();
+
+    if matches.is_empty() {
+        return Err(SshMcpError::invalid_params(format!(
+            "No files match {}",
+            path
+        )));
+    }
+
+    if matches.len() > max_matches {
+        return Err(SshMcpError::invalid_params(format!(
+            "{} matches {} files, more than the limit of {} (see --max-glob-matches)",
+            path,
+            matches.len(),
+            max_matches
+        )));
+    }
+
+    matches.sort();
+    Ok(matches)
+}
+
+/// Resolve a remote path and check it against the allowed-paths jail
+///
+/// The parent directory is canonicalized on the server, so relative paths and
+/// symlinked directories are judged by where they really point. The final
+/// component is kept as-is, which means a symlink is treated as the link
+/// itself rather than its target. With an empty `allowed` list the path is
+/// returned unchanged.
+pub async fn resolve_allowed(sftp: &SftpSession, path: &str, allowed: &[String]) -> Result<String> {
+    if allowed.is_empty() {
+        return Ok(path.to_string());
+    }
+
+    let (dir, name) = split_path(path);
+    let dir = sftp.canonicalize(dir).await?;
+    let resolved = match dir.as_str() {
+        "/" => format!("/{}", name),
+        _ => format!("{}/{}", dir, name),
+    };
+
+    if !is_path_allowed(&resolved, allowed) {
+        return Err(SshMcpError::invalid_params(format!(
+            "Path is outside the allowed paths: {}",
+            path
+        )));
+    }
+
+    Ok(resolved)
+}
+
+/// Append `data` to a remote file, creating it if needed, and return its new size
+///
+/// The file is opened in SFTP append mode, so the server writes at the end of
+/// the file whatever its size is by then, rather than at an offset computed
+/// here from a size that may already be stale. If the remote disk fills up
+/// part way, the append is undone and [`SshMcpError::DiskFull`] is returned.
+pub async fn append_file(sftp: &SftpSession, path: &str, data: &[u8]) -> Result<u64> {
+    // Size before the append, so a write that runs out of space can be undone
+    let original = match sftp.metadata(path).await {
+        Ok(metadata) => Some(metadata.len()),
+        Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut file = sftp
+        .open_with_flags(
+            path,
+            OpenFlags::CREATE | OpenFlags::APPEND | OpenFlags::WRITE,
+        )
+        .await?;
+
+    let written = match file.write_all(data).await {
+        Ok(()) => file.flush().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        let _ = file.shutdown().await;
+        return Err(write_failed(sftp, path, original, data.len(), e).await);
+    }
+    let size = file.metadata().await?.len();
+    file.shutdown().await?;
+
+    debug!("Appended {} bytes to {}", data.len(), path);
+    Ok(size)
+}
+
+/// Read a regular file of at most `max` bytes
+///
+/// Symlinks are refused rather than followed, since replacing the file
+/// afterwards would replace the link itself.
+pub async fn read_regular_file(sftp: &SftpSession, path: &str, max: u64) -> Result<Vec<u8>> {
+    let metadata = regular_file_metadata(sftp, path, "edit").await?;
+    if metadata.len() > max {
+        return Err(SshMcpError::invalid_params(format!(
+            "{} is {} bytes, more than the {} bytes that can be edited",
+            path,
+            metadata.len(),
+            max
+        )));
+    }
+    Ok(sftp.read(path).await?)
+}
+
+/// Read a regular file for the download tool, returning its size and content
+///
+/// Files larger than `max` bytes are refused before anything is read, and
+/// reading stops there too should the file grow in the meantime. Symlinks
+/// are refused like in [`read_regular_file`], since the allowed-paths check
+/// only looked at the link.
+pub async fn download_file(
+    sftp: &SftpSession,
+    path: &str,
+    max: Option<u64>,
+) -> Result<(u64, Vec<u8>)> {
+    let metadata = regular_file_metadata(sftp, path, "download").await?;
+    let too_large = |size: u64, max: u64| {
+        SshMcpError::invalid_params(format!(
+            "{} is {} bytes, more than the download limit of {} bytes \
+             (see --max-download-bytes)",
+            path, size, max
+        ))
+    };
+    if let Some(max) = max.filter(|&max| metadata.len() > max) {
+        return Err(too_large(metadata.len(), max));
+    }
+
+    let data = read_at_most(sftp, path, max.map(|max| max + 1), metadata.len()).await?;
+    if let Some(max) = max.filter(|&max| data.len() as u64 > max) {
+        return Err(too_large(data.len() as u64, max));
+    }
+
+    debug!("Downloaded {} ({} bytes)", path, data.len());
+    Ok((data.len() as u64, data))
+}
+
+/// Read the start of a regular file for the read-file tool
+///
+/// Returns the file's size, its first `max` bytes and whether the content
+/// was cut short there. Symlinks are refused like in [`download_file`].
+pub async fn read_file_head(
+    sftp: &SftpSession,
+    path: &str,
+    max: u64,
+) -> Result<(u64, Vec<u8>, bool)> {
+    let metadata = regular_file_metadata(sftp, path, "read").await?;
+    // One byte more tells a file of exactly `max` bytes from a longer one
+    let limit = max.saturating_add(1);
+    let mut data = read_at_most(sftp, path, Some(limit), metadata.len().min(max)).await?;
+    let truncated = data.len() as u64 > max;
+    data.truncate(max as usize);
+    Ok((metadata.len(), data, truncated))
+}
+
+/// Attributes of `path`, refusing symlinks and anything but regular files
+///
+/// `action` names what the caller was going to do, for the error message.
+async fn regular_file_metadata(
+    sftp: &SftpSession,
+    path: &str,
+    action: &str,
+) -> Result<FileAttributes> {
+    let metadata = sftp.symlink_metadata(path).await?;
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        return Err(SshMcpError::invalid_params(format!(
+            "{} is a symlink; {} the file it points to instead",
+            path, action
+        )));
+    }
+    if !file_type.is_file() {
+        return Err(SshMcpError::invalid_params(format!(
+            "{} is not a regular file",
+            path
+        )));
+    }
+    Ok(metadata)
+}
+
+/// Read a remote file, stopping after `limit` bytes if one is given
+async fn read_at_most(
+    sftp: &SftpSession,
+    path: &str,
+    limit: Option<u64>,
+    expected: u64,
+) -> Result<Vec<u8>> {
+    let mut file = sftp.open(path).await?;
+    let mut data = Vec::with_capacity(expected as usize);
+    match limit {
+        Some(limit) => (&mut file).take(limit).read_to_end(&mut data).await,
+        None => file.read_to_end(&mut data).await,
+    }
+    .map_err(|e| SshMcpError::sftp(format!("Failed to read {}: {}", path, e)))?;
+    Ok(data)
+}
+
+/// List a remote directory for the list-dir tool, sorted by name
+///
+/// Entries starting with `.` are left out unless `include_hidden` is set;
+/// `.` and `..` always are. Entries describe the entry itself, so a
+/// symlink reports `is_symlink` and its `target` rather than the target's
+/// attributes. The directory itself is followed if it is a symlink, which
+/// is why it is canonicalized and checked against `allowed` once more.
+pub async fn list_dir(
+    sftp: &SftpSession,
+    path: &str,
+    include_hidden: bool,
+    allowed: &[String],
+) -> Result<Vec<DirEntryInfo>> {
+    if !allowed.is_empty() {
+        let real = sftp.canonicalize(path).await?;
+        if !is_path_allowed(&real, allowed) {
+            return Err(SshMcpError::invalid_params(format!(
+                "Path is outside the allowed paths: {} (resolves to {})",
+                path, real
+            )));
+        }
+    }
+
+    let entries = sftp
+        .read_dir(path)
+        .await?
+        .map(|entry| (entry.file_name(), entry.metadata()));
+    let mut listing = dir_listing(entries, include_hidden);
+    for entry in listing.iter_mut().filter(|entry| entry.is_symlink) {
+        let link = match path {
+            "/" => format!("/{}", entry.name),
+            _ => format!("{}/{}", path.trim_end_matches('/'), entry.name),
+        };
+        // A dangling link can't be canonicalized, but still has a target
+        entry.target = match sftp.canonicalize(link.as_str()).await {
+            Ok(target) => Some(target),
+            Err(_) => sftp.read_link(link.as_str()).await.ok(),
+        };
+    }
+
+    debug!("Listed {} ({} entries)", path, listing.len());
+    Ok(listing)
+}
+
+/// Turn raw directory entries into sorted [`DirEntryInfo`]s
+fn dir_listing(
+    entries: impl IntoIterator<Item = (String, FileAttributes)>,
+    include_hidden: bool,
+) -> Vec<DirEntryInfo> {
+    let mut listing: Vec<DirEntryInfo> = entries
+        .into_iter()
+        .filter(|(name, _)| name != "." && name != "..")
+        .filter(|(name, _)| include_hidden || !name.starts_with('.'))
+        .map(|(name, attrs)| {
+            let file_type = attrs.file_type();
+            DirEntryInfo {
+                name,
+                size: attrs.len(),
+                is_dir: file_type.is_dir(),
+                is_symlink: file_type.is_symlink(),
+                mode: attrs.permissions.map(format_mode),
+                mtime: attrs.mtime,
+                target: None,
+            }
+        })
+        .collect();
+    listing.sort_by(|a, b| a.name.cmp(&b.name));
+    listing
+}
+
+/// Set the permission bits of a remote file and return its attributes
+/// before and after
+///
+/// Symlinks are refused: SFTP would change the mode of the target, which
+/// the allowed-paths check never looked at.
+pub async fn set_mode(
+    sftp: &SftpSession,
+    path: &str,
+    mode: u32,
+) -> Result<(FileAttributes, FileAttributes)> {
+    let before = sftp.symlink_metadata(path).await?;
+    if before.file_type().is_symlink() {
+        return Err(SshMcpError::invalid_params(format!(
+            "{} is a symlink; change the file it points to instead",
+            path
+        )));
+    }
+
+    let attrs = FileAttributes {
+        permissions: Some(mode),
+        ..FileAttributes::empty()
+    };
+    sftp.set_metadata(path, attrs).await?;
+    let after = sftp.symlink_metadata(path).await?;
+    debug!("Set the mode of {} to {:04o}", path, mode);
+    Ok((before, after))
+}
+
+/// Replace the contents of a remote file without it ever being half-written
+///
+/// `data` goes to a temporary file next to `path`, which takes over the
+/// original's mode (and owner, where the server allows it) and is then
+/// renamed into place. SFTP v3 renames don't replace existing files, so the
+/// original is moved aside first: to `backup` when given, replacing an
+/// older backup, otherwise to a temporary name that is removed afterwards.
+/// If the final rename fails, the original is moved back.
+pub async fn replace_file(
+    sftp: &SftpSession,
+    path: &str,
+    data: &[u8],
+    backup: Option<&str>,
+) -> Result<()> {
+    let original = sftp.metadata(path).await?;
+    let suffix = format!(".ssh-mcp-{:08x}", rand::random::<u32>());
+    let temp = format!("{}{}.tmp", path, suffix);
+
+    if let Err(e) = write_temp(sftp, path, &temp, data, &original).await {
+        let _ = sftp.remove_file(&temp).await;
+        return Err(e);
+    }
+
+    let aside = match backup {
+        Some(backup) => {
+            match sftp.remove_file(backup).await {
+                Err(SftpError::Status(status)) if status.status_code != StatusCode::NoSuchFile => {
+                    let _ = sftp.remove_file(&temp).await;
+                    return Err(SftpError::Status(status).into());
+                }
+                _ => {}
+            }
+            backup.to_string()
+        }
+        None => format!("{}{}.old", path, suffix),
+    };
+    if let Err(e) = sftp.rename(path, &aside).await {
+        let _ = sftp.remove_file(&temp).await;
+        return Err(e.into());
+    }
+    if let Err(e) = sftp.rename(&temp, path).await {
+        if let Err(restore) = sftp.rename(&aside, path).await {
+            warn!("Failed to move {} back to {}: {}", aside, path, restore);
+        }
+        let _ = sftp.remove_file(&temp).await;
+        return Err(e.into());
+    }
+    if backup.is_none() {
+        if let Err(e) = sftp.remove_file(&aside).await {
+            warn!("Failed to remove {}: {}", aside, e);
+        }
+    }
+
+    debug!("Replaced {} ({} bytes)", path, data.len());
+    Ok(())
+}
+
+/// Create the missing parent directories of `path`, returning those created
+///
+/// Walks up to the closest directory that exists and creates the rest from
+/// the top down. The returned paths are in creation order.
+pub async fn create_parent_dirs(sftp: &SftpSession, path: &str) -> Result<Vec<String>> {
+    let mut missing = Vec::new();
+    let (mut dir, _) = split_path(path);
+    while dir != "/" && dir != "." && !dir.is_empty() {
+        match sftp.metadata(dir).await {
+            Ok(metadata) if metadata.file_type().is_dir() => break,
+            Ok(_) => {
+                return Err(SshMcpError::invalid_params(format!(
+                    "{} is not a directory",
+                    dir
+                )))
+            }
+            Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+                missing.push(dir.to_string());
+                dir = split_path(dir).0;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    missing.reverse();
+    for dir in &missing {
+        sftp.create_dir(dir).await?;
+        debug!("Created directory {}", dir);
+    }
+    Ok(missing)
+}
+
+/// Write `data` to a remote file, replacing its contents
+///
+/// Like [`replace_file`], the data goes to a temporary file that is renamed
+/// into place, so an existing file is never left half-written and a full
+/// disk leaves nothing behind. An existing file keeps its owner where the
+/// server allows it, and its mode unless `mode` is given; a new file gets
+/// `mode` or [`DEFAULT_UPLOAD_MODE`]. Symlinks and directories are refused.
+/// Returns the mode the file ends up with.
+pub async fn upload_file(
+    sftp: &SftpSession,
+    path: &str,
+    data: &[u8],
+    mode: Option<u32>,
+) -> Result<u32> {
+    let final_mode = match sftp.symlink_metadata(path).await {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            return Err(SshMcpError::invalid_params(format!(
+                "{} is a symlink; upload to the file it points to instead",
+                path
+            )))
+        }
+        Ok(metadata) if !metadata.file_type().is_file() => {
+            return Err(SshMcpError::invalid_params(format!(
+                "{} exists and is not a regular file",
+                path
+            )))
+        }
+        Ok(metadata) => {
+            replace_file(sftp, path, data, None).await?;
+            if let Some(mode) = mode {
+                let attrs = FileAttributes {
+                    permissions: Some(mode),
+                    ..FileAttributes::empty()
+                };
+                sftp.set_metadata(path, attrs).await?;
+            }
+            upload_mode(metadata.permissions, mode)
+        }
+        Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+            let mode = upload_mode(None, mode);
+            let temp = format!("{}.ssh-mcp-{:08x}.tmp", path, rand::random::<u32>());
+            let attrs = FileAttributes {
+                permissions: Some(mode),
+                ..FileAttributes::empty()
+            };
+            let written = match write_temp(sftp, path, &temp, data, &attrs).await {
+                Ok(()) => sftp.rename(&temp, path).await.map_err(SshMcpError::from),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                let _ = sftp.remove_file(&temp).await;
+                return Err(e);
+            }
+            mode
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    debug!(
+        "Uploaded {} bytes to {} (mode {:04o})",
+        data.len(),
+        path,
+        final_mode
+    );
+    Ok(final_mode)
+}
+
+/// Mode an uploaded file ends up with, given the mode of the file it
+/// replaces (if any) and the mode asked for (if any)
+fn upload_mode(existing: Option<u32>, requested: Option<u32>) -> u32 {
+    requested.unwrap_or_else(|| {
+        existing
+            .map(|mode| mode & 0o7777)
+            .unwrap_or(DEFAULT_UPLOAD_MODE)
+    })
+}
+
+/// Write the replacement for `path` to `temp`, with the original's attributes
+async fn write_temp(
+    sftp: &SftpSession,
+    path: &str,
+    temp: &str,
+    data: &[u8],
+    original: &FileAttributes,
+) -> Result<()> {
+    let mut file = sftp.create(temp).await?;
+    let written = match file.write_all(data).await {
+        Ok(()) => file.flush().await,
+        Err(e) => Err(e),
+    };
+    let _ = file.shutdown().await;
+    if let Err(e) = written {
+        if is_disk_full_message(&e.to_string()) {
+            return Err(SshMcpError::disk_full(format!(
+                "no space left to write {} bytes for {}; the file was not changed",
+                data.len(),
+                path
+            )));
+        }
+        return Err(e.into());
+    }
+
+    let mode = original.permissions.map(|mode| mode & 0o7777);
+    let with_owner = FileAttributes {
+        permissions: mode,
+        uid: original.uid,
+        gid: original.gid,
+        ..FileAttributes::empty()
+    };
+    if sftp.set_metadata(temp, with_owner).await.is_err() {
+        // Only root may give files away; keep the mode at least
+        let attrs = FileAttributes {
+            permissions: mode,
+            ..FileAttributes::empty()
+        };
+        sftp.set_metadata(temp, attrs).await?;
+    }
+    Ok(())
+}
+
+/// Check whether a write error reports that the remote disk is full
+///
+/// SFTP v3 has no status code for this (`SSH_FX_NO_SPACE_ON_FILESYSTEM`
+/// only appears in later drafts), so servers report a generic failure and
+/// the message text is all there is to go on.
+fn is_disk_full_message(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    ["no space", "disk full", "quota exceeded"]
+        .iter()
+        .any(|needle| message.contains(needle))
+}
+
+/// Turn a failed write into an error, undoing it if the disk is full
+///
+/// When the message does not say why the write failed, the free space
+/// reported by the `statvfs@openssh.com` extension decides. A full disk
+/// leaves the appended data cut short, so a file created by this write is
+/// removed and an existing one is truncated back to `original` bytes.
+async fn write_failed(
+    sftp: &SftpSession,
+    path: &str,
+    original: Option<u64>,
+    len: usize,
+    err: std::io::Error,
+) -> SshMcpError {
+    let message = err.to_string();
+    let disk_full = is_disk_full_message(&message)
+        || match sftp.fs_info(path).await {
+            Ok(Some(stat)) => stat.blocks_avail.saturating_mul(stat.fragment_size) < len as u64,
+            _ => false,
+        };
+    if !disk_full {
+        return err.into();
+    }
+
+    let (undo, outcome) = match original {
+        None => (sftp.remove_file(path).await, "the partial file was removed"),
+        Some(size) => {
+            let attrs = FileAttributes {
+                size: Some(size),
+                ..FileAttributes::empty()
+            };
+            (
+                sftp.set_metadata(path, attrs).await,
+                "the file was truncated back to its original size",
+            )
+        }
+    };
+    let outcome = match undo {
+        Ok(()) => outcome.to_string(),
+        Err(e) => {
+            warn!("Failed to undo partial append to {}: {}", path, e);
+            format!("the file may be left with a partial append ({})", e)
+        }
+    };
+
+    SshMcpError::disk_full(format!(
+        "no space left to append {} bytes to {}; {}. Free up space and retry.",
+        len, path, outcome
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_disk_full_message() {
+        assert!(is_disk_full_message("Failure: No space left on device"));
+        assert!(is_disk_full_message("Failure: Disk quota exceeded"));
+        assert!(is_disk_full_message("write failed: disk full"));
+        assert!(!is_disk_full_message("Failure: Failure"));
+        assert!(!is_disk_full_message(
+            "Permission denied: Permission denied"
+        ));
+    }
+
+    #[test]
+    fn test_dir_listing() {
+        let attrs = |permissions, size| FileAttributes {
+            size: Some(size),
+            permissions: Some(permissions),
+            mtime: Some(1_700_000_000),
+            ..FileAttributes::empty()
+        };
+        let entries = vec![
+            ("zeta.txt".to_string(), attrs(0o100644, 12)),
+            (".".to_string(), attrs(0o040755, 4096)),
+            ("..".to_string(), attrs(0o040755, 4096)),
+            (".bashrc".to_string(), attrs(0o100600, 220)),
+            ("current".to_string(), attrs(0o120777, 7)),
+            ("alpha".to_string(), attrs(0o040750, 4096)),
+        ];
+
+        let listing = dir_listing(entries.clone(), false);
+        let names: Vec<&str> = listing.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["alpha", "current", "zeta.txt"]);
+        assert_eq!(
+            listing[0],
+            DirEntryInfo {
+                name: "alpha".to_string(),
+                size: 4096,
+                is_dir: true,
+                is_symlink: false,
+                mode: Some("0750".to_string()),
+                mtime: Some(1_700_000_000),
+                target: None,
+            }
+        );
+        assert!(listing[1].is_symlink && !listing[1].is_dir);
+        assert_eq!(listing[2].mode.as_deref(), Some("0644"));
+
+        let listing = dir_listing(entries, true);
+        let names: Vec<&str> = listing.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, [".bashrc", "alpha", "current", "zeta.txt"]);
+    }
+
+    #[test]
+    fn test_upload_mode() {
+        // Overwriting a 0600 file without a mode must not widen it
+        assert_eq!(upload_mode(Some(0o100600), None), 0o600);
+        assert_eq!(upload_mode(Some(0o100600), Some(0o640)), 0o640);
+        assert_eq!(upload_mode(None, None), DEFAULT_UPLOAD_MODE);
+        assert_eq!(upload_mode(None, Some(0o755)), 0o755);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_slot() {
+        let slots = Arc::new(Semaphore::new(1));
+        let wait = Duration::from_millis(20);
+
+        let first = acquire_slot(&slots, wait).await.unwrap();
+        let err = acquire_slot(&slots, wait).await.unwrap_err();
+        assert!(err.to_string().contains("--max-sftp-handles"));
+
+        // A slot freed while waiting is taken
+        let waiter = tokio::spawn({
+            let slots = slots.clone();
+            async move { acquire_slot(&slots, Duration::from_secs(5)).await }
+        });
+        drop(first);
+        assert!(waiter.await.unwrap().is_ok());
+    }
+}
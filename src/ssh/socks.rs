@@ -0,0 +1,349 @@
+//! SOCKS5 proxy support for the outbound connection (`--socks-proxy`)
+//!
+//! Implements the client side of the SOCKS5 `CONNECT` command (RFC 1928)
+//! with optional username/password authentication (RFC 1929). Host names
+//! are passed to the proxy unresolved, so they are resolved on the proxy's
+//! side of the network like with `ssh -o ProxyCommand='nc -X 5 ...'`.
+
+use std::net::IpAddr;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::error::{Result, SshMcpError};
+
+/// Port used when `--socks-proxy` names none
+pub const DEFAULT_SOCKS_PORT: u16 = 1080;
+
+const VERSION: u8 = 0x05;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+const PASSWORD_AUTH_VERSION: u8 = 0x01;
+const COMMAND_CONNECT: u8 = 0x01;
+const ADDR_IPV4: u8 = 0x01;
+const ADDR_DOMAIN: u8 = 0x03;
+const ADDR_IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy the SSH connection is made through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocksProxy {
+    /// Proxy host name or address
+    pub host: String,
+
+    /// Proxy port
+    pub port: u16,
+
+    /// Username and password, if the proxy requires them
+    pub credentials: Option<(String, String)>,
+}
+
+impl SocksProxy {
+    /// A proxy that needs no authentication
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            credentials: None,
+        }
+    }
+
+    /// Authenticate to the proxy with a username and password
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Open a TCP connection to `host:port` through the proxy
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| {
+                SshMcpError::connection(format!(
+                    "Failed to reach SOCKS5 proxy {}:{}: {}",
+                    self.host, self.port, e
+                ))
+            })?;
+        self.handshake(&mut stream, host, port).await?;
+        debug!(
+            "SOCKS5 proxy {}:{} connected to {}:{}",
+            self.host, self.port, host, port
+        );
+        Ok(stream)
+    }
+
+    /// Negotiate a `CONNECT` to `host:port` on an open proxy connection
+    pub async fn handshake<S>(&self, stream: &mut S, host: &str, port: u16) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let request = connect_request(host, port)?;
+
+        let methods: &[u8] = match self.credentials {
+            Some(_) => &[METHOD_NONE, METHOD_PASSWORD],
+            None => &[METHOD_NONE],
+        };
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await.map_err(io_error)?;
+
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice).await.map_err(io_error)?;
+        if choice[0] != VERSION {
+            return Err(proxy_error("the server is not a SOCKS5 proxy"));
+        }
+        match (choice[1], &self.credentials) {
+            (METHOD_NONE, _) => {}
+            (METHOD_PASSWORD, Some((username, password))) => {
+                self.authenticate(stream, username, password).await?
+            }
+            (METHOD_UNACCEPTABLE, None) | (METHOD_PASSWORD, None) => {
+                return Err(proxy_error(
+                    "the proxy requires authentication (set --socks-user and --socks-password)",
+                ))
+            }
+            (METHOD_UNACCEPTABLE, Some(_)) => {
+                return Err(proxy_error(
+                    "the proxy accepts none of the offered authentication methods",
+                ))
+            }
+            (method, _) => {
+                return Err(proxy_error(format!(
+                    "the proxy chose unsupported authentication method {:#04x}",
+                    method
+                )))
+            }
+        }
+
+        stream.write_all(&request).await.map_err(io_error)?;
+
+        // VER REP RSV ATYP, then the bound address, which is not needed
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await.map_err(io_error)?;
+        if reply[1] != 0 {
+            return Err(proxy_error(format!(
+                "the proxy rejected the connection to {}:{}: {}",
+                host,
+                port,
+                reply_message(reply[1])
+            )));
+        }
+        let address_len = match reply[3] {
+            ADDR_IPV4 => 4,
+            ADDR_IPV6 => 16,
+            ADDR_DOMAIN => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await.map_err(io_error)?;
+                usize::from(len[0])
+            }
+            other => {
+                return Err(proxy_error(format!(
+                    "the proxy replied with unknown address type {:#04x}",
+                    other
+                )))
+            }
+        };
+        let mut bound = vec![0u8; address_len + 2];
+        stream.read_exact(&mut bound).await.map_err(io_error)?;
+        Ok(())
+    }
+
+    /// Username/password subnegotiation (RFC 1929)
+    async fn authenticate<S>(&self, stream: &mut S, username: &str, password: &str) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut request = vec![PASSWORD_AUTH_VERSION];
+        for field in [username, password] {
+            let len = u8::try_from(field.len())
+                .map_err(|_| proxy_error("usernames and passwords are limited to 255 bytes"))?;
+            request.push(len);
+            request.extend_from_slice(field.as_bytes());
+        }
+        stream.write_all(&request).await.map_err(io_error)?;
+
+        let mut status = [0u8; 2];
+        stream.read_exact(&mut status).await.map_err(io_error)?;
+        if status[1] != 0 {
+            return Err(proxy_error("the proxy rejected the username or password"));
+        }
+        Ok(())
+    }
+}
+
+/// Build the `CONNECT` request for `host:port`
+fn connect_request(host: &str, port: u16) -> Result<Vec<u8>> {
+    let mut request = vec![VERSION, COMMAND_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ADDR_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ADDR_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len()).map_err(|_| {
+                proxy_error("host names sent to the proxy are limited to 255 bytes")
+            })?;
+            request.push(ADDR_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+/// Explanation of a SOCKS5 reply code
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn proxy_error(msg: impl std::fmt::Display) -> SshMcpError {
+    SshMcpError::connection(format!("SOCKS5 proxy error: {}", msg))
+}
+
+fn io_error(e: std::io::Error) -> SshMcpError {
+    proxy_error(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Play the proxy side of a handshake: check every expected request and
+    /// answer with the matching reply
+    async fn fake_proxy(mut stream: tokio::io::DuplexStream, script: Vec<(Vec<u8>, Vec<u8>)>) {
+        for (expected, reply) in script {
+            let mut request = vec![0u8; expected.len()];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, expected);
+            stream.write_all(&reply).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_connect_request() {
+        assert_eq!(
+            connect_request("10.0.0.5", 22).unwrap(),
+            vec![5, 1, 0, 1, 10, 0, 0, 5, 0, 22]
+        );
+        assert_eq!(
+            connect_request("db1", 2222).unwrap(),
+            vec![5, 1, 0, 3, 3, b'd', b'b', b'1', 0x08, 0xae]
+        );
+        assert_eq!(connect_request("::1", 22).unwrap()[3], ADDR_IPV6);
+        assert!(connect_request(&"a".repeat(256), 22).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_without_auth() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let proxy = tokio::spawn(fake_proxy(
+            server,
+            vec![
+                (vec![5, 1, 0], vec![5, 0]),
+                (
+                    vec![5, 1, 0, 3, 3, b'd', b'b', b'1', 0, 22],
+                    vec![5, 0, 0, 1, 127, 0, 0, 1, 0x30, 0x39],
+                ),
+            ],
+        ));
+
+        SocksProxy::new("proxy", 1080)
+            .handshake(&mut client, "db1", 22)
+            .await
+            .unwrap();
+        proxy.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_auth() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let proxy = tokio::spawn(fake_proxy(
+            server,
+            vec![
+                (vec![5, 2, 0, 2], vec![5, 2]),
+                (vec![1, 2, b'm', b'e', 2, b'p', b'w'], vec![1, 0]),
+                (
+                    vec![5, 1, 0, 1, 10, 0, 0, 5, 0, 22],
+                    vec![5, 0, 0, 3, 1, b'x', 0, 0],
+                ),
+            ],
+        ));
+
+        SocksProxy::new("proxy", 1080)
+            .with_credentials("me", "pw")
+            .handshake(&mut client, "10.0.0.5", 22)
+            .await
+            .unwrap();
+        proxy.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejections() {
+        // Wrong password
+        let (mut client, server) = tokio::io::duplex(64);
+        tokio::spawn(fake_proxy(
+            server,
+            vec![
+                (vec![5, 2, 0, 2], vec![5, 2]),
+                (vec![1, 2, b'm', b'e', 2, b'p', b'w'], vec![1, 1]),
+            ],
+        ));
+        let err = SocksProxy::new("proxy", 1080)
+            .with_credentials("me", "pw")
+            .handshake(&mut client, "db1", 22)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("rejected the username or password"));
+
+        // Authentication required but not configured
+        let (mut client, server) = tokio::io::duplex(64);
+        tokio::spawn(fake_proxy(server, vec![(vec![5, 1, 0], vec![5, 0xff])]));
+        let err = SocksProxy::new("proxy", 1080)
+            .handshake(&mut client, "db1", 22)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("--socks-user"));
+
+        // Connection refused by the proxy's ruleset
+        let (mut client, server) = tokio::io::duplex(64);
+        tokio::spawn(fake_proxy(
+            server,
+            vec![
+                (vec![5, 1, 0], vec![5, 0]),
+                (
+                    vec![5, 1, 0, 3, 3, b'd', b'b', b'1', 0, 22],
+                    vec![5, 2, 0, 1, 0, 0, 0, 0, 0, 0],
+                ),
+            ],
+        ));
+        let err = SocksProxy::new("proxy", 1080)
+            .handshake(&mut client, "db1", 22)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("rejected the connection to db1:22: connection not allowed by ruleset"));
+    }
+}
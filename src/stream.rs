@@ -0,0 +1,85 @@
+//! Incremental delivery of command output
+//!
+//! A tool call can ask to see output while its command is still running.
+//! The call's future then runs inside [`with_sink`], and the output
+//! collectors of the exec channel and `--local` mode hand every chunk they
+//! keep to that sink. Outside of such a scope, or on paths that don't stream
+//! (the elevated su shell, which has to find its end-of-command marker
+//! first), nothing is sent and only the final output is returned.
+
+use std::future::Future;
+
+use tokio::sync::mpsc;
+
+use crate::ssh::command::push_limited;
+
+/// Receives output text as a command prints it, in the order it was read
+pub type OutputSink = mpsc::UnboundedSender<String>;
+
+tokio::task_local! {
+    static SINK: OutputSink;
+}
+
+/// Run `future` with output chunks sent to `sink`
+pub async fn with_sink<F: Future>(sink: OutputSink, future: F) -> F::Output {
+    SINK.scope(sink, future).await
+}
+
+/// Like [`push_limited`], also sending the part kept to the current sink
+pub(crate) fn push_streamed(
+    buf: &mut String,
+    data: &[u8],
+    bytes_read: &mut usize,
+    limit: Option<usize>,
+) -> bool {
+    let start = buf.len();
+    let exceeded = push_limited(buf, data, bytes_read, limit);
+    // Cutting at a line break may have dropped more than was added
+    if let Some(kept) = buf.get(start..).filter(|kept| !kept.is_empty()) {
+        let _ = SINK.try_with(|sink| sink.send(kept.to_string()));
+    }
+    exceeded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_push_streamed() {
+        let (sink, mut chunks) = mpsc::unbounded_channel();
+        let mut buf = String::new();
+        let mut bytes_read = 0;
+        with_sink(sink, async {
+            push_streamed(&mut buf, b"one\n", &mut bytes_read, None);
+            push_streamed(&mut buf, b"", &mut bytes_read, None);
+            push_streamed(&mut buf, b"two\nthree", &mut bytes_read, Some(8));
+        })
+        .await;
+        assert_eq!(buf, "one\ntwo\n");
+        assert_eq!(chunks.recv().await.unwrap(), "one\n");
+        assert_eq!(chunks.recv().await.unwrap(), "two\n");
+        assert!(chunks.recv().await.is_none());
+
+        // Without a sink the output is only collected
+        push_streamed(&mut buf, b"four\n", &mut bytes_read, None);
+        assert!(buf.ends_with("four\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_local_output_is_streamed() {
+        use crate::local::LocalExecutor;
+        use std::time::Duration;
+
+        let (sink, mut chunks) = mpsc::unbounded_channel();
+        let output = with_sink(
+            sink,
+            LocalExecutor::new().exec_command("echo hi", Duration::from_secs(5)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.stdout, "hi\n");
+        assert_eq!(chunks.recv().await.unwrap(), "hi\n");
+    }
+}
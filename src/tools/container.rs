@@ -0,0 +1,180 @@
+//! Running `exec` commands inside a container (`container` parameter)
+//!
+//! The command is wrapped so the runtime on the remote host runs it in the
+//! container, through the same SSH connection as every other command.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Result, SshMcpError};
+use crate::ssh::sanitize::escape_command_for_shell;
+
+/// How commands get into a container (`--container-runtime`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContainerRuntime {
+    /// `docker exec <container>`
+    #[default]
+    Docker,
+
+    /// `podman exec <container>`
+    Podman,
+
+    /// `nsenter` into the namespaces of a process, given by its PID
+    Nsenter,
+}
+
+impl FromStr for ContainerRuntime {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "docker" => Ok(ContainerRuntime::Docker),
+            "podman" => Ok(ContainerRuntime::Podman),
+            "nsenter" => Ok(ContainerRuntime::Nsenter),
+            other => Err(format!(
+                "unknown container runtime '{}' (supported: docker, podman, nsenter)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ContainerRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+            ContainerRuntime::Nsenter => "nsenter",
+        })
+    }
+}
+
+impl ContainerRuntime {
+    /// Check a container given to this runtime
+    ///
+    /// docker and podman take a container name or ID; nsenter takes the PID
+    /// of a process in the container, such as its init process.
+    pub fn validate_container(&self, container: &str) -> Result<()> {
+        let valid = match self {
+            ContainerRuntime::Docker | ContainerRuntime::Podman => {
+                container.len() <= 255
+                    && container.starts_with(|c: char| c.is_ascii_alphanumeric())
+                    && container
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+            }
+            ContainerRuntime::Nsenter => container.parse::<u32>().is_ok_and(|pid| pid > 1),
+        };
+        if valid {
+            return Ok(());
+        }
+
+        let expected = match self {
+            ContainerRuntime::Nsenter => "the PID of a process in the container",
+            _ => "a container name or ID (letters, digits, '_', '.' and '-')",
+        };
+        Err(SshMcpError::invalid_params(format!(
+            "Invalid container {:?} for {}: expected {}",
+            container, self, expected
+        )))
+    }
+
+    /// Wrap `command` to run in `container`
+    ///
+    /// The wrapper first checks that the runtime is installed, failing with
+    /// exit code 127 and a message naming it otherwise. It never runs a
+    /// top-level `exit`, which would end the persistent su shell elevated
+    /// commands are typed into. `tty` adds `-t` for commands running on a
+    /// PTY, so the container side gets one too.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ssh_mcp::tools::container::ContainerRuntime;
+    ///
+    /// let wrapped = ContainerRuntime::Docker.wrap("web", "echo 'hi'", false);
+    /// assert!(wrapped.contains("then docker exec web sh -c 'echo '\"'\"'hi'\"'\"''; else"));
+    /// ```
+    pub fn wrap(&self, container: &str, command: &str, tty: bool) -> String {
+        let program = self.to_string();
+        let enter = match self {
+            ContainerRuntime::Docker | ContainerRuntime::Podman => {
+                let tty = if tty { "-t " } else { "" };
+                format!("{} exec {}{}", program, tty, container)
+            }
+            ContainerRuntime::Nsenter => {
+                format!(
+                    "nsenter --target {} --mount --uts --ipc --net --pid",
+                    container
+                )
+            }
+        };
+        format!(
+            "if command -v {program} >/dev/null 2>&1; then {enter} sh -c '{command}'; \
+             else echo 'ssh-mcp: {program} is not installed on the remote host \
+             (see --container-runtime)' >&2; (exit 127); fi",
+            program = program,
+            enter = enter,
+            command = escape_command_for_shell(command)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_runtime() {
+        assert_eq!("docker".parse(), Ok(ContainerRuntime::Docker));
+        assert_eq!(" podman".parse(), Ok(ContainerRuntime::Podman));
+        assert_eq!("nsenter".parse(), Ok(ContainerRuntime::Nsenter));
+        assert!("lxc".parse::<ContainerRuntime>().is_err());
+        assert_eq!(ContainerRuntime::default().to_string(), "docker");
+    }
+
+    #[test]
+    fn test_validate_container() {
+        let docker = ContainerRuntime::Docker;
+        assert!(docker.validate_container("web-1").is_ok());
+        assert!(docker.validate_container("3f2a9c1b").is_ok());
+        assert!(docker.validate_container("my_app.db").is_ok());
+        assert!(docker.validate_container("").is_err());
+        assert!(docker.validate_container("-it").is_err());
+        assert!(docker.validate_container("web; reboot").is_err());
+        assert!(docker.validate_container("web'").is_err());
+
+        let nsenter = ContainerRuntime::Nsenter;
+        assert!(nsenter.validate_container("4242").is_ok());
+        assert!(nsenter.validate_container("1").is_err());
+        let err = nsenter.validate_container("web").unwrap_err();
+        assert!(err.to_string().contains("PID"));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let wrapped = ContainerRuntime::Podman.wrap("db", "ls /", true);
+        assert!(wrapped.starts_with("if command -v podman >/dev/null 2>&1; then "));
+        assert!(wrapped.contains("podman is not installed"));
+        assert!(wrapped.contains("; then podman exec -t db sh -c 'ls /'; else "));
+
+        let wrapped = ContainerRuntime::Nsenter.wrap("4242", "ip addr", true);
+        assert!(wrapped.contains(
+            "; then nsenter --target 4242 --mount --uts --ipc --net --pid sh -c 'ip addr'; else "
+        ));
+    }
+
+    #[test]
+    fn test_wrap_has_no_top_level_exit() {
+        // A bare exit would end the su shell the wrapper is typed into
+        for runtime in [
+            ContainerRuntime::Docker,
+            ContainerRuntime::Podman,
+            ContainerRuntime::Nsenter,
+        ] {
+            let wrapped = runtime.wrap("4242", "true", false);
+            assert!(wrapped.contains("(exit 127)"));
+            assert!(!wrapped.replace("(exit 127)", "").contains("exit"));
+        }
+    }
+}
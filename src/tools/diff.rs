@@ -0,0 +1,220 @@
+//! Line-based unified diff for the `exec-diff` tool
+//!
+//! Computes a longest-common-subsequence diff between two outputs and renders
+//! it in the familiar `diff -u` format. Outputs are usually small, but inputs
+//! whose changed region is too large for the quadratic LCS table are reported
+//! as a full replacement of that region instead.
+
+/// Default number of unchanged lines shown around each change
+pub const DEFAULT_DIFF_CONTEXT: usize = 3;
+
+/// Number of exec-diff keys whose last output is kept
+pub const MAX_DIFF_KEYS: usize = 64;
+
+/// Most bytes of output kept across all exec-diff keys (32 MiB)
+pub const MAX_DIFF_BYTES: usize = 32 * 1024 * 1024;
+
+/// Largest LCS table (in cells) computed before falling back to a replacement
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Render a unified diff of `old` against `new`
+///
+/// Returns an empty string when the two texts have the same lines. `context`
+/// unchanged lines are shown around each change, and nearby changes are
+/// merged into one hunk.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::diff::unified_diff;
+///
+/// let diff = unified_diff("a\nb\n", "a\nc\n", "before", "after", 3);
+/// assert_eq!(diff, "--- before\n+++ after\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+/// ```
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_label: &str,
+    new_label: &str,
+    context: usize,
+) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_ops(&old, &new);
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != Op::Equal).collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Line positions in old/new before each op, for hunk headers
+    let mut old_pos = Vec::with_capacity(ops.len() + 1);
+    let mut new_pos = Vec::with_capacity(ops.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for &(op, _) in &ops {
+        old_pos.push(o);
+        new_pos.push(n);
+        o += usize::from(op != Op::Insert);
+        n += usize::from(op != Op::Delete);
+    }
+    old_pos.push(o);
+    new_pos.push(n);
+
+    // Group changes separated by at most 2 * context unchanged lines
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &k in &changes {
+        match groups.last_mut() {
+            Some((_, last)) if k - *last <= 2 * context + 1 => *last = k,
+            _ => groups.push((k, k)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (first, last) in groups {
+        let lo = first.saturating_sub(context);
+        let hi = (last + context + 1).min(ops.len());
+
+        let old_count = old_pos[hi] - old_pos[lo];
+        let new_count = new_pos[hi] - new_pos[lo];
+        // An empty range is addressed by the line before it, as in diff -u
+        let old_start = old_pos[lo] + usize::from(old_count > 0);
+        let new_start = new_pos[lo] + usize::from(new_count > 0);
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start, old_count, new_start, new_count
+        ));
+
+        for &(op, line) in &ops[lo..hi] {
+            out.push(match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            });
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+/// Compute the edit script turning `old` into `new`
+fn diff_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    // The common prefix and suffix never need the LCS table
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<(Op, &str)> = old[..prefix].iter().map(|&l| (Op::Equal, l)).collect();
+
+    let cells = (a.len() + 1).saturating_mul(b.len() + 1);
+    if cells > MAX_LCS_CELLS {
+        ops.extend(a.iter().map(|&l| (Op::Delete, l)));
+        ops.extend(b.iter().map(|&l| (Op::Insert, l)));
+    } else {
+        // lcs[i * width + j] = length of the LCS of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; cells];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                ops.push((Op::Equal, a[i]));
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                ops.push((Op::Delete, a[i]));
+                i += 1;
+            } else {
+                ops.push((Op::Insert, b[j]));
+                j += 1;
+            }
+        }
+        ops.extend(a[i..].iter().map(|&l| (Op::Delete, l)));
+        ops.extend(b[j..].iter().map(|&l| (Op::Insert, l)));
+    }
+
+    ops.extend(old[old.len() - suffix..].iter().map(|&l| (Op::Equal, l)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_is_empty() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new", 3), "");
+        assert_eq!(unified_diff("", "", "old", "new", 3), "");
+    }
+
+    #[test]
+    fn test_insert_into_empty() {
+        let diff = unified_diff("", "a\nb\n", "old", "new", 3);
+        assert_eq!(diff, "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n");
+    }
+
+    #[test]
+    fn test_context_is_limited() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n";
+        let diff = unified_diff(old, new, "old", "new", 1);
+        assert_eq!(
+            diff,
+            "--- old\n+++ new\n@@ -4,3 +4,3 @@\n 4\n-5\n+five\n 6\n"
+        );
+    }
+
+    #[test]
+    fn test_separate_hunks() {
+        let old = "a\n1\n2\n3\n4\n5\nb\n";
+        let new = "A\n1\n2\n3\n4\n5\nB\n";
+        let diff = unified_diff(old, new, "old", "new", 1);
+        assert_eq!(
+            diff,
+            "--- old\n+++ new\n@@ -1,2 +1,2 @@\n-a\n+A\n 1\n@@ -6,2 +6,2 @@\n 5\n-b\n+B\n"
+        );
+
+        // Close enough changes share one hunk
+        let diff = unified_diff(old, new, "old", "new", 3);
+        assert_eq!(diff.matches("@@ -").count(), 1);
+    }
+
+    #[test]
+    fn test_delete_and_insert() {
+        let diff = unified_diff(
+            "active\nloaded\nrunning\n",
+            "loaded\nstopped\n",
+            "old",
+            "new",
+            3,
+        );
+        assert_eq!(
+            diff,
+            "--- old\n+++ new\n@@ -1,3 +1,2 @@\n-active\n loaded\n-running\n+stopped\n"
+        );
+    }
+}
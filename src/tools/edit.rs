@@ -0,0 +1,81 @@
+//! Helpers for the `edit-file` tool
+//!
+//! The substitution is applied here rather than with `sed -i` on the remote
+//! host, so patterns and replacements never pass through a shell and need no
+//! escaping.
+
+use regex::Regex;
+
+use crate::error::{Result, SshMcpError};
+
+/// Largest file `edit-file` reads into memory (10 MiB)
+pub const MAX_EDIT_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Suffix of the backup copy kept with `backup: true`
+pub const BACKUP_SUFFIX: &str = ".bak";
+
+/// Compile the `pattern` parameter
+pub fn compile_pattern(pattern: &str) -> Result<Regex> {
+    if pattern.is_empty() {
+        return Err(SshMcpError::invalid_params("pattern must not be empty"));
+    }
+    Regex::new(pattern).map_err(|e| SshMcpError::invalid_params(format!("Invalid pattern: {}", e)))
+}
+
+/// Replace every match of `pattern` in `content`
+///
+/// `replacement` may refer to capture groups as `$1` or `${name}`; `$$` is a
+/// literal `$`. Returns the new content and the number of replacements.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::edit::{compile_pattern, substitute};
+///
+/// let pattern = compile_pattern(r"(?m)^#?Port \d+$").unwrap();
+/// let (content, count) = substitute("#Port 22\n", &pattern, "Port 2222");
+/// assert_eq!(content, "Port 2222\n");
+/// assert_eq!(count, 1);
+/// ```
+pub fn substitute(content: &str, pattern: &Regex, replacement: &str) -> (String, usize) {
+    let count = pattern.find_iter(content).count();
+    if count == 0 {
+        return (content.to_string(), 0);
+    }
+    (
+        pattern.replace_all(content, replacement).into_owned(),
+        count,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_pattern() {
+        assert!(compile_pattern("a+b").is_ok());
+        assert!(compile_pattern("").is_err());
+        let err = compile_pattern("(unclosed").unwrap_err();
+        assert!(err.to_string().contains("Invalid pattern"));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let pattern = compile_pattern("listen (\\d+)").unwrap();
+        let content = "listen 80;\nlisten 8080;\n";
+        let (edited, count) = substitute(content, &pattern, "listen ${1}0");
+        assert_eq!(edited, "listen 800;\nlisten 80800;\n");
+        assert_eq!(count, 2);
+
+        // Characters a shell or sed would treat specially are taken literally
+        let pattern = compile_pattern("PASS=.*").unwrap();
+        let (edited, count) = substitute("PASS=old\n", &pattern, "PASS='a/b\"c&$$d'");
+        assert_eq!(edited, "PASS='a/b\"c&$d'\n");
+        assert_eq!(count, 1);
+
+        let (edited, count) = substitute("nothing here", &pattern, "x");
+        assert_eq!(edited, "nothing here");
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,192 @@
+//! Exit code classification for `--exit-code-map`
+//!
+//! By default any exit code but 0 makes a command result an error. Some
+//! programs use other codes for outcomes that are fine or only worth a
+//! warning, such as rsync's 24 ("some files vanished") or grep's 1 ("no
+//! match"). The map lists such codes, either for every command or for
+//! commands starting with a given prefix.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+/// Characters that make a command's exit code come from another program
+const COMPOUND_OPERATORS: &[char] = &['|', ';', '&', '\n'];
+
+/// How an exit code is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitClass {
+    /// A normal result
+    Success,
+    /// A normal result, with a note about the exit code
+    Warning,
+    /// An error result
+    Error,
+}
+
+impl FromStr for ExitClass {
+    type Err = String;
+
+    fn from_str(class: &str) -> std::result::Result<Self, Self::Err> {
+        match class.trim().to_ascii_lowercase().as_str() {
+            "success" => Ok(Self::Success),
+            "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            other => Err(format!(
+                "unknown class {:?}: expected success, warning or error",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ExitClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Success => "success",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// Exit codes classified globally and per command prefix
+///
+/// Parsed from comma-separated `[PREFIX:]CODE=CLASS` entries, for example
+/// `rsync:24=success,grep:1=success,255=error`.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::exit_codes::{ExitClass, ExitCodeMap};
+///
+/// let map: ExitCodeMap = "rsync:24=success,255=error".parse().unwrap();
+/// assert_eq!(map.classify("rsync -a src/ dst/", 24), Some(ExitClass::Success));
+/// assert_eq!(map.classify("cp -a src/ dst/", 24), None);
+/// assert_eq!(map.classify("ssh other", 255), Some(ExitClass::Error));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitCodeMap {
+    global: HashMap<u32, ExitClass>,
+    commands: Vec<(String, HashMap<u32, ExitClass>)>,
+}
+
+impl ExitCodeMap {
+    /// Whether no codes are mapped
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.commands.is_empty()
+    }
+
+    /// Classification of `code` for `command`, if the map has one
+    ///
+    /// Command entries apply to commands starting with their prefix as
+    /// whole words, the longest prefix winning, and take precedence over
+    /// global entries. Pipelines and command lists only get global entries,
+    /// since their exit code may come from a later program.
+    pub fn classify(&self, command: &str, code: u32) -> Option<ExitClass> {
+        let command = command.trim();
+        let by_command = (!command.contains(COMPOUND_OPERATORS))
+            .then(|| {
+                self.commands
+                    .iter()
+                    .filter(|(prefix, codes)| {
+                        codes.contains_key(&code)
+                            && command.strip_prefix(prefix.as_str()).is_some_and(|rest| {
+                                rest.is_empty() || rest.starts_with(char::is_whitespace)
+                            })
+                    })
+                    .max_by_key(|(prefix, _)| prefix.len())
+                    .and_then(|(_, codes)| codes.get(&code).copied())
+            })
+            .flatten();
+        by_command.or_else(|| self.global.get(&code).copied())
+    }
+}
+
+impl FromStr for ExitCodeMap {
+    type Err = String;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, Self::Err> {
+        let mut map = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (target, class) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("{:?} is not of the form [PREFIX:]CODE=CLASS", entry))?;
+            let (prefix, code) = match target.rsplit_once(':') {
+                Some((prefix, code)) => (Some(prefix.trim()), code),
+                None => (None, target),
+            };
+            let code: u32 = code
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid exit code in {:?}", entry))?;
+            let class = class.parse().map_err(|e| format!("{} in {:?}", e, entry))?;
+
+            match prefix {
+                Some("") => return Err(format!("empty command prefix in {:?}", entry)),
+                Some(prefix) => {
+                    let codes = match map.commands.iter_mut().find(|(p, _)| p == prefix) {
+                        Some((_, codes)) => codes,
+                        None => {
+                            map.commands.push((prefix.to_string(), HashMap::new()));
+                            &mut map.commands.last_mut().unwrap().1
+                        }
+                    };
+                    codes.insert(code, class);
+                }
+                None => {
+                    map.global.insert(code, class);
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exit_code_map() {
+        let map: ExitCodeMap = "rsync:24=success, grep:1=warning,git diff:1=success,255=error"
+            .parse()
+            .unwrap();
+        assert_eq!(map.classify("rsync -a a/ b/", 24), Some(ExitClass::Success));
+        assert_eq!(map.classify("grep -q x f", 1), Some(ExitClass::Warning));
+        assert_eq!(
+            map.classify("git diff --exit-code", 1),
+            Some(ExitClass::Success)
+        );
+        assert_eq!(map.classify("git status", 1), None);
+        assert_eq!(map.classify("anything", 255), Some(ExitClass::Error));
+        assert_eq!(map.classify("rsync -a a/ b/", 23), None);
+        assert!(!map.is_empty());
+        assert!(ExitCodeMap::default().is_empty());
+    }
+
+    #[test]
+    fn test_classify_whole_words_and_compound_commands() {
+        let map: ExitCodeMap = "grep:1=success,1=warning".parse().unwrap();
+        // Prefixes match whole words only
+        assert_eq!(map.classify("grepx foo", 1), Some(ExitClass::Warning));
+        // The exit code of a pipeline is the last program's
+        assert_eq!(
+            map.classify("grep foo f | wc -l", 1),
+            Some(ExitClass::Warning)
+        );
+        assert_eq!(map.classify("grep foo f", 1), Some(ExitClass::Success));
+    }
+
+    #[test]
+    fn test_parse_exit_code_map_errors() {
+        assert!("rsync:24".parse::<ExitCodeMap>().is_err());
+        assert!("rsync:x=success".parse::<ExitCodeMap>().is_err());
+        assert!("rsync:24=fine".parse::<ExitCodeMap>().is_err());
+        assert!(":24=success".parse::<ExitCodeMap>().is_err());
+        assert!("".parse::<ExitCodeMap>().unwrap().is_empty());
+    }
+}
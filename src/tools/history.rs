@@ -0,0 +1,130 @@
+//! Command history for the `command-history` tool
+//!
+//! Keeps a bounded ring buffer of recently executed commands and how they
+//! ended. Only the sanitized command text is stored, never the sudo/su
+//! wrapping (which may carry a password) and never the output.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Default number of commands kept in the history
+pub const DEFAULT_HISTORY_SIZE: usize = 50;
+
+/// A single executed command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistoryEntry {
+    /// Tool that ran the command (`exec`, `sudo-exec`, ...)
+    pub tool: String,
+
+    /// Sanitized command as provided by the caller
+    pub command: String,
+
+    /// Exit code, if the command ran to completion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<u32>,
+
+    /// Error message, if the command could not be run or timed out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Completion time in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Bounded, thread-safe ring buffer of executed commands
+#[derive(Debug)]
+pub struct CommandHistory {
+    capacity: usize,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl CommandHistory {
+    /// Create a history keeping at most `capacity` entries (0 disables it)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Record a finished command, evicting the oldest entry when full
+    pub fn record(&self, tool: &str, command: &str, exit_code: Option<u32>, error: Option<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(HistoryEntry {
+            tool: tool.to_string(),
+            command: command.to_string(),
+            exit_code,
+            error,
+            timestamp,
+        });
+    }
+
+    /// Return up to `limit` most recent entries, oldest first
+    pub fn recent(&self, limit: Option<usize>) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let skip = limit.map_or(0, |n| entries.len().saturating_sub(n));
+        entries.iter().skip(skip).cloned().collect()
+    }
+
+    /// Maximum number of entries kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_evicts_oldest() {
+        let history = CommandHistory::new(2);
+        history.record("exec", "one", Some(0), None);
+        history.record("exec", "two", Some(1), None);
+        history.record("sudo-exec", "three", None, Some("timeout".to_string()));
+
+        let commands: Vec<_> = history
+            .recent(None)
+            .into_iter()
+            .map(|e| e.command)
+            .collect();
+        assert_eq!(commands, vec!["two", "three"]);
+    }
+
+    #[test]
+    fn test_history_recent_limit() {
+        let history = CommandHistory::new(10);
+        for i in 0..5 {
+            history.record("exec", &i.to_string(), Some(0), None);
+        }
+
+        let commands: Vec<_> = history
+            .recent(Some(2))
+            .into_iter()
+            .map(|e| e.command)
+            .collect();
+        assert_eq!(commands, vec!["3", "4"]);
+    }
+
+    #[test]
+    fn test_history_disabled() {
+        let history = CommandHistory::new(0);
+        history.record("exec", "ls", Some(0), None);
+        assert!(history.recent(None).is_empty());
+    }
+}
@@ -6,6 +6,29 @@
 //! Available tools:
 //! - `exec` - Execute shell commands on the remote SSH server
 //! - `sudo-exec` - Execute shell commands with sudo privileges
+//! - `session-info` - Report connection details and the remote locale
+//! - `command-history` - List recently executed commands and their exit codes
+//! - `remove` - Delete remote files over SFTP, expanding globs
+//! - `append-file` - Append text to a remote file over SFTP
+//! - `edit-file` - Apply a regex substitution to a remote file over SFTP
+//! - `upload` - Write a local file or base64 content to a remote file over SFTP
+//! - `download` - Read a remote file over SFTP, as base64 or text
+//! - `read-file` - Read the start of a remote file over SFTP, base64-encoded
+//! - `list-dir` - List a remote directory over SFTP as structured entries
+//! - `chmod` - Set the mode of a remote file over SFTP
+//! - `chown` - Change the owner and group of a remote file
+//! - `exec-diff` - Run a command and diff its output against a previous run
+//! - `broadcast-exec` - Run a command on several hosts at once
+//! - `queue-status` - Report running and queued commands and the expected wait
+//! - `cancel-all` - Abort every in-flight command and file operation
+//! - `drop-elevation` - Close the su shell and run commands as the login user
+//! - `connect` - Open the SSH session explicitly (`--enable-connection-tools`)
+//! - `disconnect` - Close the SSH session (`--enable-connection-tools`)
+//! - `list-processes` - List running processes as structured entries
+//! - `kill-process` - Send a signal to a process
+//! - `list-ports` - List listening ports as structured entries
+//! - `wait-for` - Re-run a command until its result meets a condition
+//! - `run-command` - Run the `--only-command` template; the only tool in that mode
 //!
 //! See `server.rs` for the implementation.
 
@@ -13,14 +36,106 @@
 // This module is kept for potential future expansion with additional tools
 // or utility functions.
 
+pub mod container;
+pub mod diff;
+pub mod edit;
+pub mod exit_codes;
+pub mod history;
+pub mod output;
+pub mod parsers;
+pub mod paths;
+pub mod perms;
+pub mod ports;
+pub mod processes;
+pub mod results;
+pub mod session;
+pub mod template;
+pub mod wait;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::error::{Result, SshMcpError};
 
 /// Parameters for the exec tool
 #[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct ExecParams {
     /// Shell command to execute on the remote SSH server
-    pub command: String,
+    pub command: Option<String>,
+
+    /// The command encoded as base64, instead of `command`
+    ///
+    /// Useful for commands full of quotes or other characters that are awkward
+    /// to embed in JSON arguments.
+    pub command_base64: Option<String>,
+
+    /// Environment variables to set for the command
+    pub env: Option<BTreeMap<String, String>>,
+
+    /// Run the command on a terminal this many columns wide
+    pub term_width: Option<u16>,
+
+    /// Octal umask applied before the command, e.g. `077`
+    pub umask: Option<String>,
+
+    /// Treat stderr output as failure even with exit code 0
+    /// (None = `--fail-on-stderr`)
+    pub fail_on_stderr: Option<bool>,
+
+    /// Collapse runs of identical lines in the output
+    /// (None = `--collapse-repeats`)
+    pub dedupe: Option<bool>,
+
+    /// Add the SHA-256 of stdout to the structured result
+    pub include_hash: Option<bool>,
+
+    /// Run the command inside this container, through `--container-runtime`
+    pub container: Option<String>,
+
+    /// Read output for this many milliseconds, then stop the command
+    ///
+    /// For commands that never exit on their own, such as `tail -f`.
+    pub read_duration_ms: Option<u64>,
+
+    /// Keep stdout on the server and report its `result_id`, for `stdin_from`
+    pub keep_output: Option<bool>,
+
+    /// `result_id` of an earlier exec result whose stdout is fed to the
+    /// command's standard input
+    pub stdin_from: Option<String>,
+
+    /// Return the command that would be sent instead of running it
+    pub dry_run: Option<bool>,
+}
+
+impl ExecParams {
+    /// Get the command to run, decoding `command_base64` if that was given
+    ///
+    /// Exactly one of `command` and `command_base64` must be set, and the
+    /// decoded bytes must be valid UTF-8.
+    pub fn command(&self) -> Result<String> {
+        match (&self.command, &self.command_base64) {
+            (Some(command), None) => Ok(command.clone()),
+            (None, Some(encoded)) => {
+                let bytes = BASE64.decode(encoded.trim()).map_err(|e| {
+                    SshMcpError::invalid_params(format!("Invalid command_base64: {}", e))
+                })?;
+                String::from_utf8(bytes).map_err(|_| {
+                    SshMcpError::invalid_params("command_base64 does not decode to UTF-8 text")
+                })
+            }
+            (Some(_), Some(_)) => Err(SshMcpError::invalid_params(
+                "Provide either command or command_base64, not both",
+            )),
+            (None, None) => Err(SshMcpError::invalid_params(
+                "Missing required parameter: command or command_base64",
+            )),
+        }
+    }
 }
 
 /// Parameters for the sudo-exec tool
@@ -28,6 +143,274 @@ pub struct ExecParams {
 pub struct SudoExecParams {
     /// Shell command to execute with sudo on the remote SSH server
     pub command: String,
+
+    /// Return the command that would be sent instead of running it
+    pub dry_run: Option<bool>,
+}
+
+/// Parameters for the command-history tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct CommandHistoryParams {
+    /// Maximum number of most recent entries to return
+    pub limit: Option<usize>,
+}
+
+/// Parameters for the remove tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct RemoveParams {
+    /// Remote file path; the last component may be a glob such as `*.log`
+    pub path: String,
+}
+
+/// Parameters for the append-file tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AppendFileParams {
+    /// Remote file to append to; created if it does not exist
+    pub path: String,
+
+    /// Text appended verbatim (add a trailing newline for line-based logs)
+    pub content: String,
+}
+
+/// Parameters for the edit-file tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct EditFileParams {
+    /// Remote file to edit
+    pub path: String,
+
+    /// Regular expression to replace (Rust `regex` syntax)
+    pub pattern: String,
+
+    /// Replacement for every match; `$1` or `${name}` insert capture groups
+    pub replacement: String,
+
+    /// Keep the original as `<path>.bak`
+    #[serde(default)]
+    pub backup: bool,
+}
+
+/// Parameters for the upload tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct UploadParams {
+    /// Remote file to write; replaced if it exists
+    pub remote_path: String,
+
+    /// File content, base64-encoded
+    pub content_base64: Option<String>,
+
+    /// File on the machine running this server to upload instead
+    pub local_path: Option<String>,
+
+    /// Octal mode for the remote file (default: the existing file's mode,
+    /// or `0644` for a new one)
+    pub mode: Option<String>,
+
+    /// Create missing parent directories of `remote_path`
+    #[serde(default)]
+    pub create_dirs: bool,
+}
+
+impl UploadParams {
+    /// Get the bytes to upload, decoding `content_base64` or reading
+    /// `local_path`
+    ///
+    /// Exactly one of the two must be set, and the content may be at most
+    /// `max` bytes. `local_path` is refused unless it lies within one of
+    /// `allowed_local` (`--allowed-local-paths`), which is empty by default.
+    pub async fn content(&self, max: u64, allowed_local: &[String]) -> Result<Vec<u8>> {
+        let data = match (&self.content_base64, &self.local_path) {
+            (Some(encoded), None) => BASE64.decode(encoded.trim()).map_err(|e| {
+                SshMcpError::invalid_params(format!("Invalid content_base64: {}", e))
+            })?,
+            (None, Some(path)) => {
+                let local = resolve_local_path(path, allowed_local).await?;
+                let len = tokio::fs::metadata(&local)
+                    .await
+                    .map_err(|e| {
+                        SshMcpError::invalid_params(format!("Cannot read {}: {}", path, e))
+                    })?
+                    .len();
+                if len > max {
+                    return Err(SshMcpError::invalid_params(format!(
+                        "{} is {} bytes, more than the {} bytes that can be uploaded",
+                        path, len, max
+                    )));
+                }
+                tokio::fs::read(&local).await.map_err(|e| {
+                    SshMcpError::invalid_params(format!("Cannot read {}: {}", path, e))
+                })?
+            }
+            (Some(_), Some(_)) => {
+                return Err(SshMcpError::invalid_params(
+                    "Provide either content_base64 or local_path, not both",
+                ))
+            }
+            (None, None) => {
+                return Err(SshMcpError::invalid_params(
+                    "Missing required parameter: content_base64 or local_path",
+                ))
+            }
+        };
+
+        if data.len() as u64 > max {
+            return Err(SshMcpError::invalid_params(format!(
+                "Content is {} bytes, more than the {} bytes that can be uploaded",
+                data.len(),
+                max
+            )));
+        }
+        Ok(data)
+    }
+}
+
+/// Canonicalize a local file for upload and check it against
+/// `--allowed-local-paths`
+///
+/// Symlinks and `..` are resolved first, so a link inside an allowed
+/// directory cannot point the upload at a file outside of it.
+async fn resolve_local_path(path: &str, allowed: &[String]) -> Result<PathBuf> {
+    if allowed.is_empty() {
+        return Err(SshMcpError::invalid_params(
+            "local_path is disabled; start the server with --allowed-local-paths to enable it",
+        ));
+    }
+
+    let resolved = tokio::fs::canonicalize(path)
+        .await
+        .map_err(|e| SshMcpError::invalid_params(format!("Cannot read {}: {}", path, e)))?;
+    for dir in allowed {
+        if let Ok(dir) = tokio::fs::canonicalize(dir).await {
+            if resolved.starts_with(&dir) {
+                return Ok(resolved);
+            }
+        }
+    }
+    Err(SshMcpError::invalid_params(format!(
+        "local_path is outside the allowed local paths: {}",
+        path
+    )))
+}
+
+/// Parameters for the download tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct DownloadParams {
+    /// Remote file to read
+    pub remote_path: String,
+
+    /// Refuse files larger than this many bytes; can only lower
+    /// `--max-download-bytes`
+    pub max_bytes: Option<u64>,
+}
+
+/// Parameters for the read-file tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ReadFileParams {
+    /// Remote file to read
+    pub path: String,
+
+    /// Return at most this many bytes from the start of the file
+    pub max_bytes: Option<u64>,
+}
+
+/// Parameters for the list-dir tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ListDirParams {
+    /// Remote directory to list
+    pub path: String,
+
+    /// Include entries whose names start with `.`
+    #[serde(default)]
+    pub include_hidden: bool,
+}
+
+/// Parameters for the chmod tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ChmodParams {
+    /// Remote file or directory
+    pub path: String,
+
+    /// Octal mode such as `644` or `0755`
+    pub mode: String,
+}
+
+/// Parameters for the chown tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ChownParams {
+    /// Remote file or directory
+    pub path: String,
+
+    /// New owner, as a user name or numeric id
+    pub owner: String,
+
+    /// New group, as a group name or numeric id (default: unchanged)
+    pub group: Option<String>,
+}
+
+/// Parameters for the broadcast-exec tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct BroadcastExecParams {
+    /// Shell command to execute on every configured host
+    pub command: String,
+}
+
+/// Parameters for the list-processes tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ListProcessesParams {
+    /// Only list processes whose name or command line contains this text
+    pub name: Option<String>,
+}
+
+/// Parameters for the kill-process tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct KillProcessParams {
+    /// Process to signal
+    pub pid: u32,
+
+    /// Signal name (`TERM`, `SIGKILL`) or number (default: `TERM`)
+    pub signal: Option<String>,
+
+    /// Send the signal through sudo (default: false)
+    pub sudo: Option<bool>,
+}
+
+/// Parameters for the list-ports tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ListPortsParams {
+    /// List through sudo, so the processes of other users show (default: false)
+    pub sudo: Option<bool>,
+}
+
+/// Parameters for the wait-for tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct WaitForParams {
+    /// Shell command to run on every attempt
+    pub command: String,
+
+    /// Exit code the command has to return (default: 0 unless
+    /// `expect_output_contains` is given)
+    pub expect_exit_code: Option<u32>,
+
+    /// Text the command's stdout or stderr has to contain
+    pub expect_output_contains: Option<String>,
+
+    /// Pause between attempts in milliseconds
+    pub interval_ms: Option<u64>,
+
+    /// How long to keep trying in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+/// Parameters for the exec-diff tool
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ExecDiffParams {
+    /// Name the output is stored under for comparison with the next run
+    pub key: String,
+
+    /// Shell command to execute on the remote SSH server
+    pub command: String,
+
+    /// Number of unchanged lines shown around each change
+    pub context: Option<usize>,
 }
 
 #[cfg(test)]
@@ -38,7 +421,131 @@ mod tests {
     fn test_exec_params_deserialize() {
         let json = r#"{"command": "echo hello"}"#;
         let params: ExecParams = serde_json::from_str(json).unwrap();
-        assert_eq!(params.command, "echo hello");
+        assert_eq!(params.command.as_deref(), Some("echo hello"));
+        assert_eq!(params.command().unwrap(), "echo hello");
+    }
+
+    #[test]
+    fn test_exec_params_base64() {
+        // echo 'it'"'"'s'
+        let json = r#"{"command_base64": "ZWNobyAnaXQnIiciJ3Mn"}"#;
+        let params: ExecParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.command().unwrap(), r#"echo 'it'"'"'s'"#);
+
+        let params: ExecParams = serde_json::from_str(r#"{"command_base64": "!!"}"#).unwrap();
+        assert!(params.command().is_err());
+
+        let json = r#"{"command": "ls", "command_base64": "bHM="}"#;
+        let params: ExecParams = serde_json::from_str(json).unwrap();
+        assert!(params.command().is_err());
+
+        let params: ExecParams = serde_json::from_str("{}").unwrap();
+        assert!(params.command().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_params_content() {
+        let json = r#"{"remote_path": "/tmp/a", "content_base64": "aGVsbG8K"}"#;
+        let params: UploadParams = serde_json::from_str(json).unwrap();
+        assert!(!params.create_dirs);
+        assert_eq!(params.content(1024, &[]).await.unwrap(), b"hello\n");
+        assert!(params.content(4, &[]).await.is_err());
+
+        let dir = std::env::temp_dir().join(format!("ssh-mcp-upload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let local = dir.join("file");
+        std::fs::write(&local, b"from disk").unwrap();
+        let allowed = vec![dir.to_string_lossy().into_owned()];
+        let params: UploadParams = serde_json::from_value(serde_json::json!({
+            "remote_path": "/tmp/a",
+            "local_path": local,
+        }))
+        .unwrap();
+        assert_eq!(params.content(1024, &allowed).await.unwrap(), b"from disk");
+        assert!(params.content(4, &allowed).await.is_err());
+        // Off by default, and confined to the allowed directories
+        let err = params.content(1024, &[]).await.unwrap_err();
+        assert!(err.to_string().contains("--allowed-local-paths"));
+        let elsewhere = vec!["/nonexistent-ssh-mcp".to_string()];
+        let err = params.content(1024, &elsewhere).await.unwrap_err();
+        assert!(err.to_string().contains("outside the allowed local paths"));
+        let params: UploadParams = serde_json::from_value(serde_json::json!({
+            "remote_path": "/tmp/a",
+            "local_path": dir.join("..").join(dir.file_name().unwrap()).join("file"),
+        }))
+        .unwrap();
+        assert_eq!(params.content(1024, &allowed).await.unwrap(), b"from disk");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(params.content(1024, &allowed).await.is_err());
+
+        let json = r#"{"remote_path": "/tmp/a", "content_base64": "!!"}"#;
+        let params: UploadParams = serde_json::from_str(json).unwrap();
+        assert!(params.content(1024, &[]).await.is_err());
+
+        let json = r#"{"remote_path": "/tmp/a"}"#;
+        let params: UploadParams = serde_json::from_str(json).unwrap();
+        assert!(params.content(1024, &[]).await.is_err());
+    }
+
+    #[test]
+    fn test_exec_params_env() {
+        let json = r#"{"command": "env", "env": {"TZ": "UTC", "LANG": "C"}}"#;
+        let params: ExecParams = serde_json::from_str(json).unwrap();
+        let env: Vec<_> = params.env.into_iter().flatten().collect();
+        assert_eq!(
+            env,
+            vec![
+                ("LANG".to_string(), "C".to_string()),
+                ("TZ".to_string(), "UTC".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exec_params_term_width() {
+        let json = r#"{"command": "ps aux", "term_width": 200}"#;
+        let params: ExecParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.term_width, Some(200));
+
+        let json = r#"{"command": "ps aux", "term_width": 70000}"#;
+        assert!(serde_json::from_str::<ExecParams>(json).is_err());
+    }
+
+    #[test]
+    fn test_kill_process_params() {
+        let params: KillProcessParams = serde_json::from_str(r#"{"pid": 4242}"#).unwrap();
+        assert_eq!(params.pid, 4242);
+        assert_eq!(params.signal, None);
+
+        assert!(serde_json::from_str::<KillProcessParams>(r#"{"pid": -1}"#).is_err());
+    }
+
+    #[test]
+    fn test_wait_for_params() {
+        let json = r#"{"command": "systemctl is-active nginx", "interval_ms": 500}"#;
+        let params: WaitForParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.command, "systemctl is-active nginx");
+        assert_eq!(params.interval_ms, Some(500));
+        assert_eq!(params.expect_exit_code, None);
+        assert_eq!(params.timeout_ms, None);
+    }
+
+    #[test]
+    fn test_exec_params_umask() {
+        let json = r#"{"command": "touch key", "umask": "077"}"#;
+        let params: ExecParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.umask.as_deref(), Some("077"));
+    }
+
+    #[test]
+    fn test_exec_params_fail_on_stderr() {
+        let json = r#"{"command": "make", "fail_on_stderr": true}"#;
+        let params: ExecParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.fail_on_stderr, Some(true));
+
+        let params: ExecParams = serde_json::from_str(r#"{"command": "make"}"#).unwrap();
+        assert_eq!(params.fail_on_stderr, None);
+        assert_eq!(params.include_hash, None);
     }
 
     #[test]
@@ -47,4 +554,39 @@ mod tests {
         let params: SudoExecParams = serde_json::from_str(json).unwrap();
         assert_eq!(params.command, "apt update");
     }
+
+    #[test]
+    fn test_command_history_params_deserialize() {
+        let params: CommandHistoryParams = serde_json::from_str(r#"{"limit": 5}"#).unwrap();
+        assert_eq!(params.limit, Some(5));
+
+        let params: CommandHistoryParams = serde_json::from_str("{}").unwrap();
+        assert_eq!(params.limit, None);
+    }
+
+    #[test]
+    fn test_remove_params_deserialize() {
+        let params: RemoveParams = serde_json::from_str(r#"{"path": "/tmp/*.log"}"#).unwrap();
+        assert_eq!(params.path, "/tmp/*.log");
+    }
+
+    #[test]
+    fn test_exec_diff_params_deserialize() {
+        let json = r#"{"key": "nginx", "command": "systemctl status nginx"}"#;
+        let params: ExecDiffParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.key, "nginx");
+        assert_eq!(params.command, "systemctl status nginx");
+        assert_eq!(params.context, None);
+    }
+
+    #[test]
+    fn test_append_file_params_deserialize() {
+        let json = r#"{"path": "/var/log/agent.log", "content": "done\n"}"#;
+        let params: AppendFileParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.path, "/var/log/agent.log");
+        assert_eq!(params.content, "done\n");
+
+        let json = r#"{"path": "/tmp/x"}"#;
+        assert!(serde_json::from_str::<AppendFileParams>(json).is_err());
+    }
 }
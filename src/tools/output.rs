@@ -0,0 +1,150 @@
+//! Post-processing of command output before it is returned
+//!
+//! Commands like `ping` or progress indicators print the same line over and
+//! over; collapsing the runs keeps them from flooding the caller's context.
+//! Very long output is cut down to its head and tail, which hold a command's
+//! context and its conclusion. Output can also be hashed, so callers can tell
+//! whether it changed between runs without comparing it.
+
+use std::borrow::Cow;
+
+use sha2::{Digest, Sha256};
+
+/// Hex-encoded SHA-256 digest of `data`
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::output::sha256_hex;
+///
+/// assert_eq!(
+///     sha256_hex(b"abc"),
+///     "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+/// );
+/// ```
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Collapse runs of identical consecutive lines into one
+///
+/// A line repeated N times in a row becomes `<line> (repeated N times)`.
+/// Blank lines are left alone, and line endings are kept, including a
+/// missing final newline.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::output::collapse_repeats;
+///
+/// let text = "waiting\nwaiting\nwaiting\ndone\n";
+/// assert_eq!(collapse_repeats(text), "waiting (repeated 3 times)\ndone\n");
+/// ```
+pub fn collapse_repeats(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut lines = text.split_inclusive('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let mut count = 1;
+        let mut ending = &line[content.len()..];
+        while let Some(next) = lines.peek() {
+            if content.is_empty() || next.trim_end_matches(['\n', '\r']) != content {
+                break;
+            }
+            ending = &next[content.len()..];
+            count += 1;
+            lines.next();
+        }
+
+        out.push_str(content);
+        if count > 1 {
+            out.push_str(&format!(" (repeated {} times)", count));
+        }
+        out.push_str(ending);
+    }
+
+    out
+}
+
+/// Shorten text of more than `max_lines` lines to its first `head` and last
+/// `tail` lines
+///
+/// The lines in between are replaced with a `... [N lines omitted] ...`
+/// line. Shorter text is returned unchanged.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::output::elide_middle;
+///
+/// let text = "1\n2\n3\n4\n5\n";
+/// assert_eq!(elide_middle(text, 4, 1, 2), "1\n... [2 lines omitted] ...\n4\n5\n");
+/// assert_eq!(elide_middle(text, 5, 1, 2), text);
+/// ```
+pub fn elide_middle(text: &str, max_lines: usize, head: usize, tail: usize) -> Cow<'_, str> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    if lines.len() <= max_lines || lines.len() <= head + tail {
+        return Cow::Borrowed(text);
+    }
+
+    let omitted = lines.len() - head - tail;
+    let mut out: String = lines[..head].concat();
+    out.push_str(&format!("... [{} lines omitted] ...\n", omitted));
+    out.push_str(&lines[lines.len() - tail..].concat());
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapse_repeats() {
+        assert_eq!(collapse_repeats(""), "");
+        assert_eq!(collapse_repeats("a\nb\na\n"), "a\nb\na\n");
+        assert_eq!(
+            collapse_repeats("x\nx\ny\ny\ny\nx\n"),
+            "x (repeated 2 times)\ny (repeated 3 times)\nx\n"
+        );
+    }
+
+    #[test]
+    fn test_elide_middle() {
+        let text: String = (1..=10).map(|n| format!("line {}\n", n)).collect();
+        assert_eq!(
+            elide_middle(&text, 5, 2, 2),
+            "line 1\nline 2\n... [6 lines omitted] ...\nline 9\nline 10\n"
+        );
+        assert_eq!(
+            elide_middle(&text, 5, 0, 1),
+            "... [9 lines omitted] ...\nline 10\n"
+        );
+        // A missing final newline is kept missing
+        assert_eq!(
+            elide_middle("a\nb\nc\nd", 2, 1, 1),
+            "a\n... [2 lines omitted] ...\nd"
+        );
+        assert!(matches!(elide_middle(&text, 10, 2, 2), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(sha256_hex(b"abc").len(), 64);
+    }
+
+    #[test]
+    fn test_collapse_repeats_keeps_line_endings() {
+        // The last line has no newline but still matches the run
+        assert_eq!(collapse_repeats(".\n.\n."), ". (repeated 3 times)");
+        assert_eq!(collapse_repeats("a\r\na\r\n"), "a (repeated 2 times)\r\n");
+        assert_eq!(collapse_repeats("\n\n\nend"), "\n\n\nend");
+    }
+}
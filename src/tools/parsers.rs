@@ -0,0 +1,374 @@
+//! Structured parsing of well-known command output
+//!
+//! When `exec` runs a command a parser is registered for, its stdout is
+//! turned into JSON and returned alongside the raw text, so the agent does
+//! not have to pick apart column layouts itself. Parsers are looked up by
+//! command prefix; the built-in ones cover `df`, `free`, `ip -j` and
+//! `systemctl show`, and embedders can register their own with
+//! [`SshMcpServer::with_parser`](crate::SshMcpServer::with_parser).
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::Arc;
+//! use serde_json::{json, Value};
+//! use ssh_mcp::tools::parsers::{OutputParser, ParserRegistry};
+//!
+//! struct Uptime;
+//!
+//! impl OutputParser for Uptime {
+//!     fn parse(&self, stdout: &str) -> Option<Value> {
+//!         let seconds: f64 = stdout.split_whitespace().next()?.parse().ok()?;
+//!         Some(json!({ "seconds": seconds }))
+//!     }
+//! }
+//!
+//! let mut registry = ParserRegistry::with_builtins();
+//! registry.register("cat /proc/uptime", Arc::new(Uptime));
+//!
+//! let (prefix, parser) = registry.find("cat /proc/uptime").unwrap();
+//! assert_eq!(prefix, "cat /proc/uptime");
+//! assert_eq!(parser.parse("350.5 1200.0\n"), Some(json!({ "seconds": 350.5 })));
+//! ```
+
+use std::sync::Arc;
+
+use serde_json::{Map, Value};
+
+/// Turns the stdout of a command into structured data
+pub trait OutputParser: Send + Sync {
+    /// Parse `stdout`, returning `None` when it is not in the expected format
+    fn parse(&self, stdout: &str) -> Option<Value>;
+}
+
+/// Characters that make a command more than a single invocation, whose
+/// output a parser could not rely on
+const SHELL_OPERATORS: &[char] = &['|', ';', '&', '<', '>', '`', '$', '\n'];
+
+/// Parsers keyed by the command prefix they handle
+#[derive(Clone, Default)]
+pub struct ParserRegistry {
+    parsers: Vec<(String, Arc<dyn OutputParser>)>,
+}
+
+impl ParserRegistry {
+    /// A registry without any parsers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with the built-in parsers
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("df", Arc::new(DfParser));
+        registry.register("free", Arc::new(FreeParser));
+        registry.register("ip -j", Arc::new(JsonParser));
+        registry.register("ip -json", Arc::new(JsonParser));
+        registry.register("systemctl show", Arc::new(SystemctlShowParser));
+        registry
+    }
+
+    /// Register a parser for commands starting with `prefix`
+    ///
+    /// A parser registered for the same prefix replaces the earlier one.
+    pub fn register(&mut self, prefix: impl Into<String>, parser: Arc<dyn OutputParser>) {
+        let prefix = prefix.into();
+        self.parsers.retain(|(existing, _)| *existing != prefix);
+        self.parsers.push((prefix, parser));
+    }
+
+    /// Find the parser for a command, returning it with its prefix
+    ///
+    /// The prefix has to match whole words (`df` matches `df -h` but not
+    /// `dfc`), and the longest matching prefix wins. Commands with pipes,
+    /// redirections, substitutions or several statements are never parsed.
+    pub fn find(&self, command: &str) -> Option<(&str, &dyn OutputParser)> {
+        let command = command.trim();
+        if command.contains(SHELL_OPERATORS) {
+            return None;
+        }
+
+        self.parsers
+            .iter()
+            .filter(|(prefix, _)| {
+                command
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, parser)| (prefix.as_str(), parser.as_ref()))
+    }
+}
+
+/// Turn a column header into a JSON key: `Use%` becomes `use_percent`,
+/// `buff/cache` becomes `buff_cache`
+fn header_key(header: &str) -> String {
+    header
+        .to_ascii_lowercase()
+        .replace('%', "_percent")
+        .replace(['/', '-'], "_")
+}
+
+/// A number when the field is one (with an optional trailing `%`), the
+/// text otherwise, e.g. for sizes such as `20G`
+fn field_value(field: &str) -> Value {
+    let number = field.strip_suffix('%').unwrap_or(field);
+    match number.parse::<u64>() {
+        Ok(n) => n.into(),
+        Err(_) => field.into(),
+    }
+}
+
+/// `df` in any of its column layouts (`-h`, `-T`, `-i`, `-P`)
+///
+/// Returns one object per filesystem, keyed by the column headers.
+/// Filesystem names too long for their column, which GNU df prints on a
+/// line of their own, are joined with the line that follows.
+struct DfParser;
+
+impl OutputParser for DfParser {
+    fn parse(&self, stdout: &str) -> Option<Value> {
+        let mut lines = stdout.lines().filter(|line| !line.trim().is_empty());
+        let header = lines.next()?;
+        let header = header.strip_suffix("Mounted on").unwrap_or(header);
+        let mut keys: Vec<String> = header.split_whitespace().map(header_key).collect();
+        if keys.first().map(String::as_str) != Some("filesystem") {
+            return None;
+        }
+        keys.push("mounted_on".to_string());
+
+        let mut filesystems = Vec::new();
+        let mut pending: Option<&str> = None;
+        for line in lines {
+            let mut fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() == 1 && pending.is_none() {
+                pending = Some(fields[0]);
+                continue;
+            }
+            if let Some(name) = pending.take() {
+                fields.insert(0, name);
+            }
+            if fields.len() < keys.len() {
+                return None;
+            }
+
+            // The mount point is last and may contain spaces
+            let mount = fields.split_off(keys.len() - 1).join(" ");
+            let mut entry: Map<String, Value> = keys
+                .iter()
+                .zip(&fields)
+                .map(|(key, field)| {
+                    let value = match key.as_str() {
+                        "filesystem" | "type" => Value::from(*field),
+                        _ => field_value(field),
+                    };
+                    (key.clone(), value)
+                })
+                .collect();
+            entry.insert("mounted_on".to_string(), mount.into());
+            filesystems.push(Value::Object(entry));
+        }
+
+        Some(Value::Array(filesystems))
+    }
+}
+
+/// `free` in any unit: one object per row (`mem`, `swap`), keyed by the
+/// column headers
+struct FreeParser;
+
+impl OutputParser for FreeParser {
+    fn parse(&self, stdout: &str) -> Option<Value> {
+        let mut lines = stdout.lines().filter(|line| !line.trim().is_empty());
+        let keys: Vec<String> = lines.next()?.split_whitespace().map(header_key).collect();
+        if keys.first().map(String::as_str) != Some("total") {
+            return None;
+        }
+
+        let mut rows = Map::new();
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let label = fields.next()?.strip_suffix(':')?;
+            let row: Map<String, Value> =
+                keys.iter().cloned().zip(fields.map(field_value)).collect();
+            rows.insert(label.to_ascii_lowercase(), Value::Object(row));
+        }
+
+        (!rows.is_empty()).then_some(Value::Object(rows))
+    }
+}
+
+/// Commands that print JSON themselves, such as `ip -j`
+struct JsonParser;
+
+impl OutputParser for JsonParser {
+    fn parse(&self, stdout: &str) -> Option<Value> {
+        serde_json::from_str(stdout).ok()
+    }
+}
+
+/// `systemctl show`: `Key=value` lines become an object, several units
+/// (separated by blank lines) an array of objects
+struct SystemctlShowParser;
+
+impl OutputParser for SystemctlShowParser {
+    fn parse(&self, stdout: &str) -> Option<Value> {
+        let mut units = Vec::new();
+        let mut current = Map::new();
+        for line in stdout.lines() {
+            if line.trim().is_empty() {
+                if !current.is_empty() {
+                    units.push(Value::Object(std::mem::take(&mut current)));
+                }
+                continue;
+            }
+            let (key, value) = line.split_once('=')?;
+            current.insert(key.to_string(), value.into());
+        }
+        if !current.is_empty() {
+            units.push(Value::Object(current));
+        }
+
+        match units.len() {
+            0 => None,
+            1 => units.pop(),
+            _ => Some(Value::Array(units)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(command: &str, stdout: &str) -> Option<Value> {
+        let registry = ParserRegistry::with_builtins();
+        let (_, parser) = registry.find(command)?;
+        parser.parse(stdout)
+    }
+
+    #[test]
+    fn test_find() {
+        let registry = ParserRegistry::with_builtins();
+        assert_eq!(registry.find("df -h").unwrap().0, "df");
+        assert_eq!(registry.find("  free -m ").unwrap().0, "free");
+        assert_eq!(registry.find("ip -j addr").unwrap().0, "ip -j");
+        assert_eq!(registry.find("ip -json link").unwrap().0, "ip -json");
+        assert_eq!(
+            registry.find("systemctl show nginx").unwrap().0,
+            "systemctl show"
+        );
+
+        assert!(registry.find("dfc").is_none());
+        assert!(registry.find("ip addr").is_none());
+        assert!(registry.find("df -h | grep sda").is_none());
+        assert!(registry.find("df; rm -rf /tmp/x").is_none());
+        assert!(registry.find("df $(echo /)").is_none());
+    }
+
+    #[test]
+    fn test_register_longest_prefix_wins() {
+        struct Fixed(&'static str);
+        impl OutputParser for Fixed {
+            fn parse(&self, _stdout: &str) -> Option<Value> {
+                Some(self.0.into())
+            }
+        }
+
+        let mut registry = ParserRegistry::with_builtins();
+        registry.register("df -i", Arc::new(Fixed("inodes")));
+        let (prefix, parser) = registry.find("df -i /").unwrap();
+        assert_eq!(prefix, "df -i");
+        assert_eq!(parser.parse(""), Some(json!("inodes")));
+
+        // Registering the same prefix again replaces the parser
+        registry.register("df -i", Arc::new(Fixed("replaced")));
+        let (_, parser) = registry.find("df -i").unwrap();
+        assert_eq!(parser.parse(""), Some(json!("replaced")));
+    }
+
+    #[test]
+    fn test_df() {
+        let stdout = "Filesystem      Size  Used Avail Use% Mounted on
+/dev/sda1        20G  8.1G   11G  44% /
+/dev/mapper/very-long-volume-name
+                 100G   50G   50G  50% /mnt/backup disk
+";
+        assert_eq!(
+            parse("df -h", stdout).unwrap(),
+            json!([
+                {
+                    "filesystem": "/dev/sda1",
+                    "size": "20G",
+                    "used": "8.1G",
+                    "avail": "11G",
+                    "use_percent": 44,
+                    "mounted_on": "/",
+                },
+                {
+                    "filesystem": "/dev/mapper/very-long-volume-name",
+                    "size": "100G",
+                    "used": "50G",
+                    "avail": "50G",
+                    "use_percent": 50,
+                    "mounted_on": "/mnt/backup disk",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_df_with_type() {
+        let stdout = "Filesystem     Type 1K-blocks    Used Available Use% Mounted on
+tmpfs          tmpfs   1629564    2036   1627528   1% /run
+";
+        let parsed = parse("df -T", stdout).unwrap();
+        assert_eq!(parsed[0]["type"], "tmpfs");
+        assert_eq!(parsed[0]["1k_blocks"], 1629564);
+        assert_eq!(parsed[0]["mounted_on"], "/run");
+
+        assert_eq!(parse("df", "df: /x: No such file or directory\n"), None);
+    }
+
+    #[test]
+    fn test_free() {
+        let stdout =
+            "               total        used        free      shared  buff/cache   available
+Mem:         8041224     2104532      891472      123456     5045220     5512344
+Swap:        2097148           0     2097148
+";
+        let parsed = parse("free", stdout).unwrap();
+        assert_eq!(parsed["mem"]["total"], 8041224);
+        assert_eq!(parsed["mem"]["buff_cache"], 5045220);
+        assert_eq!(parsed["swap"]["used"], 0);
+        assert!(parsed["swap"].get("available").is_none());
+
+        let parsed = parse("free -h", stdout.replace("8041224", "7.7Gi").as_str()).unwrap();
+        assert_eq!(parsed["mem"]["total"], "7.7Gi");
+    }
+
+    #[test]
+    fn test_ip_json() {
+        let stdout = r#"[{"ifindex":1,"ifname":"lo"}]"#;
+        assert_eq!(
+            parse("ip -j addr", stdout).unwrap(),
+            json!([{"ifindex": 1, "ifname": "lo"}])
+        );
+        assert_eq!(parse("ip -j addr", "Object \"x\" is unknown"), None);
+    }
+
+    #[test]
+    fn test_systemctl_show() {
+        let stdout = "Id=nginx.service\nActiveState=active\nExecStart={ path=/usr/sbin/nginx ; }\n";
+        let parsed = parse("systemctl show nginx", stdout).unwrap();
+        assert_eq!(parsed["ActiveState"], "active");
+        assert_eq!(parsed["ExecStart"], "{ path=/usr/sbin/nginx ; }");
+
+        let stdout = "Id=a.service\nActiveState=active\n\nId=b.service\nActiveState=failed\n";
+        let parsed = parse("systemctl show a b", stdout).unwrap();
+        assert_eq!(parsed[1]["ActiveState"], "failed");
+
+        assert_eq!(parse("systemctl show", ""), None);
+    }
+}
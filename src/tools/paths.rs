@@ -0,0 +1,312 @@
+//! Remote path helpers for the file tools
+//!
+//! Provides glob matching for the final path component and the
+//! allowed-paths jail that every file operation is checked against.
+
+/// Default cap on the number of files a single glob may expand to
+pub const DEFAULT_MAX_GLOB_MATCHES: usize = 100;
+
+/// Check whether a path contains glob metacharacters
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Split a path into its directory and final component
+///
+/// A path without a slash is relative to the SFTP working directory (the
+/// user's home), which is represented by `"."`.
+pub fn split_path(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(pos) => (&path[..pos], &path[pos + 1..]),
+        None => (".", path),
+    }
+}
+
+/// Expand a leading `~` or `$HOME` against the remote home directory
+///
+/// SFTP has no shell, so these would otherwise be taken literally. Other
+/// users' homes (`~user`) are left alone, as are references elsewhere in
+/// the path. Returns `None` when there is nothing to expand.
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::tools::paths::expand_home;
+///
+/// assert_eq!(expand_home("~/.bashrc", "/home/deploy"), Some("/home/deploy/.bashrc".to_string()));
+/// assert_eq!(expand_home("/etc/hosts", "/home/deploy"), None);
+/// ```
+pub fn expand_home(path: &str, home: &str) -> Option<String> {
+    let rest = ["~", "${HOME}", "$HOME"]
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix))?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    let home = if rest.is_empty() {
+        home
+    } else {
+        home.trim_end_matches('/')
+    };
+    Some(format!("{}{}", home, rest))
+}
+
+/// Match a single path component against a glob pattern
+///
+/// Supports `*` (any run of characters), `?` (one character) and bracket
+/// classes such as `[abc]`, `[a-z]` and `[!0-9]`. A leading `.` in `name`
+/// must be matched explicitly, as in the shell.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    wildcard_match(pattern, name)
+}
+
+/// Match arbitrary text against a wildcard pattern
+///
+/// Same syntax as [`glob_match`] but without the special case for a leading
+/// `.`, for matching things other than file names.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+/// Iterative wildcard matching
+///
+/// Only the position after the most recent `*` is kept to backtrack to,
+/// which is enough because every other element matches exactly one
+/// character. This keeps matching linear in the pattern times the name,
+/// where trying every split for every `*` is exponential in their number.
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    loop {
+        if pattern.get(p) == Some(&'*') {
+            star = Some((p + 1, n));
+            p += 1;
+            continue;
+        }
+        match name.get(n) {
+            Some(&c) => {
+                if let Some(width) = match_one(&pattern[p..], c) {
+                    p += width;
+                    n += 1;
+                    continue;
+                }
+            }
+            None if p == pattern.len() => return true,
+            None => {}
+        }
+        // Let the last `*` take one more character and try again
+        match star {
+            Some((after, taken)) if taken < name.len() => {
+                star = Some((after, taken + 1));
+                p = after;
+                n = taken + 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Match one character against the pattern element at the start of
+/// `pattern`, returning the number of pattern characters it spans
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match *pattern.first()? {
+        '?' => Some(1),
+        '[' => match match_class(&pattern[1..]) {
+            Some((matches, rest)) => matches(c).then_some(1 + rest),
+            // An unterminated class is a literal '['
+            None => (c == '[').then_some(1),
+        },
+        p => (p == c).then_some(1),
+    }
+}
+
+/// Parse a bracket class body, returning a matcher and the number of
+/// pattern characters consumed (including the closing `]`)
+fn match_class(class: &[char]) -> Option<(impl Fn(char) -> bool + '_, usize)> {
+    let negated = matches!(class.first(), Some('!') | Some('^'));
+    let start = usize::from(negated);
+    // A ']' right after the opening bracket is part of the class
+    let end = class
+        .iter()
+        .skip(start + 1)
+        .position(|&c| c == ']')
+        .map(|pos| pos + start + 1)?;
+    let body = &class[start..end];
+
+    let matcher = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < body.len() {
+            if i + 2 < body.len() && body[i + 1] == '-' {
+                found |= body[i] <= c && c <= body[i + 2];
+                i += 3;
+            } else {
+                found |= body[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+
+    Some((matcher, end + 1))
+}
+
+/// Lexically normalize an absolute remote path
+///
+/// Collapses repeated slashes and resolves `.` and `..` components. Returns
+/// `None` for relative paths, which cannot be checked against the jail
+/// without knowing the remote working directory.
+pub fn normalize_remote_path(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            other => parts.push(other),
+        }
+    }
+
+    Some(format!("/{}", parts.join("/")))
+}
+
+/// Check a remote path against the allowed-paths jail
+///
+/// An empty `allowed` list permits every path. Otherwise the path must be
+/// absolute and, once normalized, equal to or inside one of the allowed
+/// directories.
+pub fn is_path_allowed(path: &str, allowed: &[String]) -> bool {
+    if allowed.is_empty() {
+        return true;
+    }
+
+    let Some(path) = normalize_remote_path(path) else {
+        return false;
+    };
+
+    allowed.iter().any(|root| {
+        let Some(root) = normalize_remote_path(root) else {
+            return false;
+        };
+        root == "/"
+            || path == root
+            || path
+                .strip_prefix(&root)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_home() {
+        let home = "/home/deploy";
+        assert_eq!(expand_home("~", home).as_deref(), Some("/home/deploy"));
+        assert_eq!(
+            expand_home("~/a/b", home).as_deref(),
+            Some("/home/deploy/a/b")
+        );
+        assert_eq!(
+            expand_home("$HOME/x", home).as_deref(),
+            Some("/home/deploy/x")
+        );
+        assert_eq!(
+            expand_home("${HOME}", home).as_deref(),
+            Some("/home/deploy")
+        );
+        assert_eq!(expand_home("~/x", "/").as_deref(), Some("/x"));
+
+        assert_eq!(expand_home("~root/x", home), None);
+        assert_eq!(expand_home("$HOMEDIR/x", home), None);
+        assert_eq!(expand_home("/tmp/~/x", home), None);
+        assert_eq!(expand_home("relative", home), None);
+    }
+
+    #[test]
+    fn test_is_glob() {
+        assert!(is_glob("/var/log/*.log"));
+        assert!(is_glob("file?.txt"));
+        assert!(is_glob("/tmp/[ab].txt"));
+        assert!(!is_glob("/etc/hosts"));
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("/var/log/*.log"), ("/var/log", "*.log"));
+        assert_eq!(split_path("/*.log"), ("/", "*.log"));
+        assert_eq!(split_path("*.log"), (".", "*.log"));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.log", "syslog.log"));
+        assert!(!glob_match("*.log", "syslog.log.1"));
+        assert!(glob_match("*.log*", "syslog.log.1"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("file?.txt", "file10.txt"));
+        assert!(glob_match("[ab].txt", "a.txt"));
+        assert!(!glob_match("[ab].txt", "c.txt"));
+        assert!(glob_match("log[0-9]", "log7"));
+        assert!(!glob_match("log[!0-9]", "log7"));
+        assert!(glob_match("[]x]", "]"));
+        assert!(glob_match("a[b", "a[b"));
+    }
+
+    #[test]
+    fn test_wildcard_match_many_stars() {
+        let name = "a".repeat(10_000);
+        let start = std::time::Instant::now();
+        assert!(!wildcard_match("*a*a*a*a*a*a*a*a*a*a*b", &name));
+        assert!(wildcard_match("*a*a*a*a*a*a*a*a*a*a*", &name));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        assert!(wildcard_match("*b*c", "abxbc"));
+        assert!(!wildcard_match("*b*c", "abxbcd"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("?", ""));
+        assert!(wildcard_match("a*[0-9]", "ab12"));
+    }
+
+    #[test]
+    fn test_glob_match_hidden_files() {
+        assert!(!glob_match("*", ".bashrc"));
+        assert!(glob_match(".*", ".bashrc"));
+    }
+
+    #[test]
+    fn test_normalize_remote_path() {
+        assert_eq!(
+            normalize_remote_path("/var//log/./nginx/../syslog"),
+            Some("/var/log/syslog".to_string())
+        );
+        assert_eq!(normalize_remote_path("/../.."), Some("/".to_string()));
+        assert_eq!(normalize_remote_path("relative/path"), None);
+    }
+
+    #[test]
+    fn test_is_path_allowed() {
+        let allowed = vec!["/var/log".to_string(), "/tmp/".to_string()];
+
+        assert!(is_path_allowed("/var/log/syslog", &allowed));
+        assert!(is_path_allowed("/tmp", &allowed));
+        assert!(!is_path_allowed("/var/logs/syslog", &allowed));
+        assert!(!is_path_allowed("/var/log/../../etc/shadow", &allowed));
+        assert!(!is_path_allowed("syslog", &allowed));
+
+        assert!(is_path_allowed("anything", &[]));
+    }
+}
@@ -0,0 +1,145 @@
+//! Helpers for the `chmod` and `chown` tools
+//!
+//! Modes are set over SFTP. Ownership needs `chown` on the remote host
+//! instead: SFTP only takes numeric ids, and giving files away usually
+//! requires root, which the command can get through sudo or the su shell.
+
+use crate::error::{Result, SshMcpError};
+use crate::ssh::sanitize::escape_command_for_shell;
+
+/// Longest user or group name accepted
+const MAX_ACCOUNT_NAME_LEN: usize = 32;
+
+/// Parse an octal file mode such as `644`, `0755` or `2775`
+///
+/// Symbolic modes (`u+x`) are not accepted; the value replaces the whole
+/// mode, including the setuid, setgid and sticky bits.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::perms::{format_mode, parse_mode};
+///
+/// assert_eq!(parse_mode("755").unwrap(), 0o755);
+/// assert_eq!(format_mode(parse_mode("02775").unwrap()), "2775");
+/// assert!(parse_mode("u+x").is_err());
+/// ```
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = match mode.strip_prefix('0') {
+        Some(rest) if rest.len() >= 3 => rest,
+        _ => mode,
+    };
+    match digits.len() {
+        3 | 4 => u32::from_str_radix(digits, 8).ok(),
+        _ => None,
+    }
+    .filter(|_| digits.bytes().all(|b| (b'0'..=b'7').contains(&b)))
+    .ok_or_else(|| {
+        SshMcpError::invalid_params(format!(
+            "Invalid mode {:?}: expected an octal mode such as 644, 0755 or 2775",
+            mode
+        ))
+    })
+}
+
+/// Format the permission bits of a mode as four octal digits
+pub fn format_mode(mode: u32) -> String {
+    format!("{:04o}", mode & 0o7777)
+}
+
+/// Check a user or group name given to `chown`
+///
+/// Accepts numeric ids and the portable name set: letters, digits, `_`, `.`
+/// and `-`, not starting with `-` or `.`, with an optional trailing `$` for
+/// machine accounts.
+pub fn validate_account_name(kind: &str, name: &str) -> Result<()> {
+    let base = name.strip_suffix('$').unwrap_or(name);
+    let valid = !base.is_empty()
+        && name.len() <= MAX_ACCOUNT_NAME_LEN
+        && !base.starts_with(['-', '.'])
+        && base
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if valid {
+        return Ok(());
+    }
+    Err(SshMcpError::invalid_params(format!(
+        "Invalid {} {:?}: expected a name (letters, digits, '_', '.' and '-') or a numeric id",
+        kind, name
+    )))
+}
+
+/// Build the command giving `path` to `owner` and optionally `group`
+///
+/// `-h` changes a symlink itself rather than its target, matching how the
+/// allowed-paths check judges the last path component.
+///
+/// # Example
+///
+/// ```
+/// use ssh_mcp::tools::perms::chown_command;
+///
+/// let command = chown_command("/srv/app/data", "www-data", Some("www-data")).unwrap();
+/// assert_eq!(command, "chown -h -- www-data:www-data '/srv/app/data'");
+/// ```
+pub fn chown_command(path: &str, owner: &str, group: Option<&str>) -> Result<String> {
+    validate_account_name("owner", owner)?;
+    let spec = match group {
+        Some(group) => {
+            validate_account_name("group", group)?;
+            format!("{}:{}", owner, group)
+        }
+        None => owner.to_string(),
+    };
+    Ok(format!(
+        "chown -h -- {} '{}'",
+        spec,
+        escape_command_for_shell(path)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("644").unwrap(), 0o644);
+        assert_eq!(parse_mode("0600").unwrap(), 0o600);
+        assert_eq!(parse_mode("4755").unwrap(), 0o4755);
+        assert_eq!(parse_mode("01777").unwrap(), 0o1777);
+        for bad in ["", "7", "64", "0644x", "888", "12345", "+x", "u=rw", " 644"] {
+            assert!(parse_mode(bad).is_err(), "{:?}", bad);
+        }
+        assert_eq!(format_mode(0o100644), "0644");
+    }
+
+    #[test]
+    fn test_validate_account_name() {
+        for good in ["root", "www-data", "svc_app", "1000", "host$", "a.b"] {
+            assert!(validate_account_name("owner", good).is_ok(), "{:?}", good);
+        }
+        for bad in [
+            "",
+            "-r",
+            ".hidden",
+            "a b",
+            "a:b",
+            "a;id",
+            "$",
+            &"x".repeat(33),
+        ] {
+            assert!(validate_account_name("owner", bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_chown_command() {
+        assert_eq!(
+            chown_command("/tmp/it's", "deploy", None).unwrap(),
+            "chown -h -- deploy '/tmp/it'\"'\"'s'"
+        );
+        let err = chown_command("/tmp/x", "deploy", Some("adm;reboot")).unwrap_err();
+        assert!(err.to_string().contains("Invalid group"));
+    }
+}
@@ -0,0 +1,204 @@
+//! Helpers for the `list-ports` tool
+//!
+//! Listening sockets come from `ss`, or from `netstat` on hosts without
+//! iproute2. Both print one socket per row in different layouts, which are
+//! parsed into the same structured entries.
+
+use serde::Serialize;
+
+/// Printed to stderr when the host has neither `ss` nor `netstat`
+///
+/// The su shell folds stderr into stdout, so elevated runs report it there.
+pub const NO_PORT_TOOL_MESSAGE: &str = "ssh-mcp: neither ss nor netstat is installed";
+
+/// Command listing listening TCP and bound UDP sockets with their processes
+///
+/// Process names only show for sockets the user may inspect, which for
+/// other users' services means running as root. The failure status comes
+/// from a subshell, as a bare `exit` would end the su shell it is typed
+/// into.
+pub const PORTS_COMMAND: &str = "if command -v ss >/dev/null 2>&1; then ss -tulnp; \
+     elif command -v netstat >/dev/null 2>&1; then netstat -tulnp; \
+     else echo 'ssh-mcp: neither ss nor netstat is installed' >&2; (exit 127); fi";
+
+/// One listening socket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortInfo {
+    /// `tcp` or `udp`, for IPv4 and IPv6 alike
+    pub proto: String,
+
+    /// Address the socket is bound to, `*` or `0.0.0.0` for all
+    pub local_addr: String,
+    pub local_port: u16,
+
+    /// `LISTEN` for TCP, `UNCONN` for UDP
+    pub state: String,
+
+    /// Owning process, when the listing shows it
+    pub pid: Option<u32>,
+    pub process: Option<String>,
+}
+
+/// Parse the output of [`PORTS_COMMAND`], from either `ss` or `netstat`
+///
+/// Headers and rows that don't parse are skipped. Sockets owned by several
+/// processes (such as pre-forked servers) are reported with the first one.
+pub fn parse_ports_output(stdout: &str) -> Vec<PortInfo> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.first() {
+                Some(&("tcp" | "udp"))
+                    if fields.get(1).is_some_and(|f| f.parse::<u64>().is_err()) =>
+                {
+                    parse_ss_row(&fields)
+                }
+                Some(&("tcp" | "tcp6" | "udp" | "udp6")) => parse_netstat_row(&fields),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `Netid State Recv-Q Send-Q Local:Port Peer:Port [Process]`
+fn parse_ss_row(fields: &[&str]) -> Option<PortInfo> {
+    let (local_addr, local_port) = split_addr_port(fields.get(4)?)?;
+    let (pid, process) = fields
+        .get(6)
+        .and_then(|users| parse_ss_users(users))
+        .unzip();
+    Some(PortInfo {
+        proto: fields[0].to_string(),
+        local_addr,
+        local_port,
+        state: fields[1].to_string(),
+        pid,
+        process,
+    })
+}
+
+/// `Proto Recv-Q Send-Q Local Foreign [State] PID/Program`
+///
+/// UDP rows leave the state column empty.
+fn parse_netstat_row(fields: &[&str]) -> Option<PortInfo> {
+    let proto = fields[0].trim_end_matches('6');
+    let (local_addr, local_port) = split_addr_port(fields.get(3)?)?;
+    let (state, program) = match (proto, fields.len()) {
+        ("udp", 6) => ("UNCONN", fields[5]),
+        ("udp", 5) => ("UNCONN", "-"),
+        (_, 7) => (fields[5], fields[6]),
+        (_, 6) => (fields[5], "-"),
+        _ => return None,
+    };
+    let (pid, process) = program
+        .split_once('/')
+        .and_then(|(pid, name)| Some((pid.parse().ok()?, name.to_string())))
+        .unzip();
+    Some(PortInfo {
+        proto: proto.to_string(),
+        local_addr,
+        local_port,
+        state: state.to_string(),
+        pid,
+        process,
+    })
+}
+
+/// First process in an ss `users:` column
+///
+/// Current ss prints `users:(("sshd",pid=801,fd=3))`, older versions
+/// `users:(("sshd",801,3))`.
+fn parse_ss_users(users: &str) -> Option<(u32, String)> {
+    let entry = users.strip_prefix("users:((")?;
+    let (name, rest) = entry.strip_prefix('"')?.split_once('"')?;
+    let pid = rest.strip_prefix(',')?.split([',', ')']).next()?;
+    let pid = pid.strip_prefix("pid=").unwrap_or(pid).parse().ok()?;
+    Some((pid, name.to_string()))
+}
+
+/// Split `addr:port`, dropping IPv6 brackets and any `%interface` suffix
+fn split_addr_port(field: &str) -> Option<(String, u16)> {
+    let (addr, port) = field.rsplit_once(':')?;
+    let addr = addr.trim_start_matches('[').trim_end_matches(']');
+    let addr = addr.split_once('%').map_or(addr, |(addr, _)| addr);
+    Some((addr.to_string(), port.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SS_OUTPUT: &str = r#"Netid State  Recv-Q Send-Q      Local Address:Port  Peer Address:Port Process
+udp   UNCONN 0      0       127.0.0.53%lo:53          0.0.0.0:*     users:(("systemd-resolve",pid=612,fd=13))
+tcp   LISTEN 0      4096          0.0.0.0:22          0.0.0.0:*     users:(("sshd",pid=801,fd=3))
+tcp   LISTEN 0      511              [::]:80             [::]:*     users:(("nginx",pid=900,fd=6),("nginx",pid=899,fd=6))
+tcp   LISTEN 0      128         127.0.0.1:5432        0.0.0.0:*
+"#;
+
+    const NETSTAT_OUTPUT: &str = "Active Internet connections (only servers)
+Proto Recv-Q Send-Q Local Address           Foreign Address         State       PID/Program name
+tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN      801/sshd
+tcp6       0      0 :::80                   :::*                    LISTEN      -
+udp        0      0 127.0.0.53:53           0.0.0.0:*                           612/systemd-resolve
+";
+
+    #[test]
+    fn test_parse_ss_output() {
+        let ports = parse_ports_output(SS_OUTPUT);
+        assert_eq!(ports.len(), 4);
+        assert_eq!(
+            ports[0],
+            PortInfo {
+                proto: "udp".to_string(),
+                local_addr: "127.0.0.53".to_string(),
+                local_port: 53,
+                state: "UNCONN".to_string(),
+                pid: Some(612),
+                process: Some("systemd-resolve".to_string()),
+            }
+        );
+        assert_eq!(ports[2].local_addr, "::");
+        assert_eq!(ports[2].pid, Some(900));
+        // Without root, other users' processes are not shown
+        assert_eq!(ports[3].local_port, 5432);
+        assert_eq!(ports[3].process, None);
+    }
+
+    #[test]
+    fn test_parse_netstat_output() {
+        let ports = parse_ports_output(NETSTAT_OUTPUT);
+        assert_eq!(ports.len(), 3);
+        assert_eq!(
+            ports[0],
+            PortInfo {
+                proto: "tcp".to_string(),
+                local_addr: "0.0.0.0".to_string(),
+                local_port: 22,
+                state: "LISTEN".to_string(),
+                pid: Some(801),
+                process: Some("sshd".to_string()),
+            }
+        );
+        assert_eq!(ports[1].proto, "tcp");
+        assert_eq!(ports[1].local_addr, "::");
+        assert_eq!(ports[1].pid, None);
+        assert_eq!(ports[2].state, "UNCONN");
+        assert_eq!(ports[2].process.as_deref(), Some("systemd-resolve"));
+    }
+
+    #[test]
+    fn test_ports_command_reports_missing_tools() {
+        assert!(PORTS_COMMAND.contains(NO_PORT_TOOL_MESSAGE));
+        assert!(!PORTS_COMMAND.replace("(exit 127)", "").contains("exit"));
+    }
+
+    #[test]
+    fn test_parse_ss_users_old_format() {
+        assert_eq!(
+            parse_ss_users(r#"users:(("sshd",801,3))"#),
+            Some((801, "sshd".to_string()))
+        );
+        assert_eq!(parse_ss_users("-"), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! Helpers for the `list-processes` and `kill-process` tools
+//!
+//! Process listings come from `ps`, whose rows are parsed into structured
+//! entries so callers don't have to pick apart its column layout.
+
+use serde::Serialize;
+
+use crate::error::{Result, SshMcpError};
+
+/// Command listing every process, one row each without a header
+///
+/// The procps form is tried first. Other `ps` implementations (BSD,
+/// busybox) lack `--no-headers`, so the fallback suppresses the header the
+/// POSIX way, with an empty title for every column.
+pub const PS_COMMAND: &str = "ps -eo pid,ppid,user,%cpu,%mem,comm,args --no-headers 2>/dev/null \
+     || ps -A -o pid= -o ppid= -o user= -o pcpu= -o pmem= -o comm= -o args=";
+
+/// Signals accepted by `kill-process`, by name
+const SIGNALS: &[&str] = &[
+    "HUP", "INT", "QUIT", "KILL", "USR1", "USR2", "TERM", "STOP", "CONT",
+];
+
+/// One row of the process listing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: u32,
+    pub user: String,
+
+    /// CPU usage in percent
+    pub cpu: f64,
+
+    /// Memory usage in percent
+    pub mem: f64,
+
+    /// Executable name (`comm`)
+    pub name: String,
+
+    /// Full command line (`args`)
+    pub command: String,
+}
+
+/// Parse the output of [`PS_COMMAND`], keeping processes whose name or
+/// command line contains `filter`
+///
+/// Rows that don't parse (such as a stray header) are skipped. A name with
+/// spaces cannot be told apart from the command line, so only its first
+/// word ends up in `name`.
+pub fn parse_ps_output(stdout: &str, filter: Option<&str>) -> Vec<ProcessInfo> {
+    stdout
+        .lines()
+        .filter_map(parse_ps_line)
+        .filter(|process| {
+            filter.is_none_or(|filter| {
+                process.name.contains(filter) || process.command.contains(filter)
+            })
+        })
+        .collect()
+}
+
+fn parse_ps_line(line: &str) -> Option<ProcessInfo> {
+    let mut rest = line.trim();
+    let mut next = || {
+        let (field, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        rest = tail.trim_start();
+        (!field.is_empty()).then_some(field)
+    };
+
+    let pid = next()?.parse().ok()?;
+    let ppid = next()?.parse().ok()?;
+    let user = next()?.to_string();
+    let cpu = next()?.parse().ok()?;
+    let mem = next()?.parse().ok()?;
+    let name = next()?.to_string();
+    let command = match rest {
+        "" => name.clone(),
+        args => args.to_string(),
+    };
+
+    Some(ProcessInfo {
+        pid,
+        ppid,
+        user,
+        cpu,
+        mem,
+        name,
+        command,
+    })
+}
+
+/// Normalize a signal given as a name (`TERM`, `SIGKILL`) or number (`9`)
+///
+/// # Examples
+///
+/// ```
+/// use ssh_mcp::tools::processes::normalize_signal;
+///
+/// assert_eq!(normalize_signal("sigterm").unwrap(), "TERM");
+/// assert_eq!(normalize_signal("9").unwrap(), "9");
+/// assert!(normalize_signal("TERM; reboot").is_err());
+/// ```
+pub fn normalize_signal(signal: &str) -> Result<String> {
+    let upper = signal.trim().to_ascii_uppercase();
+    if let Ok(number) = upper.parse::<u8>() {
+        if (1..=64).contains(&number) {
+            return Ok(number.to_string());
+        }
+    }
+
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    if SIGNALS.contains(&name) {
+        return Ok(name.to_string());
+    }
+
+    Err(SshMcpError::invalid_params(format!(
+        "Unknown signal {:?}: use a number from 1 to 64 or one of {}",
+        signal,
+        SIGNALS.join(", ")
+    )))
+}
+
+/// Build the command sending `signal` to `pid`
+///
+/// Process 0 (the caller's process group) and 1 (init) are refused.
+pub fn kill_command(pid: u32, signal: &str) -> Result<String> {
+    if pid <= 1 {
+        return Err(SshMcpError::invalid_params(format!(
+            "Refusing to signal pid {}",
+            pid
+        )));
+    }
+    Ok(format!("kill -{} {}", normalize_signal(signal)?, pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PS_OUTPUT: &str = "    1     0 root      0.0  0.1 systemd         /sbin/init splash
+  812     1 www-data  12.5  3.4 nginx           nginx: worker process
+ 4242   812 deploy     0.0  0.0 sleep           sleep 600
+";
+
+    #[test]
+    fn test_parse_ps_output() {
+        let processes = parse_ps_output(PS_OUTPUT, None);
+        assert_eq!(processes.len(), 3);
+        assert_eq!(
+            processes[1],
+            ProcessInfo {
+                pid: 812,
+                ppid: 1,
+                user: "www-data".to_string(),
+                cpu: 12.5,
+                mem: 3.4,
+                name: "nginx".to_string(),
+                command: "nginx: worker process".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ps_output_filter() {
+        let processes = parse_ps_output(PS_OUTPUT, Some("sleep"));
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].pid, 4242);
+
+        // The command line matches too
+        let processes = parse_ps_output(PS_OUTPUT, Some("splash"));
+        assert_eq!(processes[0].name, "systemd");
+
+        assert!(parse_ps_output(PS_OUTPUT, Some("postgres")).is_empty());
+    }
+
+    #[test]
+    fn test_parse_ps_skips_bad_rows() {
+        let output = "  PID  PPID USER %CPU %MEM COMMAND COMMAND\n   7   1 root 0.0 0.0 kthreadd\n";
+        let processes = parse_ps_output(output, None);
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].command, "kthreadd");
+    }
+
+    #[test]
+    fn test_normalize_signal() {
+        assert_eq!(normalize_signal("TERM").unwrap(), "TERM");
+        assert_eq!(normalize_signal("SIGKILL").unwrap(), "KILL");
+        assert_eq!(normalize_signal("hup").unwrap(), "HUP");
+        assert_eq!(normalize_signal("15").unwrap(), "15");
+        assert!(normalize_signal("0").is_err());
+        assert!(normalize_signal("65").is_err());
+        assert!(normalize_signal("SIGFOO").is_err());
+        assert!(normalize_signal("").is_err());
+    }
+
+    #[test]
+    fn test_kill_command() {
+        assert_eq!(kill_command(4242, "TERM").unwrap(), "kill -TERM 4242");
+        assert_eq!(kill_command(4242, "9").unwrap(), "kill -9 4242");
+        assert!(kill_command(1, "TERM").is_err());
+        assert!(kill_command(0, "TERM").is_err());
+    }
+}
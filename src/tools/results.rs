@@ -0,0 +1,191 @@
+//! Stored command output
+//!
+//! exec calls made with `keep_output` report a `result_id`. A later call can
+//! name it in `stdin_from` to get that command's stdout on its standard
+//! input, so large intermediate output is piped on the server instead of
+//! passing through the client. exec-diff keeps the last output of each key
+//! to compare the next run against. Both keep only the most recent outputs,
+//! within a byte budget.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Number of outputs kept for `stdin_from`
+pub const MAX_STORED_RESULTS: usize = 16;
+
+/// Most bytes of output kept across all stored results (32 MiB)
+pub const MAX_STORED_RESULT_BYTES: usize = 32 * 1024 * 1024;
+
+/// Bounded, thread-safe store of outputs under string keys
+///
+/// The oldest outputs are dropped to stay within the entry cap and byte
+/// budget it was created with.
+#[derive(Debug)]
+pub struct BoundedOutputs {
+    max_entries: usize,
+    max_bytes: usize,
+    inner: Mutex<Stored>,
+}
+
+#[derive(Debug, Default)]
+struct Stored {
+    bytes: usize,
+    entries: VecDeque<(String, String)>,
+}
+
+impl BoundedOutputs {
+    /// Create an empty store keeping at most `max_entries` outputs and
+    /// `max_bytes` bytes
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            inner: Mutex::new(Stored::default()),
+        }
+    }
+
+    /// Keep `output` under `key`, returning the output it replaces
+    ///
+    /// The key becomes the most recent one. An output larger than the byte
+    /// budget on its own is not kept, and the key is then forgotten.
+    pub fn insert(&self, key: &str, output: String) -> Option<String> {
+        let mut stored = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = stored
+            .entries
+            .iter()
+            .position(|(stored_key, _)| stored_key == key)
+            .and_then(|pos| stored.entries.remove(pos))
+            .map(|(_, previous)| previous);
+        if let Some(ref previous) = previous {
+            stored.bytes -= previous.len();
+        }
+        if output.len() > self.max_bytes {
+            return previous;
+        }
+
+        while stored.entries.len() >= self.max_entries
+            || stored.bytes + output.len() > self.max_bytes
+        {
+            let Some((_, dropped)) = stored.entries.pop_front() else {
+                break;
+            };
+            stored.bytes -= dropped.len();
+        }
+        stored.bytes += output.len();
+        stored.entries.push_back((key.to_string(), output));
+        previous
+    }
+
+    /// Output stored under `key`, if it is still kept
+    pub fn get(&self, key: &str) -> Option<String> {
+        let stored = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        stored
+            .entries
+            .iter()
+            .find(|(stored_key, _)| stored_key == key)
+            .map(|(_, output)| output.clone())
+    }
+}
+
+/// Recent exec output kept for `stdin_from`, under generated ids
+#[derive(Debug)]
+pub struct ResultStore {
+    next_id: AtomicU64,
+    outputs: BoundedOutputs,
+}
+
+impl Default for ResultStore {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            outputs: BoundedOutputs::new(MAX_STORED_RESULTS, MAX_STORED_RESULT_BYTES),
+        }
+    }
+}
+
+impl ResultStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `output` and return the id it can be fetched with
+    ///
+    /// The oldest outputs are dropped to stay within [`MAX_STORED_RESULTS`]
+    /// and [`MAX_STORED_RESULT_BYTES`]. An output larger than the byte budget
+    /// on its own is not kept and gets no id.
+    pub fn insert(&self, output: String) -> Option<String> {
+        if output.len() > MAX_STORED_RESULT_BYTES {
+            return None;
+        }
+        let id = format!("r{}", self.next_id.fetch_add(1, Ordering::SeqCst) + 1);
+        self.outputs.insert(&id, output);
+        Some(id)
+    }
+
+    /// Output stored under `id`, if it is still kept
+    pub fn get(&self, id: &str) -> Option<String> {
+        self.outputs.get(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_store_keeps_recent_outputs() {
+        let store = ResultStore::new();
+        let first = store.insert("one\n".to_string()).unwrap();
+        let second = store.insert("two\n".to_string()).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(store.get(&first).as_deref(), Some("one\n"));
+        assert_eq!(store.get(&second).as_deref(), Some("two\n"));
+        assert_eq!(store.get("r999"), None);
+
+        for _ in 0..MAX_STORED_RESULTS - 1 {
+            store.insert(String::new());
+        }
+        assert_eq!(store.get(&first), None);
+        assert_eq!(store.get(&second).as_deref(), Some("two\n"));
+    }
+
+    #[test]
+    fn test_result_store_byte_budget() {
+        let store = ResultStore::new();
+        let small = store.insert("x".to_string()).unwrap();
+        let half = "y".repeat(MAX_STORED_RESULT_BYTES / 2);
+        let first_half = store.insert(half.clone()).unwrap();
+        store.insert(half);
+        // Both halves no longer fit next to the small output
+        assert_eq!(store.get(&small), None);
+        assert!(store.get(&first_half).is_some());
+
+        // Too large to keep, so it gets no id and evicts nothing
+        assert_eq!(store.insert("z".repeat(MAX_STORED_RESULT_BYTES + 1)), None);
+        assert!(store.get(&first_half).is_some());
+    }
+
+    #[test]
+    fn test_bounded_outputs_replace_key() {
+        let outputs = BoundedOutputs::new(3, 10);
+        assert_eq!(outputs.insert("a", "one".to_string()), None);
+        assert_eq!(outputs.insert("b", "two".to_string()), None);
+        // Replacing "a" makes it the newest, so "b" is evicted first
+        assert_eq!(
+            outputs.insert("a", "three".to_string()).as_deref(),
+            Some("one")
+        );
+        assert_eq!(outputs.insert("c", "four".to_string()), None);
+        assert_eq!(outputs.get("b"), None);
+        assert_eq!(outputs.get("a").as_deref(), Some("three"));
+
+        // Only "c" still fits next to "d" in the byte budget
+        outputs.insert("d", "xx".to_string());
+        assert_eq!(outputs.get("a"), None);
+        assert_eq!(outputs.insert("c", "y".repeat(11)).as_deref(), Some("four"));
+        assert_eq!(outputs.get("c"), None);
+        assert_eq!(outputs.get("d").as_deref(), Some("xx"));
+    }
+}
@@ -0,0 +1,85 @@
+//! Helpers for the `session-info` tool
+
+use serde_json::{json, Value};
+
+/// Command printing the locale variables that determine output encoding
+pub const LOCALE_PROBE_COMMAND: &str =
+    r#"printf 'LC_ALL=%s\nLC_CTYPE=%s\nLANG=%s\n' "${LC_ALL:-}" "${LC_CTYPE:-}" "${LANG:-}""#;
+
+/// Command printing the user commands run as, used by `drop-elevation`
+pub const WHOAMI_COMMAND: &str = "id -un";
+
+/// Parse the output of [`LOCALE_PROBE_COMMAND`]
+///
+/// The effective character-set locale follows the usual precedence
+/// (`LC_ALL`, then `LC_CTYPE`, then `LANG`, falling back to `POSIX`). A
+/// warning is included when it isn't UTF-8, since tools like `ls` then
+/// print `?` for non-ASCII file names.
+pub fn parse_locale_probe(stdout: &str) -> Value {
+    let lookup = |name: &str| {
+        stdout
+            .lines()
+            .filter_map(|line| line.trim_end_matches('\r').strip_prefix(name))
+            .filter_map(|rest| rest.strip_prefix('='))
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let lc_all = lookup("LC_ALL");
+    let lc_ctype = lookup("LC_CTYPE");
+    let lang = lookup("LANG");
+
+    let effective = [&lc_all, &lc_ctype, &lang]
+        .into_iter()
+        .find(|v| !v.is_empty())
+        .cloned()
+        .unwrap_or_else(|| "POSIX".to_string());
+    let lowered = effective.to_lowercase();
+    let utf8 = lowered.contains("utf-8") || lowered.contains("utf8");
+
+    let mut info = json!({
+        "LC_ALL": lc_all,
+        "LC_CTYPE": lc_ctype,
+        "LANG": lang,
+        "effective": effective,
+        "utf8": utf8,
+    });
+
+    if !utf8 {
+        info["warning"] = json!(
+            "Remote locale is not UTF-8; non-ASCII file names and output may be \
+             garbled. Start the server with --remote-locale=C.UTF-8 to fix this."
+        );
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_locale_probe_utf8() {
+        let info = parse_locale_probe("LC_ALL=\nLC_CTYPE=\nLANG=en_US.UTF-8\n");
+        assert_eq!(info["effective"], "en_US.UTF-8");
+        assert_eq!(info["utf8"], true);
+        assert!(info.get("warning").is_none());
+    }
+
+    #[test]
+    fn test_parse_locale_probe_precedence() {
+        let info = parse_locale_probe("LC_ALL=C\nLC_CTYPE=\nLANG=en_US.UTF-8\n");
+        assert_eq!(info["effective"], "C");
+        assert_eq!(info["utf8"], false);
+        assert!(info.get("warning").is_some());
+    }
+
+    #[test]
+    fn test_parse_locale_probe_unset() {
+        let info = parse_locale_probe("LC_ALL=\r\nLC_CTYPE=\r\nLANG=\r\n");
+        assert_eq!(info["effective"], "POSIX");
+        assert_eq!(info["utf8"], false);
+    }
+}
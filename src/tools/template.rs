@@ -0,0 +1,286 @@
+//! Fixed command templates for `--only-command`
+//!
+//! A template like `systemctl {action} {service}` is the only command the
+//! server runs in this mode. Callers fill in the placeholders; each value is
+//! checked and quoted as a single shell word, so it can't add commands,
+//! options or further arguments.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use crate::error::{Result, SshMcpError};
+use crate::ssh::env::is_valid_env_name;
+use crate::ssh::sanitize::escape_command_for_shell;
+
+/// Longest value accepted for a placeholder
+pub const MAX_PLACEHOLDER_VALUE_LEN: usize = 1024;
+
+/// Piece of a parsed template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Text copied as-is
+    Literal(String),
+
+    /// `{name}`, replaced with the caller's value
+    Placeholder(String),
+}
+
+/// A command with named `{placeholder}`s
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl FromStr for CommandTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> std::result::Result<Self, Self::Err> {
+        if template.trim().is_empty() {
+            return Err("the template is empty".to_string());
+        }
+        if template.contains(['\n', '\r', '\0']) {
+            return Err("the template must be a single line".to_string());
+        }
+
+        let mut segments = Vec::new();
+        let mut quote = None;
+        let mut rest = template;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                return Err(format!("unmatched '}}' in {:?}", template));
+            }
+            let close = rest[open..]
+                .find('}')
+                .map(|close| open + close)
+                .ok_or_else(|| format!("unmatched '{{' in {:?}", template))?;
+            let name = &rest[open + 1..close];
+            if !is_valid_env_name(name) {
+                return Err(format!(
+                    "invalid placeholder {{{}}}: names use letters, digits and '_'",
+                    name
+                ));
+            }
+            quote = track_quotes(&rest[..open], quote);
+            if quote.is_some() {
+                return Err(format!(
+                    "placeholder {{{}}} is inside quotes; values are quoted automatically",
+                    name
+                ));
+            }
+            if open > 0 {
+                segments.push(Segment::Literal(rest[..open].to_string()));
+            }
+            segments.push(Segment::Placeholder(name.to_string()));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for CommandTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl CommandTemplate {
+    /// Placeholder names, in order of first appearance
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for segment in &self.segments {
+            if let Segment::Placeholder(name) = segment {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// Fill in the placeholders from the tool arguments
+    ///
+    /// Every placeholder needs a string value, and no other arguments are
+    /// accepted. Values are single-quoted, may not be empty, contain control
+    /// characters or start with `-` (so they can't pass options).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ssh_mcp::tools::template::CommandTemplate;
+    ///
+    /// let template: CommandTemplate = "systemctl {action} {service}".parse().unwrap();
+    /// let args = serde_json::json!({ "action": "restart", "service": "nginx" });
+    /// let command = template.render(args.as_object().unwrap()).unwrap();
+    /// assert_eq!(command, "systemctl 'restart' 'nginx'");
+    /// ```
+    pub fn render(&self, args: &Map<String, Value>) -> Result<String> {
+        let placeholders = self.placeholders();
+        if let Some(unknown) = args
+            .keys()
+            .find(|key| !placeholders.contains(&key.as_str()))
+        {
+            return Err(SshMcpError::invalid_params(format!(
+                "Unknown parameter {:?}; this server only runs {:?}",
+                unknown, self.template
+            )));
+        }
+
+        let mut command = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => command.push_str(text),
+                Segment::Placeholder(name) => {
+                    let value = match args.get(name) {
+                        Some(Value::String(value)) => value,
+                        Some(_) => {
+                            return Err(SshMcpError::invalid_params(format!(
+                                "Parameter {} must be a string",
+                                name
+                            )))
+                        }
+                        None => {
+                            return Err(SshMcpError::invalid_params(format!(
+                                "Missing parameter {}",
+                                name
+                            )))
+                        }
+                    };
+                    validate_value(name, value)?;
+                    command.push('\'');
+                    command.push_str(&escape_command_for_shell(value));
+                    command.push('\'');
+                }
+            }
+        }
+        Ok(command)
+    }
+}
+
+/// Quote a shell is inside of after `text`, starting inside `quote`
+fn track_quotes(text: &str, mut quote: Option<char>) -> Option<char> {
+    let mut escaped = false;
+    for c in text.chars() {
+        match (quote, c) {
+            (_, _) if escaped => escaped = false,
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some('"'), '"') => quote = None,
+            _ => {}
+        }
+    }
+    quote
+}
+
+/// Check a placeholder value before it is quoted into the command
+fn validate_value(name: &str, value: &str) -> Result<()> {
+    let problem = if value.is_empty() {
+        "must not be empty"
+    } else if value.len() > MAX_PLACEHOLDER_VALUE_LEN {
+        "is too long"
+    } else if value.chars().any(char::is_control) {
+        "must not contain control characters"
+    } else if value.starts_with('-') {
+        "must not start with '-'"
+    } else {
+        return Ok(());
+    };
+    Err(SshMcpError::invalid_params(format!(
+        "Parameter {} {}",
+        name, problem
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &str, args: Value) -> Result<String> {
+        let template: CommandTemplate = template.parse().unwrap();
+        template.render(args.as_object().unwrap())
+    }
+
+    #[test]
+    fn test_parse_template() {
+        let template: CommandTemplate = "journalctl -u {unit} -n 50 --grep {text} {unit}"
+            .parse()
+            .unwrap();
+        assert_eq!(template.placeholders(), vec!["unit", "text"]);
+        assert_eq!(
+            template.to_string(),
+            "journalctl -u {unit} -n 50 --grep {text} {unit}"
+        );
+
+        let quoted: CommandTemplate = "grep 'a b' {file} \"c\\\"\" {n}".parse().unwrap();
+        assert_eq!(quoted.placeholders(), vec!["file", "n"]);
+
+        let fixed: CommandTemplate = "uptime".parse().unwrap();
+        assert!(fixed.placeholders().is_empty());
+
+        for bad in [
+            "",
+            "ls {",
+            "ls }",
+            "ls {}",
+            "ls {a b}",
+            "ls {1x}",
+            "ls\nrm {x}",
+            "echo '{x}'",
+            "echo \"a {x}\"",
+        ] {
+            assert!(bad.parse::<CommandTemplate>().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_render_quotes_values() {
+        assert_eq!(
+            render(
+                "cat /var/log/{file}",
+                json!({ "file": "app.log; rm -rf /" })
+            )
+            .unwrap(),
+            "cat /var/log/'app.log; rm -rf /'"
+        );
+        assert_eq!(
+            render("echo {msg}", json!({ "msg": "it's $(id)" })).unwrap(),
+            "echo 'it'\"'\"'s $(id)'"
+        );
+        assert_eq!(render("uptime", json!({})).unwrap(), "uptime");
+    }
+
+    #[test]
+    fn test_render_rejects_bad_arguments() {
+        let template = "systemctl {action} {service}";
+        let cases = [
+            (json!({ "action": "stop" }), "Missing parameter service"),
+            (
+                json!({ "action": "stop", "service": "a", "extra": "b" }),
+                "Unknown parameter \"extra\"",
+            ),
+            (json!({ "action": 1, "service": "a" }), "must be a string"),
+            (json!({ "action": "", "service": "a" }), "must not be empty"),
+            (json!({ "action": "stop", "service": "a\nb" }), "control"),
+            (
+                json!({ "action": "--help", "service": "a" }),
+                "start with '-'",
+            ),
+        ];
+        for (args, expected) in cases {
+            let err = render(template, args).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+    }
+}
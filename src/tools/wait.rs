@@ -0,0 +1,124 @@
+//! Helpers for the `wait-for` tool
+//!
+//! `wait-for` re-runs a command until its result meets a condition, e.g.
+//! "until the health check exits 0" or "until the log mentions `ready`",
+//! instead of the caller polling with one `exec` call per attempt.
+
+use crate::error::{Result, SshMcpError};
+use crate::ssh::CommandOutput;
+
+/// Pause between attempts when none is given
+pub const DEFAULT_WAIT_INTERVAL_MS: u64 = 1_000;
+
+/// Shortest pause between attempts, so a wait cannot hammer the host
+pub const MIN_WAIT_INTERVAL_MS: u64 = 100;
+
+/// Shortest timeout an attempt gets, so one started right at the deadline
+/// still has time to run
+pub const MIN_WAIT_ATTEMPT_MS: u64 = 500;
+
+/// How long to keep trying when no timeout is given
+pub const DEFAULT_WAIT_TIMEOUT_MS: u64 = 60_000;
+
+/// Longest a single wait may last (one hour)
+pub const MAX_WAIT_TIMEOUT_MS: u64 = 3_600_000;
+
+/// What a command's result has to look like for the wait to end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitCondition {
+    /// Required exit code
+    pub exit_code: Option<u32>,
+
+    /// Text required in stdout or stderr
+    pub output_contains: Option<String>,
+}
+
+impl WaitCondition {
+    /// Build a condition from the tool parameters
+    ///
+    /// Without either expectation the command has to exit 0.
+    pub fn new(exit_code: Option<u32>, output_contains: Option<String>) -> Self {
+        let exit_code = match (exit_code, &output_contains) {
+            (None, None) => Some(0),
+            (code, _) => code,
+        };
+        Self {
+            exit_code,
+            output_contains,
+        }
+    }
+
+    /// Whether `output` meets every expectation
+    pub fn is_met(&self, output: &CommandOutput) -> bool {
+        self.exit_code
+            .is_none_or(|code| output.exit_code == Some(code))
+            && self
+                .output_contains
+                .as_deref()
+                .is_none_or(|text| output.stdout.contains(text) || output.stderr.contains(text))
+    }
+}
+
+/// Check the polling interval and timeout of a wait
+pub fn validate_wait(interval_ms: u64, timeout_ms: u64) -> Result<()> {
+    if interval_ms < MIN_WAIT_INTERVAL_MS {
+        return Err(SshMcpError::invalid_params(format!(
+            "interval_ms must be at least {}",
+            MIN_WAIT_INTERVAL_MS
+        )));
+    }
+    if timeout_ms == 0 || timeout_ms > MAX_WAIT_TIMEOUT_MS {
+        return Err(SshMcpError::invalid_params(format!(
+            "timeout_ms must be between 1 and {}",
+            MAX_WAIT_TIMEOUT_MS
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(stdout: &str, exit_code: u32) -> CommandOutput {
+        CommandOutput {
+            stdout: stdout.to_string(),
+            exit_code: Some(exit_code),
+            ..CommandOutput::new()
+        }
+    }
+
+    #[test]
+    fn test_default_condition_is_success() {
+        let condition = WaitCondition::new(None, None);
+        assert!(condition.is_met(&output("", 0)));
+        assert!(!condition.is_met(&output("", 1)));
+        assert!(!condition.is_met(&CommandOutput::new()));
+    }
+
+    #[test]
+    fn test_condition_output_contains() {
+        // Any exit code goes when only the output is checked
+        let condition = WaitCondition::new(None, Some("ready".to_string()));
+        assert!(condition.is_met(&output("server ready\n", 3)));
+        assert!(!condition.is_met(&output("starting\n", 0)));
+
+        let stderr = CommandOutput {
+            stderr: "ready".to_string(),
+            ..CommandOutput::new()
+        };
+        assert!(condition.is_met(&stderr));
+
+        let condition = WaitCondition::new(Some(0), Some("ready".to_string()));
+        assert!(!condition.is_met(&output("ready", 1)));
+        assert!(condition.is_met(&output("ready", 0)));
+    }
+
+    #[test]
+    fn test_validate_wait() {
+        assert!(validate_wait(DEFAULT_WAIT_INTERVAL_MS, DEFAULT_WAIT_TIMEOUT_MS).is_ok());
+        assert!(validate_wait(10, 1_000).is_err());
+        assert!(validate_wait(1_000, 0).is_err());
+        assert!(validate_wait(1_000, MAX_WAIT_TIMEOUT_MS + 1).is_err());
+    }
+}